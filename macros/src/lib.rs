@@ -0,0 +1,217 @@
+use mini6502::asm::{self, Assembly, Options};
+use proc_macro::{Delimiter, Span, TokenStream, TokenTree};
+
+/// Assemble 6502 code at compile time.
+///
+/// Statements are separated by `;` and use the syntax of `mini6502::asm`. Use `//` for
+/// comments. The macro expands to a value with these fields:
+///
+/// * `origin: u16` - Address of the first byte
+/// * `bytes: [u8; N]` - Assembled binary
+/// * `symbols: [(&'static str, u16); K]` - Labels and constants
+///
+/// and a `symbol(name) -> u16` method that panics if `name` isn't defined.
+///
+/// ```rust
+/// use mini6502_macros::asm6502;
+///
+/// let program = asm6502! {
+///     .org $0600;
+///     ldx #$03;
+///     loop: dex;
+///     bne loop
+/// };
+///
+/// assert_eq!(program.origin, 0x0600);
+/// assert_eq!(program.bytes, [0xA2, 0x03, 0xCA, 0xD0, 0xFD]);
+/// assert_eq!(program.symbol("loop"), 0x0602);
+/// ```
+///
+/// Hex numbers that Rust can't tokenize, such as `$1E` (read as a float with a missing
+/// exponent), can be written as `0x1E`.
+#[proc_macro]
+pub fn asm6502(input: TokenStream) -> TokenStream {
+    let statements = split_statements(input);
+    let source: String = statements
+        .iter()
+        .map(|(text, _)| format!("{text}\n"))
+        .collect();
+
+    match asm::assemble(&source, Options::default()) {
+        Ok(assembly) => expand(&assembly),
+        Err(err) => {
+            let (text, span) = &statements[err.line - 1];
+            compile_error(&format!("{} (in \"{text}\")", err.kind), *span)
+        }
+    }
+}
+
+/// Split the input on `;` and turn each statement back into a line of assembler source, along
+/// with the span of its first token.
+fn split_statements(input: TokenStream) -> Vec<(String, Span)> {
+    let mut statements = vec![];
+    let mut current = vec![];
+    for tt in input.into_iter().chain(std::iter::once(separator())) {
+        match tt {
+            TokenTree::Punct(ref p) if p.as_char() == ';' => {
+                if let Some(first) = current.first() {
+                    let span = TokenTree::span(first);
+                    statements.push((statement_text(&current), span));
+                }
+                current.clear();
+            }
+            tt => current.push(tt),
+        }
+    }
+    statements
+}
+
+fn separator() -> TokenTree {
+    TokenTree::Punct(proc_macro::Punct::new(';', proc_macro::Spacing::Alone))
+}
+
+fn is_punct(tt: &TokenTree, c: char) -> bool {
+    matches!(tt, TokenTree::Punct(p) if p.as_char() == c)
+}
+
+fn statement_text(tokens: &[TokenTree]) -> String {
+    let mut text = String::new();
+    let mut rest = tokens;
+
+    // `label:` or `@local:`
+    let label_len = match rest {
+        [at, TokenTree::Ident(_), colon, ..] if is_punct(at, '@') && is_punct(colon, ':') => 3,
+        [TokenTree::Ident(_), colon, ..] if is_punct(colon, ':') => 2,
+        _ => 0,
+    };
+    if label_len > 0 {
+        text.push_str(&join(&rest[..label_len]));
+        text.push(' ');
+        rest = &rest[label_len..];
+    }
+
+    // The mnemonic, directive or constant name has to be followed by whitespace
+    let word_len = match rest {
+        [dot, TokenTree::Ident(_), ..] if is_punct(dot, '.') => 2,
+        [] => 0,
+        _ => 1,
+    };
+    text.push_str(&join(&rest[..word_len]));
+    text.push(' ');
+    text.push_str(&join(&rest[word_len..]));
+    text.trim().to_string()
+}
+
+/// Concatenate tokens, only separating identifiers and literals that would otherwise merge.
+fn join(tokens: &[TokenTree]) -> String {
+    let mut text = String::new();
+    let mut last_was_word = false;
+    for tt in tokens {
+        let (piece, is_word) = match tt {
+            TokenTree::Group(group) => {
+                let inner: Vec<TokenTree> = group.stream().into_iter().collect();
+                let inner = join(&inner);
+                let piece = match group.delimiter() {
+                    Delimiter::Parenthesis => format!("({inner})"),
+                    Delimiter::Bracket => format!("[{inner}]"),
+                    Delimiter::Brace => format!("{{{inner}}}"),
+                    Delimiter::None => inner,
+                };
+                (piece, false)
+            }
+            TokenTree::Ident(ident) => (ident.to_string(), true),
+            TokenTree::Punct(punct) => (punct.as_char().to_string(), false),
+            TokenTree::Literal(literal) => {
+                let mut literal = literal.to_string();
+                if literal.starts_with(|c: char| c.is_ascii_digit()) {
+                    // Drop Rust digit separators
+                    literal.retain(|c| c != '_');
+                }
+                let piece = if let Some(hex) = literal.strip_prefix("0x") {
+                    format!("${hex}")
+                } else if let Some(bin) = literal.strip_prefix("0b") {
+                    format!("%{bin}")
+                } else {
+                    literal
+                };
+                (piece, true)
+            }
+        };
+        if last_was_word && is_word {
+            text.push(' ');
+        }
+        text.push_str(&piece);
+        last_was_word = is_word;
+    }
+    text
+}
+
+fn expand(assembly: &Assembly) -> TokenStream {
+    let bytes: Vec<String> = assembly
+        .binary
+        .iter()
+        .map(|b| format!("{b:#04x}u8"))
+        .collect();
+    let symbols: Vec<String> = assembly
+        .symbols
+        .iter()
+        .map(|(name, value)| format!("({name:?}, {value:#06x}u16)"))
+        .collect();
+
+    format!(
+        "{{
+            #[allow(dead_code)]
+            #[derive(Clone, Copy, Debug)]
+            struct Asm6502 {{
+                origin: u16,
+                bytes: [u8; {}],
+                symbols: [(&'static str, u16); {}],
+            }}
+
+            #[allow(dead_code)]
+            impl Asm6502 {{
+                fn symbol(&self, name: &str) -> u16 {{
+                    match self.symbols.iter().find(|(symbol, _)| *symbol == name) {{
+                        Some((_, value)) => *value,
+                        None => panic!(\"Symbol \\\"{{}}\\\" is not defined\", name),
+                    }}
+                }}
+            }}
+
+            Asm6502 {{
+                origin: {:#06x}u16,
+                bytes: [{}],
+                symbols: [{}],
+            }}
+        }}",
+        bytes.len(),
+        symbols.len(),
+        assembly.origin,
+        bytes.join(", "),
+        symbols.join(", "),
+    )
+    .parse()
+    .unwrap()
+}
+
+fn with_span(stream: TokenStream, span: Span) -> TokenStream {
+    stream
+        .into_iter()
+        .map(|mut tt| {
+            if let TokenTree::Group(group) = &tt {
+                let mut new_group =
+                    proc_macro::Group::new(group.delimiter(), with_span(group.stream(), span));
+                new_group.set_span(span);
+                tt = TokenTree::Group(new_group);
+            } else {
+                tt.set_span(span);
+            }
+            tt
+        })
+        .collect()
+}
+
+fn compile_error(msg: &str, span: Span) -> TokenStream {
+    let tokens: TokenStream = format!("compile_error!({msg:?})").parse().unwrap();
+    with_span(tokens, span)
+}
@@ -0,0 +1,266 @@
+use crate::device::Device;
+use crate::serial::Serial;
+
+// Registers, selected by the low 2 bits of the address
+const DATA: u16 = 0x0;
+/// Status when read, programmed reset when written
+const STATUS: u16 = 0x1;
+const COMMAND: u16 = 0x2;
+const CONTROL: u16 = 0x3;
+
+// Status bits. DSR and DCD read as 0, ready and carrier present.
+const IRQ_STATUS: u8 = 0x80;
+const TDRE_STATUS: u8 = 0x10;
+const RDRF_STATUS: u8 = 0x08;
+
+// Command bits
+const DTR: u8 = 0x01;
+/// Set to disable the receiver interrupt
+const RX_IRQ_DISABLE: u8 = 0x02;
+const TX_CONTROL: u8 = 0x0C;
+/// Transmitter control bits enabling its interrupt
+const TX_IRQ_ENABLE: u8 = 0x04;
+const ECHO: u8 = 0x10;
+
+/// Cycles a byte takes to go in or out by default, about what it takes at 115200 baud and
+/// 1 MHz.
+pub const CHAR_CYCLES: u32 = 100;
+
+/// A MOS 6551 Asynchronous Communications Interface Adapter, connected to a [`Serial`] line.
+/// It decodes 2 address lines: data, status, command and control.
+///
+/// Bytes take [`Acia::char_cycles`] cycles to go in or out, whatever baud rate the control
+/// register selects, and a byte only comes in once the last one was read so none is lost to an
+/// overrun.
+///
+/// ```rust
+/// use mini6502::acia::Acia;
+/// use mini6502::device::Device;
+/// use mini6502::serial::Buffer;
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// let line = Rc::new(RefCell::new(Buffer::new(b"?")));
+/// let mut acia = Acia::new(Box::new(line.clone()));
+/// // DTR ready, receiver interrupt enabled
+/// acia.write(0x2, 0x09);
+/// acia.tick(100);
+/// assert!(acia.irq());
+/// assert_eq!(acia.read(0x1), 0x98);
+/// assert_eq!(acia.read(0x0), b'?');
+///
+/// acia.write(0x0, b'!');
+/// assert_eq!(acia.read(0x1) & 0x10, 0x00);
+/// acia.tick(100);
+/// assert_eq!(acia.read(0x1) & 0x10, 0x10);
+/// assert_eq!(line.borrow().output, b"!");
+/// ```
+pub struct Acia {
+    pub serial: Box<dyn Serial>,
+    /// Cycles a byte takes to be sent or received, [`CHAR_CYCLES`] by default
+    pub char_cycles: u32,
+    rx_data: u8,
+    rdrf: bool,
+    /// Cycles until the receiver looks for another byte
+    rx_timer: u32,
+    tx_data: u8,
+    tdre: bool,
+    /// Cycles until the byte in `tx_data` is sent
+    tx_timer: u32,
+    irq: bool,
+    command: u8,
+    control: u8,
+}
+
+impl Acia {
+    /// An ACIA after a hardware reset, with the receiver interrupt disabled and DTR not ready.
+    pub fn new(serial: Box<dyn Serial>) -> Self {
+        Acia {
+            serial,
+            char_cycles: CHAR_CYCLES,
+            rx_data: 0x00,
+            rdrf: false,
+            rx_timer: 0,
+            tx_data: 0x00,
+            tdre: true,
+            tx_timer: 0,
+            irq: false,
+            command: RX_IRQ_DISABLE,
+            control: 0x00,
+        }
+    }
+
+    fn tx_irq_enabled(&self) -> bool {
+        self.command & TX_CONTROL == TX_IRQ_ENABLE
+    }
+
+    fn tick_receiver(&mut self, cycles: u32) {
+        if self.command & DTR == 0 || self.rdrf {
+            return;
+        }
+        self.rx_timer = self.rx_timer.saturating_sub(cycles);
+        if self.rx_timer > 0 {
+            return;
+        }
+        if let Some(byte) = self.serial.receive() {
+            self.rx_data = byte;
+            self.rdrf = true;
+            self.rx_timer = self.char_cycles;
+            if self.command & RX_IRQ_DISABLE == 0 {
+                self.irq = true;
+            }
+            // Echo only works with the transmitter's interrupt disabled and RTS high
+            if self.command & (ECHO | TX_CONTROL) == ECHO {
+                self.serial.send(byte);
+            }
+        }
+    }
+
+    fn tick_transmitter(&mut self, cycles: u32) {
+        if self.tdre {
+            return;
+        }
+        self.tx_timer = self.tx_timer.saturating_sub(cycles);
+        if self.tx_timer == 0 {
+            self.serial.send(self.tx_data);
+            self.tdre = true;
+            if self.tx_irq_enabled() && self.command & DTR != 0 {
+                self.irq = true;
+            }
+        }
+    }
+}
+
+impl Device for Acia {
+    fn read(&mut self, reg: u16) -> u8 {
+        let value = self.peek(reg);
+        match reg & 0x03 {
+            DATA => self.rdrf = false,
+            STATUS => self.irq = false,
+            _ => {}
+        }
+        value
+    }
+
+    fn peek(&self, reg: u16) -> u8 {
+        match reg & 0x03 {
+            DATA => self.rx_data,
+            STATUS => {
+                (self.irq as u8 * IRQ_STATUS)
+                    | (self.tdre as u8 * TDRE_STATUS)
+                    | (self.rdrf as u8 * RDRF_STATUS)
+            }
+            COMMAND => self.command,
+            CONTROL => self.control,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg & 0x03 {
+            DATA => {
+                self.tx_data = value;
+                self.tdre = false;
+                self.tx_timer = self.char_cycles;
+            }
+            // A programmed reset leaves the control register and the parity bits alone
+            STATUS => {
+                self.command &= 0xE0;
+                self.irq = false;
+            }
+            COMMAND => {
+                self.command = value;
+                if self.tdre && self.tx_irq_enabled() && value & DTR != 0 {
+                    self.irq = true;
+                }
+            }
+            CONTROL => self.control = value,
+            _ => unreachable!(),
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.tick_transmitter(cycles as u32);
+        self.tick_receiver(cycles as u32);
+    }
+
+    fn irq(&self) -> bool {
+        self.irq
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::DeviceMemory;
+    use crate::hooks::until;
+    use crate::memory::SimpleMemory;
+    use crate::serial::Buffer;
+    use crate::Cpu;
+    use mini6502_macros::asm6502;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_echo_program() {
+        // Echo 3 bytes back in uppercase, polling the status register
+        let program = asm6502! {
+            lda #$0B;
+            sta $8002;
+            ldx #$03;
+            wait_rx: lda $8001;
+            and #$08;
+            beq wait_rx;
+            lda $8000;
+            and #$DF;
+            sta $8000;
+            wait_tx: lda $8001;
+            and #$10;
+            beq wait_tx;
+            dex;
+            bne wait_rx;
+            done: jmp done
+        };
+        let line = Rc::new(RefCell::new(Buffer::new(b"abc")));
+        let mut mem = DeviceMemory::new(SimpleMemory::from_rom(&program.bytes));
+        let mut acia = Acia::new(Box::new(line.clone()));
+        acia.char_cycles = 50;
+        mem.map(0x8000..=0x8003, Box::new(acia));
+        let mut cpu = Cpu::with_mem(mem);
+        cpu.run(&mut until(|cpu| cpu.pc() == program.symbol("done")))
+            .unwrap();
+        assert_eq!(line.borrow().output, b"ABC");
+        assert!(cpu.cycle_count() >= 3 * 50);
+    }
+
+    #[test]
+    fn test_interrupts_and_echo() {
+        let line = Rc::new(RefCell::new(Buffer::new(b"xy")));
+        let mut acia = Acia::new(Box::new(line.clone()));
+        acia.char_cycles = 10;
+        // The receiver waits for DTR
+        acia.tick(100);
+        assert_eq!(acia.read(STATUS), TDRE_STATUS);
+
+        // Transmitter interrupt only, it's empty already
+        acia.write(COMMAND, 0x07);
+        assert!(acia.irq());
+        assert_eq!(acia.read(STATUS), IRQ_STATUS | TDRE_STATUS);
+        assert!(!acia.irq());
+        acia.tick(10);
+        assert_eq!(acia.read(STATUS), TDRE_STATUS | RDRF_STATUS);
+        // The next byte waits for this one to be read
+        acia.tick(100);
+        assert_eq!(acia.read(DATA), b'x');
+        assert_eq!(line.borrow().input, b"y");
+
+        // Programmed reset disables the interrupts and DTR, then echo
+        acia.write(STATUS, 0x00);
+        assert_eq!(acia.read(COMMAND), 0x00);
+        acia.write(COMMAND, 0x13);
+        acia.tick(10);
+        assert_eq!(acia.read(DATA), b'y');
+        assert!(!acia.irq());
+        assert_eq!(line.borrow().output, b"y");
+    }
+}
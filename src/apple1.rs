@@ -0,0 +1,282 @@
+use crate::device::{Device, DeviceMemory};
+use crate::error::RomSizeError;
+use crate::memory::SimpleMemory;
+use crate::pia::Pia;
+use crate::serial::Serial;
+use std::ops::{Range, RangeInclusive};
+
+/// The RAM on the board
+pub const RAM: Range<u32> = 0x0000..0x2000;
+/// KBD, KBDCR, DSP and DSPCR
+pub const PIA: RangeInclusive<u16> = 0xD010..=0xD013;
+/// Where a 256 byte monitor ROM goes, bigger ones start lower so they still end at $FFFF
+pub const ROM_START: u16 = 0xFF00;
+
+const KBDCR: u16 = 0x1;
+const DSPCR: u16 = 0x3;
+
+/// The keyboard and display of the Apple I, connected to a [`Serial`] line, with the PIA they
+/// hang off.
+///
+/// Keys go to port A with bit 7 set, strobing CA1, and only once the program read the last one.
+/// Lower case letters are typed in upper case, line feeds as returns, and backspace and delete as
+/// `_`, the monitor's rubout. The display takes port B's 7 bits when CB2 signals a write, shows
+/// printable characters in upper case and returns as new lines, then answers on CB1. The PIA's
+/// interrupt outputs aren't connected.
+pub struct Terminal {
+    pub pia: Pia,
+    pub serial: Box<dyn Serial>,
+}
+
+impl Terminal {
+    pub fn new(serial: Box<dyn Serial>) -> Self {
+        let mut pia = Pia::new();
+        // PB7 low: the display is always ready
+        pia.set_port_b(0x00);
+        Terminal { pia, serial }
+    }
+
+    /// Send an active edge to C1 of the side whose control register is `control`.
+    fn strobe(&mut self, control: u16) {
+        let positive = self.pia.peek(control) & 0x02 != 0;
+        let set_c1 = match control {
+            KBDCR => Pia::set_ca1,
+            _ => Pia::set_cb1,
+        };
+        set_c1(&mut self.pia, !positive);
+        set_c1(&mut self.pia, positive);
+    }
+
+    fn display(&mut self) {
+        if self.pia.cb2() {
+            return;
+        }
+        match self.pia.port_b() & 0x7F {
+            b'\r' => {
+                self.serial.send(b'\r');
+                self.serial.send(b'\n');
+            }
+            byte @ 0x20..=0x7E => self.serial.send(byte.to_ascii_uppercase()),
+            _ => {}
+        }
+        self.strobe(DSPCR);
+    }
+
+    fn keyboard(&mut self) {
+        // The last key is still waiting to be read
+        if self.pia.peek(KBDCR) & 0x80 != 0 {
+            return;
+        }
+        if let Some(byte) = self.serial.receive() {
+            let key = match byte {
+                b'\n' => b'\r',
+                0x08 | 0x7F => b'_',
+                byte => byte.to_ascii_uppercase() & 0x7F,
+            };
+            self.pia.set_port_a(key | 0x80);
+            self.strobe(KBDCR);
+        }
+    }
+}
+
+impl Device for Terminal {
+    fn read(&mut self, reg: u16) -> u8 {
+        self.pia.read(reg)
+    }
+
+    fn peek(&self, reg: u16) -> u8 {
+        self.pia.peek(reg)
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        self.pia.write(reg, value);
+        self.display();
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.pia.tick(cycles);
+        self.keyboard();
+    }
+}
+
+/// Reads of nothing
+struct Unmapped;
+
+impl Device for Unmapped {
+    fn read(&mut self, _reg: u16) -> u8 {
+        0x00
+    }
+
+    fn peek(&self, _reg: u16) -> u8 {
+        0x00
+    }
+
+    fn write(&mut self, _reg: u16, _value: u8) {}
+}
+
+/// Read only memory
+struct Rom(Vec<u8>);
+
+impl Device for Rom {
+    fn read(&mut self, reg: u16) -> u8 {
+        self.peek(reg)
+    }
+
+    fn peek(&self, reg: u16) -> u8 {
+        self.0[reg as usize]
+    }
+
+    fn write(&mut self, _reg: u16, _value: u8) {}
+}
+
+/// Where `rom` goes, see [`ROM_START`].
+pub fn rom_range(rom: &[u8]) -> Result<RangeInclusive<u16>, RomSizeError> {
+    let max = 0xFFFF - *PIA.end() as usize;
+    if rom.is_empty() || rom.len() > max {
+        return Err(RomSizeError {
+            size: rom.len(),
+            max,
+        });
+    }
+    let start = ROM_START.min((0x10000 - rom.len()) as u16);
+    Ok(start..=start + (rom.len() - 1) as u16)
+}
+
+/// The memory of an Apple I: 8 KiB of RAM, the PIA with the keyboard and display on `serial`,
+/// and `rom`, like the 256 bytes of WozMon, at the top. Nothing else answers.
+///
+/// ```rust
+/// use mini6502::apple1;
+/// use mini6502::serial::Buffer;
+/// use mini6502::Cpu;
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// // Set up the display like WozMon does and print "A":
+/// // ldy #$7F; sty $D012; lda #$A7; sta $D013; lda #$C1; sta $D012
+/// let mut rom = vec![
+///     0xA0, 0x7F, 0x8C, 0x12, 0xD0, 0xA9, 0xA7, 0x8D, 0x13, 0xD0, 0xA9, 0xC1, 0x8D, 0x12, 0xD0,
+/// ];
+/// // Reset vector at $FF00
+/// rom.resize(0xFC, 0x00);
+/// rom.extend([0x00, 0xFF, 0x00, 0x00]);
+/// let display = Rc::new(RefCell::new(Buffer::new(b"")));
+/// let mem = apple1::memory(&rom, Box::new(display.clone())).unwrap();
+///
+/// let mut cpu = Cpu::with_mem(mem);
+/// assert_eq!(cpu.pc(), 0xFF00);
+/// for _ in 0..6 {
+///     cpu.step().unwrap();
+/// }
+/// assert_eq!(display.borrow().output, b"A");
+/// ```
+pub fn memory(
+    rom: &[u8],
+    serial: Box<dyn Serial>,
+) -> Result<DeviceMemory<SimpleMemory>, RomSizeError> {
+    let rom_range = rom_range(rom)?;
+    let mut mem = DeviceMemory::new(SimpleMemory::from_rom(&[]));
+    mem.map(RAM.end as u16..=0xFFFF, Box::new(Unmapped));
+    mem.map(PIA, Box::new(Terminal::new(serial)));
+    mem.map(rom_range, Box::new(Rom(rom.to_vec())));
+    Ok(mem)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::hooks::until;
+    use crate::memory::Memory;
+    use crate::serial::Buffer;
+    use crate::trace::{TraceFormat, Tracer};
+    use mini6502_macros::asm6502;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_echo() {
+        // WozMon's setup and its keyboard and display loops
+        let program = asm6502! {
+            .org $FF00;
+            cld;
+            ldy #$7F;
+            sty $D012;
+            lda #$A7;
+            sta $D011;
+            sta $D013;
+            ldx #$04;
+            next: lda $D011;
+            bpl next;
+            lda $D010;
+            jsr echo;
+            dex;
+            bne next;
+            done: jmp done;
+            echo: bit $D012;
+            bmi echo;
+            sta $D012;
+            rts;
+            .res $FFFC - *;
+            .word $FF00;
+            .word $0000
+        };
+        assert_eq!(program.bytes.len(), 0x100);
+        let line = Rc::new(RefCell::new(Buffer::new(b"a\x7F1\n")));
+        let mut cpu = Cpu::with_mem(memory(&program.bytes, Box::new(line.clone())).unwrap());
+        cpu.run(&mut until(|cpu| cpu.pc() == program.symbol("done")))
+            .unwrap();
+        assert_eq!(line.borrow().output, b"A_1\r\n");
+
+        // Writes to ROM and unmapped memory go nowhere
+        cpu.mem.write_byte(0xFF00, 0xEA);
+        cpu.mem.write_byte(0x2000, 0xEA);
+        cpu.mem.write_byte(0x1FFF, 0xEA);
+        let read: Vec<u8> = [0xFF00, 0x2000, 0x1FFF]
+            .iter()
+            .map(|&addr| cpu.mem.read_byte(addr))
+            .collect();
+        assert_eq!(read, [0xD8, 0x00, 0xEA]);
+    }
+
+    #[test]
+    fn test_trace_keyboard_read() {
+        let program = asm6502! {
+            .org $FF00;
+            lda #$A7;
+            sta $D011;
+            wait: lda $D011;
+            bpl wait;
+            read: lda $D010;
+            done: jmp done;
+            .res $FFFC - *;
+            .word $FF00;
+            .word $0000
+        };
+        let line = Buffer::new(b"a");
+        let mut cpu = Cpu::with_mem(memory(&program.bytes, Box::new(line)).unwrap());
+        cpu.run(&mut until(|cpu| cpu.pc() == program.symbol("read")))
+            .unwrap();
+
+        // Tracing the read of the key doesn't take it
+        let mut tracer = Tracer::new(vec![], TraceFormat::Nestest);
+        tracer.trace(&cpu).unwrap();
+        let trace = String::from_utf8(tracer.into_inner()).unwrap();
+        assert!(trace.contains("LDA $D010 = C1"));
+        assert_eq!(cpu.mem.peek(0xD011) & 0x80, 0x80);
+        cpu.step().unwrap();
+        assert_eq!(cpu.ac(), 0xC1);
+        assert_eq!(cpu.mem.peek(0xD011) & 0x80, 0x00);
+    }
+
+    #[test]
+    fn test_rom_range() {
+        assert_eq!(rom_range(&[0; 0x100]), Ok(0xFF00..=0xFFFF));
+        assert_eq!(rom_range(&[0; 0x10]), Ok(0xFF00..=0xFF0F));
+        assert_eq!(rom_range(&[0; 0x2000]), Ok(0xE000..=0xFFFF));
+        assert_eq!(
+            rom_range(&[0; 0x3000]).unwrap_err().to_string(),
+            "A ROM of 12288 bytes doesn't fit, it can be at most 12268"
+        );
+    }
+}
@@ -0,0 +1,1027 @@
+pub use crate::disasm::Options;
+use crate::disasm::{opcode_table, Mode};
+use crate::error::{AsmError, AsmErrorKind};
+use crate::memory::Memory;
+use crate::symbols::SymbolTable;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+type Encodings = HashMap<&'static str, HashMap<Mode, u8>>;
+
+/// Invert the disassembler table into mnemonic -> addressing mode -> opcode, preferring
+/// documented encodings (e.g. `NOP` is `$EA`, not `$1A`).
+fn encodings(options: Options) -> Encodings {
+    let table = opcode_table(options);
+    let mut encodings: Encodings = HashMap::new();
+    for documented in [true, false] {
+        for (byte, entry) in table.iter().enumerate() {
+            if let Some(opcode) = entry.filter(|opcode| opcode.documented == documented) {
+                encodings
+                    .entry(opcode.mnemonic)
+                    .or_default()
+                    .entry(opcode.mode)
+                    .or_insert(byte as u8);
+            }
+        }
+    }
+    encodings
+}
+
+/// Result of a successful assembly.
+#[derive(Clone, Debug, Default)]
+pub struct Assembly {
+    /// Address of the first byte of `binary`
+    pub origin: u16,
+    /// Everything from the lowest to the highest address written, gaps are filled with `$00`
+    pub binary: Vec<u8>,
+    /// Labels and constants. Local labels are named `global@local`
+    pub symbols: BTreeMap<String, u16>,
+    pub listing: Vec<ListingLine>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListingLine {
+    /// 1-based source line
+    pub line: usize,
+    /// Program counter at the start of the line, `None` for lines that don't touch it
+    pub addr: Option<u16>,
+    pub bytes: Vec<u8>,
+    pub source: String,
+}
+
+impl Assembly {
+    /// Copy the binary into `mem` at its origin.
+    pub fn load_into<M: Memory>(&self, mem: &mut M) {
+        for (offset, byte) in self.binary.iter().enumerate() {
+            mem.write_byte(self.origin.wrapping_add(offset as u16), *byte);
+        }
+    }
+
+    pub fn symbol_table(&self) -> SymbolTable {
+        self.symbols
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value))
+            .collect()
+    }
+
+    /// One `name = $HHLL` line per symbol.
+    pub fn symbols_text(&self) -> String {
+        self.symbols
+            .iter()
+            .map(|(name, value)| format!("{name} = ${value:04X}\n"))
+            .collect()
+    }
+
+    pub fn listing_text(&self) -> String {
+        const BYTES_PER_LINE: usize = 4;
+        let mut out = String::new();
+        for line in &self.listing {
+            let addr = match line.addr {
+                Some(addr) => format!("{addr:04X}"),
+                None => "    ".to_string(),
+            };
+            let mut bytes: Vec<String> = line
+                .bytes
+                .iter()
+                .take(BYTES_PER_LINE)
+                .map(|b| format!("{b:02X}"))
+                .collect();
+            if line.bytes.len() > BYTES_PER_LINE {
+                bytes.push("..".to_string());
+            }
+            out.push_str(&format!(
+                "{:>5}  {addr}  {:<14} {}\n",
+                line.line,
+                bytes.join(" "),
+                line.source
+            ));
+        }
+        out
+    }
+}
+
+/// Assemble `source` in two passes: the first one lays out labels, the second one encodes.
+///
+/// ```rust
+/// use mini6502::asm::{self, Options};
+///
+/// let assembly = asm::assemble(
+///     "
+///         .org $0600
+///         ldx #$03
+/// loop:   dex
+///         bne loop
+///     ",
+///     Options::default(),
+/// )
+/// .unwrap();
+///
+/// assert_eq!(assembly.origin, 0x0600);
+/// assert_eq!(assembly.binary, [0xA2, 0x03, 0xCA, 0xD0, 0xFD]);
+/// assert_eq!(assembly.symbols["loop"], 0x0602);
+/// ```
+pub fn assemble(source: &str, options: Options) -> Result<Assembly, AsmError> {
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(idx, text)| {
+            parse_line(text).map_err(|kind| AsmError {
+                line: idx + 1,
+                kind,
+            })
+        })
+        .collect::<Result<Vec<Line>, AsmError>>()?;
+
+    let mut assembler = Assembler {
+        encodings: encodings(options),
+        symbols: HashMap::new(),
+        modes: vec![None; lines.len()],
+        image: vec![None; 0x10000],
+        listing: vec![],
+    };
+    assembler.pass(&lines, false)?;
+    assembler.pass(&lines, true)?;
+
+    let written: Vec<usize> = assembler
+        .image
+        .iter()
+        .enumerate()
+        .filter_map(|(addr, byte)| byte.map(|_| addr))
+        .collect();
+    let (origin, binary) = match (written.first(), written.last()) {
+        (Some(&lowest), Some(&highest)) => (
+            lowest as u16,
+            assembler.image[lowest..=highest]
+                .iter()
+                .map(|byte| byte.unwrap_or(0x00))
+                .collect(),
+        ),
+        _ => (0, vec![]),
+    };
+
+    Ok(Assembly {
+        origin,
+        binary,
+        symbols: assembler
+            .symbols
+            .into_iter()
+            .map(|(name, value)| (name, value as u16))
+            .collect(),
+        listing: assembler.listing,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Operand {
+    None,
+    Acc,
+    Imm(String),
+    Direct(String),
+    IndexedX(String),
+    IndexedY(String),
+    Ind(String),
+    IndX(String),
+    IndY(String),
+    Pair(String, String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Statement {
+    Empty,
+    Org(String),
+    Constant(String, String),
+    Instruction(String, Operand),
+    Byte(Vec<String>),
+    Word(Vec<String>),
+    Res(String, Option<String>),
+}
+
+#[derive(Clone, Debug)]
+struct Line {
+    source: String,
+    label: Option<String>,
+    statement: Statement,
+}
+
+/// Split `text` on commas that aren't inside parentheses or quotes.
+fn split_args(text: &str) -> Vec<&str> {
+    let mut args = vec![];
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (idx, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                args.push(text[start..idx].trim());
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(text[start..].trim());
+    args
+}
+
+/// Strip a `;` comment, ignoring semicolons inside quotes.
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (idx, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, ';') => return &text[..idx],
+            _ => {}
+        }
+    }
+    text
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '@'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Split a leading identifier off `text`.
+fn take_ident(text: &str) -> Option<(&str, &str)> {
+    let mut chars = text.char_indices();
+    match chars.next() {
+        Some((_, c)) if is_ident_start(c) => {}
+        _ => return None,
+    }
+    let end = chars
+        .find(|(_, c)| !is_ident_char(*c))
+        .map(|(idx, _)| idx)
+        .unwrap_or(text.len());
+    Some((&text[..end], &text[end..]))
+}
+
+fn is_register(text: &str, register: &str) -> bool {
+    text.trim().eq_ignore_ascii_case(register)
+}
+
+fn parse_operand(text: &str) -> Result<Operand, AsmErrorKind> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(Operand::None);
+    }
+    if is_register(text, "a") {
+        return Ok(Operand::Acc);
+    }
+    if let Some(imm) = text.strip_prefix('#') {
+        return Ok(Operand::Imm(imm.trim().to_string()));
+    }
+
+    if text.starts_with('(') {
+        let mut depth = 0;
+        let close = text
+            .char_indices()
+            .find(|(_, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })
+            .map(|(idx, _)| idx)
+            .ok_or_else(|| AsmErrorKind::Syntax("unbalanced parentheses".to_string()))?;
+        let inner = &text[1..close];
+        let after = text[close + 1..].trim();
+
+        if after.is_empty() {
+            let parts = split_args(inner);
+            return match parts[..] {
+                [expr] => Ok(Operand::Ind(expr.to_string())),
+                [expr, x] if is_register(x, "x") => Ok(Operand::IndX(expr.to_string())),
+                _ => Err(AsmErrorKind::Syntax(format!(
+                    "bad indirect operand \"{text}\""
+                ))),
+            };
+        }
+        if let Some(y) = after.strip_prefix(',') {
+            if is_register(y, "y") {
+                return Ok(Operand::IndY(inner.trim().to_string()));
+            }
+        }
+        // Otherwise the parentheses just group part of an expression
+    }
+
+    match split_args(text)[..] {
+        [expr] => Ok(Operand::Direct(expr.to_string())),
+        [expr, x] if is_register(x, "x") => Ok(Operand::IndexedX(expr.to_string())),
+        [expr, y] if is_register(y, "y") => Ok(Operand::IndexedY(expr.to_string())),
+        [first, second] => Ok(Operand::Pair(first.to_string(), second.to_string())),
+        _ => Err(AsmErrorKind::Syntax(format!("bad operand \"{text}\""))),
+    }
+}
+
+fn parse_line(source: &str) -> Result<Line, AsmErrorKind> {
+    let mut text = strip_comment(source).trim();
+    let mut label = None;
+
+    if let Some((name, rest)) = take_ident(text) {
+        if let Some(rest) = rest.strip_prefix(':') {
+            label = Some(name.to_string());
+            text = rest.trim();
+        }
+    }
+
+    let statement = if text.is_empty() {
+        Statement::Empty
+    } else if let Some(expr) = text
+        .strip_prefix('*')
+        .and_then(|rest| rest.trim_start().strip_prefix('='))
+    {
+        Statement::Org(expr.trim().to_string())
+    } else if let Some((name, expr)) = take_ident(text).and_then(|(name, rest)| {
+        let expr = rest.trim_start().strip_prefix('=')?;
+        Some((name, expr))
+    }) {
+        if label.is_some() {
+            return Err(AsmErrorKind::Syntax(
+                "a constant can't be labeled".to_string(),
+            ));
+        }
+        Statement::Constant(name.to_string(), expr.trim().to_string())
+    } else {
+        let (word, rest) = text.split_at(text.find(char::is_whitespace).unwrap_or(text.len()));
+        let rest = rest.trim();
+        let args = || {
+            split_args(rest)
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<String>>()
+        };
+        match word.to_ascii_lowercase().as_str() {
+            ".org" => Statement::Org(rest.to_string()),
+            ".byte" | ".db" => Statement::Byte(args()),
+            ".word" | ".dw" => Statement::Word(args()),
+            ".res" | ".ds" => match args()[..] {
+                [ref count] => Statement::Res(count.clone(), None),
+                [ref count, ref fill] => Statement::Res(count.clone(), Some(fill.clone())),
+                _ => return Err(AsmErrorKind::Syntax(".res takes 1 or 2 arguments".into())),
+            },
+            directive if directive.starts_with('.') => {
+                return Err(AsmErrorKind::UnknownDirective(word.to_string()))
+            }
+            _ => Statement::Instruction(word.to_ascii_uppercase(), parse_operand(rest)?),
+        }
+    };
+
+    Ok(Line {
+        source: source.to_string(),
+        label,
+        statement,
+    })
+}
+
+/// Evaluates expressions. `None` means the expression refers to a symbol that isn't known yet,
+/// which is only allowed during the first pass.
+struct ExprParser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    symbols: &'a HashMap<String, i64>,
+    scope: &'a str,
+    pc: i64,
+    final_pass: bool,
+}
+
+type Value = Result<Option<i64>, AsmErrorKind>;
+
+impl<'a> ExprParser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.src.len() && self.src[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.src.get(self.pos).copied()
+    }
+
+    /// Consume `op` if it's next in the input.
+    fn eat(&mut self, op: &str) -> bool {
+        self.skip_whitespace();
+        if self.src[self.pos..].starts_with(op.as_bytes()) {
+            self.pos += op.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn syntax_error(&self, msg: &str) -> AsmErrorKind {
+        AsmErrorKind::Syntax(format!(
+            "{msg} in expression \"{}\"",
+            String::from_utf8_lossy(self.src)
+        ))
+    }
+
+    fn parse(mut self) -> Value {
+        let value = self.parse_or()?;
+        if self.peek().is_some() {
+            return Err(self.syntax_error("unexpected character"));
+        }
+        Ok(value)
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[&str],
+        next: fn(&mut Self) -> Value,
+        apply: fn(&str, i64, i64) -> Result<i64, AsmErrorKind>,
+    ) -> Value {
+        let mut lhs = next(self)?;
+        'outer: loop {
+            for op in ops {
+                if self.eat(op) {
+                    let rhs = next(self)?;
+                    lhs = match (lhs, rhs) {
+                        (Some(lhs), Some(rhs)) => Some(apply(op, lhs, rhs)?),
+                        _ => None,
+                    };
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn parse_or(&mut self) -> Value {
+        self.binary(&["|"], Self::parse_xor, |_, l, r| Ok(l | r))
+    }
+
+    fn parse_xor(&mut self) -> Value {
+        self.binary(&["^"], Self::parse_and, |_, l, r| Ok(l ^ r))
+    }
+
+    fn parse_and(&mut self) -> Value {
+        self.binary(&["&"], Self::parse_shift, |_, l, r| Ok(l & r))
+    }
+
+    fn parse_shift(&mut self) -> Value {
+        self.binary(&["<<", ">>"], Self::parse_sum, |op, l, r| {
+            let r = u32::try_from(r).map_err(|_| AsmErrorKind::ValueOutOfRange(r))?;
+            Ok(match op {
+                "<<" => l.checked_shl(r).unwrap_or(0),
+                _ => l.checked_shr(r).unwrap_or(0),
+            })
+        })
+    }
+
+    fn parse_sum(&mut self) -> Value {
+        self.binary(&["+", "-"], Self::parse_product, |op, l, r| {
+            Ok(match op {
+                "+" => l.wrapping_add(r),
+                _ => l.wrapping_sub(r),
+            })
+        })
+    }
+
+    fn parse_product(&mut self) -> Value {
+        self.binary(&["*", "/", "%"], Self::parse_unary, |op, l, r| match op {
+            "*" => Ok(l.wrapping_mul(r)),
+            _ if r == 0 => Err(AsmErrorKind::Syntax("division by zero".to_string())),
+            // Only i64::MIN / -1 overflows
+            "/" => l.checked_div(r).ok_or(AsmErrorKind::ValueOutOfRange(l)),
+            _ => l.checked_rem(r).ok_or(AsmErrorKind::ValueOutOfRange(l)),
+        })
+    }
+
+    fn parse_unary(&mut self) -> Value {
+        let apply = |value: Option<i64>, f: fn(i64) -> i64| value.map(f);
+        if self.eat("-") {
+            match self.parse_unary()? {
+                Some(v) => v
+                    .checked_neg()
+                    .map(Some)
+                    .ok_or(AsmErrorKind::ValueOutOfRange(v)),
+                None => Ok(None),
+            }
+        } else if self.eat("~") {
+            Ok(apply(self.parse_unary()?, |v| !v))
+        } else if self.eat("<") {
+            // Low byte
+            Ok(apply(self.parse_unary()?, |v| v & 0xFF))
+        } else if self.eat(">") {
+            // High byte
+            Ok(apply(self.parse_unary()?, |v| (v >> 8) & 0xFF))
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_number(&mut self, radix: u32) -> Value {
+        let start = self.pos;
+        while self.pos < self.src.len() && (self.src[self.pos] as char).is_digit(radix) {
+            self.pos += 1;
+        }
+        let digits = std::str::from_utf8(&self.src[start..self.pos]).unwrap();
+        i64::from_str_radix(digits, radix)
+            .map(Some)
+            .map_err(|_| self.syntax_error("bad number"))
+    }
+
+    fn parse_primary(&mut self) -> Value {
+        match self.peek() {
+            Some(b'(') => {
+                self.pos += 1;
+                let value = self.parse_or()?;
+                if !self.eat(")") {
+                    return Err(self.syntax_error("missing \")\""));
+                }
+                Ok(value)
+            }
+            Some(b'*') => {
+                self.pos += 1;
+                Ok(Some(self.pc))
+            }
+            Some(b'$') => {
+                self.pos += 1;
+                self.parse_number(16)
+            }
+            Some(b'%') => {
+                self.pos += 1;
+                self.parse_number(2)
+            }
+            Some(b'\'') => match self.src.get(self.pos + 1..self.pos + 3) {
+                Some([c, b'\'']) => {
+                    self.pos += 3;
+                    Ok(Some(*c as i64))
+                }
+                _ => Err(self.syntax_error("bad character literal")),
+            },
+            Some(c) if c.is_ascii_digit() => self.parse_number(10),
+            Some(c) if is_ident_start(c as char) => {
+                let rest = std::str::from_utf8(&self.src[self.pos..]).unwrap();
+                let (name, _) = take_ident(rest).unwrap();
+                self.pos += name.len();
+                let name = qualify(self.scope, name);
+                match self.symbols.get(&name) {
+                    Some(value) => Ok(Some(*value)),
+                    None if self.final_pass => Err(AsmErrorKind::UndefinedSymbol(name)),
+                    None => Ok(None),
+                }
+            }
+            _ => Err(self.syntax_error("expected a value")),
+        }
+    }
+}
+
+/// Local labels (`@name`) belong to the last global label.
+fn qualify(scope: &str, name: &str) -> String {
+    if name.starts_with('@') {
+        format!("{scope}{name}")
+    } else {
+        name.to_string()
+    }
+}
+
+fn check_range(value: i64, min: i64, max: i64) -> Result<i64, AsmErrorKind> {
+    if (min..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(AsmErrorKind::ValueOutOfRange(value))
+    }
+}
+
+/// Pick between the zero page and the absolute flavor of a mode, preferring zero page when the
+/// operand is already known to fit in it.
+fn zpg_or_abs(
+    available: &HashMap<Mode, u8>,
+    zpg: Mode,
+    abs: Mode,
+    value: Option<i64>,
+) -> Option<Mode> {
+    match (available.contains_key(&zpg), available.contains_key(&abs)) {
+        (true, true) if matches!(value, Some(0..=0xFF)) => Some(zpg),
+        (_, true) => Some(abs),
+        (true, false) => Some(zpg),
+        (false, false) => None,
+    }
+}
+
+struct Assembler {
+    encodings: Encodings,
+    symbols: HashMap<String, i64>,
+    /// Addressing mode chosen for each line during the first pass, so both passes agree on
+    /// instruction lengths
+    modes: Vec<Option<Mode>>,
+    image: Vec<Option<u8>>,
+    listing: Vec<ListingLine>,
+}
+
+impl Assembler {
+    fn pass(&mut self, lines: &[Line], final_pass: bool) -> Result<(), AsmError> {
+        let mut pc: i64 = 0;
+        let mut scope = String::new();
+        let mut defined = HashSet::new();
+
+        for (idx, line) in lines.iter().enumerate() {
+            let line_pc = pc;
+            let mut bytes = vec![];
+            let mut shows_pc = true;
+
+            let mut step = || -> Result<(), AsmErrorKind> {
+                if let Some(label) = &line.label {
+                    if !label.starts_with('@') {
+                        scope = label.clone();
+                    }
+                    let name = qualify(&scope, label);
+                    if !defined.insert(name.clone()) {
+                        return Err(AsmErrorKind::DuplicateSymbol(name));
+                    }
+                    self.symbols.insert(name, pc);
+                }
+
+                let eval = |expr: &str, pc: i64| {
+                    ExprParser {
+                        src: expr.as_bytes(),
+                        pos: 0,
+                        symbols: &self.symbols,
+                        scope: &scope,
+                        pc,
+                        final_pass,
+                    }
+                    .parse()
+                };
+                // Values that must be known during the first pass
+                let eval_now = |expr: &str, pc: i64| match eval(expr, pc)? {
+                    Some(value) => Ok(value),
+                    None => Err(AsmErrorKind::Syntax(format!(
+                        "\"{expr}\" can't refer to symbols defined later"
+                    ))),
+                };
+
+                match &line.statement {
+                    Statement::Empty => shows_pc = line.label.is_some(),
+                    Statement::Org(expr) => {
+                        pc = check_range(eval_now(expr, pc)?, 0, 0xFFFF)?;
+                        shows_pc = false;
+                    }
+                    Statement::Constant(name, expr) => {
+                        shows_pc = false;
+                        if !defined.insert(name.clone()) {
+                            return Err(AsmErrorKind::DuplicateSymbol(name.clone()));
+                        }
+                        if let Some(value) = eval(expr, pc)? {
+                            self.symbols.insert(name.clone(), value);
+                        }
+                    }
+                    Statement::Byte(items) => {
+                        for item in items {
+                            if let Some(text) = item.strip_prefix('"') {
+                                let text = text.strip_suffix('"').ok_or_else(|| {
+                                    AsmErrorKind::Syntax("unterminated string".to_string())
+                                })?;
+                                bytes.extend(text.bytes());
+                            } else {
+                                let value = eval(item, pc)?.unwrap_or(0);
+                                bytes.push(check_range(value, -0x80, 0xFF)? as u8);
+                            }
+                        }
+                    }
+                    Statement::Word(items) => {
+                        for item in items {
+                            let value = eval(item, pc)?.unwrap_or(0);
+                            let word = check_range(value, -0x8000, 0xFFFF)? as u16;
+                            bytes.extend(word.to_le_bytes());
+                        }
+                    }
+                    Statement::Res(count, fill) => {
+                        let count = check_range(eval_now(count, pc)?, 0, 0x10000)?;
+                        let fill = match fill {
+                            Some(fill) => eval(fill, pc)?.unwrap_or(0),
+                            None => 0,
+                        };
+                        let fill = check_range(fill, -0x80, 0xFF)? as u8;
+                        bytes.resize(count as usize, fill);
+                    }
+                    Statement::Instruction(mnemonic, operand) => {
+                        let available = self
+                            .encodings
+                            .get(mnemonic.as_str())
+                            .ok_or_else(|| AsmErrorKind::UnknownMnemonic(mnemonic.clone()))?;
+                        let bad_mode = || AsmErrorKind::BadAddressMode(mnemonic.clone());
+
+                        let mode = match self.modes[idx] {
+                            Some(mode) if final_pass => mode,
+                            _ => {
+                                let has = |mode| available.contains_key(&mode);
+                                let mode = match operand {
+                                    Operand::None if has(Mode::IMPL) => Some(Mode::IMPL),
+                                    Operand::None | Operand::Acc => Some(Mode::ACC),
+                                    Operand::Imm(_) => Some(Mode::IMM),
+                                    Operand::Direct(_) if has(Mode::REL) => Some(Mode::REL),
+                                    Operand::Direct(expr) => {
+                                        zpg_or_abs(available, Mode::ZPG, Mode::ABS, eval(expr, pc)?)
+                                    }
+                                    Operand::IndexedX(expr) => zpg_or_abs(
+                                        available,
+                                        Mode::ZPGX,
+                                        Mode::ABSX,
+                                        eval(expr, pc)?,
+                                    ),
+                                    Operand::IndexedY(expr) => zpg_or_abs(
+                                        available,
+                                        Mode::ZPGY,
+                                        Mode::ABSY,
+                                        eval(expr, pc)?,
+                                    ),
+                                    Operand::Ind(_) if has(Mode::IND) => Some(Mode::IND),
+                                    Operand::Ind(_) => Some(Mode::ZPGIND),
+                                    Operand::IndX(_) if has(Mode::INDX) => Some(Mode::INDX),
+                                    Operand::IndX(_) => Some(Mode::ABSINDX),
+                                    Operand::IndY(_) => Some(Mode::INDY),
+                                    Operand::Pair(_, _) => Some(Mode::ZPGREL),
+                                };
+                                let mode = mode.filter(|mode| has(*mode)).ok_or_else(bad_mode)?;
+                                self.modes[idx] = Some(mode);
+                                mode
+                            }
+                        };
+
+                        bytes.push(available[&mode]);
+                        let len = mode.instr_len() as i64;
+                        match operand {
+                            Operand::None | Operand::Acc => {}
+                            Operand::Imm(expr) => {
+                                let value = eval(expr, pc)?.unwrap_or(0);
+                                bytes.push(check_range(value, -0x80, 0xFF)? as u8);
+                            }
+                            Operand::Pair(zpg, target) => {
+                                let zpg = eval(zpg, pc)?.unwrap_or(0);
+                                bytes.push(check_range(zpg, 0, 0xFF)? as u8);
+                                let target = eval(target, pc)?.unwrap_or(pc + len);
+                                let offset = target - (pc + len);
+                                if !(-0x80..=0x7F).contains(&offset) {
+                                    return Err(AsmErrorKind::BranchOutOfRange(offset));
+                                }
+                                bytes.push(offset as u8);
+                            }
+                            Operand::Direct(expr)
+                            | Operand::IndexedX(expr)
+                            | Operand::IndexedY(expr)
+                            | Operand::Ind(expr)
+                            | Operand::IndX(expr)
+                            | Operand::IndY(expr) => {
+                                let value = eval(expr, pc)?;
+                                if mode == Mode::REL {
+                                    let target = value.unwrap_or(pc + len);
+                                    let offset = target - (pc + len);
+                                    if !(-0x80..=0x7F).contains(&offset) {
+                                        return Err(AsmErrorKind::BranchOutOfRange(offset));
+                                    }
+                                    bytes.push(offset as u8);
+                                } else if len == 2 {
+                                    let value = check_range(value.unwrap_or(0), 0, 0xFF)?;
+                                    bytes.push(value as u8);
+                                } else {
+                                    let value = check_range(value.unwrap_or(0), 0, 0xFFFF)?;
+                                    bytes.extend((value as u16).to_le_bytes());
+                                }
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            };
+
+            step().map_err(|kind| AsmError {
+                line: idx + 1,
+                kind,
+            })?;
+
+            if final_pass {
+                for (offset, byte) in bytes.iter().enumerate() {
+                    self.image[line_pc as usize + offset] = Some(*byte);
+                }
+                self.listing.push(ListingLine {
+                    line: idx + 1,
+                    addr: (shows_pc || !bytes.is_empty()).then_some(line_pc as u16),
+                    bytes: bytes.clone(),
+                    source: line.source.clone(),
+                });
+            }
+
+            pc += bytes.len() as i64;
+            if pc > 0x10000 {
+                return Err(AsmError {
+                    line: idx + 1,
+                    kind: AsmErrorKind::PcOverflow,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disasm::Disassembler;
+
+    fn assemble_ok(source: &str) -> Assembly {
+        assemble(source, Options::default()).unwrap()
+    }
+
+    fn error_kind(source: &str, options: Options) -> AsmErrorKind {
+        assemble(source, options).unwrap_err().kind
+    }
+
+    #[test]
+    fn test_addressing_modes() {
+        let assembly = assemble_ok(
+            "
+            asl
+            asl a
+            lda #10
+            lda $10
+            lda $10,x
+            ldx $10,Y
+            lda $1234
+            lda $0010
+            lda $1234,X
+            lda $1234,y
+            jmp ($FFFC)
+            lda ($20,x)
+            lda ($20),y
+            ",
+        );
+        assert_eq!(
+            assembly.binary,
+            [
+                0x0A, 0x0A, 0xA9, 0x0A, 0xA5, 0x10, 0xB5, 0x10, 0xB6, 0x10, 0xAD, 0x34, 0x12, 0xA5,
+                0x10, 0xBD, 0x34, 0x12, 0xB9, 0x34, 0x12, 0x6C, 0xFC, 0xFF, 0xA1, 0x20, 0xB1, 0x20
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels_and_expressions() {
+        let assembly = assemble_ok(
+            "
+            ptr = $20
+            size = 2 * 3 + 1     ; comment
+                .org $C000
+            start:
+                lda #<table
+                sta ptr
+                lda #>table
+                sta ptr+1
+                jmp forward
+            @loop:
+                dex
+                bne @loop
+            forward:
+                bne @loop
+            @loop:
+                beq * + 2
+            table:
+                .byte size, 'A', \"hi\", -1
+                .word start, $1234
+                .res 2, $EA
+            ",
+        );
+        assert_eq!(assembly.origin, 0xC000);
+        assert_eq!(assembly.symbols["start"], 0xC000);
+        assert_eq!(assembly.symbols["start@loop"], 0xC00B);
+        assert_eq!(assembly.symbols["forward@loop"], 0xC010);
+        assert_eq!(assembly.symbols["table"], 0xC012);
+        assert_eq!(assembly.symbols["size"], 7);
+        assert_eq!(
+            assembly.binary,
+            [
+                0xA9, 0x12, 0x85, 0x20, 0xA9, 0xC0, 0x85, 0x21, 0x4C, 0x0E, 0xC0, 0xCA, 0xD0, 0xFD,
+                0xD0, 0x00, 0xF0, 0x00, 0x07, 0x41, 0x68, 0x69, 0xFF, 0x00, 0xC0, 0x34, 0x12, 0xEA,
+                0xEA
+            ]
+        );
+    }
+
+    #[test]
+    fn test_forward_references_use_absolute() {
+        // `later` isn't known in the first pass, so it gets an absolute operand even though it
+        // ends up in the zero page.
+        let assembly = assemble_ok(
+            "
+            lda later
+            later = $10
+            lda later
+            ",
+        );
+        assert_eq!(assembly.binary, [0xAD, 0x10, 0x00, 0xA5, 0x10]);
+    }
+
+    #[test]
+    fn test_cmos_and_illegal() {
+        let cmos = Options {
+            cmos: true,
+            illegal: false,
+        };
+        let source = "
+            .org $0200
+            lda ($10)
+            jmp ($1234,x)
+            stz $20
+            bra end
+            bbr3 $10,end
+            end: rmb7 $11
+            ";
+        let assembly = assemble(source, cmos).unwrap();
+        assert_eq!(
+            assembly.binary,
+            [0xB2, 0x10, 0x7C, 0x34, 0x12, 0x64, 0x20, 0x80, 0x03, 0x3F, 0x10, 0x00, 0x77, 0x11]
+        );
+
+        // Round trip through the disassembler
+        let text: Vec<String> = Disassembler::new(cmos)
+            .disassemble(&assembly.binary, assembly.origin)
+            .iter()
+            .map(|inst| inst.to_string())
+            .collect();
+        assert_eq!(text[4], "BBR3 $10,$020C");
+
+        let illegal = Options {
+            cmos: false,
+            illegal: true,
+        };
+        let assembly = assemble("lax ($10),y\nnop\nnop #1\nsbc #2", illegal).unwrap();
+        assert_eq!(assembly.binary, [0xB3, 0x10, 0xEA, 0x80, 0x01, 0xE9, 0x02]);
+
+        assert_eq!(
+            error_kind("stz $10", Options::default()),
+            AsmErrorKind::UnknownMnemonic("STZ".to_string())
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let options = Options::default();
+        assert_eq!(
+            error_kind("lda undefined", options),
+            AsmErrorKind::UndefinedSymbol("undefined".to_string())
+        );
+        assert_eq!(
+            error_kind("a: nop\na: nop", options),
+            AsmErrorKind::DuplicateSymbol("a".to_string())
+        );
+        assert_eq!(
+            error_kind("jmp $10,y", options),
+            AsmErrorKind::BadAddressMode("JMP".to_string())
+        );
+        assert_eq!(
+            error_kind("lda #$100", options),
+            AsmErrorKind::ValueOutOfRange(0x100)
+        );
+        assert_eq!(
+            error_kind("x: .res 200\nbne x", options),
+            AsmErrorKind::BranchOutOfRange(-202)
+        );
+        assert_eq!(
+            error_kind(".org $FFFF\nnop\nnop", options),
+            AsmErrorKind::PcOverflow
+        );
+        assert_eq!(assemble("\n\nfoo bar", options).unwrap_err().line, 3);
+
+        // Arithmetic that overflows an i64 is an error rather than a panic
+        let min = "(-9223372036854775807 - 1)";
+        for expr in [
+            format!("-{min}"),
+            format!("{min} / -1"),
+            format!("{min} % -1"),
+        ] {
+            assert_eq!(
+                error_kind(&format!("lda #{expr}"), options),
+                AsmErrorKind::ValueOutOfRange(i64::MIN)
+            );
+        }
+        assert_eq!(
+            error_kind("lda #1 / (2 - 2)", options),
+            AsmErrorKind::Syntax("division by zero".to_string())
+        );
+    }
+
+    #[test]
+    fn test_listing() {
+        let assembly = assemble_ok(".org $10\nstart: lda #1 ; one\nval = 3\n");
+        let listing = assembly.listing_text();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("    1   "));
+        assert!(lines[0].ends_with(" .org $10"));
+        assert!(lines[1].starts_with("    2  0010  A9 01 "));
+        assert!(lines[1].ends_with(" start: lda #1 ; one"));
+        assert!(lines[2].ends_with(" val = 3"));
+        assert_eq!(assembly.symbols_text(), "start = $0010\nval = $0003\n");
+    }
+}
@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Why the cpu put an address on the bus.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BusKind {
+    /// The opcode of an instruction
+    Fetch,
+    /// The bytes following the opcode
+    Operand,
+    /// Anything an instruction reads or writes through its address, pointers and vectors too
+    Data,
+    /// Pushes and pulls
+    Stack,
+    /// Accesses whose value is thrown away, like read-modify-write instructions writing the
+    /// unmodified byte back before the result
+    Dummy,
+}
+
+/// A single read or write on the cpu bus.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BusAccess {
+    pub addr: u16,
+    /// The byte read or written
+    pub value: u8,
+    /// Cycle the instruction started at, plus the accesses it made before this one
+    pub cycle: usize,
+    pub kind: BusKind,
+}
+
+/// Gets notified of every memory access the cpu makes while executing, see
+/// [`Cpu::add_observer`](crate::Cpu::add_observer). Accesses made through `Cpu::mem` directly
+/// aren't seen.
+pub trait BusObserver {
+    /// An opcode fetch. Counts as a read unless overridden.
+    fn fetch(&mut self, access: &BusAccess) {
+        self.read(access);
+    }
+
+    fn read(&mut self, _access: &BusAccess) {}
+
+    fn write(&mut self, _access: &BusAccess) {}
+}
+
+/// Keep every access, reads and writes alike.
+impl BusObserver for Vec<BusAccess> {
+    fn read(&mut self, access: &BusAccess) {
+        self.push(*access);
+    }
+
+    fn write(&mut self, access: &BusAccess) {
+        self.push(*access);
+    }
+}
+
+/// Lets the caller keep a handle to an observer owned by the cpu.
+impl<T: BusObserver> BusObserver for Rc<RefCell<T>> {
+    fn fetch(&mut self, access: &BusAccess) {
+        self.borrow_mut().fetch(access);
+    }
+
+    fn read(&mut self, access: &BusAccess) {
+        self.borrow_mut().read(access);
+    }
+
+    fn write(&mut self, access: &BusAccess) {
+        self.borrow_mut().write(access);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::memory::SimpleMemory;
+    use mini6502_macros::asm6502;
+
+    fn accesses(bytes: &[u8], steps: usize) -> Vec<BusAccess> {
+        let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(bytes));
+        let accesses = Rc::new(RefCell::new(vec![]));
+        cpu.add_observer(Box::new(accesses.clone()));
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+        accesses.take()
+    }
+
+    #[test]
+    fn test_kinds() {
+        let program = asm6502! {
+            jsr sub;
+            brk;
+            sub: inc $10;
+            rts
+        };
+        let sub = program.symbol("sub");
+        let kinds: Vec<(u16, u8, BusKind)> = accesses(&program.bytes, 3)
+            .iter()
+            .map(|access| (access.addr, access.value, access.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (0x0000, 0x20, BusKind::Fetch),
+                (0x01FF, 0x00, BusKind::Stack),
+                (0x01FE, 0x02, BusKind::Stack),
+                (0x0001, sub as u8, BusKind::Operand),
+                (0x0002, 0x00, BusKind::Operand),
+                (sub, 0xE6, BusKind::Fetch),
+                (sub + 1, 0x10, BusKind::Operand),
+                (0x0010, 0x00, BusKind::Data),
+                (0x0010, 0x00, BusKind::Dummy),
+                (0x0010, 0x01, BusKind::Data),
+                (sub + 2, 0x60, BusKind::Fetch),
+                (0x01FE, 0x02, BusKind::Stack),
+                (0x01FF, 0x00, BusKind::Stack),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cycles() {
+        // lda $10; nop
+        let cycles: Vec<usize> = accesses(&[0xA5, 0x10, 0xEA], 2)
+            .iter()
+            .map(|access| access.cycle)
+            .collect();
+        // The nop starts once the lda's 3 cycles are over
+        assert_eq!(cycles, [0, 1, 2, 3]);
+
+        #[derive(Default)]
+        struct Fetches(usize);
+        impl BusObserver for Fetches {
+            fn fetch(&mut self, _access: &BusAccess) {
+                self.0 += 1;
+            }
+        }
+        let fetches = Rc::new(RefCell::new(Fetches::default()));
+        let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&[0xEA, 0xEA]));
+        cpu.add_observer(Box::new(fetches.clone()));
+        cpu.step().unwrap();
+        cpu.clear_observers();
+        cpu.step().unwrap();
+        assert_eq!(fetches.borrow().0, 1);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use crate::opc::Inst;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    /// Entered through a `JSR`, left through an `RTS`
+    Call,
+    /// Entered by taking an IRQ or NMI, left through an `RTI`
+    Interrupt,
+}
+
+/// A subroutine call or interrupt handler that hasn't returned yet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// First instruction of the subroutine or handler
+    pub entry: u16,
+    /// Address of the `JSR`, or of the instruction the interrupt came before
+    pub call_site: u16,
+    /// Stack pointer before the return address was pushed
+    pub sp: u16,
+}
+
+impl Frame {
+    pub const fn return_addr(&self) -> u16 {
+        match self.kind {
+            FrameKind::Call => self.call_site.wrapping_add(3),
+            FrameKind::Interrupt => self.call_site,
+        }
+    }
+}
+
+/// Shadow call stack rebuilt from `JSR`s, interrupts and the stack pointer, as the 6502 doesn't keep frame
+/// pointers around.
+///
+/// ```rust
+/// use mini6502::callstack::CallStack;
+/// use mini6502::{Cpu, SimpleMemory};
+///
+/// // jsr $0004; brk; rts
+/// let mem = SimpleMemory::from_rom(&[0x20, 0x04, 0x00, 0x00, 0x60]);
+/// let mut cpu = Cpu::with_mem(mem);
+/// let mut calls = CallStack::new();
+///
+/// let (pc, sp) = (cpu.pc(), cpu.sp());
+/// cpu.step().unwrap();
+/// calls.update(&cpu, pc, sp);
+/// assert_eq!(calls.frames()[0].entry, 0x0004);
+///
+/// let (pc, sp) = (cpu.pc(), cpu.sp());
+/// cpu.step().unwrap();
+/// calls.update(&cpu, pc, sp);
+/// assert!(calls.frames().is_empty());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack::default()
+    }
+
+    /// Outermost call first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Account for the instruction the cpu just executed, which was at `pc` with the stack
+    /// pointer at `sp`.
+    pub fn update<M: Memory>(&mut self, cpu: &Cpu<M>, pc: u16, sp: u16) {
+        let kind = match cpu.ir() {
+            Some(Inst::JSR) => Some(FrameKind::Call),
+            // The cpu took an interrupt instead of executing an instruction
+            None => Some(FrameKind::Interrupt),
+            Some(_) => None,
+        };
+        if let Some(kind) = kind {
+            self.frames.push(Frame {
+                kind,
+                entry: cpu.pc(),
+                call_site: pc,
+                sp,
+            });
+            return;
+        }
+        // Any frame whose return address is no longer on the stack has returned, whether it was
+        // through an RTS, an RTI or by discarding the stack
+        while let Some(frame) = self.frames.last() {
+            if frame.sp <= cpu.sp() {
+                self.frames.pop();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SimpleMemory;
+
+    /// Memory with an IRQ line the test drives.
+    struct IrqLine {
+        mem: SimpleMemory,
+        irq: bool,
+    }
+
+    impl Memory for IrqLine {
+        fn write_byte(&mut self, addr: u16, byte: u8) {
+            self.mem.write_byte(addr, byte)
+        }
+
+        fn read_byte(&self, addr: u16) -> u8 {
+            self.mem.read_byte(addr)
+        }
+
+        fn irq(&self) -> bool {
+            self.irq
+        }
+    }
+
+    fn step(cpu: &mut Cpu<IrqLine>, calls: &mut CallStack) {
+        let (pc, sp) = (cpu.pc(), cpu.sp());
+        cpu.step().unwrap();
+        calls.update(cpu, pc, sp);
+    }
+
+    #[test]
+    fn test_interrupt_after_jsr() {
+        // cli; jsr $0005; rts; with the IRQ handler at $8000: rti
+        let mut mem = SimpleMemory::from_rom(&[0x58, 0x20, 0x05, 0x00, 0x00, 0x60]);
+        mem.load(0xFFFE, &[0x00, 0x80]);
+        mem.load(0x8000, &[0x40]);
+        let mut cpu = Cpu::with_mem(IrqLine { mem, irq: false });
+        let mut calls = CallStack::new();
+
+        step(&mut cpu, &mut calls);
+        step(&mut cpu, &mut calls);
+        let sp = cpu.sp();
+        cpu.mem.irq = true;
+        step(&mut cpu, &mut calls);
+        cpu.mem.irq = false;
+        assert_eq!(cpu.ir(), None);
+        assert_eq!(
+            calls.frames(),
+            [
+                Frame {
+                    kind: FrameKind::Call,
+                    entry: 0x0005,
+                    call_site: 0x0001,
+                    sp: sp + 2,
+                },
+                Frame {
+                    kind: FrameKind::Interrupt,
+                    entry: 0x8000,
+                    call_site: 0x0005,
+                    sp,
+                },
+            ]
+        );
+        assert_eq!(calls.frames()[1].return_addr(), 0x0005);
+
+        // rti, then rts
+        step(&mut cpu, &mut calls);
+        assert_eq!(cpu.pc(), 0x0005);
+        assert_eq!(calls.depth(), 1);
+        step(&mut cpu, &mut calls);
+        assert_eq!(cpu.pc(), 0x0004);
+        assert!(calls.frames().is_empty());
+    }
+}
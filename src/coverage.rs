@@ -0,0 +1,323 @@
+use crate::cpu::Cpu;
+use crate::dbginfo::DebugInfo;
+use crate::disasm::{self, Disassembler, Instruction, Mode};
+use crate::memory::Memory;
+use crate::symbols::SymbolTable;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// How often a conditional branch went each way.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Whether the branch `opcode` is taken with flags `p`, `None` for any other opcode. Branch
+/// opcodes are `xxy10000`, where `xx` selects N, V, C or Z and `y` is the value that takes it.
+fn branch_taken(opcode: u8, p: u8) -> Option<bool> {
+    if opcode & 0x1F != 0x10 {
+        return None;
+    }
+    let flag = [0x80, 0x40, 0x01, 0x02][(opcode >> 6) as usize];
+    Some((p & flag != 0) == (opcode & 0x20 != 0))
+}
+
+/// Executed instructions and branch directions, for finding code a test suite never reaches.
+///
+/// ```rust
+/// use mini6502::coverage::Coverage;
+/// use mini6502::hooks::until;
+/// use mini6502::{Cpu, SimpleMemory};
+///
+/// // ldx #$02; dex; bne $0002; brk
+/// let mem = SimpleMemory::from_rom(&[0xA2, 0x02, 0xCA, 0xD0, 0xFD, 0x00]);
+/// let mut cpu = Cpu::with_mem(mem);
+/// let mut coverage = Coverage::new();
+/// cpu.run(&mut until(|cpu| {
+///     coverage.record(cpu);
+///     cpu.pc() == 0x0005
+/// }))
+/// .unwrap();
+///
+/// assert_eq!(coverage.hits(0x0002), 2);
+/// let branch = coverage.branch(0x0003).unwrap();
+/// assert_eq!((branch.taken, branch.not_taken), (1, 1));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Coverage {
+    /// Times the instruction at each address was executed
+    hits: Vec<u64>,
+    branches: BTreeMap<u16, Branch>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage {
+            hits: vec![0; 0x10000],
+            branches: BTreeMap::new(),
+        }
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    /// Account for the instruction at PC, which is about to be executed. Call it before every
+    /// instruction, e.g. from the [`Cpu::run`] callback.
+    pub fn record<M: Memory>(&mut self, cpu: &Cpu<M>) {
+        let pc = cpu.pc();
+        self.hits[pc as usize] += 1;
+        if let Some(taken) = branch_taken(cpu.mem.peek(pc), cpu.p()) {
+            let branch = self.branches.entry(pc).or_default();
+            if taken {
+                branch.taken += 1;
+            } else {
+                branch.not_taken += 1;
+            }
+        }
+    }
+
+    pub fn hits(&self, addr: u16) -> u64 {
+        self.hits[addr as usize]
+    }
+
+    /// The directions the branch at `addr` took, if it was ever executed.
+    pub fn branch(&self, addr: u16) -> Option<Branch> {
+        self.branches.get(&addr).copied()
+    }
+
+    /// Number of distinct addresses an instruction was executed from.
+    pub fn executed(&self) -> usize {
+        self.hits.iter().filter(|&&hits| hits > 0).count()
+    }
+
+    /// Disassemble `start..=end` with the times each instruction was executed in front, `#####`
+    /// for the ones that never were, and the directions each branch took.
+    pub fn listing<M: Memory>(
+        &self,
+        mem: &M,
+        start: u16,
+        end: u16,
+        symbols: Option<&SymbolTable>,
+    ) -> String {
+        let mut out = String::new();
+        let (mut executed, mut directions, mut taken_directions) = (0, 0, 0);
+        let instructions = self.instructions(mem, start, end);
+        for inst in &instructions {
+            if let Some(label) = symbols.and_then(|s| s.name_of(inst.addr)) {
+                let _ = writeln!(out, "{:>9}  {label}:", "");
+            }
+            let hits = self.hits(inst.addr);
+            let count = match hits {
+                0 => "#####".to_string(),
+                _ => hits.to_string(),
+            };
+            let bytes: Vec<String> = inst.bytes.iter().map(|b| format!("{b:02X}")).collect();
+            let _ = write!(
+                out,
+                "{count:>9}  {:04X}  {:<8}  {}",
+                inst.addr,
+                bytes.join(" "),
+                inst.text(symbols)
+            );
+            if is_branch(inst) {
+                let branch = self.branch(inst.addr).unwrap_or_default();
+                let _ = write!(
+                    out,
+                    "  ; taken {}, not taken {}",
+                    branch.taken, branch.not_taken
+                );
+                directions += 2;
+                taken_directions += (branch.taken > 0) as usize + (branch.not_taken > 0) as usize;
+            }
+            out.push('\n');
+            executed += (hits > 0) as usize;
+        }
+        let _ = writeln!(
+            out,
+            "; {executed} of {} instructions executed, {taken_directions} of {directions} branch \
+             directions taken",
+            instructions.len()
+        );
+        out
+    }
+
+    /// Write an lcov tracefile with line and branch coverage for the source files in `info`.
+    /// A line counts as executed as often as its most executed instruction.
+    pub fn write_lcov<M: Memory>(
+        &self,
+        out: &mut impl Write,
+        mem: &M,
+        info: &DebugInfo,
+    ) -> io::Result<()> {
+        // Hits and branch addresses of each line
+        let mut files: BTreeMap<&str, BTreeMap<usize, (u64, Vec<u16>)>> = BTreeMap::new();
+        for (location, ranges) in info.lines() {
+            let line = files
+                .entry(location.file)
+                .or_default()
+                .entry(location.line)
+                .or_default();
+            for range in ranges {
+                let end = (range.end - 1) as u16;
+                for inst in self.instructions(mem, range.start as u16, end) {
+                    line.0 = line.0.max(self.hits(inst.addr));
+                    if is_branch(&inst) && !line.1.contains(&inst.addr) {
+                        line.1.push(inst.addr);
+                    }
+                }
+            }
+        }
+
+        for (file, lines) in files {
+            writeln!(out, "TN:")?;
+            writeln!(out, "SF:{file}")?;
+            for (line, (hits, _)) in &lines {
+                writeln!(out, "DA:{line},{hits}")?;
+            }
+            let hit_lines = lines.values().filter(|(hits, _)| *hits > 0).count();
+            writeln!(out, "LF:{}", lines.len())?;
+            writeln!(out, "LH:{hit_lines}")?;
+
+            let (mut found, mut hit) = (0, 0);
+            for (line, (hits, branches)) in &lines {
+                for (block, addr) in branches.iter().enumerate() {
+                    let branch = self.branch(*addr).unwrap_or_default();
+                    for (index, count) in [branch.taken, branch.not_taken].iter().enumerate() {
+                        // lcov tells branches on lines that never ran from ones never taken
+                        if *hits == 0 {
+                            writeln!(out, "BRDA:{line},{block},{index},-")?;
+                        } else {
+                            writeln!(out, "BRDA:{line},{block},{index},{count}")?;
+                        }
+                        found += 1;
+                        hit += (*count > 0) as usize;
+                    }
+                }
+            }
+            writeln!(out, "BRF:{found}")?;
+            writeln!(out, "BRH:{hit}")?;
+            writeln!(out, "end_of_record")?;
+        }
+        Ok(())
+    }
+
+    /// Disassemble `start..=end`, starting over at any address that was executed so data
+    /// doesn't throw off the instructions that follow it.
+    fn instructions<M: Memory>(&self, mem: &M, start: u16, end: u16) -> Vec<Instruction> {
+        let disassembler = Disassembler::new(disasm::Options::default());
+        let read = |addr: u16| Some(mem.peek(addr));
+        let mut instructions = vec![];
+        let mut addr = start as usize;
+        while addr <= end as usize {
+            let mut inst = disassembler.decode(addr as u16, read).unwrap();
+            let overlaps =
+                (1..inst.len()).any(|offset| self.hits(inst.addr.wrapping_add(offset)) > 0);
+            if overlaps {
+                inst = Instruction {
+                    addr: inst.addr,
+                    bytes: vec![inst.bytes[0]],
+                    opcode: None,
+                };
+            }
+            addr += inst.bytes.len();
+            instructions.push(inst);
+        }
+        instructions
+    }
+}
+
+fn is_branch(inst: &Instruction) -> bool {
+    inst.opcode.is_some_and(|opcode| opcode.mode == Mode::REL)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm;
+    use crate::hooks::until;
+    use crate::memory::SimpleMemory;
+
+    const SOURCE: &str = "\
+start:  ldy #$03
+loop:   dey
+        bne loop
+        cpy #$01
+        beq never
+done:   jmp done
+never:  iny
+";
+
+    fn covered() -> (Coverage, Cpu<SimpleMemory>, asm::Assembly) {
+        let assembly = asm::assemble(SOURCE, asm::Options::default()).unwrap();
+        let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&assembly.binary));
+        let done = assembly.symbols["done"];
+        let mut coverage = Coverage::new();
+        cpu.run(&mut until(|cpu| {
+            coverage.record(cpu);
+            cpu.pc() == done
+        }))
+        .unwrap();
+        (coverage, cpu, assembly)
+    }
+
+    #[test]
+    fn test_listing() {
+        let (coverage, cpu, assembly) = covered();
+        assert_eq!(coverage.executed(), 6);
+        assert_eq!(branch_taken(0xD0, 0x00), Some(true));
+        assert_eq!(branch_taken(0x30, 0x00), Some(false));
+        assert_eq!(branch_taken(0xEA, 0x00), None);
+
+        let info = DebugInfo::from_assembly("loop.s", &assembly);
+        let end = assembly.binary.len() as u16 - 1;
+        let listing = coverage.listing(&cpu.mem, 0, end, Some(info.symbols()));
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            lines[4],
+            "        3  0003  D0 FD     BNE loop  ; taken 2, not taken 1"
+        );
+        assert_eq!(lines[10], "    #####  000C  C8        INY");
+        assert_eq!(
+            lines[11],
+            "; 6 of 7 instructions executed, 3 of 4 branch directions taken"
+        );
+    }
+
+    #[test]
+    fn test_lcov() {
+        let (coverage, cpu, assembly) = covered();
+        let info = DebugInfo::from_assembly("loop.s", &assembly);
+        let mut out = vec![];
+        coverage.write_lcov(&mut out, &cpu.mem, &info).unwrap();
+        let lcov = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = lcov.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "TN:",
+                "SF:loop.s",
+                "DA:1,1",
+                "DA:2,3",
+                "DA:3,3",
+                "DA:4,1",
+                "DA:5,1",
+                "DA:6,1",
+                "DA:7,0",
+                "LF:7",
+                "LH:6",
+                "BRDA:3,0,0,2",
+                "BRDA:3,0,1,1",
+                "BRDA:5,0,0,0",
+                "BRDA:5,0,1,1",
+                "BRF:4",
+                "BRH:3",
+                "end_of_record",
+            ]
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use crate::bcd;
+use crate::bus::{BusAccess, BusKind, BusObserver};
 use crate::error::Error6502;
+use crate::hooks::{Control, Hooks};
 use crate::memory::Memory;
 use crate::opc::{self, AddressMode, Inst, OpMode};
 use crate::util;
@@ -16,6 +18,10 @@ const FLAGS_ALWAYS_ON: u8 = RESERVED_FLAG_BITMASK | B_FLAG_BITMASK;
 const FLAGS_DEFAULT: u8 = 0b00100000;
 const STACK_ADDR_DEFAULT: u8 = 0xFF;
 const STACK_DEFAULT_PAGE: u8 = 0x01;
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+const INTERRUPT_CYCLES: u8 = 7;
 
 pub struct Cpu<M> {
     // Program counter
@@ -31,6 +37,14 @@ pub struct Cpu<M> {
     pub mem: M,
     ir: Option<Inst>,
     cycle_count: usize,
+    opc_arr: [Option<OpMode>; 0xFF],
+    observers: Vec<Box<dyn BusObserver>>,
+    /// Bus accesses made by the instruction being executed so far
+    bus_cycle: usize,
+    /// Level of the NMI line after the last step
+    nmi_line: bool,
+    /// Whether NMI went active and the cpu hasn't taken it yet
+    nmi_pending: bool,
 }
 
 impl<M> Cpu<M>
@@ -57,14 +71,20 @@ where
             ir: None,
             mem,
             cycle_count: 0,
+            opc_arr: opc::init_opc_array(),
+            observers: vec![],
+            bus_cycle: 0,
+            nmi_line: false,
+            nmi_pending: false,
         }
     }
 
-    /// Run program loaded in cpu, with `callback_exit` called before each instruction is executed.
-    /// The cpu will execute the fetch - decode - execute cycle until it encounters an instruction
-    /// that can't decode and then will return an `Err`.
-    /// Alternatively, it can stop execution before that by returning `true` from `callback_exit`.
+    /// Run program loaded in cpu, calling `hooks` around each instruction. The cpu will execute
+    /// the fetch - decode - execute cycle until a hook returns [`Control::Stop`], or it
+    /// encounters an instruction that can't decode and [`Hooks::on_error`] doesn't handle it,
+    /// returning an `Err`. Pass `&mut ()` for no hooks.
     ///```no_run
+    /// use mini6502::hooks::until;
     /// use mini6502::{Cpu, SimpleMemory};
     ///
     /// let buf = [0xa2, 0x10, 0xCA, 0xD0, 0xFD];
@@ -73,41 +93,288 @@ where
     ///
     /// let mut cpu = Cpu::with_mem(mem);
     ///
-    /// cpu.run(&mut |cpu: &Cpu<SimpleMemory>|{
+    /// cpu.run(&mut until(|cpu: &Cpu<SimpleMemory>| {
     ///     println!("ir: {:?} x:{} y:{} status reg:{}",cpu.ir() , cpu.x(), cpu.y(), cpu.p());
     ///     cpu.pc() as usize >= buf.len()
-    /// });
+    /// }));
     ///
     /// assert_eq!(cpu.x(), 0x10);
     ///```
-    pub fn run(&mut self, callback_exit: &mut dyn FnMut(&Cpu<M>) -> bool) -> Result<(), Error6502> {
-        let opc_arr = opc::init_opc_array();
+    pub fn run(&mut self, hooks: &mut impl Hooks<M>) -> Result<(), Error6502> {
         loop {
-            // Loop until we encounter an unknown opcode
-            if let Ok(OpMode(instruction, address_mode, cycles)) = self.fetch_next_inst(&opc_arr) {
-                self.set_ir(instruction);
-                if callback_exit(&self) {
-                    break;
+            if let Some(vector) = self.pending_interrupt() {
+                self.interrupt(vector);
+                match hooks.on_interrupt(self, vector) {
+                    Control::Stop => return Ok(()),
+                    Control::Reset => self.reset(),
+                    Control::Continue | Control::Skip => {}
                 }
-                self.step_inst(instruction, address_mode)?;
-                self.add_to_cycle_count(cycles)
-            } else {
-                return Err(Error6502::UnknownOpcode(self.read_byte_from_mem(self.pc)));
+                continue;
+            }
+            let control = match self.fetch_next_inst() {
+                Ok(OpMode(instruction, _, _)) => {
+                    self.set_ir(instruction);
+                    match hooks.before_instruction(self) {
+                        Control::Continue => match self.step() {
+                            Ok(cycles) => {
+                                let control = match instruction {
+                                    Inst::BRK => hooks.on_interrupt(self, IRQ_VECTOR),
+                                    _ => Control::Continue,
+                                };
+                                match control {
+                                    Control::Continue | Control::Skip => {
+                                        hooks.after_instruction(self, cycles)
+                                    }
+                                    control => control,
+                                }
+                            }
+                            Err(err) => self.handle_error(hooks, err)?,
+                        },
+                        Control::Skip => {
+                            // The hook may have moved PC
+                            let len = self
+                                .fetch_next_inst()
+                                .map_or(1, |OpMode(_, address_mode, _)| {
+                                    get_instr_len(&address_mode)
+                                });
+                            self.pc = self.pc.wrapping_add(len);
+                            Control::Continue
+                        }
+                        control => control,
+                    }
+                }
+                Err(err) => self.handle_error(hooks, err)?,
+            };
+            match control {
+                Control::Stop => return Ok(()),
+                Control::Reset => self.reset(),
+                Control::Continue | Control::Skip => {}
             }
         }
-        Ok(())
+    }
+
+    /// What `run` does after failing with `err`, or `err` if it should return it.
+    fn handle_error(
+        &mut self,
+        hooks: &mut impl Hooks<M>,
+        err: Error6502,
+    ) -> Result<Control, Error6502> {
+        match hooks.on_error(self, &err) {
+            Control::Stop => Err(err),
+            Control::Skip => {
+                self.pc = self.pc.wrapping_add(1);
+                Ok(Control::Continue)
+            }
+            control => Ok(control),
+        }
+    }
+
+    /// Reset the cpu like its RES line does: PC is loaded from the reset vector, SP goes down by
+    /// 3 without writing to the stack and interrupts are disabled.
+    pub fn reset(&mut self) {
+        let ll = self.mem.read_byte(RESET_VECTOR);
+        let hh = self.mem.read_byte(RESET_VECTOR + 1);
+        self.pc = util::combine_u8_to_u16(hh, ll);
+        self.sp = self.sp.wrapping_sub(3);
+        self.write_i_flag(true);
+    }
+
+    /// Fetch, decode and execute the instruction at PC. Returns the number of cycles it took.
+    ///```
+    /// use mini6502::{Cpu, SimpleMemory};
+    ///
+    /// let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&[0xa2, 0x10, 0xCA]));
+    ///
+    /// cpu.step().unwrap();
+    /// assert_eq!(cpu.x(), 0x10);
+    /// cpu.step().unwrap();
+    /// assert_eq!(cpu.x(), 0x0F);
+    /// assert_eq!(cpu.pc(), 0x0003);
+    ///```
+    pub fn step(&mut self) -> Result<u8, Error6502> {
+        if let Some(vector) = self.pending_interrupt() {
+            return Ok(self.interrupt(vector));
+        }
+        self.bus_cycle = 0;
+        let opcode = self.bus_read(self.pc, BusKind::Fetch);
+        let OpMode(instruction, address_mode, cycles) = self.decode(opcode)?;
+        self.set_ir(instruction);
+        self.step_inst(instruction, address_mode)?;
+        self.add_to_cycle_count(cycles);
+        self.tick(cycles);
+        Ok(cycles)
+    }
+
+    /// The vector of the interrupt the next `step` takes instead of executing an instruction:
+    /// NMI after the memory's NMI line went active, or IRQ while its IRQ line is asserted and
+    /// interrupts are enabled.
+    ///
+    /// ```rust
+    /// use mini6502::memory::Memory;
+    /// use mini6502::{Cpu, SimpleMemory};
+    ///
+    /// /// Memory with the IRQ line always asserted
+    /// struct Irq(SimpleMemory);
+    ///
+    /// impl Memory for Irq {
+    ///     fn write_byte(&mut self, addr: u16, byte: u8) {
+    ///         self.0.write_byte(addr, byte)
+    ///     }
+    ///
+    ///     fn read_byte(&self, addr: u16) -> u8 {
+    ///         self.0.read_byte(addr)
+    ///     }
+    ///
+    ///     fn irq(&self) -> bool {
+    ///         true
+    ///     }
+    /// }
+    ///
+    /// // cli; nop
+    /// let mut mem = SimpleMemory::from_rom(&[0x58, 0xEA]);
+    /// mem.load(0xFFFE, &[0x00, 0x80]);
+    /// let mut cpu = Cpu::with_mem(Irq(mem));
+    /// cpu.write_i_flag(true);
+    /// assert_eq!(cpu.pending_interrupt(), None);
+    /// cpu.step().unwrap();
+    /// assert_eq!(cpu.pending_interrupt(), Some(0xFFFE));
+    /// assert_eq!(cpu.step().unwrap(), 7);
+    /// assert_eq!((cpu.pc(), cpu.i_flag()), (0x8000, true));
+    /// ```
+    pub fn pending_interrupt(&self) -> Option<u16> {
+        if self.nmi_pending {
+            Some(NMI_VECTOR)
+        } else if self.mem.irq() && !self.i_flag() {
+            Some(IRQ_VECTOR)
+        } else {
+            None
+        }
+    }
+
+    /// Push PC and P like BRK does, but with B clear, and jump through `vector`. Clears IR, as no
+    /// instruction was executed.
+    fn interrupt(&mut self, vector: u16) -> u8 {
+        self.bus_cycle = 0;
+        self.ir = None;
+        if vector == NMI_VECTOR {
+            self.nmi_pending = false;
+        }
+        let [pc_hh, pc_ll] = self.pc.to_be_bytes();
+        self.stack_push(pc_hh);
+        self.stack_push(pc_ll);
+        self.stack_push((self.p | RESERVED_FLAG_BITMASK) & !B_FLAG_BITMASK);
+        self.write_i_flag(true);
+        let new_pc_ll = self.read_data(vector);
+        let new_pc_hh = self.read_data(vector + 1);
+        self.pc = u16::from_be_bytes([new_pc_hh, new_pc_ll]);
+        self.add_to_cycle_count(INTERRUPT_CYCLES);
+        self.tick(INTERRUPT_CYCLES);
+        INTERRUPT_CYCLES
+    }
+
+    /// Let the memory's devices know `cycles` went by, and latch an NMI edge.
+    fn tick(&mut self, cycles: u8) {
+        self.mem.tick(cycles);
+        let nmi = self.mem.nmi();
+        self.nmi_pending |= nmi && !self.nmi_line;
+        self.nmi_line = nmi;
+    }
+
+    /// Replace the memory with `f(mem)`, keeping the registers, e.g. to wrap it.
+    pub fn map_mem<N: Memory>(self, f: impl FnOnce(M) -> N) -> Cpu<N> {
+        Cpu {
+            pc: self.pc,
+            ac: self.ac,
+            x: self.x,
+            y: self.y,
+            p: self.p,
+            sp: self.sp,
+            mem: f(self.mem),
+            ir: self.ir,
+            cycle_count: self.cycle_count,
+            opc_arr: self.opc_arr,
+            observers: self.observers,
+            bus_cycle: self.bus_cycle,
+            nmi_line: self.nmi_line,
+            nmi_pending: self.nmi_pending,
+        }
+    }
+
+    /// Notify `observer` of every memory access from now on.
+    ///
+    /// ```rust
+    /// use mini6502::bus::{BusAccess, BusKind};
+    /// use mini6502::{Cpu, SimpleMemory};
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    ///
+    /// // lda $10
+    /// let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&[0xA5, 0x10]));
+    /// let accesses = Rc::new(RefCell::new(Vec::<BusAccess>::new()));
+    /// cpu.add_observer(Box::new(accesses.clone()));
+    /// cpu.step().unwrap();
+    ///
+    /// let kinds: Vec<(u16, BusKind)> = accesses.borrow().iter().map(|a| (a.addr, a.kind)).collect();
+    /// assert_eq!(
+    ///     kinds,
+    ///     [(0x0000, BusKind::Fetch), (0x0001, BusKind::Operand), (0x0010, BusKind::Data)]
+    /// );
+    /// ```
+    pub fn add_observer(&mut self, observer: Box<dyn BusObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn clear_observers(&mut self) {
+        self.observers.clear();
+    }
+
+    /// Every read by an instruction goes through here.
+    fn bus_read(&mut self, addr: u16, kind: BusKind) -> u8 {
+        let value = self.mem.read_byte(addr);
+        if !self.observers.is_empty() {
+            let access = self.bus_access(addr, value, kind);
+            for observer in &mut self.observers {
+                match kind {
+                    BusKind::Fetch => observer.fetch(&access),
+                    _ => observer.read(&access),
+                }
+            }
+        }
+        self.bus_cycle += 1;
+        value
+    }
+
+    /// Every write by an instruction goes through here.
+    fn bus_write(&mut self, addr: u16, value: u8, kind: BusKind) {
+        self.mem.write_byte(addr, value);
+        if !self.observers.is_empty() {
+            let access = self.bus_access(addr, value, kind);
+            for observer in &mut self.observers {
+                observer.write(&access);
+            }
+        }
+        self.bus_cycle += 1;
+    }
+
+    fn bus_access(&self, addr: u16, value: u8, kind: BusKind) -> BusAccess {
+        BusAccess {
+            addr,
+            value,
+            cycle: self.cycle_count + self.bus_cycle,
+            kind,
+        }
     }
 
     pub(crate) fn stack_push(&mut self, bb: u8) {
         let stack_addr = u16::from_be_bytes([STACK_DEFAULT_PAGE, self.sp]);
-        self.mem.write_byte(stack_addr, bb);
+        self.bus_write(stack_addr, bb, BusKind::Stack);
         self.sp = self.sp.wrapping_sub(1);
     }
 
     pub(crate) fn stack_pop(&mut self) -> u8 {
         self.sp = self.sp.wrapping_add(1);
         let stack_addr = u16::from_be_bytes([STACK_DEFAULT_PAGE, self.sp]);
-        self.mem.read_byte(stack_addr)
+        self.bus_read(stack_addr, BusKind::Stack)
     }
 
     /// Checks if value is Zero and updates Z flag accordingly
@@ -117,7 +384,6 @@ where
     /// * `val` - value to be checked
     pub(crate) fn update_z_flag_with(&mut self, val: u8) {
         if val == 0x00 {
-            dbg!(val);
             self.p |= Z_FLAG_BITMASK;
         } else {
             self.p &= !Z_FLAG_BITMASK;
@@ -203,18 +469,27 @@ where
         self.pc as usize & 0xFFFF
     }
 
-    pub(crate) fn set_pc(&mut self, val: u16) {
+    pub fn set_pc(&mut self, val: u16) {
         self.pc = val;
     }
 
-    pub(crate) fn set_x(&mut self, val: u8) {
+    pub fn set_x(&mut self, val: u8) {
         self.x = val;
     }
 
-    pub(crate) fn set_y(&mut self, val: u8) {
+    pub fn set_y(&mut self, val: u8) {
         self.y = val;
     }
 
+    /// Set the low byte of the stack pointer, the stack always lives in page 1.
+    pub fn set_sp(&mut self, val: u8) {
+        self.sp = val;
+    }
+
+    pub fn set_p(&mut self, val: u8) {
+        self.p = val;
+    }
+
     pub(crate) fn add_to_pc(&mut self, val: u16) {
         self.pc += val;
     }
@@ -279,30 +554,44 @@ where
         (self.p & C_FLAG_BITMASK) != 0
     }
 
-    pub(crate) fn fetch_next_inst(
-        &self,
-        opc_arr: &[Option<OpMode>; 0xFF],
-    ) -> Result<OpMode, Error6502> {
+    pub(crate) fn fetch_next_inst(&self) -> Result<OpMode, Error6502> {
         // NOTE: we could define opc_arr as a global const, but then we miss initialization checks
-        // of opcode repetition, as we currently can't make init_opc_array() const.
+        // of opcode repetition, as we currently can't make init_opc_array() const. It is built
+        // once per cpu instead.
 
         // Read byte at pc
         // dbg!(self.pc);
-        let byte = self.mem.read_byte(self.pc);
-        match opc_arr[byte as usize] {
+        let byte = self.mem.peek(self.pc);
+        self.decode(byte)
+    }
+
+    fn decode(&self, opcode: u8) -> Result<OpMode, Error6502> {
+        match self.opc_arr.get(opcode as usize).copied().flatten() {
             Some(op_mode) => Ok(op_mode),
-            None => Err(Error6502::UnknownOpcode(byte)),
+            None => Err(Error6502::UnknownOpcode(opcode)),
         }
     }
 
+    /// The instruction at PC, or the error executing it would fail with.
+    pub fn next_inst(&self) -> Result<Inst, Error6502> {
+        self.fetch_next_inst().map(|OpMode(inst, _, _)| inst)
+    }
+
     fn set_ir(&mut self, inst: Inst) {
         self.ir = Some(inst);
     }
 
+    /// The instruction the cpu executed last, or `None` before the first one and right after
+    /// taking an interrupt.
     pub fn ir(&self) -> Option<Inst> {
         self.ir
     }
 
+    /// Total number of cycles executed so far.
+    pub fn cycle_count(&self) -> usize {
+        self.cycle_count
+    }
+
     pub fn step_inst(&mut self, inst: Inst, address_mode: AddressMode) -> Result<(), Error6502> {
         // Should "panic" if the program is not well formed
         let mut add_to_pc = true;
@@ -316,7 +605,7 @@ where
                         }
                         _ => {
                             let effective_addr = self.get_effective_address(&address_mode);
-                            self.mem.read_byte(effective_addr)
+                            self.read_data(effective_addr)
                         }
                     }
                 };
@@ -349,7 +638,7 @@ where
                         }
                         _ => {
                             let effective_addr = self.get_effective_address(&address_mode);
-                            self.mem.read_byte(effective_addr)
+                            self.read_data(effective_addr)
                         }
                     }
                 };
@@ -368,7 +657,7 @@ where
                         }
                         _ => {
                             let effective_addr = self.get_effective_address(&address_mode);
-                            (true, self.mem.read_byte(effective_addr), effective_addr)
+                            (true, self.read_data(effective_addr), effective_addr)
                         }
                     }
                 };
@@ -377,7 +666,7 @@ where
                 let result = operand << 1;
 
                 if is_memory {
-                    self.mem.write_byte(address, result);
+                    self.write_modified(address, operand, result);
                 } else {
                     self.ac = result;
                 }
@@ -413,7 +702,7 @@ where
             Inst::BIT => {
                 let operand = {
                     let addr = self.get_effective_address(&address_mode);
-                    self.mem.read_byte(addr)
+                    self.read_data(addr)
                 };
                 let m7 = 0b1000_0000 & operand != 0;
                 let m6 = 0b0100_0000 & operand != 0;
@@ -452,8 +741,8 @@ where
                 self.stack_push(pc_ll);
                 self.stack_push(self.p | FLAGS_ALWAYS_ON);
                 self.write_i_flag(true);
-                let new_pc_ll = self.mem.read_byte(0xFFFE);
-                let new_pc_hh = self.mem.read_byte(0xFFFF);
+                let new_pc_ll = self.read_data(IRQ_VECTOR);
+                let new_pc_hh = self.read_data(IRQ_VECTOR + 1);
                 let new_pc = u16::from_be_bytes([new_pc_hh, new_pc_ll]);
                 self.pc = new_pc;
                 add_to_pc = false;
@@ -486,10 +775,10 @@ where
             }
             Inst::CMP => {
                 let data = match address_mode {
-                    AddressMode::IMM => self.mem.read_byte(self.pc + 1),
+                    AddressMode::IMM => self.read_immediate_byte(),
                     _ => {
                         let addr = self.get_effective_address(&address_mode);
-                        self.mem.read_byte(addr)
+                        self.read_data(addr)
                     }
                 };
 
@@ -515,10 +804,10 @@ where
                 // Compare to register X
                 let data = {
                     match address_mode {
-                        AddressMode::IMM => self.mem.read_byte(self.pc + 1),
+                        AddressMode::IMM => self.read_immediate_byte(),
                         _ => {
                             let addr = self.get_effective_address(&address_mode);
-                            self.mem.read_byte(addr)
+                            self.read_data(addr)
                         }
                     }
                 };
@@ -545,10 +834,10 @@ where
                 // Compare to register Y
                 let data = {
                     match address_mode {
-                        AddressMode::IMM => self.mem.read_byte(self.pc + 1),
+                        AddressMode::IMM => self.read_immediate_byte(),
                         _ => {
                             let addr = self.get_effective_address(&address_mode);
-                            self.mem.read_byte(addr)
+                            self.read_data(addr)
                         }
                     }
                 };
@@ -574,10 +863,10 @@ where
             Inst::DEC => {
                 let (addr, operand) = {
                     let addr = self.get_effective_address(&address_mode);
-                    (addr, self.mem.read_byte(addr))
+                    (addr, self.read_data(addr))
                 };
                 let result = operand.wrapping_sub(1);
-                self.mem.write_byte(addr, result);
+                self.write_modified(addr, operand, result);
                 self.update_n_flag_with(result);
                 self.update_z_flag_with(result);
             }
@@ -597,10 +886,10 @@ where
             }
             Inst::EOR => {
                 let operand = match address_mode {
-                    AddressMode::IMM => self.mem.read_byte(self.pc + 1),
+                    AddressMode::IMM => self.read_immediate_byte(),
                     _ => {
                         let addr = self.get_effective_address(&address_mode);
-                        self.mem.read_byte(addr)
+                        self.read_data(addr)
                     }
                 };
 
@@ -613,10 +902,10 @@ where
             Inst::INC => {
                 let (addr, operand) = {
                     let addr = self.get_effective_address(&address_mode);
-                    (addr, self.mem.read_byte(addr))
+                    (addr, self.read_data(addr))
                 };
                 let result = operand.wrapping_add(1);
-                self.mem.write_byte(addr, result);
+                self.write_modified(addr, operand, result);
                 self.update_n_flag_with(result);
                 self.update_z_flag_with(result);
             }
@@ -648,10 +937,10 @@ where
             }
             Inst::LDA => {
                 let data = match address_mode {
-                    AddressMode::IMM => self.mem.read_byte(self.pc + 1),
+                    AddressMode::IMM => self.read_immediate_byte(),
                     _ => {
                         let addr = self.get_effective_address(&address_mode);
-                        self.mem.read_byte(addr)
+                        self.read_data(addr)
                     }
                 };
                 self.set_ac(data);
@@ -660,10 +949,10 @@ where
             }
             Inst::LDX => {
                 let data = match address_mode {
-                    AddressMode::IMM => self.mem.read_byte(self.pc + 1),
+                    AddressMode::IMM => self.read_immediate_byte(),
                     _ => {
                         let addr = self.get_effective_address(&address_mode);
-                        self.mem.read_byte(addr)
+                        self.read_data(addr)
                     }
                 };
                 self.set_x(data);
@@ -672,10 +961,10 @@ where
             }
             Inst::LDY => {
                 let data = match address_mode {
-                    AddressMode::IMM => self.mem.read_byte(self.pc + 1),
+                    AddressMode::IMM => self.read_immediate_byte(),
                     _ => {
                         let addr = self.get_effective_address(&address_mode);
-                        self.mem.read_byte(addr)
+                        self.read_data(addr)
                     }
                 };
                 self.set_y(data);
@@ -687,7 +976,7 @@ where
                     AddressMode::ACC => (true, 0x0000, self.ac),
                     _ => {
                         let addr = self.get_effective_address(&address_mode);
-                        let operand = self.mem.read_byte(addr);
+                        let operand = self.read_data(addr);
                         (false, addr, operand)
                     }
                 };
@@ -704,17 +993,17 @@ where
                 if is_acc {
                     self.ac = result;
                 } else {
-                    self.write_to_mem(address, result);
+                    self.write_modified(address, operand, result);
                 }
             }
             Inst::NOP => {}
             Inst::ORA => {
                 let data = {
                     match address_mode {
-                        AddressMode::IMM => self.mem.read_byte(self.pc + 1),
+                        AddressMode::IMM => self.read_immediate_byte(),
                         _ => {
                             let addr = self.get_effective_address(&address_mode);
-                            self.mem.read_byte(addr)
+                            self.read_data(addr)
                         }
                     }
                 };
@@ -751,7 +1040,7 @@ where
                         }
                         _ => {
                             let effective_addr = self.get_effective_address(&address_mode);
-                            (true, self.mem.read_byte(effective_addr), effective_addr)
+                            (true, self.read_data(effective_addr), effective_addr)
                         }
                     }
                 };
@@ -764,7 +1053,7 @@ where
                 }
 
                 if is_memory {
-                    self.mem.write_byte(address, result);
+                    self.write_modified(address, operand, result);
                 } else {
                     self.ac = result;
                 }
@@ -782,7 +1071,7 @@ where
                         }
                         _ => {
                             let effective_addr = self.get_effective_address(&address_mode);
-                            (true, self.mem.read_byte(effective_addr), effective_addr)
+                            (true, self.read_data(effective_addr), effective_addr)
                         }
                     }
                 };
@@ -795,7 +1084,7 @@ where
                 }
 
                 if is_memory {
-                    self.mem.write_byte(address, result);
+                    self.write_modified(address, operand, result);
                 } else {
                     self.ac = result;
                 }
@@ -823,10 +1112,10 @@ where
             Inst::SBC => {
                 let data = {
                     match address_mode {
-                        AddressMode::IMM => self.mem.read_byte(self.pc + 1),
+                        AddressMode::IMM => self.read_immediate_byte(),
                         _ => {
                             let addr = self.get_effective_address(&address_mode);
-                            self.mem.read_byte(addr)
+                            self.read_data(addr)
                         }
                     }
                 };
@@ -939,19 +1228,35 @@ where
         Ok(())
     }
 
-    pub(crate) fn set_ac(&mut self, val: u8) {
+    pub fn set_ac(&mut self, val: u8) {
         self.ac = val;
     }
 
     pub(crate) fn write_to_mem(&mut self, addr: u16, byte: u8) {
-        self.mem.write_byte(addr, byte);
+        self.bus_write(addr, byte, BusKind::Data);
     }
+    /// Read memory without it counting as a bus access, nor having side effects on devices.
     pub(crate) fn read_byte_from_mem(&self, addr: u16) -> u8 {
-        self.mem.read_byte(addr)
+        self.mem.peek(addr)
+    }
+
+    fn read_data(&mut self, addr: u16) -> u8 {
+        self.bus_read(addr, BusKind::Data)
+    }
+
+    fn read_operand(&mut self, addr: u16) -> u8 {
+        self.bus_read(addr, BusKind::Operand)
+    }
+
+    pub(crate) fn read_immediate_byte(&mut self) -> u8 {
+        self.read_operand(self.pc + 1)
     }
 
-    pub(crate) fn read_immediate_byte(&self) -> u8 {
-        self.mem.read_byte(self.pc + 1)
+    /// Write `new` over `old` at `addr` the way read-modify-write instructions do, writing
+    /// `old` back first.
+    fn write_modified(&mut self, addr: u16, old: u8, new: u8) {
+        self.bus_write(addr, old, BusKind::Dummy);
+        self.bus_write(addr, new, BusKind::Data);
     }
 
     pub(crate) fn add_to_cycle_count(&mut self, cycles: u8) {
@@ -959,8 +1264,8 @@ where
     }
 
     /// Get relative address for jump instruction, min -128 and max 127
-    pub(crate) fn get_relative_address(&self, offset_address: u16) -> u16 {
-        let offset = self.mem.read_byte(offset_address);
+    pub(crate) fn get_relative_address(&mut self, offset_address: u16) -> u16 {
+        let offset = self.read_operand(offset_address);
         let offset_16 = {
             if util::test_negative(offset) {
                 // Number is negative, extend with 0xFF
@@ -976,7 +1281,7 @@ where
         offset_16.wrapping_add(offset_address).wrapping_add(1)
     }
 
-    pub(crate) fn get_effective_address(&self, address_mode: &AddressMode) -> u16 {
+    pub(crate) fn get_effective_address(&mut self, address_mode: &AddressMode) -> u16 {
         match address_mode {
             // As accumulator, immediate and implied addressing modes are 1 byte length operators,
             // implementors of opcodes must check for these modes before calling this function.
@@ -988,33 +1293,33 @@ where
             AddressMode::REL => unreachable!(),
             AddressMode::ZPG => {
                 // Zero Page address 0LL
-                let addr = self.mem.read_byte(self.pc + 1);
+                let addr = self.read_immediate_byte();
                 util::u8_to_u16(addr)
             }
             AddressMode::ZPGX => {
                 // Read zero page address 0LL + X without carry
-                let addr = self.mem.read_byte(self.pc + 1);
+                let addr = self.read_immediate_byte();
                 let effective_addr = u8::wrapping_add(addr, self.x);
                 util::u8_to_u16(effective_addr)
             }
             AddressMode::ZPGY => {
                 // Read zero page address 0LL + Y without carry
-                let addr = self.mem.read_byte(self.pc + 1);
+                let addr = self.read_immediate_byte();
                 let effective_addr = u8::wrapping_add(addr, self.y);
                 util::u8_to_u16(effective_addr)
             }
             AddressMode::ABS => {
                 let ll_addr = u16::wrapping_add(self.pc, 1);
                 let hh_addr = u16::wrapping_add(self.pc, 2);
-                let ll = self.mem.read_byte(ll_addr);
-                let hh = self.mem.read_byte(hh_addr);
+                let ll = self.read_operand(ll_addr);
+                let hh = self.read_operand(hh_addr);
                 util::combine_u8_to_u16(hh, ll)
             }
             AddressMode::ABSX => {
                 let ll_addr = u16::wrapping_add(self.pc, 1);
                 let hh_addr = u16::wrapping_add(self.pc, 2);
-                let ll = self.mem.read_byte(ll_addr);
-                let hh = self.mem.read_byte(hh_addr);
+                let ll = self.read_operand(ll_addr);
+                let hh = self.read_operand(hh_addr);
                 let base = util::combine_u8_to_u16(hh, ll);
                 let index = util::u8_to_u16(self.x);
                 u16::wrapping_add(base, index)
@@ -1024,8 +1329,8 @@ where
                 let ll_addr = u16::wrapping_add(self.pc, 1);
                 // PC + 2
                 let hh_addr = u16::wrapping_add(self.pc, 2);
-                let ll = self.read_byte_from_mem(ll_addr);
-                let hh = self.read_byte_from_mem(hh_addr);
+                let ll = self.read_operand(ll_addr);
+                let hh = self.read_operand(hh_addr);
                 let base = util::combine_u8_to_u16(hh, ll);
                 let index = util::u8_to_u16(self.y);
                 u16::wrapping_add(base, index)
@@ -1036,21 +1341,21 @@ where
                 // If first byte of address is in $xxFF then second byte is in  $xx00
                 let ll_addr = util::wrapping_add_same_page(self.pc, 1);
                 let hh_addr = util::wrapping_add_same_page(self.pc, 2);
-                let ll = self.mem.read_byte(ll_addr);
-                let hh = self.mem.read_byte(hh_addr);
+                let ll = self.read_operand(ll_addr);
+                let hh = self.read_operand(hh_addr);
 
                 let a1 = util::combine_u8_to_u16(hh, ll);
-                let ll = self.read_byte_from_mem(a1);
+                let ll = self.read_data(a1);
                 let hh = {
                     let addr = util::wrapping_add_same_page(a1, 1);
-                    self.read_byte_from_mem(addr)
+                    self.read_data(addr)
                 };
 
                 util::combine_u8_to_u16(hh, ll)
             }
             AddressMode::INDX => {
                 let bb_addr = u16::wrapping_add(self.pc, 1);
-                let bb = self.mem.read_byte(bb_addr);
+                let bb = self.read_operand(bb_addr);
                 // 00BB + X no carry, no page boundary crossing
                 let ind_addr_ll = u8::wrapping_add(bb, self.x);
                 let ind_addr_ll_zpg = util::u8_to_u16(ind_addr_ll);
@@ -1058,14 +1363,14 @@ where
                 let ind_addr_hh = u8::wrapping_add(ind_addr_ll, 1);
                 let ind_addr_hh_zpg = util::u8_to_u16(ind_addr_hh);
 
-                let ll = self.mem.read_byte(ind_addr_ll_zpg);
-                let hh = self.mem.read_byte(ind_addr_hh_zpg);
+                let ll = self.read_data(ind_addr_ll_zpg);
+                let hh = self.read_data(ind_addr_hh_zpg);
 
                 util::combine_u8_to_u16(hh, ll)
             }
             AddressMode::INDY => {
                 let zpg_addr_addr = u16::wrapping_add(self.pc, 1);
-                let zpg_addr = self.read_byte_from_mem(zpg_addr_addr);
+                let zpg_addr = self.read_operand(zpg_addr_addr);
 
                 // $0x00LL
                 let ind_ll_addr = util::u8_to_u16(zpg_addr);
@@ -1073,8 +1378,8 @@ where
                 let ind_hh_addr = u8::wrapping_add(zpg_addr, 1);
                 let ind_hh_addr = util::u8_to_u16(ind_hh_addr);
 
-                let ind_ll = self.read_byte_from_mem(ind_ll_addr);
-                let ind_hh = self.read_byte_from_mem(ind_hh_addr);
+                let ind_ll = self.read_data(ind_ll_addr);
+                let ind_hh = self.read_data(ind_hh_addr);
                 let ind = util::combine_u8_to_u16(ind_hh, ind_ll);
                 let y = util::u8_to_u16(self.y);
 
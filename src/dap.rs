@@ -0,0 +1,1032 @@
+use crate::asm;
+use crate::callstack::CallStack;
+use crate::cpu::Cpu;
+use crate::dbginfo::DebugInfo;
+use crate::disasm::{self, Disassembler, Instruction};
+use crate::error::Error6502;
+use crate::expr::Expr;
+use crate::ines::{InesRom, NromMemory};
+use crate::json::Json;
+use crate::memory::{Memory, SimpleMemory};
+use crate::opc::Inst;
+use crate::symbols::SymbolTable;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+const THREAD_ID: i64 = 1;
+const REGISTERS_REF: i64 = 1;
+const FLAGS_REF: i64 = 2;
+/// Instructions executed between checks for new requests while running
+const SLICE_LEN: usize = 0x4000;
+const JSR_OPCODE: u8 = 0x20;
+const FLAG_NAMES: [(&str, u8); 7] = [
+    ("N", 0x80),
+    ("V", 0x40),
+    ("B", 0x10),
+    ("D", 0x08),
+    ("I", 0x04),
+    ("Z", 0x02),
+    ("C", 0x01),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum RunMode {
+    Continue,
+    /// Run until PC reaches `pc` with the stack pointer back at `sp`
+    Until {
+        pc: u16,
+        sp: u16,
+    },
+    /// Run until an RTS or RTI leaves the stack pointer above `sp`
+    Out {
+        sp: u16,
+    },
+    /// Run until PC reaches the code of a different source line than the one at `start`,
+    /// skipping lines of subroutines called deeper than `sp` if `over` is set
+    Line {
+        start: u16,
+        sp: u16,
+        over: bool,
+    },
+}
+
+/// The program being debugged.
+struct Target {
+    cpu: Cpu<Box<dyn Memory>>,
+    calls: CallStack,
+    symbols: SymbolTable,
+    debug_info: Option<DebugInfo>,
+    line_breakpoints: HashMap<String, Vec<u16>>,
+    instruction_breakpoints: Vec<u16>,
+    function_breakpoints: Vec<u16>,
+    breakpoints: BTreeSet<u16>,
+    /// Why the last single step failed
+    last_error: Option<String>,
+}
+
+impl Target {
+    fn launch(args: &Json) -> Result<Target, String> {
+        let program = args
+            .get("program")
+            .and_then(Json::as_str)
+            .ok_or("Missing \"program\" in launch arguments")?;
+        let contents =
+            std::fs::read(program).map_err(|e| format!("Can't read \"{program}\": {e}"))?;
+        let start_pc = match args.get("pc") {
+            Some(Json::String(pc)) => Some(parse_addr(pc)?),
+            Some(pc) => Some(pc.as_i64().ok_or("Invalid \"pc\"")? as u16),
+            None => None,
+        };
+
+        let mut symbols = SymbolTable::new();
+        let mut debug_info = None;
+        let mut entry = None;
+        let mem: Box<dyn Memory> = if is_source(program) {
+            let source = String::from_utf8_lossy(&contents);
+            let assembly = asm::assemble(&source, asm::Options::default())
+                .map_err(|e| format!("{program}: {e}"))?;
+            let mut mem = SimpleMemory::from_rom(&[]);
+            assembly.load_into(&mut mem);
+            debug_info = Some(DebugInfo::from_assembly(program, &assembly));
+            symbols = assembly.symbol_table();
+            entry = assembly
+                .listing
+                .iter()
+                .find(|line| !line.bytes.is_empty())
+                .and_then(|line| line.addr);
+            Box::new(mem)
+        } else if InesRom::is_ines(&contents) {
+            Box::new(NromMemory::from_ines(&contents).map_err(|e| e.to_string())?)
+        } else {
+            Box::new(SimpleMemory::from_rom(&contents))
+        };
+
+        if let Some(path) = args.get("dbgfile").and_then(Json::as_str) {
+            let info = DebugInfo::load(path).map_err(|e| e.to_string())?;
+            symbols.extend(info.symbols());
+            debug_info = Some(info);
+        }
+        if let Some(path) = args.get("symbols").and_then(Json::as_str) {
+            let imported = SymbolTable::load(path).map_err(|e| e.to_string())?;
+            symbols.extend(&imported);
+        }
+
+        let mut cpu = Cpu::with_mem(mem);
+        if let Some(pc) = start_pc {
+            cpu.set_pc(pc);
+        } else if let Some(addr) = entry {
+            // Assembled programs start at their first instruction
+            cpu.set_pc(addr);
+        }
+
+        Ok(Target {
+            cpu,
+            calls: CallStack::new(),
+            symbols,
+            debug_info,
+            line_breakpoints: HashMap::new(),
+            instruction_breakpoints: vec![],
+            function_breakpoints: vec![],
+            breakpoints: BTreeSet::new(),
+            last_error: None,
+        })
+    }
+
+    fn update_breakpoints(&mut self) {
+        self.breakpoints = self
+            .line_breakpoints
+            .values()
+            .flatten()
+            .chain(&self.instruction_breakpoints)
+            .chain(&self.function_breakpoints)
+            .copied()
+            .collect();
+    }
+
+    /// Whether PC is on a different source line than `start`.
+    fn left_line(&self, start: u16) -> bool {
+        let info = match &self.debug_info {
+            Some(info) => info,
+            None => return true,
+        };
+        let location = info.location(self.cpu.pc());
+        location.is_some() && location != info.location(start)
+    }
+
+    fn step(&mut self) -> Result<(), Error6502> {
+        let (pc, sp) = (self.cpu.pc(), self.cpu.sp());
+        self.cpu.step()?;
+        self.calls.update(&self.cpu, pc, sp);
+        Ok(())
+    }
+
+    fn function_name(&self, entry: Option<u16>) -> String {
+        let c_function = |entry| self.debug_info.as_ref()?.function(entry);
+        match entry {
+            Some(entry) => match c_function(entry).or_else(|| self.symbols.name_of(entry)) {
+                Some(name) => name.to_string(),
+                None => format!("${entry:04X}"),
+            },
+            None => "main".to_string(),
+        }
+    }
+}
+
+fn is_source(path: &str) -> bool {
+    matches!(
+        Path::new(path).extension().and_then(|ext| ext.to_str()),
+        Some("s" | "asm" | "a65")
+    )
+}
+
+fn parse_addr(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|e| format!("Invalid address \"{s}\": {e}"))
+}
+
+fn memory_reference(addr: u16) -> Json {
+    Json::from(format!("0x{addr:04X}"))
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let mut n = 0u32;
+    let mut bits = 0;
+    for c in s.bytes().filter(|c| *c != b'=') {
+        let value = BASE64.iter().position(|b| *b == c)? as u32;
+        n = n << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Read `Content-Length` framed messages from `input` until it closes.
+fn read_messages(input: impl Read, tx: mpsc::Sender<Result<Json, String>>) {
+    let mut input = BufReader::new(input);
+    loop {
+        let mut len = None;
+        let mut header = String::new();
+        loop {
+            header.clear();
+            match input.read_line(&mut header) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+        let mut body = vec![0u8; len.unwrap_or(0)];
+        if input.read_exact(&mut body).is_err() {
+            return;
+        }
+        let message = Json::parse(&String::from_utf8_lossy(&body));
+        if tx.send(message).is_err() {
+            return;
+        }
+    }
+}
+
+/// Debug Adapter Protocol server, so editors such as VS Code can drive the emulator.
+///
+/// The `launch` request takes a `program`, which can be a binary, an iNES image or an assembler
+/// source (`.s`, `.asm` or `.a65`) that is assembled on the fly so breakpoints can be set by
+/// line. `dbgfile` names the debug info ld65 wrote for the program, for source breakpoints and
+/// stepping by line in ca65 or cc65 sources. `pc` overrides the start address, `symbols` names a
+/// label file to import (see `SymbolTable`) and `stopOnEntry` stops before the first instruction.
+///
+/// There is a single thread, the cpu. Its stack frames are rebuilt from the `JSR`s executed
+/// since launch, and its registers and flags are shown as variables.
+pub struct DapServer<W> {
+    out: W,
+    seq: i64,
+    target: Option<Target>,
+    running: Option<RunMode>,
+    stop_on_entry: bool,
+    disassembler: Disassembler,
+    done: bool,
+}
+
+impl<W> DapServer<W>
+where
+    W: Write,
+{
+    pub fn new(out: W) -> Self {
+        DapServer {
+            out,
+            seq: 1,
+            target: None,
+            running: None,
+            stop_on_entry: false,
+            disassembler: Disassembler::new(disasm::Options::default()),
+            done: false,
+        }
+    }
+
+    /// Serve requests from `input` until the client disconnects. Requests are read on a separate
+    /// thread so the cpu can keep running while waiting for them.
+    pub fn serve(&mut self, input: impl Read + Send + 'static) -> io::Result<()> {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || read_messages(input, tx));
+        self.serve_channel(&rx)
+    }
+
+    fn serve_channel(&mut self, rx: &Receiver<Result<Json, String>>) -> io::Result<()> {
+        while !self.done {
+            let message = if self.running.is_some() {
+                match rx.try_recv() {
+                    Ok(message) => message,
+                    Err(TryRecvError::Empty) => {
+                        self.run_slice()?;
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match rx.recv() {
+                    Ok(message) => message,
+                    Err(_) => return Ok(()),
+                }
+            };
+            match message {
+                Ok(request) => self.handle(&request)?,
+                Err(err) => self.send_event("output", output_body(&format!("{err}\n")))?,
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, mut fields: Vec<(&str, Json)>) -> io::Result<()> {
+        fields.insert(0, ("seq", Json::from(self.seq)));
+        self.seq += 1;
+        let body = Json::object(fields).to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        self.out.flush()
+    }
+
+    fn send_event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send(vec![
+            ("type", Json::from("event")),
+            ("event", Json::from(event)),
+            ("body", body),
+        ])
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, String>) -> io::Result<()> {
+        let mut fields = vec![
+            ("type", Json::from("response")),
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            (
+                "command",
+                request.get("command").cloned().unwrap_or(Json::Null),
+            ),
+        ];
+        match result {
+            Ok(body) => {
+                fields.push(("success", Json::from(true)));
+                fields.push(("body", body));
+            }
+            Err(message) => {
+                fields.push(("success", Json::from(false)));
+                fields.push(("message", Json::from(message)));
+            }
+        }
+        self.send(fields)
+    }
+
+    fn send_stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        self.running = None;
+        let mut body = vec![
+            ("reason", Json::from(reason)),
+            ("threadId", Json::from(THREAD_ID)),
+            ("allThreadsStopped", Json::from(true)),
+        ];
+        if let Some(text) = text {
+            body.push(("text", Json::from(text)));
+        }
+        self.send_event("stopped", Json::object(body))
+    }
+
+    /// Handle a request, sending its response and any events it causes.
+    pub fn handle(&mut self, request: &Json) -> io::Result<()> {
+        let command = request.get("command").and_then(Json::as_str).unwrap_or("");
+        let null = Json::Null;
+        let args = request.get("arguments").unwrap_or(&null);
+
+        let result = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" => match Target::launch(args) {
+                Ok(target) => {
+                    self.stop_on_entry =
+                        args.get("stopOnEntry").and_then(Json::as_bool) == Some(true);
+                    self.target = Some(target);
+                    Ok(Json::Null)
+                }
+                Err(err) => Err(err),
+            },
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(Json::Null)
+            }
+            _ => match self.target.as_mut() {
+                Some(_) => self.handle_target(command, args),
+                None if command == "configurationDone" || command == "threads" => {
+                    Ok(threads_body())
+                }
+                None => Err(format!("\"{command}\" needs a launched program")),
+            },
+        };
+        let ok = result.is_ok();
+        self.respond(request, result)?;
+
+        if !ok {
+            return Ok(());
+        }
+        match command {
+            "initialize" => self.send_event("initialized", Json::object([]))?,
+            "configurationDone" if self.target.is_some() => {
+                if self.stop_on_entry {
+                    self.send_stopped("entry", None)?;
+                } else {
+                    self.running = Some(RunMode::Continue);
+                }
+            }
+            "pause" => self.send_stopped("pause", None)?,
+            // Steps that could be finished right away
+            "next" | "stepIn" | "stepOut" if self.running.is_none() => self.finish_step()?,
+            "disconnect" | "terminate" => {
+                self.send_event("terminated", Json::object([]))?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Report the result of a single step.
+    fn finish_step(&mut self) -> io::Result<()> {
+        let error = self.target.as_ref().and_then(|t| t.last_error.clone());
+        match error {
+            Some(err) => self.send_stopped("exception", Some(err)),
+            None => self.send_stopped("step", None),
+        }
+    }
+
+    fn handle_target(&mut self, command: &str, args: &Json) -> Result<Json, String> {
+        let target = self.target.as_mut().unwrap();
+        target.last_error = None;
+        match command {
+            "configurationDone" | "threads" => Ok(threads_body()),
+            "setBreakpoints" => {
+                let path = args
+                    .get("source")
+                    .and_then(|source| source.get("path"))
+                    .and_then(Json::as_str)
+                    .unwrap_or("")
+                    .to_string();
+                let requested = args
+                    .get("breakpoints")
+                    .and_then(Json::as_array)
+                    .unwrap_or(&[]);
+                let info = target.debug_info.as_ref();
+                let mut addrs = vec![];
+                let breakpoints = requested
+                    .iter()
+                    .map(|bp| {
+                        let line = bp.get("line").and_then(Json::as_i64).unwrap_or(0) as usize;
+                        match info.and_then(|info| info.addresses(&path, line)) {
+                            Some((line, line_addrs)) => {
+                                let bp = Json::object([
+                                    ("verified", Json::from(true)),
+                                    ("line", Json::from(line)),
+                                    ("instructionReference", memory_reference(line_addrs[0])),
+                                ]);
+                                addrs.extend(line_addrs);
+                                bp
+                            }
+                            None => Json::object([
+                                ("verified", Json::from(false)),
+                                ("message", Json::from("No code at this line")),
+                            ]),
+                        }
+                    })
+                    .collect::<Vec<Json>>();
+                target.line_breakpoints.insert(path, addrs);
+                target.update_breakpoints();
+                Ok(Json::object([("breakpoints", Json::from(breakpoints))]))
+            }
+            "setInstructionBreakpoints" | "setFunctionBreakpoints" => {
+                let requested = args
+                    .get("breakpoints")
+                    .and_then(Json::as_array)
+                    .unwrap_or(&[]);
+                let mut addrs = vec![];
+                let breakpoints = requested
+                    .iter()
+                    .map(|bp| {
+                        let addr = if command == "setFunctionBreakpoints" {
+                            let name = bp.get("name").and_then(Json::as_str).unwrap_or("");
+                            target.symbols.resolve(name)
+                        } else {
+                            let reference = bp.get("instructionReference").and_then(Json::as_str);
+                            let offset = bp.get("offset").and_then(Json::as_i64).unwrap_or(0);
+                            reference
+                                .and_then(|r| parse_addr(r).ok())
+                                .map(|addr| addr.wrapping_add(offset as u16))
+                        };
+                        if let Some(addr) = addr {
+                            addrs.push(addr);
+                        }
+                        Json::object([
+                            ("verified", Json::from(addr.is_some())),
+                            ("instructionReference", addr.map(memory_reference).into()),
+                        ])
+                    })
+                    .collect::<Vec<Json>>();
+                if command == "setFunctionBreakpoints" {
+                    target.function_breakpoints = addrs;
+                } else {
+                    target.instruction_breakpoints = addrs;
+                }
+                target.update_breakpoints();
+                Ok(Json::object([("breakpoints", Json::from(breakpoints))]))
+            }
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(Json::object([(
+                "scopes",
+                Json::from(vec![
+                    Json::object([
+                        ("name", Json::from("Registers")),
+                        ("presentationHint", Json::from("registers")),
+                        ("variablesReference", Json::from(REGISTERS_REF)),
+                        ("expensive", Json::from(false)),
+                    ]),
+                    Json::object([
+                        ("name", Json::from("Flags")),
+                        ("variablesReference", Json::from(FLAGS_REF)),
+                        ("expensive", Json::from(false)),
+                    ]),
+                ]),
+            )])),
+            "variables" => {
+                let reference = args.get("variablesReference").and_then(Json::as_i64);
+                Ok(Json::object([(
+                    "variables",
+                    Json::from(variables(&target.cpu, reference)),
+                )]))
+            }
+            "setVariable" => {
+                let name = args.get("name").and_then(Json::as_str).unwrap_or("");
+                let value = args.get("value").and_then(Json::as_str).unwrap_or("");
+                let value = parse_addr(value)?;
+                set_variable(&mut target.cpu, name, value)?;
+                let shown = variables(&target.cpu, Some(REGISTERS_REF))
+                    .into_iter()
+                    .chain(variables(&target.cpu, Some(FLAGS_REF)))
+                    .find(|var| var.get("name").and_then(Json::as_str) == Some(name))
+                    .and_then(|var| var.get("value").cloned())
+                    .unwrap_or(Json::Null);
+                Ok(Json::object([("value", shown)]))
+            }
+            "evaluate" => {
+                let expression = args.get("expression").and_then(Json::as_str).unwrap_or("");
+                let expr = Expr::parse(expression, &target.symbols).map_err(|e| e.to_string())?;
+                let value = expr.format_value(expr.eval(&target.cpu, 0));
+                Ok(Json::object([
+                    ("result", Json::from(value)),
+                    ("variablesReference", Json::from(0)),
+                ]))
+            }
+            "readMemory" => {
+                let addr = memory_address(args)?;
+                let count = args.get("count").and_then(Json::as_i64).unwrap_or(0);
+                let bytes: Vec<u8> = (0..count.clamp(0, 0x10000))
+                    .map(|offset| target.cpu.mem.peek(addr.wrapping_add(offset as u16)))
+                    .collect();
+                Ok(Json::object([
+                    ("address", memory_reference(addr)),
+                    ("data", Json::from(base64_encode(&bytes))),
+                ]))
+            }
+            "writeMemory" => {
+                let addr = memory_address(args)?;
+                let data = args.get("data").and_then(Json::as_str).unwrap_or("");
+                let bytes = base64_decode(data).ok_or("Invalid base64 data")?;
+                for (offset, b) in bytes.iter().enumerate() {
+                    target
+                        .cpu
+                        .mem
+                        .write_byte(addr.wrapping_add(offset as u16), *b);
+                }
+                Ok(Json::object([("bytesWritten", Json::from(bytes.len()))]))
+            }
+            "disassemble" => Ok(self.disassemble(args)?),
+            "continue" => {
+                self.running = Some(RunMode::Continue);
+                Ok(Json::object([("allThreadsContinued", Json::from(true))]))
+            }
+            "next" | "stepIn" if by_line(args) && !target.left_line(target.cpu.pc()) => {
+                self.running = Some(RunMode::Line {
+                    start: target.cpu.pc(),
+                    sp: target.cpu.sp(),
+                    over: command == "next",
+                });
+                Ok(Json::Null)
+            }
+            "next" => {
+                let pc = target.cpu.pc();
+                if target.cpu.mem.peek(pc) == JSR_OPCODE {
+                    self.running = Some(RunMode::Until {
+                        pc: pc.wrapping_add(3),
+                        sp: target.cpu.sp(),
+                    });
+                } else {
+                    target.last_error = target.step().err().map(|e| e.to_string());
+                }
+                Ok(Json::Null)
+            }
+            "stepIn" => {
+                target.last_error = target.step().err().map(|e| e.to_string());
+                Ok(Json::Null)
+            }
+            "stepOut" => {
+                self.running = Some(RunMode::Out {
+                    sp: target.cpu.sp(),
+                });
+                Ok(Json::Null)
+            }
+            "pause" => {
+                self.running = None;
+                Ok(Json::Null)
+            }
+            _ => Err(format!("Unsupported request \"{command}\"")),
+        }
+    }
+
+    /// Execute up to `SLICE_LEN` instructions of the current run.
+    fn run_slice(&mut self) -> io::Result<()> {
+        let mode = match self.running {
+            Some(mode) => mode,
+            None => return Ok(()),
+        };
+        let target = self.target.as_mut().unwrap();
+        for _ in 0..SLICE_LEN {
+            if let Err(err) = target.step() {
+                return self.send_stopped("exception", Some(err.to_string()));
+            }
+            let cpu = &target.cpu;
+            let done = match mode {
+                RunMode::Continue => false,
+                RunMode::Until { pc, sp } => cpu.pc() == pc && cpu.sp() == sp,
+                RunMode::Out { sp } => {
+                    matches!(cpu.ir(), Some(Inst::RTS | Inst::RTI)) && cpu.sp() > sp
+                }
+                RunMode::Line { start, sp, over } => {
+                    (!over || cpu.sp() >= sp) && target.left_line(start)
+                }
+            };
+            if done {
+                return self.send_stopped("step", None);
+            }
+            if target.breakpoints.contains(&cpu.pc()) {
+                return self.send_stopped("breakpoint", None);
+            }
+        }
+        Ok(())
+    }
+
+    fn stack_trace(&self) -> Json {
+        let target = self.target.as_ref().unwrap();
+        let calls = target.calls.frames();
+        let mut frames = vec![];
+        let mut pc = target.cpu.pc();
+        for depth in (0..=calls.len()).rev() {
+            let entry = depth.checked_sub(1).map(|i| calls[i].entry);
+            let mut frame = vec![
+                ("id", Json::from(frames.len())),
+                ("name", Json::from(target.function_name(entry))),
+                ("instructionPointerReference", memory_reference(pc)),
+                ("column", Json::from(0)),
+            ];
+            let location = target
+                .debug_info
+                .as_ref()
+                .and_then(|info| info.location(pc));
+            match location {
+                Some(location) => {
+                    frame.push(("line", Json::from(location.line)));
+                    frame.push(("source", source(location.file)));
+                }
+                None => frame.push(("line", Json::from(0))),
+            }
+            frames.push(Json::object(frame));
+            if depth > 0 {
+                pc = calls[depth - 1].call_site;
+            }
+        }
+        Json::object([
+            ("totalFrames", Json::from(frames.len())),
+            ("stackFrames", Json::from(frames)),
+        ])
+    }
+
+    fn disassemble(&self, args: &Json) -> Result<Json, String> {
+        let target = self.target.as_ref().unwrap();
+        let base = memory_address(args)?;
+        let instruction_offset = args
+            .get("instructionOffset")
+            .and_then(Json::as_i64)
+            .unwrap_or(0);
+        let count = args
+            .get("instructionCount")
+            .and_then(Json::as_i64)
+            .unwrap_or(0)
+            .clamp(0, 0x1000) as usize;
+
+        let read = |addr: u16| Some(target.cpu.mem.peek(addr));
+        let decode_from = |mut addr: u16, n: usize| {
+            let mut instructions: Vec<Instruction> = vec![];
+            for _ in 0..n {
+                let inst = self.disassembler.decode(addr, read).unwrap();
+                addr = addr.wrapping_add(inst.len());
+                instructions.push(inst);
+            }
+            instructions
+        };
+
+        let instructions = if instruction_offset < 0 {
+            // There is no way to know where instructions start before `base`, so decode from far
+            // enough back and keep the ones that end up aligned with it
+            let before = instruction_offset.unsigned_abs() as usize;
+            let start = base.wrapping_sub(3 * before as u16);
+            let mut decoded = decode_from(start, 3 * before);
+            decoded.retain(|inst| inst.addr.wrapping_sub(start) < base.wrapping_sub(start));
+            let skip = decoded.len().saturating_sub(before);
+            let mut instructions = decoded.split_off(skip);
+            instructions.extend(decode_from(base, count.saturating_sub(instructions.len())));
+            instructions.truncate(count);
+            instructions
+        } else {
+            let skipped = decode_from(base, instruction_offset as usize);
+            let start = skipped
+                .last()
+                .map_or(base, |inst| inst.addr.wrapping_add(inst.len()));
+            decode_from(start, count)
+        };
+
+        let instructions: Vec<Json> = instructions
+            .iter()
+            .map(|inst| {
+                let bytes: Vec<String> = inst.bytes.iter().map(|b| format!("{b:02X}")).collect();
+                let mut fields = vec![
+                    ("address", memory_reference(inst.addr)),
+                    ("instructionBytes", Json::from(bytes.join(" "))),
+                    ("instruction", Json::from(inst.to_string())),
+                ];
+                if let Some(symbol) = target.symbols.name_of(inst.addr) {
+                    fields.push(("symbol", Json::from(symbol)));
+                }
+                let info = target.debug_info.as_ref();
+                if let Some(location) = info.and_then(|info| info.location(inst.addr)) {
+                    fields.push(("location", source(location.file)));
+                    fields.push(("line", Json::from(location.line)));
+                }
+                Json::object(fields)
+            })
+            .collect();
+        Ok(Json::object([("instructions", Json::from(instructions))]))
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object([
+        ("supportsConfigurationDoneRequest", Json::from(true)),
+        ("supportsFunctionBreakpoints", Json::from(true)),
+        ("supportsInstructionBreakpoints", Json::from(true)),
+        ("supportsSetVariable", Json::from(true)),
+        ("supportsReadMemoryRequest", Json::from(true)),
+        ("supportsWriteMemoryRequest", Json::from(true)),
+        ("supportsDisassembleRequest", Json::from(true)),
+        ("supportsSteppingGranularity", Json::from(true)),
+        ("supportsTerminateRequest", Json::from(true)),
+    ])
+}
+
+fn threads_body() -> Json {
+    Json::object([(
+        "threads",
+        Json::from(vec![Json::object([
+            ("id", Json::from(THREAD_ID)),
+            ("name", Json::from("6502")),
+        ])]),
+    )])
+}
+
+fn output_body(text: &str) -> Json {
+    Json::object([
+        ("category", Json::from("console")),
+        ("output", Json::from(text)),
+    ])
+}
+
+fn source(path: &str) -> Json {
+    let name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string());
+    Json::object([("name", Json::from(name)), ("path", Json::from(path))])
+}
+
+/// Whether a step request asks for source line granularity, the default.
+fn by_line(args: &Json) -> bool {
+    !matches!(
+        args.get("granularity").and_then(Json::as_str),
+        Some("instruction")
+    )
+}
+
+fn memory_address(args: &Json) -> Result<u16, String> {
+    let reference = args
+        .get("memoryReference")
+        .and_then(Json::as_str)
+        .ok_or("Missing memoryReference")?;
+    let offset = args.get("offset").and_then(Json::as_i64).unwrap_or(0);
+    Ok(parse_addr(reference)?.wrapping_add(offset as u16))
+}
+
+fn variables(cpu: &Cpu<Box<dyn Memory>>, reference: Option<i64>) -> Vec<Json> {
+    let variable = |name: &str, value: String, memory: Option<u16>| {
+        let mut fields = vec![
+            ("name", Json::from(name)),
+            ("value", Json::from(value)),
+            ("variablesReference", Json::from(0)),
+        ];
+        if let Some(addr) = memory {
+            fields.push(("memoryReference", memory_reference(addr)));
+        }
+        Json::object(fields)
+    };
+    match reference {
+        Some(REGISTERS_REF) => vec![
+            variable("PC", format!("${:04X}", cpu.pc()), Some(cpu.pc())),
+            variable("A", format!("${:02X}", cpu.ac()), None),
+            variable("X", format!("${:02X}", cpu.x()), None),
+            variable("Y", format!("${:02X}", cpu.y()), None),
+            variable("SP", format!("${:04X}", cpu.sp()), Some(cpu.sp())),
+            variable("P", format!("${:02X}", cpu.p()), None),
+        ],
+        Some(FLAGS_REF) => FLAG_NAMES
+            .iter()
+            .map(|(name, mask)| variable(name, ((cpu.p() & mask != 0) as u8).to_string(), None))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn set_variable(cpu: &mut Cpu<Box<dyn Memory>>, name: &str, value: u16) -> Result<(), String> {
+    if let Some((_, mask)) = FLAG_NAMES.iter().find(|(flag, _)| *flag == name) {
+        let p = if value != 0 {
+            cpu.p() | mask
+        } else {
+            cpu.p() & !mask
+        };
+        cpu.set_p(p);
+        return Ok(());
+    }
+    match name {
+        "PC" => cpu.set_pc(value),
+        "SP" => cpu.set_sp(value as u8),
+        "A" | "X" | "Y" | "P" => {
+            let value =
+                u8::try_from(value).map_err(|_| format!("${value:X} doesn't fit in a byte"))?;
+            match name {
+                "A" => cpu.set_ac(value),
+                "X" => cpu.set_x(value),
+                "Y" => cpu.set_y(value),
+                _ => cpu.set_p(value),
+            }
+        }
+        _ => return Err(format!("Unknown variable \"{name}\"")),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(seq: i64, command: &str, arguments: Json) -> Json {
+        Json::object([
+            ("seq", Json::from(seq)),
+            ("type", Json::from("request")),
+            ("command", Json::from(command)),
+            ("arguments", arguments),
+        ])
+    }
+
+    /// Parse the framed messages written by the server.
+    fn messages(out: &[u8]) -> Vec<Json> {
+        let text = String::from_utf8(out.to_vec()).unwrap();
+        text.split("Content-Length: ")
+            .skip(1)
+            .map(|message| Json::parse(message.split_once("\r\n\r\n").unwrap().1).unwrap())
+            .collect()
+    }
+
+    fn find<'a>(messages: &'a [Json], kind: &str, name: &str) -> Option<&'a Json> {
+        messages.iter().rev().find(|m| {
+            m.get("type").and_then(Json::as_str) == Some(kind)
+                && (m.get("command").or_else(|| m.get("event"))).and_then(Json::as_str)
+                    == Some(name)
+        })
+    }
+
+    #[test]
+    fn test_session() {
+        let path = std::env::temp_dir().join(format!("mini6502-dap-{}.s", std::process::id()));
+        std::fs::write(
+            &path,
+            "
+        .org $0600
+start:  ldx #$03
+        jsr sub
+        jmp start
+sub:    dex
+        rts
+",
+        )
+        .unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let mut server = DapServer::new(vec![]);
+        let launch = Json::object([
+            ("program", Json::from(path.as_str())),
+            ("stopOnEntry", Json::from(true)),
+        ]);
+        server
+            .handle(&request(1, "initialize", Json::Null))
+            .unwrap();
+        server.handle(&request(2, "launch", launch)).unwrap();
+        let breakpoints = Json::object([
+            (
+                "source",
+                Json::object([("path", Json::from(path.as_str()))]),
+            ),
+            (
+                "breakpoints",
+                Json::from(vec![Json::object([("line", Json::from(6))])]),
+            ),
+        ]);
+        server
+            .handle(&request(3, "setBreakpoints", breakpoints))
+            .unwrap();
+        server
+            .handle(&request(4, "configurationDone", Json::Null))
+            .unwrap();
+        server.handle(&request(5, "continue", Json::Null)).unwrap();
+        server.run_slice().unwrap();
+        server
+            .handle(&request(6, "stackTrace", Json::Null))
+            .unwrap();
+        let refs = Json::object([("variablesReference", Json::from(REGISTERS_REF))]);
+        server.handle(&request(7, "variables", refs)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let messages = messages(&server.out);
+        server.out.clear();
+        let bp = find(&messages, "response", "setBreakpoints").unwrap();
+        let bp = &bp
+            .get("body")
+            .unwrap()
+            .get("breakpoints")
+            .unwrap()
+            .as_array()
+            .unwrap()[0];
+        assert_eq!(bp.get("verified"), Some(&Json::Bool(true)));
+        assert_eq!(
+            bp.get("instructionReference").unwrap().as_str(),
+            Some("0x0608")
+        );
+
+        let stopped = find(&messages, "event", "stopped").unwrap();
+        assert_eq!(
+            stopped.get("body").unwrap().get("reason").unwrap().as_str(),
+            Some("breakpoint")
+        );
+
+        let frames = find(&messages, "response", "stackTrace").unwrap();
+        let frames = frames.get("body").unwrap().get("stackFrames").unwrap();
+        let names: Vec<&str> = frames
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|frame| frame.get("name").unwrap().as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["sub", "main"]);
+        assert_eq!(
+            frames.as_array().unwrap()[1].get("line"),
+            Some(&Json::from(4))
+        );
+
+        let registers = find(&messages, "response", "variables").unwrap();
+        let x = &registers
+            .get("body")
+            .unwrap()
+            .get("variables")
+            .unwrap()
+            .as_array()
+            .unwrap()[2];
+        assert_eq!(x.get("value").unwrap().as_str(), Some("$03"));
+
+        // Stepping over a line finishes the subroutine and stops after the JSR
+        server.handle(&request(8, "next", Json::Null)).unwrap();
+        server.run_slice().unwrap();
+        server.handle(&request(9, "next", Json::Null)).unwrap();
+        server.run_slice().unwrap();
+        server
+            .handle(&request(10, "stackTrace", Json::Null))
+            .unwrap();
+        let messages = self::messages(&server.out);
+        let frames = find(&messages, "response", "stackTrace").unwrap();
+        let top = &frames.get("body").unwrap().get("stackFrames").unwrap();
+        assert_eq!(top.as_array().unwrap()[0].get("line"), Some(&Json::from(5)));
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64_encode(b"6502!"), "NjUwMiE=");
+        assert_eq!(base64_decode("NjUwMiE=").unwrap(), b"6502!");
+    }
+}
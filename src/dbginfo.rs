@@ -0,0 +1,495 @@
+use crate::asm::Assembly;
+use crate::error::DebugInfoError;
+use crate::symbols::SymbolTable;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::ops::Range;
+use std::path::Path;
+
+const NONE: u32 = u32::MAX;
+const ADDR_SPACE: usize = 0x10000;
+
+/// What produced a source line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LineKind {
+    Asm,
+    /// A line of the C source a compiler generated assembly for
+    C,
+    /// A line inside a macro definition
+    Macro,
+}
+
+impl LineKind {
+    /// Lower is preferred when several lines cover the same address
+    const fn rank(&self) -> u8 {
+        match self {
+            LineKind::C => 0,
+            LineKind::Asm => 1,
+            LineKind::Macro => 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct SourceLine {
+    file: usize,
+    line: usize,
+    kind: LineKind,
+    ranges: Vec<Range<u32>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Scope {
+    name: String,
+    /// Name of the C function whose code the scope holds, for compiled code
+    function: Option<String>,
+    ranges: Vec<Range<u32>>,
+}
+
+/// Where an address came from in the source.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Location<'a> {
+    pub file: &'a str,
+    /// 1-based
+    pub line: usize,
+    pub kind: LineKind,
+}
+
+impl Display for Location<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{}:{}", self.file, self.line))
+    }
+}
+
+/// Source files, lines, scopes and C functions of a program, read from the debug file ld65
+/// writes with `--dbgfile`, or taken from an `Assembly`.
+///
+/// ```rust
+/// use mini6502::dbginfo::DebugInfo;
+///
+/// let info = DebugInfo::parse(
+///     "version\tmajor=2,minor=0
+/// file\tid=0,name=\"main.s\",size=100,mtime=0x00000000,mod=0
+/// seg\tid=0,name=\"CODE\",start=0x000600,size=0x0004,addrsize=absolute,type=ro
+/// span\tid=0,seg=0,start=0,size=2
+/// span\tid=1,seg=0,start=2,size=2
+/// line\tid=0,file=0,line=3,span=0
+/// line\tid=1,file=0,line=4,span=1
+/// scope\tid=0,name=\"\",mod=0,size=4,span=0+1
+/// ",
+/// )
+/// .unwrap();
+///
+/// assert_eq!(info.location(0x0603).unwrap().to_string(), "main.s:4");
+/// assert_eq!(info.addresses("main.s", 1), Some((3, vec![0x0600])));
+/// ```
+#[derive(Clone, Debug)]
+pub struct DebugInfo {
+    files: Vec<String>,
+    lines: Vec<SourceLine>,
+    scopes: Vec<Scope>,
+    symbols: SymbolTable,
+    /// Preferred line for each address, index into `lines`
+    line_at: Vec<u32>,
+    /// Innermost named scope for each address, index into `scopes`
+    scope_at: Vec<u32>,
+}
+
+/// One line of a debug file: `keyword<TAB>key=value,key=value...`
+struct Record<'a> {
+    keyword: &'a str,
+    attrs: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Record<'a> {
+    fn parse(line: &'a str) -> Option<Record<'a>> {
+        let (keyword, rest) = line.split_once(|c: char| c.is_whitespace())?;
+        let mut attrs = HashMap::new();
+        let mut rest = rest.trim();
+        while !rest.is_empty() {
+            let (key, value) = rest.split_once('=')?;
+            let end = if let Some(quoted) = value.strip_prefix('"') {
+                // Quoted strings may contain commas
+                quoted.find('"')? + 2
+            } else {
+                value.find(',').unwrap_or(value.len())
+            };
+            attrs.insert(key.trim(), &value[..end]);
+            rest = value[end..].trim_start_matches(',');
+        }
+        Some(Record { keyword, attrs })
+    }
+
+    fn string(&self, key: &str) -> Option<&'a str> {
+        let value = self.attrs.get(key)?;
+        value.strip_prefix('"')?.strip_suffix('"')
+    }
+
+    fn number(&self, key: &str) -> Option<u32> {
+        parse_number(self.attrs.get(key)?)
+    }
+
+    /// `id` or a `+` separated list of ids.
+    fn ids(&self, key: &str) -> Option<Vec<usize>> {
+        self.attrs
+            .get(key)?
+            .split('+')
+            .map(|id| parse_number(id).map(|id| id as usize))
+            .collect()
+    }
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Put `item` at `id`, growing `items` as needed, since records aren't sorted by id.
+fn place<T: Default>(items: &mut Vec<T>, id: usize, item: T) {
+    if items.len() <= id {
+        items.resize_with(id + 1, T::default);
+    }
+    items[id] = item;
+}
+
+#[derive(Default, Clone)]
+struct RawScope {
+    name: String,
+    parent: Option<usize>,
+    sym: Option<usize>,
+    spans: Vec<usize>,
+}
+
+impl DebugInfo {
+    pub fn load(path: impl AsRef<Path>) -> Result<DebugInfo, DebugInfoError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| DebugInfoError::Io(format!("{}: {e}", path.display())))?;
+        DebugInfo::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<DebugInfo, DebugInfoError> {
+        let mut files: Vec<String> = vec![];
+        let mut segs: Vec<u32> = vec![];
+        // (segment, offset, size)
+        let mut spans: Vec<(usize, u32, u32)> = vec![];
+        let mut raw_lines: Vec<(usize, usize, LineKind, Vec<usize>)> = vec![];
+        let mut raw_scopes: Vec<RawScope> = vec![];
+        // (name, sym) of C functions and globals
+        let mut csyms: Vec<(String, usize)> = vec![];
+        let mut syms: Vec<(String, u32, bool)> = vec![];
+
+        for (idx, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let error = || DebugInfoError::Syntax {
+                line: idx + 1,
+                text: line.to_string(),
+            };
+            let record = Record::parse(line).ok_or_else(error)?;
+            let id = || record.number("id").map(|id| id as usize).ok_or_else(error);
+            match record.keyword {
+                "version" => {
+                    let major = record.number("major").ok_or_else(error)?;
+                    if major != 2 {
+                        return Err(DebugInfoError::Version(major));
+                    }
+                }
+                "file" => {
+                    let name = record.string("name").ok_or_else(error)?;
+                    place(&mut files, id()?, name.to_string());
+                }
+                "seg" => {
+                    let start = record.number("start").ok_or_else(error)?;
+                    place(&mut segs, id()?, start);
+                }
+                "span" => {
+                    let seg = record.number("seg").ok_or_else(error)? as usize;
+                    let start = record.number("start").ok_or_else(error)?;
+                    let size = record.number("size").ok_or_else(error)?;
+                    place(&mut spans, id()?, (seg, start, size));
+                }
+                "line" => {
+                    // Lines without spans didn't generate code
+                    if let Some(span_ids) = record.ids("span") {
+                        let file = record.number("file").ok_or_else(error)? as usize;
+                        let number = record.number("line").ok_or_else(error)? as usize;
+                        let kind = match record.number("type") {
+                            Some(1) => LineKind::C,
+                            Some(2) => LineKind::Macro,
+                            _ => LineKind::Asm,
+                        };
+                        raw_lines.push((file, number, kind, span_ids));
+                    }
+                }
+                "scope" => {
+                    let scope = RawScope {
+                        name: record.string("name").ok_or_else(error)?.to_string(),
+                        parent: record.number("parent").map(|id| id as usize),
+                        sym: record.number("sym").map(|id| id as usize),
+                        spans: record.ids("span").unwrap_or_default(),
+                    };
+                    place(&mut raw_scopes, id()?, scope);
+                }
+                "csym" => {
+                    let storage = record.attrs.get("sc").copied();
+                    if let (Some("ext" | "static"), Some(sym)) = (storage, record.number("sym")) {
+                        let name = record.string("name").ok_or_else(error)?;
+                        csyms.push((name.to_string(), sym as usize));
+                    }
+                }
+                "sym" => {
+                    let name = record.string("name").ok_or_else(error)?;
+                    let value = record.number("val").unwrap_or(0);
+                    let is_label = record.attrs.get("type") == Some(&"lab");
+                    place(&mut syms, id()?, (name.to_string(), value, is_label));
+                }
+                // info, lib, mod and type records aren't needed
+                _ => {}
+            }
+        }
+
+        let span_range = |id: &usize| -> Option<Range<u32>> {
+            let (seg, start, size) = spans.get(*id)?;
+            let start = segs.get(*seg)? + start;
+            Some(start..start + size)
+        };
+
+        let lines = raw_lines
+            .into_iter()
+            .map(|(file, line, kind, span_ids)| SourceLine {
+                file,
+                line,
+                kind,
+                ranges: span_ids.iter().filter_map(span_range).collect(),
+            })
+            .collect();
+
+        let scopes = raw_scopes
+            .iter()
+            .map(|scope| {
+                // A C function is a scope whose symbol is also a C symbol
+                let function = scope.sym.and_then(|sym| {
+                    csyms
+                        .iter()
+                        .find(|(_, csym)| *csym == sym)
+                        .map(|(name, _)| name.clone())
+                });
+                let mut name = scope.name.clone();
+                let mut parent = scope.parent;
+                while let Some(p) = parent.and_then(|p| raw_scopes.get(p)) {
+                    if !p.name.is_empty() {
+                        name = format!("{}::{name}", p.name);
+                    }
+                    parent = p.parent;
+                }
+                Scope {
+                    name,
+                    function,
+                    ranges: scope.spans.iter().filter_map(span_range).collect(),
+                }
+            })
+            .collect();
+
+        let symbols = syms
+            .iter()
+            .filter(|(name, _, is_label)| *is_label && !name.is_empty())
+            .map(|(name, value, _)| (name.as_str(), *value as u16))
+            .collect();
+
+        Ok(DebugInfo::new(files, lines, scopes, symbols))
+    }
+
+    /// Line information for an assembled program, where every source line is in `path`.
+    pub fn from_assembly(path: &str, assembly: &Assembly) -> DebugInfo {
+        let lines = assembly
+            .listing
+            .iter()
+            .filter(|line| !line.bytes.is_empty())
+            .filter_map(|line| {
+                let start = line.addr? as u32;
+                Some(SourceLine {
+                    file: 0,
+                    line: line.line,
+                    kind: LineKind::Asm,
+                    ranges: std::iter::once(start..start + line.bytes.len() as u32).collect(),
+                })
+            })
+            .collect();
+        DebugInfo::new(
+            vec![path.to_string()],
+            lines,
+            vec![],
+            assembly.symbol_table(),
+        )
+    }
+
+    fn new(
+        files: Vec<String>,
+        lines: Vec<SourceLine>,
+        scopes: Vec<Scope>,
+        symbols: SymbolTable,
+    ) -> DebugInfo {
+        let mut line_at = vec![NONE; ADDR_SPACE];
+        let mut line_rank = vec![(u8::MAX, u32::MAX); ADDR_SPACE];
+        for (idx, line) in lines.iter().enumerate() {
+            for range in &line.ranges {
+                let rank = (line.kind.rank(), range.len() as u32);
+                for addr in range.clone().filter(|addr| (*addr as usize) < ADDR_SPACE) {
+                    if rank < line_rank[addr as usize] {
+                        line_rank[addr as usize] = rank;
+                        line_at[addr as usize] = idx as u32;
+                    }
+                }
+            }
+        }
+
+        let mut scope_at = vec![NONE; ADDR_SPACE];
+        let mut scope_size = vec![u32::MAX; ADDR_SPACE];
+        for (idx, scope) in scopes.iter().enumerate() {
+            if scope.name.is_empty() {
+                continue;
+            }
+            let size: u32 = scope.ranges.iter().map(|r| r.len() as u32).sum();
+            for range in &scope.ranges {
+                for addr in range.clone().filter(|addr| (*addr as usize) < ADDR_SPACE) {
+                    if size < scope_size[addr as usize] {
+                        scope_size[addr as usize] = size;
+                        scope_at[addr as usize] = idx as u32;
+                    }
+                }
+            }
+        }
+
+        DebugInfo {
+            files,
+            lines,
+            scopes,
+            symbols,
+            line_at,
+            scope_at,
+        }
+    }
+
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// Labels defined in the program.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// The source line that generated the code at `addr`. C lines are preferred over the
+    /// assembly the compiler generated for them.
+    pub fn location(&self, addr: u16) -> Option<Location<'_>> {
+        let line = self.lines.get(self.line_at[addr as usize] as usize)?;
+        Some(Location {
+            file: self.files.get(line.file)?,
+            line: line.line,
+            kind: line.kind,
+        })
+    }
+
+    /// Every source line that generated code or data, with the addresses it covers.
+    pub fn lines(&self) -> impl Iterator<Item = (Location<'_>, &[Range<u32>])> {
+        self.lines.iter().filter(|l| !l.ranges.is_empty()).map(|l| {
+            let location = Location {
+                file: &self.files[l.file],
+                line: l.line,
+                kind: l.kind,
+            };
+            (location, l.ranges.as_slice())
+        })
+    }
+
+    /// Name of the innermost scope around `addr`, as `outer::inner`.
+    pub fn scope(&self, addr: u16) -> Option<&str> {
+        let scope = self.scopes.get(self.scope_at[addr as usize] as usize)?;
+        Some(&scope.name)
+    }
+
+    /// The C function `addr` belongs to.
+    pub fn function(&self, addr: u16) -> Option<&str> {
+        let scope = self.scopes.get(self.scope_at[addr as usize] as usize)?;
+        scope.function.as_deref()
+    }
+
+    /// Start addresses of the code for the first line at or after `line` in `file` that has
+    /// any, along with that line. `file` can be given with more or fewer leading directories
+    /// than the debug info uses.
+    pub fn addresses(&self, file: &str, line: usize) -> Option<(usize, Vec<u16>)> {
+        let found = self
+            .lines
+            .iter()
+            .filter(|l| l.line >= line && !l.ranges.is_empty())
+            .filter(|l| same_file(&self.files[l.file], file))
+            .min_by_key(|l| (l.line, l.kind.rank()))?;
+        let mut addrs: Vec<u16> = self
+            .lines
+            .iter()
+            .filter(|l| l.line == found.line && l.file == found.file)
+            .flat_map(|l| l.ranges.iter().map(|r| r.start as u16))
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        Some((found.line, addrs))
+    }
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    let (a, b) = (Path::new(a), Path::new(b));
+    a.ends_with(b) || b.ends_with(a)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // What ld65 writes for a C function `main` compiled by cc65 into hello.s
+    const HELLO_DBG: &str = r#"version	major=2,minor=0
+info	csym=1,file=2,lib=0,line=4,mod=1,scope=2,seg=1,span=4,sym=1,type=1
+file	id=0,name="src/hello.c",size=60,mtime=0x5F000000,mod=0
+file	id=1,name="hello.s",size=900,mtime=0x5F000000,mod=0
+mod	id=0,name="hello.o",file=1
+seg	id=0,name="CODE",start=0x000800,size=0x0008,addrsize=absolute,type=ro,oname="hello.bin",ooffs=0
+span	id=0,seg=0,start=0,size=8
+span	id=1,seg=0,start=0,size=3
+span	id=2,seg=0,start=3,size=5
+span	id=3,seg=0,start=3,size=2
+line	id=0,file=0,line=4,type=1,span=1
+line	id=1,file=0,line=5,type=1,span=2
+line	id=2,file=1,line=20,span=1
+line	id=3,file=1,line=22,span=3
+scope	id=0,name="",mod=0,size=8,span=0
+scope	id=1,name="_main",mod=0,type=scope,size=8,parent=0,sym=0,span=0
+sym	id=0,name="_main",addrsize=absolute,size=8,scope=0,def=2,ref=1,val=0x800,seg=0,type=lab
+csym	id=0,name="main",scope=0,type=0,sc=ext,sym=0
+"#;
+
+    #[test]
+    fn test_ld65_dbgfile() {
+        let info = DebugInfo::parse(HELLO_DBG).unwrap();
+
+        let location = info.location(0x0803).unwrap();
+        assert_eq!(location.to_string(), "src/hello.c:5");
+        assert_eq!(location.kind, LineKind::C);
+        assert_eq!(info.location(0x0808), None);
+
+        assert_eq!(info.scope(0x0804), Some("_main"));
+        assert_eq!(info.function(0x0804), Some("main"));
+        assert_eq!(info.symbols().lookup("_main"), Some(0x0800));
+
+        assert_eq!(info.addresses("hello.c", 5), Some((5, vec![0x0803])));
+        assert_eq!(info.addresses("hello.s", 21), Some((22, vec![0x0803])));
+        assert_eq!(info.addresses("other.c", 1), None);
+
+        assert_eq!(
+            DebugInfo::parse("version\tmajor=3,minor=0").err(),
+            Some(DebugInfoError::Version(3))
+        );
+    }
+}
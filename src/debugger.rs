@@ -0,0 +1,1023 @@
+use crate::cpu::Cpu;
+use crate::dbginfo::DebugInfo;
+use crate::disasm::{self, Disassembler, Instruction};
+use crate::error::{Error6502, StackViolation};
+use crate::expr::Expr;
+use crate::format::flags_text;
+use crate::memory::Memory;
+use crate::opc::Inst;
+use crate::stackcheck::{Severity, StackChecker};
+use crate::symbols::SymbolTable;
+use crate::watchpoint::{Access, AccessKind, Watcher, Watchpoint};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+const JSR_OPCODE: u8 = 0x20;
+/// How many executed instruction addresses are remembered to disassemble backwards from PC
+const RECENT_LEN: usize = 16;
+const DEFAULT_DUMP_LEN: u16 = 0x40;
+const DEFAULT_DISASM_LEN: usize = 8;
+/// Source lines shown on each side of the current one by `list`
+const LIST_CONTEXT: usize = 5;
+
+const HELP: &str = "\
+Commands (ADDR, VALUE and LEN are hex, N is decimal, ADDR can be a symbol, see the `expr`
+module for EXPR):
+  s, step [N]            Execute N instructions, stepping into subroutines
+  n, next                Execute one instruction, stepping over JSR
+  fin, finish            Run until the current subroutine returns
+  sl, stepline           Run until the next source line, stepping into subroutines
+  nl, nextline           Run until the next source line, stepping over JSR
+  c, continue            Run until a breakpoint or an error
+  b, break [ADDR] [if EXPR]
+                         Set a breakpoint at ADDR or FILE:LINE that stops when EXPR is
+                         non-zero, or list breakpoints. `hits` counts the times it was reached
+  d, delete ADDR|all     Delete a breakpoint
+  r, regs                Show registers
+  set REG VALUE          Set a register: a, x, y, sp, p or pc
+  m, mem ADDR [LEN]      Dump memory
+  w, write ADDR VALUE..  Write bytes to memory
+  u, disasm [ADDR] [N]   Disassemble N instructions at ADDR, around PC by default
+  l, list                Show the source around the current line
+  watch EXPR             Show EXPR every time execution stops
+  unwatch N              Delete watch expression N
+  wp [r|w|x] ADDR[..END] Stop when ADDR up to END is read, written or executed, or list
+                         watchpoints. Writes are watched by default
+  unwp N                 Delete watchpoint N
+  p, print EXPR          Evaluate EXPR
+  history                Show command history, `!N` repeats command N
+  q, quit                Exit
+An empty line repeats the last command.";
+
+/// Why execution stopped after a step or `continue`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// The command completed
+    Done,
+    Breakpoint(u16),
+    /// An instruction jumped to itself and would loop forever, with no interrupt to get it out
+    Stuck(u16),
+    Watchpoint(Access),
+    Error(Error6502),
+    /// The stack checker found a violation with a severity other than warn
+    Stack(StackViolation),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    P,
+    Pc,
+}
+
+impl Register {
+    fn parse(s: &str) -> Option<Register> {
+        match s.to_ascii_lowercase().as_str() {
+            "a" | "ac" => Some(Register::A),
+            "x" => Some(Register::X),
+            "y" => Some(Register::Y),
+            "s" | "sp" => Some(Register::Sp),
+            "p" => Some(Register::P),
+            "pc" => Some(Register::Pc),
+            _ => None,
+        }
+    }
+}
+
+/// A breakpoint stops when its condition, if any, is non-zero.
+#[derive(Clone, Debug, Default)]
+struct Breakpoint {
+    condition: Option<Expr>,
+    /// Times PC has reached the breakpoint
+    hits: u64,
+}
+
+fn parse_hex(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|e| format!("Invalid number \"{s}\": {e}"))
+}
+
+fn resolve(symbols: &SymbolTable, s: &str) -> Result<u16, String> {
+    symbols
+        .resolve(s)
+        .ok_or_else(|| format!("\"{s}\" is neither an address nor a symbol"))
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+    let value = parse_hex(s)?;
+    u8::try_from(value).map_err(|_| format!("${value:X} doesn't fit in a byte"))
+}
+
+/// Interactive debugger driving a `Cpu` one instruction at a time.
+///
+/// ```rust
+/// use mini6502::debugger::Debugger;
+/// use mini6502::{Cpu, SimpleMemory};
+///
+/// // ldx #$03; loop: dex; bne loop; brk
+/// let mem = SimpleMemory::from_rom(&[0xa2, 0x03, 0xCA, 0xD0, 0xFD, 0x00]);
+/// let mut debugger = Debugger::new(Cpu::with_mem(mem));
+///
+/// let mut out = vec![];
+/// debugger.execute("break 5", &mut out).unwrap();
+/// debugger.execute("continue", &mut out).unwrap();
+/// assert_eq!(debugger.cpu.pc(), 0x0005);
+/// assert_eq!(debugger.cpu.x(), 0x00);
+/// ```
+pub struct Debugger<M> {
+    pub cpu: Cpu<M>,
+    /// Names accepted in place of addresses and shown in the output
+    pub symbols: SymbolTable,
+    /// Source lines for addresses, from ld65 `--dbgfile` output
+    pub debug_info: Option<DebugInfo>,
+    /// Checks every executed instruction when set
+    pub stack_checker: Option<StackChecker>,
+    /// Violations with warn severity not shown yet
+    stack_warnings: Vec<StackViolation>,
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    /// Observes the cpu to catch accesses to watchpoints
+    watcher: Rc<RefCell<Watcher>>,
+    watches: Vec<Expr>,
+    history: Vec<String>,
+    /// Addresses of the last executed instructions, most recent last
+    recent: VecDeque<u16>,
+    disassembler: Disassembler,
+}
+
+impl<M> Debugger<M>
+where
+    M: Memory,
+{
+    pub fn new(mut cpu: Cpu<M>) -> Self {
+        let watcher = Rc::new(RefCell::new(Watcher::new()));
+        cpu.add_observer(Box::new(watcher.clone()));
+        Debugger {
+            cpu,
+            symbols: SymbolTable::new(),
+            debug_info: None,
+            stack_checker: None,
+            stack_warnings: vec![],
+            breakpoints: BTreeMap::new(),
+            watcher,
+            watches: vec![],
+            history: vec![],
+            recent: VecDeque::with_capacity(RECENT_LEN),
+            disassembler: Disassembler::new(disasm::Options::default()),
+        }
+    }
+
+    /// Stop at `addr` whenever `condition` is `None` or evaluates to non-zero. Replaces any
+    /// breakpoint already at `addr`.
+    pub fn add_breakpoint(&mut self, addr: u16, condition: Option<Expr>) {
+        self.breakpoints
+            .insert(addr, Breakpoint { condition, hits: 0 });
+    }
+
+    /// Returns `false` if there was no breakpoint at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    pub fn add_watch(&mut self, watch: Expr) {
+        self.watches.push(watch);
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watcher.borrow_mut().watchpoints.push(watchpoint);
+    }
+
+    /// Count a hit of the breakpoint at PC, if there is one, and check its condition.
+    fn at_breakpoint(&mut self) -> bool {
+        let pc = self.cpu.pc();
+        match self.breakpoints.get_mut(&pc) {
+            Some(breakpoint) => {
+                breakpoint.hits += 1;
+                match &breakpoint.condition {
+                    Some(condition) => condition.eval(&self.cpu, breakpoint.hits) != 0,
+                    None => true,
+                }
+            }
+            None => false,
+        }
+    }
+
+    /// Execute a single instruction.
+    pub fn step(&mut self) -> Stop {
+        let pc = self.cpu.pc();
+        if let Some(checker) = &mut self.stack_checker {
+            checker.before_instruction(&self.cpu);
+        }
+        if let Err(err) = self.cpu.step() {
+            return Stop::Error(err);
+        }
+        if self.recent.len() == RECENT_LEN {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(pc);
+        // Before the checker reads memory itself
+        let hit = self.watcher.borrow_mut().take_hit();
+        if let Some(checker) = &mut self.stack_checker {
+            if let Some(violation) = checker.after_instruction(&self.cpu) {
+                match checker.severity {
+                    Severity::Warn => self.stack_warnings.push(violation),
+                    Severity::Error | Severity::Break => return Stop::Stack(violation),
+                }
+            }
+        }
+        if let Some(access) = hit {
+            return Stop::Watchpoint(access);
+        }
+        let next = self.cpu.pc();
+        if self.watcher.borrow().watches_execute(next) {
+            return Stop::Watchpoint(Access {
+                addr: next,
+                kind: AccessKind::Execute,
+                value: self.cpu.mem.peek(next),
+            });
+        }
+        // An idle loop waiting for an interrupt isn't stuck
+        let wakeable = self.cpu.pending_interrupt().is_some() || self.cpu.mem.can_interrupt();
+        if self.cpu.pc() == pc && !wakeable {
+            Stop::Stuck(pc)
+        } else {
+            Stop::Done
+        }
+    }
+
+    /// Execute instructions until `done` returns `true`, a breakpoint or watchpoint is hit or
+    /// the cpu can't go on. The breakpoint at the current PC, if any, is ignored so execution
+    /// can resume from it.
+    fn run_until(&mut self, mut done: impl FnMut(&Cpu<M>) -> bool) -> Stop {
+        loop {
+            let stop = self.step();
+            if stop != Stop::Done || done(&self.cpu) {
+                return stop;
+            }
+            if self.at_breakpoint() {
+                return Stop::Breakpoint(self.cpu.pc());
+            }
+        }
+    }
+
+    /// Execute one instruction, or a whole subroutine call if it's a `JSR`.
+    pub fn step_over(&mut self) -> Stop {
+        if self.cpu.mem.peek(self.cpu.pc()) != JSR_OPCODE {
+            return self.step();
+        }
+        let return_addr = self.cpu.pc().wrapping_add(3);
+        let sp = self.cpu.sp();
+        self.run_until(|cpu| cpu.pc() == return_addr && cpu.sp() == sp)
+    }
+
+    /// Run until the subroutine or interrupt handler we are in returns to its caller.
+    pub fn step_out(&mut self) -> Stop {
+        let sp = self.cpu.sp();
+        // The RTS or RTI that returns to our caller pops its return address from above the
+        // current SP
+        self.run_until(|cpu| matches!(cpu.ir(), Some(Inst::RTS | Inst::RTI)) && cpu.sp() > sp)
+    }
+
+    /// Run until the PC reaches the code of a different source line, stepping over
+    /// subroutine calls if `over` is set. Without debug info this steps one instruction.
+    pub fn step_line(&mut self, over: bool) -> Stop {
+        let start = self.line_at(self.cpu.pc());
+        loop {
+            let stop = if over { self.step_over() } else { self.step() };
+            if stop != Stop::Done {
+                return stop;
+            }
+            let line = self.line_at(self.cpu.pc());
+            if start.is_none() || (line.is_some() && line != start) {
+                return Stop::Done;
+            }
+            if self.at_breakpoint() {
+                return Stop::Breakpoint(self.cpu.pc());
+            }
+        }
+    }
+
+    /// File and line of `addr`, if there is debug info for it.
+    fn line_at(&self, addr: u16) -> Option<(String, usize)> {
+        let location = self.debug_info.as_ref()?.location(addr)?;
+        Some((location.file.to_string(), location.line))
+    }
+
+    pub fn cont(&mut self) -> Stop {
+        self.run_until(|_| false)
+    }
+
+    /// Read commands from `input` until it ends or `quit` is entered.
+    pub fn repl(&mut self, input: &mut dyn BufRead, out: &mut dyn Write) -> io::Result<()> {
+        self.show_stop(&Stop::Done, out)?;
+        let mut line = String::new();
+        loop {
+            write!(out, "(6502) ")?;
+            out.flush()?;
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(out)?;
+                return Ok(());
+            }
+            if !self.execute(line.trim(), out)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Execute a debugger command, writing its output to `out`. Returns `false` if the command
+    /// was `quit`.
+    pub fn execute(&mut self, line: &str, out: &mut dyn Write) -> io::Result<bool> {
+        let line = if line.is_empty() {
+            // Repeat the last command
+            match self.history.last() {
+                Some(last) => last.clone(),
+                None => return Ok(true),
+            }
+        } else if let Some(n) = line.strip_prefix('!') {
+            match n.parse::<usize>().ok().and_then(|n| self.history.get(n)) {
+                Some(command) => command.clone(),
+                None => {
+                    writeln!(out, "No command {n} in history")?;
+                    return Ok(true);
+                }
+            }
+        } else {
+            line.to_string()
+        };
+        if self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match self.command(&words, out) {
+            Ok(keep_going) => Ok(keep_going),
+            Err(CommandError::Io(err)) => Err(err),
+            Err(CommandError::Usage(msg)) => {
+                writeln!(out, "{msg}")?;
+                Ok(true)
+            }
+        }
+    }
+
+    fn command(&mut self, words: &[&str], out: &mut dyn Write) -> Result<bool, CommandError> {
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(true),
+        };
+
+        match command {
+            "h" | "help" => writeln!(out, "{HELP}")?,
+            "q" | "quit" => return Ok(false),
+            "s" | "step" => {
+                let count = match args.first() {
+                    Some(n) => n
+                        .parse::<usize>()
+                        .map_err(|e| format!("Invalid count \"{n}\": {e}"))?,
+                    None => 1,
+                };
+                let mut stop = Stop::Done;
+                for _ in 0..count {
+                    stop = self.step();
+                    if stop != Stop::Done {
+                        break;
+                    }
+                }
+                self.show_stop(&stop, out)?;
+            }
+            "n" | "next" => {
+                let stop = self.step_over();
+                self.show_stop(&stop, out)?;
+            }
+            "fin" | "finish" => {
+                let stop = self.step_out();
+                self.show_stop(&stop, out)?;
+            }
+            "sl" | "stepline" => {
+                let stop = self.step_line(false);
+                self.show_stop(&stop, out)?;
+            }
+            "nl" | "nextline" => {
+                let stop = self.step_line(true);
+                self.show_stop(&stop, out)?;
+            }
+            "c" | "continue" => {
+                let stop = self.cont();
+                self.show_stop(&stop, out)?;
+            }
+            "b" | "break" => match args {
+                [] if self.breakpoints.is_empty() => writeln!(out, "No breakpoints")?,
+                [] => {
+                    for (addr, breakpoint) in &self.breakpoints {
+                        let condition = match &breakpoint.condition {
+                            Some(condition) => format!(" if {condition}"),
+                            None => String::new(),
+                        };
+                        writeln!(
+                            out,
+                            "  {}{condition}, hit {} times",
+                            self.addr_text(*addr),
+                            breakpoint.hits
+                        )?;
+                    }
+                }
+                [location, rest @ ..] => {
+                    let condition = match rest {
+                        [] => None,
+                        ["if", condition @ ..] if !condition.is_empty() => Some(
+                            Expr::parse(&condition.join(" "), &self.symbols)
+                                .map_err(|e| e.to_string())?,
+                        ),
+                        _ => return Err("Usage: break ADDR [if EXPR]".into()),
+                    };
+                    let addrs = if location.contains(':') {
+                        self.resolve_line(location)?
+                    } else {
+                        vec![resolve(&self.symbols, location)?]
+                    };
+                    for addr in addrs {
+                        self.add_breakpoint(addr, condition.clone());
+                        writeln!(out, "Breakpoint set at {}", self.addr_text(addr))?;
+                    }
+                }
+            },
+            "d" | "delete" => match args.first() {
+                Some(&"all") => self.breakpoints.clear(),
+                Some(addr) => {
+                    let addr = resolve(&self.symbols, addr)?;
+                    if !self.remove_breakpoint(addr) {
+                        writeln!(out, "No breakpoint at ${addr:04X}")?;
+                    }
+                }
+                None => return Err("Usage: delete ADDR|all".into()),
+            },
+            "r" | "regs" => self.show_registers(out)?,
+            "set" => {
+                let (register, value) = match args {
+                    [register, value] => (*register, resolve(&self.symbols, value)?),
+                    _ => return Err("Usage: set REG VALUE".into()),
+                };
+                let register = Register::parse(register)
+                    .ok_or_else(|| format!("Unknown register \"{register}\""))?;
+                match register {
+                    Register::Pc => self.cpu.set_pc(value),
+                    // The stack always lives in page 1, so $1FD and $FD are the same SP
+                    Register::Sp => self.cpu.set_sp(value as u8),
+                    _ => {
+                        let value = u8::try_from(value)
+                            .map_err(|_| format!("${value:X} doesn't fit in a byte"))?;
+                        match register {
+                            Register::A => self.cpu.set_ac(value),
+                            Register::X => self.cpu.set_x(value),
+                            Register::Y => self.cpu.set_y(value),
+                            _ => self.cpu.set_p(value),
+                        }
+                    }
+                }
+                self.show_registers(out)?;
+            }
+            "m" | "mem" => {
+                let (addr, len) = match args {
+                    [addr] => (resolve(&self.symbols, addr)?, DEFAULT_DUMP_LEN),
+                    [addr, len] => (resolve(&self.symbols, addr)?, parse_hex(len)?),
+                    _ => return Err("Usage: mem ADDR [LEN]".into()),
+                };
+                self.dump(addr, len, out)?;
+            }
+            "w" | "write" => {
+                let addr = match args.first() {
+                    Some(addr) if args.len() > 1 => resolve(&self.symbols, addr)?,
+                    _ => return Err("Usage: write ADDR VALUE..".into()),
+                };
+                let bytes = args[1..]
+                    .iter()
+                    .map(|b| parse_byte(b))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                for (offset, b) in bytes.iter().enumerate() {
+                    self.cpu
+                        .mem
+                        .write_byte(addr.wrapping_add(offset as u16), *b);
+                }
+            }
+            "u" | "disasm" => {
+                let instructions = match args {
+                    [] => self.around_pc(DEFAULT_DISASM_LEN),
+                    [addr] => self.disassemble(resolve(&self.symbols, addr)?, DEFAULT_DISASM_LEN),
+                    [addr, n] => {
+                        let n = n
+                            .parse::<usize>()
+                            .map_err(|e| format!("Invalid count \"{n}\": {e}"))?;
+                        self.disassemble(resolve(&self.symbols, addr)?, n)
+                    }
+                    _ => return Err("Usage: disasm [ADDR] [N]".into()),
+                };
+                for inst in &instructions {
+                    self.show_instruction(inst, out)?;
+                }
+            }
+            "l" | "list" => self.list(out)?,
+            "watch" if !args.is_empty() => {
+                let watch = self.parse_expr(args)?;
+                writeln!(
+                    out,
+                    "{}: {watch} = {}",
+                    self.watches.len(),
+                    watch.format_value(watch.eval(&self.cpu, 0))
+                )?;
+                self.add_watch(watch);
+            }
+            "watch" => return Err("Usage: watch EXPR".into()),
+            "unwatch" => {
+                let n = args.first().and_then(|n| n.parse::<usize>().ok());
+                match n {
+                    Some(n) if n < self.watches.len() => {
+                        self.watches.remove(n);
+                    }
+                    _ => return Err("Usage: unwatch N".into()),
+                }
+            }
+            "p" | "print" if !args.is_empty() => {
+                let expr = self.parse_expr(args)?;
+                let value = expr.eval(&self.cpu, 0);
+                writeln!(out, "{} ({value})", expr.format_value(value))?;
+            }
+            "p" | "print" => return Err("Usage: print EXPR".into()),
+            "wp" if !args.is_empty() => {
+                let watchpoint = Watchpoint::parse(&args.join(" "), &self.symbols)?;
+                writeln!(
+                    out,
+                    "Watchpoint {}: {watchpoint}",
+                    self.watcher.borrow().watchpoints.len()
+                )?;
+                self.add_watchpoint(watchpoint);
+            }
+            "wp" if self.watcher.borrow().watchpoints.is_empty() => {
+                writeln!(out, "No watchpoints")?
+            }
+            "wp" => {
+                for (n, watchpoint) in self.watcher.borrow().watchpoints.iter().enumerate() {
+                    writeln!(out, "{n}: {watchpoint}")?;
+                }
+            }
+            "unwp" => {
+                let n = args.first().and_then(|n| n.parse::<usize>().ok());
+                match n {
+                    Some(n) if n < self.watcher.borrow().watchpoints.len() => {
+                        self.watcher.borrow_mut().watchpoints.remove(n);
+                    }
+                    _ => return Err("Usage: unwp N".into()),
+                }
+            }
+            "history" => {
+                for (n, command) in self.history.iter().enumerate() {
+                    writeln!(out, "{n:>4}  {command}")?;
+                }
+            }
+            _ => writeln!(out, "Unknown command \"{command}\", try \"help\"")?,
+        }
+        Ok(true)
+    }
+
+    fn show_stop(&mut self, stop: &Stop, out: &mut dyn Write) -> io::Result<()> {
+        for violation in self.stack_warnings.drain(..) {
+            writeln!(out, "Warning: {violation}")?;
+        }
+        match stop {
+            Stop::Done => {}
+            Stop::Breakpoint(addr) => writeln!(out, "Breakpoint at {}", self.addr_text(*addr))?,
+            Stop::Stuck(addr) => writeln!(out, "Stuck in a loop at {}", self.addr_text(*addr))?,
+            Stop::Watchpoint(access) => writeln!(out, "Watchpoint: {access}")?,
+            Stop::Error(err) => writeln!(out, "Stopped: {err}")?,
+            Stop::Stack(violation) => writeln!(out, "{violation}")?,
+        }
+        if let Some(info) = &self.debug_info {
+            if let Some(location) = info.location(self.cpu.pc()) {
+                match info.function(self.cpu.pc()).or(info.scope(self.cpu.pc())) {
+                    Some(name) => writeln!(out, "{location} in {name}")?,
+                    None => writeln!(out, "{location}")?,
+                }
+            }
+        }
+        if let Some(inst) = self.disassemble(self.cpu.pc(), 1).first() {
+            self.show_instruction(inst, out)?;
+        }
+        self.show_registers(out)?;
+        for (n, watch) in self.watches.iter().enumerate() {
+            let value = watch.eval(&self.cpu, 0);
+            writeln!(out, "{n}: {watch} = {}", watch.format_value(value))?;
+        }
+        Ok(())
+    }
+
+    fn show_registers(&self, out: &mut dyn Write) -> io::Result<()> {
+        let flags = flags_text(self.cpu.p());
+        writeln!(
+            out,
+            "PC={} A=${:02X} X=${:02X} Y=${:02X} SP=${:04X} P={flags} CYC={}",
+            self.addr_text(self.cpu.pc()),
+            self.cpu.ac(),
+            self.cpu.x(),
+            self.cpu.y(),
+            self.cpu.sp(),
+            self.cpu.cycle_count()
+        )
+    }
+
+    fn show_instruction(&self, inst: &Instruction, out: &mut dyn Write) -> io::Result<()> {
+        let marker = if inst.addr == self.cpu.pc() {
+            "=>"
+        } else if self.breakpoints.contains_key(&inst.addr) {
+            " *"
+        } else {
+            "  "
+        };
+        if let Some(label) = self.symbols.name_of(inst.addr) {
+            writeln!(out, "{label}:")?;
+        }
+        let bytes: Vec<String> = inst.bytes.iter().map(|b| format!("{b:02X}")).collect();
+        writeln!(
+            out,
+            "{marker} ${:04X}  {:<8}  {}",
+            inst.addr,
+            bytes.join(" "),
+            inst.text(Some(&self.symbols))
+        )
+    }
+
+    fn parse_expr(&self, words: &[&str]) -> Result<Expr, String> {
+        Expr::parse(&words.join(" "), &self.symbols).map_err(|e| e.to_string())
+    }
+
+    /// Addresses of the code for `FILE:LINE`.
+    fn resolve_line(&self, location: &str) -> Result<Vec<u16>, String> {
+        let info = self
+            .debug_info
+            .as_ref()
+            .ok_or("No debug info loaded, FILE:LINE breakpoints need --dbgfile")?;
+        let (file, line) = location
+            .rsplit_once(':')
+            .and_then(|(file, line)| Some((file, line.parse::<usize>().ok()?)))
+            .ok_or_else(|| format!("Invalid location \"{location}\""))?;
+        info.addresses(file, line)
+            .map(|(_, addrs)| addrs)
+            .ok_or_else(|| format!("No code for {location}"))
+    }
+
+    /// Print the source lines around the one at PC, read from disk.
+    fn list(&self, out: &mut dyn Write) -> Result<(), CommandError> {
+        let (file, line) = self
+            .line_at(self.cpu.pc())
+            .ok_or("No source line for the current PC")?;
+        let text = std::fs::read_to_string(&file).map_err(|e| format!("{file}: {e}"))?;
+        let first = line.saturating_sub(LIST_CONTEXT).max(1);
+        for (n, source) in text
+            .lines()
+            .enumerate()
+            .skip(first - 1)
+            .take(LIST_CONTEXT * 2 + 1)
+        {
+            let marker = if n + 1 == line { "=>" } else { "  " };
+            writeln!(out, "{marker} {:>5}  {source}", n + 1)?;
+        }
+        Ok(())
+    }
+
+    /// `$HHLL`, followed by the closest symbol if there is one.
+    fn addr_text(&self, addr: u16) -> String {
+        let name = self.symbols.describe(addr);
+        if name.starts_with('$') {
+            format!("${addr:04X}")
+        } else {
+            format!("${addr:04X} <{name}>")
+        }
+    }
+
+    fn dump(&self, addr: u16, len: u16, out: &mut dyn Write) -> io::Result<()> {
+        let bytes: Vec<u8> = (0..len)
+            .map(|offset| self.cpu.mem.peek(addr.wrapping_add(offset)))
+            .collect();
+        for (row, chunk) in bytes.chunks(16).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{b:02X}")).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|b| {
+                    if b.is_ascii_graphic() || *b == b' ' {
+                        *b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            writeln!(
+                out,
+                "${:04X}  {:<47}  {ascii}",
+                addr.wrapping_add(row as u16 * 16),
+                hex.join(" ")
+            )?;
+        }
+        Ok(())
+    }
+
+    fn disassemble(&self, addr: u16, count: usize) -> Vec<Instruction> {
+        let read = |addr: u16| Some(self.cpu.mem.peek(addr));
+        let mut instructions = Vec::with_capacity(count);
+        let mut addr = addr;
+        for _ in 0..count {
+            let inst = self.disassembler.decode(addr, read).unwrap();
+            addr = addr.wrapping_add(inst.len());
+            instructions.push(inst);
+        }
+        instructions
+    }
+
+    /// Disassemble `count` instructions starting at PC, preceded by the ones that were just
+    /// executed in a straight line before reaching it.
+    fn around_pc(&self, count: usize) -> Vec<Instruction> {
+        let mut before = vec![];
+        let mut next = self.cpu.pc();
+        for addr in self.recent.iter().rev().take(3) {
+            let inst = self.disassemble(*addr, 1).remove(0);
+            if addr.wrapping_add(inst.len()) != next {
+                break;
+            }
+            next = *addr;
+            before.push(inst);
+        }
+        before.reverse();
+        before.extend(self.disassemble(self.cpu.pc(), count));
+        before
+    }
+}
+
+enum CommandError {
+    Io(io::Error),
+    /// Bad arguments, reported to the user
+    Usage(String),
+}
+
+impl From<io::Error> for CommandError {
+    fn from(err: io::Error) -> Self {
+        CommandError::Io(err)
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(msg: String) -> Self {
+        CommandError::Usage(msg)
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(msg: &str) -> Self {
+        CommandError::Usage(msg.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::DeviceMemory;
+    use crate::error::StackViolationKind;
+    use crate::timer::Timer;
+    use crate::watchpoint::AccessKind;
+    use crate::SimpleMemory;
+    use mini6502_macros::asm6502;
+
+    fn debugger(bytes: &[u8]) -> Debugger<SimpleMemory> {
+        Debugger::new(Cpu::with_mem(SimpleMemory::from_rom(bytes)))
+    }
+
+    fn run(debugger: &mut Debugger<SimpleMemory>, line: &str) -> String {
+        let mut out = vec![];
+        assert!(debugger.execute(line, &mut out).unwrap());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let program = asm6502! {
+            ldx #$00;
+            jsr sub;
+            inx;
+            brk;
+            sub: jsr inner;
+            ldy #$07;
+            rts;
+            inner: lda #$42;
+            rts
+        };
+        let mut debugger = debugger(&program.bytes);
+        debugger.cpu.set_sp(0xFF);
+
+        debugger.step();
+        assert_eq!(debugger.step_over(), Stop::Done);
+        assert_eq!(debugger.cpu.pc(), program.symbol("sub") - 2);
+        assert_eq!(debugger.cpu.ac(), 0x42);
+        assert_eq!(debugger.cpu.y(), 0x07);
+        assert_eq!(debugger.cpu.sp(), 0x01FF);
+
+        debugger.cpu.set_pc(0x0002);
+        debugger.step();
+        debugger.step();
+        assert_eq!(debugger.cpu.pc(), program.symbol("inner"));
+        assert_eq!(debugger.step_out(), Stop::Done);
+        assert_eq!(debugger.cpu.pc(), program.symbol("sub") + 3);
+    }
+
+    #[test]
+    fn test_breakpoints_and_stuck_loops() {
+        let program = asm6502! {
+            ldx #$03;
+            loop: dex;
+            bne loop;
+            done: jmp done
+        };
+        let mut debugger = debugger(&program.bytes);
+
+        debugger.symbols = program.symbols.iter().copied().collect();
+        assert!(run(&mut debugger, "b loop").contains("$0002 <loop>"));
+        assert_eq!(debugger.cont(), Stop::Breakpoint(program.symbol("loop")));
+        assert_eq!(debugger.cont(), Stop::Breakpoint(program.symbol("loop")));
+        assert_eq!(debugger.cpu.x(), 0x02);
+
+        run(&mut debugger, "delete all");
+        assert_eq!(debugger.cont(), Stop::Stuck(program.symbol("done")));
+        assert_eq!(debugger.cpu.x(), 0x00);
+    }
+
+    #[test]
+    fn test_idle_loop_interrupt() {
+        // Wait in place for a timer IRQ after 100 cycles
+        let program = asm6502! {
+            .org $F000;
+            reset: lda #$64;
+            sta $8000;
+            lda #$05;
+            sta $8005;
+            cli;
+            wait: jmp wait;
+            irq: lda #$80;
+            sta $8006;
+            rti;
+            .res $FFFC - *;
+            .word reset;
+            .word irq
+        };
+        let mut rom = vec![0x00; 0xF000];
+        rom.extend(&program.bytes);
+        let mut mem = DeviceMemory::new(SimpleMemory::from_rom(&rom));
+        mem.map(0x8000..=0x8007, Box::new(Timer::new()));
+        let mut cpu = Cpu::with_mem(mem);
+        cpu.set_pc(program.symbol("reset"));
+        let mut debugger = Debugger::new(cpu);
+        debugger.add_breakpoint(program.symbol("irq"), None);
+        assert_eq!(debugger.cont(), Stop::Breakpoint(program.symbol("irq")));
+        assert!(debugger.cpu.cycle_count() >= 100);
+
+        // Stepping out of the handler stops after its RTI
+        assert_eq!(debugger.step_out(), Stop::Done);
+        assert_eq!(debugger.cpu.pc(), program.symbol("wait"));
+    }
+
+    #[test]
+    fn test_source_lines() {
+        // ldx #$03; loop: dex; bne loop; nop; jmp *
+        let mut debugger = debugger(&[0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0xEA, 0x4C, 0x06, 0x00]);
+        let info = DebugInfo::parse(
+            "seg\tid=0,name=\"CODE\",start=0x0000,size=9
+file\tid=0,name=\"src/main.s\",size=1,mtime=0,mod=0
+span\tid=0,seg=0,start=0,size=2
+span\tid=1,seg=0,start=2,size=3
+span\tid=2,seg=0,start=5,size=1
+line\tid=0,file=0,line=10,span=0
+line\tid=1,file=0,line=11,span=1
+line\tid=2,file=0,line=12,span=2
+",
+        )
+        .unwrap();
+        debugger.debug_info = Some(info);
+
+        // The loop is a single line, so it runs to completion
+        assert_eq!(debugger.step_line(false), Stop::Done);
+        assert_eq!(debugger.cpu.pc(), 0x0002);
+        assert!(run(&mut debugger, "sl").starts_with("src/main.s:12\n"));
+        assert_eq!(debugger.cpu.x(), 0x00);
+
+        debugger.cpu.set_pc(0x0000);
+        assert!(run(&mut debugger, "b main.s:11").contains("$0002"));
+        assert_eq!(debugger.cont(), Stop::Breakpoint(0x0002));
+        assert!(run(&mut debugger, "b main.s:20").contains("No code for main.s:20"));
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut debugger = debugger(&[0xEA, 0xEA, 0xEA]);
+
+        run(&mut debugger, "set a 7f");
+        run(&mut debugger, "w 200 de ad");
+        assert_eq!(debugger.cpu.ac(), 0x7F);
+        assert_eq!(debugger.cpu.mem.read_byte(0x0201), 0xAD);
+        assert!(run(&mut debugger, "m 200 2").starts_with("$0200  DE AD"));
+
+        run(&mut debugger, "watch [$200].w");
+        let out = run(&mut debugger, "step");
+        assert!(out.contains("=> $0001  EA        NOP"));
+        assert!(out.contains("0: [$0200].w = $ADDE"));
+
+        // Repeat the last command
+        run(&mut debugger, "");
+        assert_eq!(debugger.cpu.pc(), 0x0002);
+        assert!(run(&mut debugger, "u").starts_with("   $0000  EA        NOP\n"));
+        assert!(run(&mut debugger, "history").contains("   3  watch [$200].w\n   4  step\n   5  u"));
+
+        assert!(run(&mut debugger, "set q 1").contains("Unknown register"));
+        assert_eq!(
+            run(&mut debugger, "watch [$12"),
+            "Unexpected end of expression\n"
+        );
+    }
+
+    #[test]
+    fn test_conditions_and_watchpoints() {
+        let program = asm6502! {
+            ldx #$05;
+            loop: txa;
+            sta $0200,x;
+            dex;
+            bne loop;
+            lda $0203;
+            done: jmp done
+        };
+        let mut debugger = debugger(&program.bytes);
+        debugger.symbols = program.symbols.iter().copied().collect();
+
+        run(&mut debugger, "b loop if x == 2 || hits == 2");
+        assert_eq!(debugger.cont(), Stop::Breakpoint(program.symbol("loop")));
+        assert_eq!(debugger.cpu.x(), 0x04);
+        assert_eq!(debugger.cont(), Stop::Breakpoint(program.symbol("loop")));
+        assert_eq!(debugger.cpu.x(), 0x02);
+        assert!(run(&mut debugger, "b")
+            .contains("<loop> if ((x == $0002) || (hits == $0002)), hit 4 times"));
+
+        run(&mut debugger, "delete all");
+        run(&mut debugger, "wp 200..202");
+        run(&mut debugger, "wp r 203");
+        assert!(run(&mut debugger, "c").starts_with("Watchpoint: write $01 to $0201\n"));
+        // Reads by the debugger itself don't count
+        assert_eq!(run(&mut debugger, "p [$203] + 1"), "$0004 (4)\n");
+        assert_eq!(
+            debugger.cont(),
+            Stop::Watchpoint(Access {
+                addr: 0x0203,
+                kind: AccessKind::Read,
+                value: 0x03,
+            })
+        );
+
+        run(&mut debugger, "unwp 1");
+        run(&mut debugger, "wp x done");
+        assert_eq!(
+            debugger.cont(),
+            Stop::Watchpoint(Access {
+                addr: program.symbol("done"),
+                kind: AccessKind::Execute,
+                value: 0x4C,
+            })
+        );
+    }
+
+    #[test]
+    fn test_stack_checker() {
+        let program = asm6502! {
+            jsr leaky;
+            done: jmp done;
+            leaky: pha;
+            rts
+        };
+        let mut warning = debugger(&program.bytes);
+        warning.stack_checker = Some(StackChecker::new());
+        let out = run(&mut warning, "s 3");
+        assert!(out
+            .starts_with("Warning: Stack check at $0007: returned with 1 byte(s) still pushed\n"));
+
+        let mut error = debugger(&program.bytes);
+        let mut checker = StackChecker::new();
+        checker.severity = Severity::Error;
+        error.stack_checker = Some(checker);
+        assert_eq!(
+            error.cont(),
+            Stop::Stack(StackViolation {
+                pc: program.symbol("leaky") + 1,
+                kind: StackViolationKind::Unbalanced { bytes: 1 },
+            })
+        );
+    }
+}
@@ -0,0 +1,247 @@
+use crate::memory::Memory;
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+/// A memory mapped peripheral, like the chips in [`via`](crate::via).
+pub trait Device {
+    /// Read the register at offset `reg` into the device's range. Reading registers can have
+    /// side effects, like clearing interrupt flags.
+    fn read(&mut self, reg: u16) -> u8;
+
+    /// What `read` would return, without its side effects, for tools looking at memory.
+    fn peek(&self, reg: u16) -> u8;
+
+    fn write(&mut self, reg: u16, value: u8);
+
+    /// Let `cycles` cpu cycles go by.
+    fn tick(&mut self, _cycles: u8) {}
+
+    /// Whether the device is pulling the IRQ line.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Whether the device is pulling the NMI line.
+    fn nmi(&self) -> bool {
+        false
+    }
+}
+
+/// Lets the caller keep a handle to a device mapped into memory, to drive its pins.
+impl<T: Device> Device for Rc<RefCell<T>> {
+    fn read(&mut self, reg: u16) -> u8 {
+        self.borrow_mut().read(reg)
+    }
+
+    fn peek(&self, reg: u16) -> u8 {
+        self.borrow().peek(reg)
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        self.borrow_mut().write(reg, value)
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.borrow_mut().tick(cycles)
+    }
+
+    fn irq(&self) -> bool {
+        self.borrow().irq()
+    }
+
+    fn nmi(&self) -> bool {
+        self.borrow().nmi()
+    }
+}
+
+struct Mapping {
+    range: RangeInclusive<u16>,
+    // Memory reads take `&self`
+    device: RefCell<Box<dyn Device>>,
+}
+
+/// Memory with devices mapped over parts of it. Accesses to a device's range go to the device,
+/// with the offset into the range as the register, everything else goes to `inner`. The IRQ
+/// and NMI lines are the devices' ORed together.
+///
+/// [`Memory::peek`] peeks at the devices, so tools looking at memory leave them alone.
+///
+/// ```rust
+/// use mini6502::device::DeviceMemory;
+/// use mini6502::via::Via;
+/// use mini6502::{Cpu, SimpleMemory};
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// // lda #$FF; sta $6002; lda #$2A; sta $6000
+/// let rom = [0xA9, 0xFF, 0x8D, 0x02, 0x60, 0xA9, 0x2A, 0x8D, 0x00, 0x60];
+/// let mut mem = DeviceMemory::new(SimpleMemory::from_rom(&rom));
+/// let via = Rc::new(RefCell::new(Via::new()));
+/// mem.map(0x6000..=0x600F, Box::new(via.clone()));
+///
+/// let mut cpu = Cpu::with_mem(mem);
+/// for _ in 0..4 {
+///     cpu.step().unwrap();
+/// }
+/// assert_eq!(via.borrow().port_b(), 0x2A);
+/// ```
+pub struct DeviceMemory<M> {
+    pub inner: M,
+    mappings: Vec<Mapping>,
+}
+
+impl<M> DeviceMemory<M>
+where
+    M: Memory,
+{
+    pub fn new(inner: M) -> Self {
+        DeviceMemory {
+            inner,
+            mappings: vec![],
+        }
+    }
+
+    /// Map `device` over `range`. Where ranges overlap the device mapped last wins.
+    pub fn map(&mut self, range: RangeInclusive<u16>, device: Box<dyn Device>) {
+        self.mappings.insert(
+            0,
+            Mapping {
+                range,
+                device: RefCell::new(device),
+            },
+        );
+    }
+
+    fn mapping(&self, addr: u16) -> Option<&Mapping> {
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.range.contains(&addr))
+    }
+}
+
+impl<M> Memory for DeviceMemory<M>
+where
+    M: Memory,
+{
+    fn write_byte(&mut self, addr: u16, byte: u8) {
+        match self.mapping(addr) {
+            Some(mapping) => mapping
+                .device
+                .borrow_mut()
+                .write(addr - mapping.range.start(), byte),
+            None => self.inner.write_byte(addr, byte),
+        }
+    }
+
+    fn read_byte(&self, addr: u16) -> u8 {
+        match self.mapping(addr) {
+            Some(mapping) => mapping
+                .device
+                .borrow_mut()
+                .read(addr - mapping.range.start()),
+            None => self.inner.read_byte(addr),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match self.mapping(addr) {
+            Some(mapping) => mapping.device.borrow().peek(addr - mapping.range.start()),
+            None => self.inner.peek(addr),
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.inner.tick(cycles);
+        for mapping in &mut self.mappings {
+            mapping.device.get_mut().tick(cycles);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.inner.irq() || self.mappings.iter().any(|m| m.device.borrow().irq())
+    }
+
+    fn nmi(&self) -> bool {
+        self.inner.nmi() || self.mappings.iter().any(|m| m.device.borrow().nmi())
+    }
+
+    fn can_interrupt(&self) -> bool {
+        self.inner.can_interrupt() || !self.mappings.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::SimpleMemory;
+
+    /// Remembers the last write and reads back its register number
+    #[derive(Default)]
+    struct Registers {
+        written: Option<(u16, u8)>,
+        cycles: usize,
+    }
+
+    impl Device for Registers {
+        fn read(&mut self, reg: u16) -> u8 {
+            reg as u8
+        }
+
+        fn peek(&self, reg: u16) -> u8 {
+            reg as u8
+        }
+
+        fn write(&mut self, reg: u16, value: u8) {
+            self.written = Some((reg, value));
+        }
+
+        fn tick(&mut self, cycles: u8) {
+            self.cycles += cycles as usize;
+        }
+
+        fn irq(&self) -> bool {
+            self.cycles >= 10
+        }
+    }
+
+    #[test]
+    fn test_mapping() {
+        let mut mem = DeviceMemory::new(SimpleMemory::from_rom(&[0x11; 0x20]));
+        let low = Rc::new(RefCell::new(Registers::default()));
+        let high = Rc::new(RefCell::new(Registers::default()));
+        mem.map(0x0008..=0x000F, Box::new(low.clone()));
+        mem.map(0x000C..=0x0013, Box::new(high.clone()));
+        let read: Vec<u8> = (0x0006..0x0016).map(|addr| mem.read_byte(addr)).collect();
+        assert_eq!(
+            read,
+            [0x11, 0x11, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 6, 7, 0x11, 0x11]
+        );
+
+        mem.write_byte(0x000A, 0x42);
+        mem.write_byte(0x000C, 0x43);
+        mem.write_byte(0x0014, 0x44);
+        assert_eq!(low.borrow().written, Some((0x0002, 0x42)));
+        assert_eq!(high.borrow().written, Some((0x0000, 0x43)));
+        assert_eq!(mem.inner.read_byte(0x0014), 0x44);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mem = DeviceMemory::new(SimpleMemory::from_rom(&[0x58, 0xEA, 0xEA, 0xEA, 0xEA]));
+        mem.inner.load(0xFFFE, &[0x00, 0x80]);
+        let device = Rc::new(RefCell::new(Registers::default()));
+        mem.map(0x6000..=0x6000, Box::new(device.clone()));
+        let mut cpu = crate::cpu::Cpu::with_mem(mem);
+        // cli and 4 nops make 10 cycles
+        for _ in 0..5 {
+            assert_eq!(cpu.pending_interrupt(), None);
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.pending_interrupt(), Some(0xFFFE));
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc(), 0x8000);
+        assert_eq!(cpu.mem.inner.read_byte(0x01FD), 0x20);
+        assert_eq!(device.borrow().cycles, 17);
+    }
+}
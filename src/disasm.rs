@@ -0,0 +1,577 @@
+use crate::memory::Memory;
+use crate::opc::{self, AddressMode};
+use crate::symbols::SymbolTable;
+use std::fmt::Display;
+
+/// Selects which opcodes are decoded on top of the documented NMOS set.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    /// Decode the 65C02 instruction set (WDC, including the Rockwell bit instructions)
+    pub cmos: bool,
+    /// Decode undocumented opcodes: the "illegal" NMOS instructions or, with `cmos`, the
+    /// 65C02's reserved NOPs
+    pub illegal: bool,
+}
+
+/// Addressing modes of the instructions the disassembler, assembler and [`isa`](crate::isa)
+/// know: the cpu's [`AddressMode`]s and the ones the 65C02 adds.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum Mode {
+    ACC,
+    ABS,
+    ABSX,
+    ABSY,
+    IMM,
+    IMPL,
+    IND,
+    INDX,
+    INDY,
+    REL,
+    ZPG,
+    ZPGX,
+    ZPGY,
+    /// 65C02 only: ($LL)
+    ZPGIND,
+    /// 65C02 only: ($HHLL,X), used by JMP
+    ABSINDX,
+    /// 65C02 only: $LL,$BB, used by BBR and BBS
+    ZPGREL,
+}
+
+impl Mode {
+    /// Bytes of an instruction in this mode, opcode included.
+    pub const fn instr_len(self) -> u16 {
+        match self {
+            Mode::ACC | Mode::IMPL => 1,
+            Mode::IMM
+            | Mode::INDX
+            | Mode::INDY
+            | Mode::REL
+            | Mode::ZPG
+            | Mode::ZPGX
+            | Mode::ZPGY
+            | Mode::ZPGIND => 2,
+            Mode::ABS | Mode::ABSX | Mode::ABSY | Mode::IND | Mode::ABSINDX | Mode::ZPGREL => 3,
+        }
+    }
+}
+
+impl From<AddressMode> for Mode {
+    fn from(mode: AddressMode) -> Self {
+        match mode {
+            AddressMode::ACC => Mode::ACC,
+            AddressMode::ABS => Mode::ABS,
+            AddressMode::ABSX => Mode::ABSX,
+            AddressMode::ABSY => Mode::ABSY,
+            AddressMode::IMM => Mode::IMM,
+            AddressMode::IMPL => Mode::IMPL,
+            AddressMode::IND => Mode::IND,
+            AddressMode::INDX => Mode::INDX,
+            AddressMode::INDY => Mode::INDY,
+            AddressMode::REL => Mode::REL,
+            AddressMode::ZPG => Mode::ZPG,
+            AddressMode::ZPGX => Mode::ZPGX,
+            AddressMode::ZPGY => Mode::ZPGY,
+        }
+    }
+}
+
+/// Entry of the opcode decoding table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub mode: Mode,
+    pub documented: bool,
+}
+
+const fn op(mnemonic: &'static str, mode: Mode) -> Opcode {
+    Opcode {
+        mnemonic,
+        mode,
+        documented: true,
+    }
+}
+
+const fn undoc(mnemonic: &'static str, mode: Mode) -> Opcode {
+    Opcode {
+        mnemonic,
+        mode,
+        documented: false,
+    }
+}
+
+/// Build the decoding table for `options`. Documented NMOS opcodes come straight from the table
+/// the cpu executes.
+pub(crate) fn opcode_table(options: Options) -> [Option<Opcode>; 0x100] {
+    use Mode::*;
+
+    let mut table = [None; 0x100];
+    for (byte, op_mode) in opc::init_opc_array().iter().enumerate() {
+        if let Some(opc::OpMode(inst, mode, _cycles)) = op_mode {
+            table[byte] = Some(op(inst.mnemonic(), (*mode).into()));
+        }
+    }
+
+    if options.cmos {
+        let cmos_ops = [
+            (0x12, op("ORA", ZPGIND)),
+            (0x32, op("AND", ZPGIND)),
+            (0x52, op("EOR", ZPGIND)),
+            (0x72, op("ADC", ZPGIND)),
+            (0x92, op("STA", ZPGIND)),
+            (0xB2, op("LDA", ZPGIND)),
+            (0xD2, op("CMP", ZPGIND)),
+            (0xF2, op("SBC", ZPGIND)),
+            (0x89, op("BIT", IMM)),
+            (0x34, op("BIT", ZPGX)),
+            (0x3C, op("BIT", ABSX)),
+            (0x1A, op("INC", ACC)),
+            (0x3A, op("DEC", ACC)),
+            (0x80, op("BRA", REL)),
+            (0x7C, op("JMP", ABSINDX)),
+            (0x5A, op("PHY", IMPL)),
+            (0x7A, op("PLY", IMPL)),
+            (0xDA, op("PHX", IMPL)),
+            (0xFA, op("PLX", IMPL)),
+            (0x64, op("STZ", ZPG)),
+            (0x74, op("STZ", ZPGX)),
+            (0x9C, op("STZ", ABS)),
+            (0x9E, op("STZ", ABSX)),
+            (0x14, op("TRB", ZPG)),
+            (0x1C, op("TRB", ABS)),
+            (0x04, op("TSB", ZPG)),
+            (0x0C, op("TSB", ABS)),
+            (0xCB, op("WAI", IMPL)),
+            (0xDB, op("STP", IMPL)),
+        ];
+        for (byte, opcode) in cmos_ops {
+            table[byte] = Some(opcode);
+        }
+
+        const RMB: [&str; 8] = [
+            "RMB0", "RMB1", "RMB2", "RMB3", "RMB4", "RMB5", "RMB6", "RMB7",
+        ];
+        const SMB: [&str; 8] = [
+            "SMB0", "SMB1", "SMB2", "SMB3", "SMB4", "SMB5", "SMB6", "SMB7",
+        ];
+        const BBR: [&str; 8] = [
+            "BBR0", "BBR1", "BBR2", "BBR3", "BBR4", "BBR5", "BBR6", "BBR7",
+        ];
+        const BBS: [&str; 8] = [
+            "BBS0", "BBS1", "BBS2", "BBS3", "BBS4", "BBS5", "BBS6", "BBS7",
+        ];
+        for bit in 0..8 {
+            table[(bit << 4) | 0x07] = Some(op(RMB[bit], ZPG));
+            table[((bit + 8) << 4) | 0x07] = Some(op(SMB[bit], ZPG));
+            table[(bit << 4) | 0x0F] = Some(op(BBR[bit], ZPGREL));
+            table[((bit + 8) << 4) | 0x0F] = Some(op(BBS[bit], ZPGREL));
+        }
+
+        if options.illegal {
+            // Every reserved opcode is a NOP on the 65C02, with varying lengths
+            for (byte, mode) in [
+                (0x02, IMM),
+                (0x22, IMM),
+                (0x42, IMM),
+                (0x62, IMM),
+                (0x82, IMM),
+                (0xC2, IMM),
+                (0xE2, IMM),
+                (0x44, ZPG),
+                (0x54, ZPGX),
+                (0xD4, ZPGX),
+                (0xF4, ZPGX),
+                (0x5C, ABS),
+                (0xDC, ABS),
+                (0xFC, ABS),
+            ] {
+                table[byte] = Some(undoc("NOP", mode));
+            }
+            for entry in table.iter_mut().filter(|entry| entry.is_none()) {
+                *entry = Some(undoc("NOP", IMPL));
+            }
+        }
+    } else if options.illegal {
+        // Read-modify-write combos share the ORA/AND/EOR/ADC column layout
+        for (row, mnemonic) in [(0x00, "SLO"), (0x20, "RLA"), (0x40, "SRE"), (0x60, "RRA")]
+            .into_iter()
+            .chain([(0xC0, "DCP"), (0xE0, "ISC")])
+        {
+            for (col, mode) in [
+                (0x03, INDX),
+                (0x07, ZPG),
+                (0x0F, ABS),
+                (0x13, INDY),
+                (0x17, ZPGX),
+                (0x1B, ABSY),
+                (0x1F, ABSX),
+            ] {
+                table[row + col] = Some(undoc(mnemonic, mode));
+            }
+        }
+
+        let illegal_ops = [
+            (0x83, undoc("SAX", INDX)),
+            (0x87, undoc("SAX", ZPG)),
+            (0x8F, undoc("SAX", ABS)),
+            (0x97, undoc("SAX", ZPGY)),
+            (0xA3, undoc("LAX", INDX)),
+            (0xA7, undoc("LAX", ZPG)),
+            (0xAF, undoc("LAX", ABS)),
+            (0xB3, undoc("LAX", INDY)),
+            (0xB7, undoc("LAX", ZPGY)),
+            (0xBF, undoc("LAX", ABSY)),
+            (0xAB, undoc("LXA", IMM)),
+            (0x0B, undoc("ANC", IMM)),
+            (0x2B, undoc("ANC", IMM)),
+            (0x4B, undoc("ALR", IMM)),
+            (0x6B, undoc("ARR", IMM)),
+            (0x8B, undoc("ANE", IMM)),
+            (0xCB, undoc("SBX", IMM)),
+            (0xEB, undoc("SBC", IMM)),
+            (0x93, undoc("SHA", INDY)),
+            (0x9F, undoc("SHA", ABSY)),
+            (0x9B, undoc("TAS", ABSY)),
+            (0x9C, undoc("SHY", ABSX)),
+            (0x9E, undoc("SHX", ABSY)),
+            (0xBB, undoc("LAS", ABSY)),
+            (0x0C, undoc("NOP", ABS)),
+            (0x04, undoc("NOP", ZPG)),
+            (0x44, undoc("NOP", ZPG)),
+            (0x64, undoc("NOP", ZPG)),
+        ];
+        for (byte, opcode) in illegal_ops {
+            table[byte] = Some(opcode);
+        }
+
+        for byte in [0x1A, 0x3A, 0x5A, 0x7A, 0xDA, 0xFA] {
+            table[byte] = Some(undoc("NOP", IMPL));
+        }
+        for byte in [0x80, 0x82, 0x89, 0xC2, 0xE2] {
+            table[byte] = Some(undoc("NOP", IMM));
+        }
+        for byte in [0x14, 0x34, 0x54, 0x74, 0xD4, 0xF4] {
+            table[byte] = Some(undoc("NOP", ZPGX));
+        }
+        for byte in [0x1C, 0x3C, 0x5C, 0x7C, 0xDC, 0xFC] {
+            table[byte] = Some(undoc("NOP", ABSX));
+        }
+        for byte in [
+            0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2,
+        ] {
+            table[byte] = Some(undoc("JAM", IMPL));
+        }
+    }
+
+    table
+}
+
+/// A decoded instruction, or a single `.byte` if the opcode is unknown or its operand is cut
+/// short.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub opcode: Option<Opcode>,
+}
+
+impl Instruction {
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self.opcode {
+            Some(opcode) => opcode.mnemonic,
+            None => ".byte",
+        }
+    }
+
+    /// Raw operand: the byte after the opcode for one byte operands, the little-endian word for
+    /// two byte operands.
+    pub fn operand(&self) -> Option<u16> {
+        match self.bytes.len() {
+            2 => Some(self.bytes[1] as u16),
+            3 => Some(u16::from_le_bytes([self.bytes[1], self.bytes[2]])),
+            _ => None,
+        }
+    }
+
+    /// Address control is transferred to by a branch, `JMP $HHLL` or `JSR`.
+    pub fn target(&self) -> Option<u16> {
+        let opcode = self.opcode?;
+        match opcode.mode {
+            Mode::REL => Some(branch_target(self.addr, 2, self.bytes[1])),
+            Mode::ZPGREL => Some(branch_target(self.addr, 3, self.bytes[2])),
+            Mode::ABS if matches!(opcode.mnemonic, "JMP" | "JSR") => self.operand(),
+            _ => None,
+        }
+    }
+
+    /// Format the operand as assembler source, substituting addresses found in `symbols`.
+    pub fn operand_text(&self, symbols: Option<&SymbolTable>) -> String {
+        let opcode = match self.opcode {
+            Some(opcode) => opcode,
+            None => return format!("${:02X}", self.bytes[0]),
+        };
+
+        let name = |addr: u16, digits: usize| match symbols.and_then(|s| s.name_of(addr)) {
+            Some(name) => name.to_string(),
+            None => format!("${:0digits$X}", addr),
+        };
+        let zpg = || name(self.bytes[1] as u16, 2);
+        let abs = || name(self.operand().unwrap(), 4);
+
+        match opcode.mode {
+            Mode::ACC => "A".to_string(),
+            Mode::IMPL => String::new(),
+            Mode::IMM => format!("#${:02X}", self.bytes[1]),
+            Mode::ZPG => zpg(),
+            Mode::ZPGX => format!("{},X", zpg()),
+            Mode::ZPGY => format!("{},Y", zpg()),
+            Mode::ABS => abs(),
+            Mode::ABSX => format!("{},X", abs()),
+            Mode::ABSY => format!("{},Y", abs()),
+            Mode::IND => format!("({})", abs()),
+            Mode::INDX => format!("({},X)", zpg()),
+            Mode::INDY => format!("({}),Y", zpg()),
+            Mode::ZPGIND => format!("({})", zpg()),
+            Mode::ABSINDX => format!("({},X)", abs()),
+            Mode::REL => name(self.target().unwrap(), 4),
+            Mode::ZPGREL => format!("{},{}", zpg(), name(self.target().unwrap(), 4)),
+        }
+    }
+
+    /// Format the instruction as assembler source, substituting addresses found in `symbols`.
+    pub fn text(&self, symbols: Option<&SymbolTable>) -> String {
+        let operand = self.operand_text(symbols);
+        if operand.is_empty() {
+            self.mnemonic().to_string()
+        } else {
+            format!("{} {}", self.mnemonic(), operand)
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text(None))
+    }
+}
+
+const fn branch_target(addr: u16, len: u16, offset: u8) -> u16 {
+    addr.wrapping_add(len).wrapping_add(offset as i8 as u16)
+}
+
+pub struct Disassembler {
+    table: [Option<Opcode>; 0x100],
+}
+
+impl Disassembler {
+    pub fn new(options: Options) -> Self {
+        Disassembler {
+            table: opcode_table(options),
+        }
+    }
+
+    /// Decode the instruction at `addr`, reading bytes through `read`. `read` returns `None`
+    /// past the end of the available bytes.
+    pub fn decode(&self, addr: u16, read: impl Fn(u16) -> Option<u8>) -> Option<Instruction> {
+        let byte = read(addr)?;
+        let undecoded = Instruction {
+            addr,
+            bytes: vec![byte],
+            opcode: None,
+        };
+
+        let opcode = match self.table[byte as usize] {
+            Some(opcode) => opcode,
+            None => return Some(undecoded),
+        };
+
+        let mut bytes = vec![byte];
+        for i in 1..opcode.mode.instr_len() {
+            match read(addr.wrapping_add(i)) {
+                Some(b) => bytes.push(b),
+                None => return Some(undecoded),
+            }
+        }
+
+        Some(Instruction {
+            addr,
+            bytes,
+            opcode: Some(opcode),
+        })
+    }
+
+    /// Disassemble `bytes` as if they were loaded at `origin`.
+    ///
+    /// ```rust
+    /// use mini6502::disasm::{Disassembler, Options};
+    ///
+    /// let disasm = Disassembler::new(Options::default());
+    /// let lines: Vec<String> = disasm
+    ///     .disassemble(&[0xa2, 0x03, 0xCA, 0xD0, 0xFD], 0x0600)
+    ///     .iter()
+    ///     .map(|inst| inst.to_string())
+    ///     .collect();
+    ///
+    /// assert_eq!(lines, ["LDX #$03", "DEX", "BNE $0602"]);
+    /// ```
+    pub fn disassemble(&self, bytes: &[u8], origin: u16) -> Vec<Instruction> {
+        let read = |addr: u16| bytes.get(addr.wrapping_sub(origin) as usize).copied();
+        let mut instructions = vec![];
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let addr = origin.wrapping_add(offset as u16);
+            let inst = self.decode(addr, read).unwrap();
+            offset += inst.bytes.len();
+            instructions.push(inst);
+        }
+        instructions
+    }
+
+    /// Disassemble the instructions starting in `start..=end`. The last one may extend past
+    /// `end`.
+    pub fn disassemble_memory<M: Memory>(&self, mem: &M, start: u16, end: u16) -> Vec<Instruction> {
+        let read = |addr: u16| Some(mem.peek(addr));
+        let mut instructions = vec![];
+        let mut addr = start as usize;
+        while addr <= end as usize {
+            let inst = self.decode(addr as u16, read).unwrap();
+            addr += inst.bytes.len();
+            instructions.push(inst);
+        }
+        instructions
+    }
+}
+
+/// Format `instructions` as a listing with address, raw bytes and source, adding a label line
+/// wherever an address is found in `symbols`.
+pub fn listing(instructions: &[Instruction], symbols: Option<&SymbolTable>) -> String {
+    let mut out = String::new();
+    for inst in instructions {
+        if let Some(label) = symbols.and_then(|s| s.name_of(inst.addr)) {
+            out.push_str(&format!("{label}:\n"));
+        }
+        let bytes: Vec<String> = inst.bytes.iter().map(|b| format!("{b:02X}")).collect();
+        out.push_str(&format!(
+            "{:04X}  {:<8}  {}\n",
+            inst.addr,
+            bytes.join(" "),
+            inst.text(symbols)
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SimpleMemory;
+
+    fn text(options: Options, bytes: &[u8]) -> Vec<String> {
+        Disassembler::new(options)
+            .disassemble(bytes, 0xC000)
+            .iter()
+            .map(|inst| inst.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_operand_formatting() {
+        let lines = text(
+            Options::default(),
+            &[
+                0x0A, 0xA9, 0x10, 0xB5, 0x20, 0xB6, 0x21, 0xAD, 0x34, 0x12, 0x7D, 0x00, 0x02, 0x79,
+                0xFF, 0x00, 0x6C, 0xFC, 0xFF, 0xA1, 0x40, 0xB1, 0x41, 0xEA,
+            ],
+        );
+        assert_eq!(
+            lines,
+            [
+                "ASL A",
+                "LDA #$10",
+                "LDA $20,X",
+                "LDX $21,Y",
+                "LDA $1234",
+                "ADC $0200,X",
+                "ADC $00FF,Y",
+                "JMP ($FFFC)",
+                "LDA ($40,X)",
+                "LDA ($41),Y",
+                "NOP",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_branch_targets() {
+        let disasm = Disassembler::new(Options::default());
+        let insts = disasm.disassemble(&[0xD0, 0xFE, 0x10, 0x7F, 0x20, 0x00, 0xC0], 0xC000);
+        assert_eq!(insts[0].target(), Some(0xC000));
+        assert_eq!(insts[1].target(), Some(0xC083));
+        assert_eq!(insts[2].target(), Some(0xC000));
+    }
+
+    #[test]
+    fn test_unknown_and_truncated() {
+        // $02 is a JAM on NMOS and LDA abs is cut short
+        assert_eq!(
+            text(Options::default(), &[0x02, 0xAD, 0x00]),
+            [".byte $02", ".byte $AD", "BRK"]
+        );
+        let illegal = Options {
+            illegal: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            text(illegal, &[0x02, 0xA7, 0x10, 0xEB, 0x01, 0xFF, 0x00, 0x02]),
+            ["JAM", "LAX $10", "SBC #$01", "ISC $0200,X"]
+        );
+    }
+
+    #[test]
+    fn test_cmos() {
+        let cmos = Options {
+            cmos: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            text(
+                cmos,
+                &[0xB2, 0x10, 0x7C, 0x00, 0x20, 0x80, 0xFE, 0x1A, 0xFF, 0x10, 0xFD, 0x03]
+            ),
+            [
+                "LDA ($10)",
+                "JMP ($2000,X)",
+                "BRA $C005",
+                "INC A",
+                "BBS7 $10,$C008",
+                ".byte $03"
+            ]
+        );
+        let all = Options {
+            cmos: true,
+            illegal: true,
+        };
+        assert_eq!(text(all, &[0x03, 0x5C, 0x00, 0x00]), ["NOP", "NOP $0000"]);
+    }
+
+    #[test]
+    fn test_symbols_and_memory() {
+        let mem = SimpleMemory::from_rom(&[0x20, 0x06, 0x00, 0x85, 0x10, 0x60, 0xCA, 0x60]);
+        let symbols: SymbolTable = [("sub", 0x0006), ("tmp", 0x0010)].into_iter().collect();
+        let insts = Disassembler::new(Options::default()).disassemble_memory(&mem, 0x0000, 0x0007);
+        assert_eq!(insts[0].text(Some(&symbols)), "JSR sub");
+        assert_eq!(insts[1].text(Some(&symbols)), "STA tmp");
+        assert_eq!(
+            listing(&insts[2..4], Some(&symbols)),
+            "0005  60        RTS\nsub:\n0006  CA        DEX\n"
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use std::error::Error;
 use std::fmt::Display;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Error6502 {
     UnknownOpcode(u8),
 }
@@ -17,3 +17,299 @@ impl Display for Error6502 {
 }
 
 impl Error for Error6502 {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InesError {
+    /// The image doesn't start with `NES<EOF>`
+    BadMagic,
+    /// The image is shorter than what its header declares
+    Truncated { expected: usize, found: usize },
+    /// Only mapper 0 (NROM) is implemented
+    UnsupportedMapper(u16),
+    /// NROM boards carry either 16 KiB or 32 KiB of PRG ROM
+    BadPrgSize(usize),
+    /// The ROM sizes in the NES 2.0 header add up to more than fits in memory
+    SizeOverflow,
+}
+
+impl Display for InesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InesError::BadMagic => f.write_str("Not an iNES image (missing \"NES\\x1A\" magic)"),
+            InesError::Truncated { expected, found } => f.write_fmt(format_args!(
+                "iNES image is truncated: expected {expected} bytes, found {found}"
+            )),
+            InesError::UnsupportedMapper(m) => f.write_fmt(format_args!(
+                "Mapper {m} is not supported, only NROM (0) is"
+            )),
+            InesError::BadPrgSize(size) => f.write_fmt(format_args!(
+                "NROM expects 16 KiB or 32 KiB of PRG ROM, found {size} bytes"
+            )),
+            InesError::SizeOverflow => {
+                f.write_str("iNES header declares ROM sizes too big to load")
+            }
+        }
+    }
+}
+
+impl Error for InesError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    Syntax(String),
+    UnknownMnemonic(String),
+    UnknownDirective(String),
+    /// The instruction doesn't support the addressing mode of its operand
+    BadAddressMode(String),
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    /// Distance in bytes to the branch target
+    BranchOutOfRange(i64),
+    ValueOutOfRange(i64),
+    /// Code or data was placed past $FFFF
+    PcOverflow,
+}
+
+impl Display for AsmErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AsmErrorKind::Syntax(msg) => f.write_fmt(format_args!("Syntax error: {msg}")),
+            AsmErrorKind::UnknownMnemonic(m) => {
+                f.write_fmt(format_args!("Unknown instruction \"{m}\""))
+            }
+            AsmErrorKind::UnknownDirective(d) => {
+                f.write_fmt(format_args!("Unknown directive \"{d}\""))
+            }
+            AsmErrorKind::BadAddressMode(m) => {
+                f.write_fmt(format_args!("Addressing mode not available for \"{m}\""))
+            }
+            AsmErrorKind::UndefinedSymbol(s) => {
+                f.write_fmt(format_args!("Symbol \"{s}\" is not defined"))
+            }
+            AsmErrorKind::DuplicateSymbol(s) => {
+                f.write_fmt(format_args!("Symbol \"{s}\" is already defined"))
+            }
+            AsmErrorKind::BranchOutOfRange(d) => {
+                f.write_fmt(format_args!("Branch target is out of range ({d} bytes)"))
+            }
+            AsmErrorKind::ValueOutOfRange(v) => {
+                f.write_fmt(format_args!("Value {v} doesn't fit in the operand"))
+            }
+            AsmErrorKind::PcOverflow => f.write_str("Program counter overflowed past $FFFF"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// 1-based source line
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("line {}: {}", self.line, self.kind))
+    }
+}
+
+impl Error for AsmError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolError {
+    /// The file couldn't be read
+    Io(String),
+    /// A line isn't in any of the supported formats
+    Syntax { line: usize, text: String },
+}
+
+impl Display for SymbolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SymbolError::Io(msg) => f.write_str(msg),
+            SymbolError::Syntax { line, text } => f.write_fmt(format_args!(
+                "line {line}: Unknown symbol format \"{text}\""
+            )),
+        }
+    }
+}
+
+impl Error for SymbolError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugInfoError {
+    /// The file couldn't be read
+    Io(String),
+    /// A line isn't a valid ld65 debug info record
+    Syntax { line: usize, text: String },
+    /// The major version of the format isn't 2
+    Version(u32),
+}
+
+impl Display for DebugInfoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DebugInfoError::Io(msg) => f.write_str(msg),
+            DebugInfoError::Syntax { line, text } => f.write_fmt(format_args!(
+                "line {line}: Invalid debug info record \"{text}\""
+            )),
+            DebugInfoError::Version(major) => f.write_fmt(format_args!(
+                "Unsupported debug info version {major}, expected 2"
+            )),
+        }
+    }
+}
+
+impl Error for DebugInfoError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprError {
+    /// `found` at byte offset `pos` doesn't fit the expression
+    Unexpected { pos: usize, found: String },
+    /// The expression ended early
+    End,
+    /// A name that isn't a register or a known symbol
+    UnknownName(String),
+    /// What follows `in` at byte offset `pos` is neither `START..END` nor a symbol
+    NotARange(usize),
+}
+
+impl Display for ExprError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExprError::Unexpected { pos, found } => {
+                f.write_fmt(format_args!("Unexpected \"{found}\" at {pos}"))
+            }
+            ExprError::End => f.write_str("Unexpected end of expression"),
+            ExprError::UnknownName(name) => f.write_fmt(format_args!(
+                "\"{name}\" is neither a register nor a symbol"
+            )),
+            ExprError::NotARange(pos) => f.write_fmt(format_args!(
+                "Expected START..END or a symbol after \"in\" at {pos}"
+            )),
+        }
+    }
+}
+
+impl Error for ExprError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceFormatError {
+    /// A `{field}` that isn't one of the trace fields
+    UnknownField(String),
+    /// A `{` without a matching `}`
+    Unclosed(String),
+}
+
+impl Display for TraceFormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceFormatError::UnknownField(name) => {
+                f.write_fmt(format_args!("Unknown trace field \"{{{name}}}\""))
+            }
+            TraceFormatError::Unclosed(name) => {
+                f.write_fmt(format_args!("Missing \"}}\" after \"{{{name}\""))
+            }
+        }
+    }
+}
+
+impl Error for TraceFormatError {}
+
+/// A trace log line that is neither in the nestest nor in the JSON Lines format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceParseError {
+    pub line: usize,
+    pub text: String,
+}
+
+impl Display for TraceParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "line {}: Unknown trace format \"{}\"",
+            self.line, self.text
+        ))
+    }
+}
+
+impl Error for TraceParseError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackViolationKind {
+    /// A push wrapped SP around from `$00` to `$FF`
+    Overflow,
+    /// A pull wrapped SP around from `$FF` to `$00`
+    Underflow,
+    /// An RTS or RTI with bytes still pushed, or with more pulled than pushed if negative,
+    /// since the call or interrupt it returns from
+    Unbalanced {
+        bytes: i16,
+    },
+    /// An RTS or RTI that didn't resume after its JSR or interrupt, `expected` is `None` when
+    /// there was none to return from
+    WrongReturn {
+        expected: Option<u16>,
+        actual: u16,
+    },
+    RtiWithoutInterrupt,
+}
+
+/// Something an instruction did to the stack that well-behaved code doesn't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackViolation {
+    /// The instruction responsible
+    pub pc: u16,
+    pub kind: StackViolationKind,
+}
+
+impl Display for StackViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("Stack check at ${:04X}: ", self.pc))?;
+        match self.kind {
+            StackViolationKind::Overflow => f.write_str("stack overflow"),
+            StackViolationKind::Underflow => f.write_str("stack underflow"),
+            StackViolationKind::Unbalanced { bytes } if bytes > 0 => {
+                f.write_fmt(format_args!("returned with {bytes} byte(s) still pushed"))
+            }
+            StackViolationKind::Unbalanced { bytes } => f.write_fmt(format_args!(
+                "returned after pulling {} byte(s) more than were pushed",
+                -bytes
+            )),
+            StackViolationKind::WrongReturn {
+                expected: Some(expected),
+                actual,
+            } => f.write_fmt(format_args!(
+                "returned to ${actual:04X} instead of ${expected:04X}"
+            )),
+            StackViolationKind::WrongReturn {
+                expected: None,
+                actual,
+            } => f.write_fmt(format_args!(
+                "returned to ${actual:04X} without a matching JSR"
+            )),
+            StackViolationKind::RtiWithoutInterrupt => {
+                f.write_str("RTI without a matching interrupt")
+            }
+        }
+    }
+}
+
+impl Error for StackViolation {}
+
+/// A machine's ROM image is too big for the space it goes in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomSizeError {
+    pub size: usize,
+    pub max: usize,
+}
+
+impl Display for RomSizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "A ROM of {} bytes doesn't fit, it can be at most {}",
+            self.size, self.max
+        ))
+    }
+}
+
+impl Error for RomSizeError {}
@@ -0,0 +1,584 @@
+use crate::cpu::Cpu;
+use crate::error::ExprError;
+use crate::memory::Memory;
+use crate::symbols::SymbolTable;
+use std::fmt::Display;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Var {
+    A,
+    X,
+    Y,
+    Sp,
+    P,
+    Pc,
+    Cycles,
+    /// How many times the breakpoint being tested has been reached, including this time
+    Hits,
+}
+
+impl Var {
+    fn parse(s: &str) -> Option<Var> {
+        match s.to_ascii_lowercase().as_str() {
+            "a" | "ac" => Some(Var::A),
+            "x" => Some(Var::X),
+            "y" => Some(Var::Y),
+            "s" | "sp" => Some(Var::Sp),
+            "p" => Some(Var::P),
+            "pc" => Some(Var::Pc),
+            "cycles" => Some(Var::Cycles),
+            "hits" => Some(Var::Hits),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinOp {
+    const fn symbol(&self) -> &'static str {
+        match self {
+            BinOp::Or => "||",
+            BinOp::And => "&&",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::BitOr => "|",
+            BinOp::BitXor => "^",
+            BinOp::BitAnd => "&",
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Rem => "%",
+        }
+    }
+
+    const fn is_boolean(&self) -> bool {
+        matches!(
+            self,
+            BinOp::Or
+                | BinOp::And
+                | BinOp::Eq
+                | BinOp::Ne
+                | BinOp::Lt
+                | BinOp::Le
+                | BinOp::Gt
+                | BinOp::Ge
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnOp {
+    Not,
+    Neg,
+    BitNot,
+}
+
+/// An expression over registers and memory, used for breakpoint conditions and watch
+/// expressions.
+///
+/// Values are integers. `[ADDR]` reads a byte and `[ADDR].w` a little-endian word, numbers
+/// are `$hex`, `0xhex`, `%binary` or decimal, and names are registers (`a`, `x`, `y`, `sp`,
+/// `p`, `pc`), `cycles`, `hits` or symbols. `X in START..END` tests `START <= X < END`, and
+/// `X in label` tests if `X` is between `label` and the next symbol. Comparisons and logical
+/// operators give 1 or 0, and dividing by zero gives 0.
+///
+/// ```rust
+/// use mini6502::expr::Expr;
+/// use mini6502::memory::Memory;
+/// use mini6502::symbols::SymbolTable;
+/// use mini6502::{Cpu, SimpleMemory};
+///
+/// let symbols = SymbolTable::parse("count = $0200\nloop = $0002\ndone = $0005").unwrap();
+/// let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&[0xEA; 8]));
+/// cpu.set_ac(0x40);
+/// cpu.set_pc(0x0003);
+/// cpu.mem.write_byte(0x0200, 4);
+///
+/// let expr = Expr::parse("A == $40 && [count] > 3 && pc in loop", &symbols).unwrap();
+/// assert_eq!(expr.eval(&cpu, 0), 1);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Var(Var),
+    Byte(Box<Expr>),
+    Word(Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    /// `value in start..end`
+    In(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn parse(text: &str, symbols: &SymbolTable) -> Result<Expr, ExprError> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            symbols,
+        };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some((pos, token)) => Err(ExprError::Unexpected {
+                pos: *pos,
+                found: token.to_string(),
+            }),
+        }
+    }
+
+    /// Evaluate against the state of `cpu`, with `hits` as the value of `hits`.
+    pub fn eval<M: Memory>(&self, cpu: &Cpu<M>, hits: u64) -> i64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Var(var) => match var {
+                Var::A => cpu.ac() as i64,
+                Var::X => cpu.x() as i64,
+                Var::Y => cpu.y() as i64,
+                Var::Sp => cpu.sp() as i64,
+                Var::P => cpu.p() as i64,
+                Var::Pc => cpu.pc() as i64,
+                Var::Cycles => cpu.cycle_count() as i64,
+                Var::Hits => hits as i64,
+            },
+            Expr::Byte(addr) => cpu.mem.peek(addr.eval(cpu, hits) as u16) as i64,
+            Expr::Word(addr) => {
+                let addr = addr.eval(cpu, hits) as u16;
+                u16::from_le_bytes([cpu.mem.peek(addr), cpu.mem.peek(addr.wrapping_add(1))]) as i64
+            }
+            Expr::Unary(op, operand) => {
+                let value = operand.eval(cpu, hits);
+                match op {
+                    UnOp::Not => (value == 0) as i64,
+                    UnOp::Neg => value.wrapping_neg(),
+                    UnOp::BitNot => !value,
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(cpu, hits);
+                // && and || don't evaluate their right side when the left decides
+                match op {
+                    BinOp::And if lhs == 0 => return 0,
+                    BinOp::Or if lhs != 0 => return 1,
+                    _ => {}
+                }
+                let rhs = rhs.eval(cpu, hits);
+                match op {
+                    BinOp::Or | BinOp::And => (rhs != 0) as i64,
+                    BinOp::Eq => (lhs == rhs) as i64,
+                    BinOp::Ne => (lhs != rhs) as i64,
+                    BinOp::Lt => (lhs < rhs) as i64,
+                    BinOp::Le => (lhs <= rhs) as i64,
+                    BinOp::Gt => (lhs > rhs) as i64,
+                    BinOp::Ge => (lhs >= rhs) as i64,
+                    BinOp::BitOr => lhs | rhs,
+                    BinOp::BitXor => lhs ^ rhs,
+                    BinOp::BitAnd => lhs & rhs,
+                    BinOp::Add => lhs.wrapping_add(rhs),
+                    BinOp::Sub => lhs.wrapping_sub(rhs),
+                    BinOp::Mul => lhs.wrapping_mul(rhs),
+                    BinOp::Div => lhs.checked_div(rhs).unwrap_or(0),
+                    BinOp::Rem => lhs.checked_rem(rhs).unwrap_or(0),
+                }
+            }
+            Expr::In(value, start, end) => {
+                let value = value.eval(cpu, hits);
+                (start.eval(cpu, hits) <= value && value < end.eval(cpu, hits)) as i64
+            }
+        }
+    }
+
+    /// Whether the value always fits in a byte: 8-bit registers, byte reads and booleans.
+    fn is_byte(&self) -> bool {
+        match self {
+            Expr::Number(n) => (0..=0xFF).contains(n),
+            Expr::Var(var) => matches!(var, Var::A | Var::X | Var::Y | Var::P),
+            Expr::Byte(_) | Expr::In(..) => true,
+            Expr::Unary(op, _) => *op == UnOp::Not,
+            Expr::Binary(op, lhs, rhs) => {
+                op.is_boolean() || (*op == BinOp::BitAnd && (lhs.is_byte() || rhs.is_byte()))
+            }
+            Expr::Word(_) => false,
+        }
+    }
+
+    /// `value` as hex with as many digits as this expression's values have, or as decimal if it
+    /// doesn't fit in a word.
+    pub fn format_value(&self, value: i64) -> String {
+        if self.is_byte() && (0..=0xFF).contains(&value) {
+            format!("${value:02X}")
+        } else if (0..=0xFFFF).contains(&value) {
+            format!("${value:04X}")
+        } else {
+            value.to_string()
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Number(n) if (0..=0xFFFF).contains(n) => f.write_fmt(format_args!("${n:04X}")),
+            Expr::Number(n) => f.write_fmt(format_args!("{n}")),
+            Expr::Var(var) => f.write_str(&format!("{var:?}").to_lowercase()),
+            Expr::Byte(addr) => f.write_fmt(format_args!("[{addr}]")),
+            Expr::Word(addr) => f.write_fmt(format_args!("[{addr}].w")),
+            Expr::Unary(op, operand) => {
+                let op = match op {
+                    UnOp::Not => "!",
+                    UnOp::Neg => "-",
+                    UnOp::BitNot => "~",
+                };
+                f.write_fmt(format_args!("{op}{operand}"))
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                f.write_fmt(format_args!("({lhs} {} {rhs})", op.symbol()))
+            }
+            Expr::In(value, start, end) => f.write_fmt(format_args!("({value} in {start}..{end})")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => f.write_fmt(format_args!("{n}")),
+            Token::Name(name) => f.write_str(name),
+            Token::Op(op) => f.write_str(op),
+        }
+    }
+}
+
+/// Longest first, so `<=` isn't read as `<`
+const OPERATORS: [&str; 23] = [
+    "||", "&&", "==", "!=", "<=", ">=", "..", "<", ">", "|", "^", "&", "+", "-", "*", "/", "%",
+    "!", "~", "(", ")", "[", "]",
+];
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '@' | '.')
+}
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, ExprError> {
+    let mut tokens = vec![];
+    let mut pos = 0;
+    while pos < text.len() {
+        let rest = &text[pos..];
+        let c = rest.chars().next().unwrap();
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue;
+        }
+        let start = pos;
+        let after_bracket = matches!(tokens.last(), Some((_, Token::Op("]"))));
+        let token = if let Some(digits) = rest.strip_prefix('$') {
+            let len = digits
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(digits.len());
+            pos += 1 + len;
+            Token::Number(parse_radix(&digits[..len], 16, start)?)
+        } else if let Some(digits) = rest.strip_prefix("0x") {
+            let len = digits
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(digits.len());
+            pos += 2 + len;
+            Token::Number(parse_radix(&digits[..len], 16, start)?)
+        } else if let Some(digits) = rest.strip_prefix('%').filter(|d| d.starts_with(['0', '1'])) {
+            let len = digits
+                .find(|c| c != '0' && c != '1')
+                .unwrap_or(digits.len());
+            pos += 1 + len;
+            Token::Number(parse_radix(&digits[..len], 2, start)?)
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            pos += len;
+            Token::Number(parse_radix(&rest[..len], 10, start)?)
+        } else if after_bracket && rest.starts_with(".w") {
+            pos += 2;
+            Token::Op(".w")
+        } else if c.is_ascii_alphabetic() || matches!(c, '_' | '@' | '.') && !rest.starts_with("..")
+        {
+            let mut len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+            // Names stop before a `..` range
+            if let Some(dots) = rest[..len].find("..") {
+                len = dots;
+            }
+            pos += len;
+            Token::Name(rest[..len].to_string())
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            pos += op.len();
+            Token::Op(op)
+        } else {
+            return Err(ExprError::Unexpected {
+                pos,
+                found: c.to_string(),
+            });
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+fn parse_radix(digits: &str, radix: u32, pos: usize) -> Result<i64, ExprError> {
+    i64::from_str_radix(digits, radix).map_err(|_| ExprError::Unexpected {
+        pos,
+        found: digits.to_string(),
+    })
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    symbols: &'a SymbolTable,
+}
+
+impl Parser<'_> {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some((_, Token::Op(op))) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        let found = self.peek_op() == Some(op);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn is_name(&self, name: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some((_, Token::Name(n))) if n == name)
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), ExprError> {
+        if self.eat(op) {
+            return Ok(());
+        }
+        Err(match self.tokens.get(self.pos) {
+            Some((pos, token)) => ExprError::Unexpected {
+                pos: *pos,
+                found: token.to_string(),
+            },
+            None => ExprError::End,
+        })
+    }
+
+    /// Parse operands of `ops` separated by those operators, with `next` parsing the operands.
+    fn binary(
+        &mut self,
+        ops: &[(&str, BinOp)],
+        next: fn(&mut Self) -> Result<Expr, ExprError>,
+    ) -> Result<Expr, ExprError> {
+        let mut lhs = next(self)?;
+        'outer: loop {
+            for (symbol, op) in ops {
+                if self.eat(symbol) {
+                    let rhs = next(self)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, ExprError> {
+        self.binary(&[("||", BinOp::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, ExprError> {
+        self.binary(&[("&&", BinOp::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ExprError> {
+        let lhs = self.binary(
+            &[
+                ("==", BinOp::Eq),
+                ("!=", BinOp::Ne),
+                ("<=", BinOp::Le),
+                (">=", BinOp::Ge),
+                ("<", BinOp::Lt),
+                (">", BinOp::Gt),
+            ],
+            Self::bit_or,
+        )?;
+        if !self.is_name("in") {
+            return Ok(lhs);
+        }
+        self.pos += 1;
+        let range_pos = self.tokens.get(self.pos).map(|(pos, _)| *pos);
+        // A symbol on its own stands for the code up to the next symbol
+        if let Some((_, Token::Name(name))) = self.tokens.get(self.pos) {
+            let next_is_range = matches!(self.tokens.get(self.pos + 1), Some((_, Token::Op(".."))));
+            if !next_is_range {
+                if let Some(range) = self.symbols.extent(name) {
+                    self.pos += 1;
+                    return Ok(Expr::In(
+                        Box::new(lhs),
+                        Box::new(Expr::Number(range.start as i64)),
+                        Box::new(Expr::Number(range.end as i64)),
+                    ));
+                }
+            }
+        }
+        let start = self.bit_or()?;
+        if !self.eat("..") {
+            return Err(ExprError::NotARange(range_pos.unwrap_or(0)));
+        }
+        let end = self.bit_or()?;
+        Ok(Expr::In(Box::new(lhs), Box::new(start), Box::new(end)))
+    }
+
+    fn bit_or(&mut self) -> Result<Expr, ExprError> {
+        self.binary(&[("|", BinOp::BitOr)], Self::bit_xor)
+    }
+
+    fn bit_xor(&mut self) -> Result<Expr, ExprError> {
+        self.binary(&[("^", BinOp::BitXor)], Self::bit_and)
+    }
+
+    fn bit_and(&mut self) -> Result<Expr, ExprError> {
+        self.binary(&[("&", BinOp::BitAnd)], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr, ExprError> {
+        self.binary(&[("+", BinOp::Add), ("-", BinOp::Sub)], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, ExprError> {
+        self.binary(
+            &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Rem)],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExprError> {
+        for (symbol, op) in [("!", UnOp::Not), ("-", UnOp::Neg), ("~", UnOp::BitNot)] {
+            if self.eat(symbol) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, ExprError> {
+        let (pos, token) = self.tokens.get(self.pos).cloned().ok_or(ExprError::End)?;
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Name(name) => match Var::parse(&name) {
+                Some(var) => Ok(Expr::Var(var)),
+                None => match self.symbols.lookup(&name) {
+                    Some(addr) => Ok(Expr::Number(addr as i64)),
+                    None => Err(ExprError::UnknownName(name)),
+                },
+            },
+            Token::Op("(") => {
+                let expr = self.or()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Op("[") => {
+                let addr = Box::new(self.or()?);
+                self.expect("]")?;
+                if self.eat(".w") {
+                    Ok(Expr::Word(addr))
+                } else {
+                    Ok(Expr::Byte(addr))
+                }
+            }
+            token => Err(ExprError::Unexpected {
+                pos,
+                found: token.to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SimpleMemory;
+
+    fn eval(text: &str, cpu: &Cpu<SimpleMemory>) -> i64 {
+        let symbols = SymbolTable::parse("start = $0600\nloop = $0604\nend = $0610").unwrap();
+        Expr::parse(text, &symbols).unwrap().eval(cpu, 3)
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&[]));
+        cpu.set_x(0x10);
+        cpu.set_pc(0x0605);
+        cpu.mem.write_byte(0x0200, 0x34);
+        cpu.mem.write_byte(0x0201, 0x12);
+
+        assert_eq!(eval("1 + 2 * 3 - -1", &cpu), 8);
+        assert_eq!(eval("(1 + 2) * 3 % 4", &cpu), 1);
+        assert_eq!(eval("x == $10 && [$200] == $34", &cpu), 1);
+        assert_eq!(eval("[$200].w", &cpu), 0x1234);
+        assert_eq!(eval("[$1F0 + x].w & $FF00 | %1", &cpu), 0x1201);
+        assert_eq!(eval("pc in loop", &cpu), 1);
+        assert_eq!(eval("pc in start..loop", &cpu), 0);
+        assert_eq!(eval("!(hits > 2) || 1 / 0", &cpu), 0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let symbols = SymbolTable::new();
+        let parse = |text| Expr::parse(text, &symbols);
+        assert_eq!(parse("a == "), Err(ExprError::End));
+        assert_eq!(
+            parse("a == )"),
+            Err(ExprError::Unexpected {
+                pos: 5,
+                found: ")".into()
+            })
+        );
+        assert_eq!(
+            parse("count > 3"),
+            Err(ExprError::UnknownName("count".into()))
+        );
+        assert_eq!(parse("pc in 3"), Err(ExprError::NotARange(6)));
+
+        let expr = parse("[$200].w == 3 && a").unwrap();
+        assert_eq!(expr.to_string(), "(([$0200].w == $0003) && a)");
+        assert_eq!(expr.format_value(1), "$01");
+        assert_eq!(parse("[$200].w").unwrap().format_value(1), "$0001");
+    }
+}
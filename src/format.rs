@@ -1,15 +1,54 @@
 use crate::memory::Memory;
+use crate::symbols::SymbolTable;
 use crate::Cpu;
-impl<M> std::fmt::Display for Cpu<M>
+
+/// Shows a `Cpu` like its `Display` impl, naming the PC after the closest symbol.
+pub struct CpuWithSymbols<'a, M> {
+    cpu: &'a Cpu<M>,
+    symbols: &'a SymbolTable,
+}
+
+impl<M> Cpu<M>
 where
     M: Memory,
 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!(
-            "
+    /// Display the cpu with addresses named after `symbols`.
+    ///
+    /// ```rust
+    /// use mini6502::symbols::SymbolTable;
+    /// use mini6502::{Cpu, SimpleMemory};
+    ///
+    /// let cpu = Cpu::with_mem(SimpleMemory::from_rom(&[0xEA]));
+    /// let symbols = SymbolTable::parse("reset = $0000").unwrap();
+    /// assert!(cpu.with_symbols(&symbols).to_string().contains("PC 0x0000 <reset>"));
+    /// ```
+    pub fn with_symbols<'a>(&'a self, symbols: &'a SymbolTable) -> CpuWithSymbols<'a, M> {
+        CpuWithSymbols { cpu: self, symbols }
+    }
+}
+
+/// The status flags as `NV-BDIZC`, in lowercase where they're clear.
+pub(crate) fn flags_text(p: u8) -> String {
+    "NV-BDIZC"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| match p & (0x80 >> i) != 0 {
+            true => c,
+            false => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+fn fmt_cpu<M: Memory>(
+    cpu: &Cpu<M>,
+    symbols: Option<&SymbolTable>,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    f.write_fmt(format_args!(
+        "
     Instruction: {}
     Registers:
-    PC {:#06x}
+    PC {:#06x}{}
     AC {:#04x}
     X: {:#04x}
     Y: {:#04x}
@@ -19,23 +58,44 @@ where
     NV-BDIZC
     {:08b}
     ",
-            match self.ir() {
-                Some(inst) => format!("{inst:?}"),
-                None => "NONE".to_string(),
-            },
-            self.pc(),
-            self.ac(),
-            self.x(),
-            self.y(),
-            self.sp(),
-            if self.sp() == 0x01FF {
-                "empty".to_string()
-            } else {
-                let sp = &self.sp();
-                let bb = self.read_byte_from_mem(*sp + 1);
-                format!("{:#04x}", bb)
-            },
-            self.p()
-        ))
+        match cpu.ir() {
+            Some(inst) => format!("{inst:?}"),
+            None => "NONE".to_string(),
+        },
+        cpu.pc(),
+        match symbols {
+            Some(symbols) if !symbols.is_empty() => format!(" <{}>", symbols.describe(cpu.pc())),
+            _ => String::new(),
+        },
+        cpu.ac(),
+        cpu.x(),
+        cpu.y(),
+        cpu.sp(),
+        if cpu.sp() == 0x01FF {
+            "empty".to_string()
+        } else {
+            let sp = &cpu.sp();
+            let bb = cpu.read_byte_from_mem(*sp + 1);
+            format!("{:#04x}", bb)
+        },
+        cpu.p()
+    ))
+}
+
+impl<M> std::fmt::Display for Cpu<M>
+where
+    M: Memory,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt_cpu(self, None, f)
+    }
+}
+
+impl<M> std::fmt::Display for CpuWithSymbols<'_, M>
+where
+    M: Memory,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt_cpu(self.cpu, Some(self.symbols), f)
     }
 }
@@ -0,0 +1,609 @@
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// Check for an interrupt request from the debugger every this many instructions while running
+const POLL_INTERVAL: usize = 0x1000;
+const PACKET_SIZE: usize = 0x1000;
+const INTERRUPT: u8 = 0x03;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// Register layout, in the order used by the `g` and `G` packets and by register numbers. All
+/// registers are 8 bits except for PC, which is sent little-endian like the rest of the target.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.mini6502.cpu">
+    <reg name="a" bitsize="8" regnum="0" type="uint8"/>
+    <reg name="x" bitsize="8" regnum="1" type="uint8"/>
+    <reg name="y" bitsize="8" regnum="2" type="uint8"/>
+    <reg name="p" bitsize="8" regnum="3" type="uint8"/>
+    <reg name="sp" bitsize="8" regnum="4" type="uint8"/>
+    <reg name="pc" bitsize="16" regnum="5" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+const REGISTER_COUNT: usize = 6;
+
+/// A stream the stub can talk to the debugger over.
+pub trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// Packet level side of the protocol: framing, checksums and acknowledgements.
+struct Session<C> {
+    conn: C,
+    pending: VecDeque<u8>,
+    no_ack: bool,
+    last_reply: Vec<u8>,
+}
+
+enum Packet {
+    Command(String),
+    Interrupt,
+}
+
+impl<C> Session<C>
+where
+    C: Connection,
+{
+    fn new(conn: C) -> Self {
+        Session {
+            conn,
+            pending: VecDeque::new(),
+            no_ack: false,
+            last_reply: vec![],
+        }
+    }
+
+    /// Read whatever is available into `pending`. Returns `false` at end of stream.
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut buf = [0u8; PACKET_SIZE];
+        match self.conn.read(&mut buf) {
+            Ok(0) => Ok(false),
+            Ok(n) => {
+                self.pending.extend(&buf[..n]);
+                Ok(true)
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => Ok(true),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        while self.pending.is_empty() {
+            if !self.fill()? {
+                return Ok(None);
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    /// Read the next packet. Returns `None` once the debugger hangs up.
+    fn read_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            match self.next_byte()? {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some(Packet::Interrupt)),
+                Some(b'-') => {
+                    let reply = self.last_reply.clone();
+                    self.conn.write_all(&reply)?;
+                }
+                Some(b'$') => {
+                    let mut data = vec![];
+                    loop {
+                        match self.next_byte()? {
+                            None => return Ok(None),
+                            Some(b'#') => break,
+                            Some(b) => data.push(b),
+                        }
+                    }
+                    let mut checksum = [0u8; 2];
+                    for digit in checksum.iter_mut() {
+                        match self.next_byte()? {
+                            None => return Ok(None),
+                            Some(b) => *digit = b,
+                        }
+                    }
+                    let expected = std::str::from_utf8(&checksum)
+                        .ok()
+                        .and_then(|s| u8::from_str_radix(s, 16).ok());
+                    if !self.no_ack {
+                        if expected != Some(checksum_of(&data)) {
+                            self.conn.write_all(b"-")?;
+                            continue;
+                        }
+                        self.conn.write_all(b"+")?;
+                    }
+                    return Ok(Some(Packet::Command(
+                        String::from_utf8_lossy(&data).into_owned(),
+                    )));
+                }
+                // Acks and line noise
+                Some(_) => {}
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        for b in data.bytes() {
+            // `#`, `$`, `}` and `*` have to be escaped in replies
+            if matches!(b, b'#' | b'$' | b'}' | b'*') {
+                packet.extend([b'}', b ^ 0x20]);
+            } else {
+                packet.push(b);
+            }
+        }
+        let checksum = checksum_of(&packet[1..]);
+        packet.extend(format!("#{checksum:02x}").bytes());
+        self.conn.write_all(&packet)?;
+        self.conn.flush()?;
+        self.last_reply = packet;
+        Ok(())
+    }
+
+    /// Check, without blocking, if the debugger asked us to stop.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.conn.set_nonblocking(true)?;
+        let filled = self.fill();
+        self.conn.set_nonblocking(false)?;
+        match filled {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) => return Err(err),
+        }
+        match self.pending.iter().position(|b| *b == INTERRUPT) {
+            Some(pos) => {
+                self.pending.remove(pos);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_u16(s: &str) -> Option<u16> {
+    u16::from_str_radix(s, 16).ok()
+}
+
+/// Parse the `ADDR,LEN` part of memory packets.
+fn parse_range(s: &str) -> Option<(u16, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((parse_u16(addr)?, usize::from_str_radix(len, 16).ok()?))
+}
+
+enum Action {
+    Reply(String),
+    Resume {
+        step: bool,
+    },
+    Detach,
+    /// End the session without a reply
+    Kill,
+}
+
+/// GDB remote serial protocol server controlling a `Cpu`.
+///
+/// Registers are exposed as `a`, `x`, `y`, `p`, `sp` (8 bits each) and `pc` (16 bits), in that
+/// order, and described to the debugger through `target.xml`. Software and hardware breakpoints
+/// behave the same, as the emulator can check every address for free. A halt caused by an
+/// `Error6502` is reported as `SIGILL`, with the error printed in the debugger console.
+pub struct GdbStub<M> {
+    pub cpu: Cpu<M>,
+    sw_breakpoints: BTreeSet<u16>,
+    hw_breakpoints: BTreeSet<u16>,
+}
+
+impl<M> GdbStub<M>
+where
+    M: Memory,
+{
+    pub fn new(cpu: Cpu<M>) -> Self {
+        GdbStub {
+            cpu,
+            sw_breakpoints: BTreeSet::new(),
+            hw_breakpoints: BTreeSet::new(),
+        }
+    }
+
+    /// Wait for a debugger to connect to `addr` and serve it until it detaches.
+    ///
+    /// `addr` is `[HOST]:PORT`, where `HOST` defaults to `127.0.0.1`, or the path of a Unix
+    /// socket.
+    pub fn listen(&mut self, addr: &str) -> io::Result<()> {
+        if let Some((host, port)) = addr.rsplit_once(':') {
+            let host = if host.is_empty() { "127.0.0.1" } else { host };
+            let port = port.parse::<u16>().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid port \"{port}\" in \"{addr}\""),
+                )
+            })?;
+            let listener = TcpListener::bind((host, port))?;
+            eprintln!("Waiting for GDB on {}", listener.local_addr()?);
+            let (stream, _) = listener.accept()?;
+            stream.set_nodelay(true)?;
+            return self.serve(stream);
+        }
+
+        #[cfg(unix)]
+        {
+            let _ = std::fs::remove_file(addr);
+            let listener = UnixListener::bind(addr)?;
+            eprintln!("Waiting for GDB on {addr}");
+            let (stream, _) = listener.accept()?;
+            self.serve(stream)
+        }
+        #[cfg(not(unix))]
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid address \"{addr}\", expected [HOST]:PORT"),
+        ))
+    }
+
+    /// Serve a single debugger session over `conn` until it detaches or disconnects.
+    pub fn serve<C: Connection>(&mut self, conn: C) -> io::Result<()> {
+        let mut session = Session::new(conn);
+        while let Some(packet) = session.read_packet()? {
+            let command = match packet {
+                Packet::Command(command) => command,
+                // The cpu is already stopped
+                Packet::Interrupt => continue,
+            };
+            match self.handle(&command, &mut session) {
+                Action::Reply(reply) => session.send(&reply)?,
+                Action::Resume { step } => {
+                    let reply = self.resume(step, &mut session)?;
+                    session.send(&reply)?;
+                }
+                Action::Detach => {
+                    session.send("OK")?;
+                    return Ok(());
+                }
+                Action::Kill => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle<C: Connection>(&mut self, command: &str, session: &mut Session<C>) -> Action {
+        let reply = |s: &str| Action::Reply(s.to_string());
+        let error = || reply("E01");
+
+        // Packets are decoded lossily, the first char may be more than a byte
+        let (kind, args) = command.split_at(command.chars().next().map_or(0, char::len_utf8));
+        match kind {
+            "?" => Action::Reply(format!("S{SIGTRAP:02x}")),
+            "g" => Action::Reply(to_hex(&self.registers())),
+            "G" => match from_hex(args) {
+                Some(bytes) if bytes.len() == REGISTER_COUNT + 1 => {
+                    for (n, value) in [0, 1, 2, 3, 4].iter().zip(&bytes) {
+                        self.write_register(*n, *value as u16);
+                    }
+                    self.write_register(5, u16::from_le_bytes([bytes[5], bytes[6]]));
+                    reply("OK")
+                }
+                _ => error(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTER_COUNT => {
+                    let regs = self.registers();
+                    let bytes = if n == 5 { &regs[5..] } else { &regs[n..=n] };
+                    Action::Reply(to_hex(bytes))
+                }
+                _ => error(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, value)| {
+                    let n = usize::from_str_radix(n, 16).ok()?;
+                    let bytes = from_hex(value)?;
+                    let value = bytes
+                        .iter()
+                        .rev()
+                        .fold(0u16, |value, b| value << 8 | *b as u16);
+                    (n < REGISTER_COUNT).then_some((n, value))
+                });
+                match parsed {
+                    Some((n, value)) => {
+                        self.write_register(n, value);
+                        reply("OK")
+                    }
+                    None => error(),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((addr, len)) => {
+                    let bytes: Vec<u8> = (0..len.min(PACKET_SIZE / 2))
+                        .map(|offset| self.cpu.mem.peek(addr.wrapping_add(offset as u16)))
+                        .collect();
+                    Action::Reply(to_hex(&bytes))
+                }
+                None => error(),
+            },
+            "M" => {
+                let parsed = args
+                    .split_once(':')
+                    .and_then(|(range, data)| Some((parse_range(range)?, from_hex(data)?)));
+                match parsed {
+                    Some(((addr, len), bytes)) if bytes.len() == len => {
+                        for (offset, b) in bytes.iter().enumerate() {
+                            self.cpu
+                                .mem
+                                .write_byte(addr.wrapping_add(offset as u16), *b);
+                        }
+                        reply("OK")
+                    }
+                    _ => error(),
+                }
+            }
+            "Z" | "z" => {
+                let parsed = args.split(',').collect::<Vec<&str>>();
+                let (set, addr) = match parsed[..] {
+                    ["0", addr, _] => (&mut self.sw_breakpoints, addr),
+                    ["1", addr, _] => (&mut self.hw_breakpoints, addr),
+                    // Watchpoints aren't supported
+                    _ => return reply(""),
+                };
+                match parse_u16(addr) {
+                    Some(addr) => {
+                        if kind == "Z" {
+                            set.insert(addr);
+                        } else {
+                            set.remove(&addr);
+                        }
+                        reply("OK")
+                    }
+                    None => error(),
+                }
+            }
+            "c" | "s" => {
+                if let Some(addr) = parse_u16(args) {
+                    self.cpu.set_pc(addr);
+                }
+                Action::Resume { step: kind == "s" }
+            }
+            "D" => Action::Detach,
+            "k" => Action::Kill,
+            "H" => reply("OK"),
+            "T" => reply("OK"),
+            "q" | "Q" => self.handle_query(command, session),
+            _ => reply(""),
+        }
+    }
+
+    fn handle_query<C: Connection>(&mut self, command: &str, session: &mut Session<C>) -> Action {
+        let reply = |s: &str| Action::Reply(s.to_string());
+        if command.starts_with("qSupported") {
+            return Action::Reply(format!(
+                "PacketSize={PACKET_SIZE:x};qXfer:features:read+;QStartNoAckMode+"
+            ));
+        }
+        if let Some(range) = command.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_range(range) {
+                Some((offset, len)) => {
+                    let offset = (offset as usize).min(TARGET_XML.len());
+                    let end = (offset + len).min(TARGET_XML.len());
+                    let more = if end < TARGET_XML.len() { "m" } else { "l" };
+                    Action::Reply(format!("{more}{}", &TARGET_XML[offset..end]))
+                }
+                None => reply("E01"),
+            };
+        }
+        match command {
+            "QStartNoAckMode" => {
+                // The OK itself is still acknowledged
+                session.no_ack = true;
+                reply("OK")
+            }
+            "qAttached" => reply("1"),
+            "qC" => reply("QC1"),
+            "qfThreadInfo" => reply("m1"),
+            "qsThreadInfo" => reply("l"),
+            _ => reply(""),
+        }
+    }
+
+    /// Execute one instruction, or run until a breakpoint, an error or an interrupt request.
+    /// Returns the stop reply.
+    fn resume<C: Connection>(
+        &mut self,
+        step: bool,
+        session: &mut Session<C>,
+    ) -> io::Result<String> {
+        let mut count = 0usize;
+        loop {
+            if let Err(err) = self.cpu.step() {
+                // Tell the user why, as the signal alone isn't very helpful
+                let msg = format!("{err} at ${:04X}\n", self.cpu.pc());
+                session.send(&format!("O{}", to_hex(msg.as_bytes())))?;
+                return Ok(format!("S{SIGILL:02x}"));
+            }
+            let pc = self.cpu.pc();
+            if step || self.sw_breakpoints.contains(&pc) || self.hw_breakpoints.contains(&pc) {
+                return Ok(format!("S{SIGTRAP:02x}"));
+            }
+            count += 1;
+            if count.is_multiple_of(POLL_INTERVAL) && session.interrupted()? {
+                return Ok(format!("S{SIGINT:02x}"));
+            }
+        }
+    }
+
+    fn registers(&self) -> [u8; REGISTER_COUNT + 1] {
+        let [pc_ll, pc_hh] = self.cpu.pc().to_le_bytes();
+        [
+            self.cpu.ac(),
+            self.cpu.x(),
+            self.cpu.y(),
+            self.cpu.p(),
+            self.cpu.sp() as u8,
+            pc_ll,
+            pc_hh,
+        ]
+    }
+
+    fn write_register(&mut self, n: usize, value: u16) {
+        match n {
+            0 => self.cpu.set_ac(value as u8),
+            1 => self.cpu.set_x(value as u8),
+            2 => self.cpu.set_y(value as u8),
+            3 => self.cpu.set_p(value as u8),
+            4 => self.cpu.set_sp(value as u8),
+            _ => self.cpu.set_pc(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::Error6502;
+    use crate::SimpleMemory;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Feeds canned packets to the stub and records its replies.
+    struct MockConnection {
+        input: VecDeque<u8>,
+        output: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Read for MockConnection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.input.len());
+            for b in buf.iter_mut().take(n) {
+                *b = self.input.pop_front().unwrap();
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for MockConnection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for MockConnection {
+        fn set_nonblocking(&self, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${data}#{:02x}", checksum_of(data.as_bytes()))
+    }
+
+    /// Send `commands` and return the replies, without the acks.
+    fn session(stub: &mut GdbStub<SimpleMemory>, commands: &[&str]) -> Vec<String> {
+        let input: String = commands.iter().map(|c| packet(c)).collect();
+        let output = Rc::new(RefCell::new(vec![]));
+        let conn = MockConnection {
+            input: input.bytes().collect(),
+            output: output.clone(),
+        };
+        stub.serve(conn).unwrap();
+
+        let output = String::from_utf8(output.borrow().clone()).unwrap();
+        output
+            .split('$')
+            .skip(1)
+            .map(|reply| reply.split('#').next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        // ldx #$10; nop
+        let mem = SimpleMemory::from_rom(&[0xA2, 0x10, 0xEA]);
+        let mut stub = GdbStub::new(Cpu::with_mem(mem));
+        stub.cpu.set_sp(0xFD);
+
+        let replies = session(
+            &mut stub,
+            &["g", "P0=7f", "p0", "M200,2:dead", "m1ff,3", "s", "p5", "D"],
+        );
+        assert_eq!(
+            replies,
+            [
+                "00000020fd0000",
+                "OK",
+                "7f",
+                "OK",
+                "00dead",
+                "S05",
+                "0200",
+                "OK"
+            ]
+        );
+        assert_eq!(stub.cpu.x(), 0x10);
+    }
+
+    #[test]
+    fn test_breakpoints_and_errors() {
+        // ldx #$03; loop: dex; bne loop; .byte $02
+        let mem = SimpleMemory::from_rom(&[0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x02]);
+        let mut stub = GdbStub::new(Cpu::with_mem(mem));
+
+        let replies = session(&mut stub, &["Z0,2,1", "c", "c", "z0,2,1", "c", "k"]);
+        let error = to_hex(format!("{} at $0005\n", Error6502::UnknownOpcode(0x02)).as_bytes());
+        assert_eq!(
+            replies,
+            ["OK", "S05", "S05", "OK", &format!("O{error}"), "S04"]
+        );
+        assert_eq!(stub.cpu.x(), 0x00);
+
+        let err = stub.listen(":12345x").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "Invalid port \"12345x\" in \":12345x\"");
+
+        // Packets starting with a non-ASCII byte are unknown commands
+        let replies = session(&mut stub, &["\u{FFFD}", "\u{E9}g", "D"]);
+        assert_eq!(replies, ["", "", "OK"]);
+    }
+}
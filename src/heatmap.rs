@@ -0,0 +1,305 @@
+use crate::bus::{BusAccess, BusKind, BusObserver};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::ops::Range;
+
+const ADDR_SPACE: usize = 0x10000;
+
+/// Code that was written before it was executed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SelfModification {
+    pub addr: u16,
+    /// PC of the last instruction that wrote to `addr` before it was executed
+    pub written_by: u16,
+    /// PC of the first instruction executed with the written byte in it
+    pub executed_by: u16,
+}
+
+/// Reads of a byte that was never written nor loaded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UninitializedRead {
+    pub addr: u16,
+    /// PC of the first instruction that read it
+    pub pc: u16,
+    pub count: u64,
+}
+
+/// Counts how every address is accessed, as a [`BusObserver`] of the cpu. Only accesses made
+/// by the cpu are counted, tools looking at memory don't show up in the heatmap.
+///
+/// ```rust
+/// use mini6502::heatmap::Heatmap;
+/// use mini6502::{Cpu, SimpleMemory};
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// // lda $10; sta $11; brk
+/// let rom = [0xA5, 0x10, 0x85, 0x11, 0x00];
+/// let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&rom));
+/// let heatmap = Rc::new(RefCell::new(Heatmap::new()));
+/// heatmap.borrow_mut().mark_initialized(0..rom.len() as u32);
+/// cpu.add_observer(Box::new(heatmap.clone()));
+/// cpu.step().unwrap();
+/// cpu.step().unwrap();
+///
+/// let heatmap = heatmap.borrow();
+/// assert_eq!((heatmap.executes(0x0000), heatmap.reads(0x0000)), (1, 0));
+/// assert_eq!((heatmap.reads(0x0010), heatmap.writes(0x0011)), (1, 1));
+/// let uninitialized: Vec<u16> = heatmap.uninitialized_reads().map(|read| read.addr).collect();
+/// assert_eq!(uninitialized, [0x0010]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Heatmap {
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+    /// Counts every byte of an executed instruction, not just the opcode
+    executes: Vec<u64>,
+    initialized: Vec<bool>,
+    last_writer: Vec<u16>,
+    self_modified: BTreeMap<u16, SelfModification>,
+    uninitialized_reads: BTreeMap<u16, UninitializedRead>,
+    /// PC of the instruction being executed
+    pc: u16,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap::new()
+    }
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Heatmap {
+            reads: vec![0; ADDR_SPACE],
+            writes: vec![0; ADDR_SPACE],
+            executes: vec![0; ADDR_SPACE],
+            initialized: vec![false; ADDR_SPACE],
+            last_writer: vec![0; ADDR_SPACE],
+            self_modified: BTreeMap::new(),
+            uninitialized_reads: BTreeMap::new(),
+            pc: 0x0000,
+        }
+    }
+
+    /// Count reads of `addrs` as initialized, e.g. for ROM or a loaded program.
+    pub fn mark_initialized(&mut self, addrs: Range<u32>) {
+        for addr in addrs {
+            self.initialized[addr as usize & 0xFFFF] = true;
+        }
+    }
+
+    /// Count a byte of the instruction being executed.
+    fn execute(&mut self, addr: u16) {
+        self.executes[addr as usize] += 1;
+        if self.writes[addr as usize] > 0 {
+            let written_by = self.last_writer[addr as usize];
+            self.self_modified.entry(addr).or_insert(SelfModification {
+                addr,
+                written_by,
+                executed_by: self.pc,
+            });
+        }
+    }
+
+    pub fn reads(&self, addr: u16) -> u64 {
+        self.reads[addr as usize]
+    }
+
+    pub fn writes(&self, addr: u16) -> u64 {
+        self.writes[addr as usize]
+    }
+
+    pub fn executes(&self, addr: u16) -> u64 {
+        self.executes[addr as usize]
+    }
+
+    /// The first time each written byte was executed, by address.
+    pub fn self_modified(&self) -> impl Iterator<Item = &SelfModification> {
+        self.self_modified.values()
+    }
+
+    /// Reads of bytes nothing had written yet, by address.
+    pub fn uninitialized_reads(&self) -> impl Iterator<Item = &UninitializedRead> {
+        self.uninitialized_reads.values()
+    }
+
+    /// Totals for every page that was accessed, followed by the self-modifying code and
+    /// uninitialized reads found.
+    pub fn summary(&self) -> String {
+        let mut out = format!(
+            "{:<6}{:>12}{:>12}{:>12}\n",
+            "Page", "Reads", "Writes", "Executes"
+        );
+        for page in 0..0x100 {
+            let range = page * 0x100..(page + 1) * 0x100;
+            let sum = |counts: &[u64]| counts[range.clone()].iter().sum::<u64>();
+            let (reads, writes, executes) =
+                (sum(&self.reads), sum(&self.writes), sum(&self.executes));
+            if reads + writes + executes > 0 {
+                let _ = writeln!(out, "${page:02X}   {reads:>12}{writes:>12}{executes:>12}");
+            }
+        }
+        if !self.self_modified.is_empty() {
+            out.push_str("\nSelf-modifying code:\n");
+            for modification in self.self_modified() {
+                let _ = writeln!(
+                    out,
+                    "  ${:04X} written by ${:04X}, executed by ${:04X}",
+                    modification.addr, modification.written_by, modification.executed_by
+                );
+            }
+        }
+        if !self.uninitialized_reads.is_empty() {
+            out.push_str("\nReads of never-written memory:\n");
+            for read in self.uninitialized_reads() {
+                let _ = writeln!(
+                    out,
+                    "  ${:04X} read {} times, first by ${:04X}",
+                    read.addr, read.count, read.pc
+                );
+            }
+        }
+        out
+    }
+
+    /// Write a 256x256 binary PPM image with a pixel per address, a row per page. Writes are
+    /// red, reads green and executes blue, brighter the more often they happened.
+    pub fn write_ppm(&self, out: &mut impl Write) -> io::Result<()> {
+        let scale = |counts: &[u64]| {
+            let max = (*counts.iter().max().unwrap_or(&0) as f64).ln_1p();
+            move |count: u64| match count {
+                0 => 0,
+                _ => (64.0 + 191.0 * (count as f64).ln_1p() / max) as u8,
+            }
+        };
+        let (red, green, blue) = (
+            scale(&self.writes),
+            scale(&self.reads),
+            scale(&self.executes),
+        );
+        write!(out, "P6\n256 256\n255\n")?;
+        let mut pixels = Vec::with_capacity(ADDR_SPACE * 3);
+        for addr in 0..ADDR_SPACE {
+            pixels.extend([
+                red(self.writes[addr]),
+                green(self.reads[addr]),
+                blue(self.executes[addr]),
+            ]);
+        }
+        out.write_all(&pixels)
+    }
+}
+
+impl BusObserver for Heatmap {
+    fn fetch(&mut self, access: &BusAccess) {
+        self.pc = access.addr;
+        self.execute(access.addr);
+    }
+
+    fn read(&mut self, access: &BusAccess) {
+        let addr = access.addr;
+        if access.kind == BusKind::Operand {
+            self.execute(addr);
+            return;
+        }
+        self.reads[addr as usize] += 1;
+        if !self.initialized[addr as usize] {
+            let pc = self.pc;
+            self.uninitialized_reads
+                .entry(addr)
+                .or_insert(UninitializedRead { addr, pc, count: 0 })
+                .count += 1;
+        }
+    }
+
+    fn write(&mut self, access: &BusAccess) {
+        let addr = access.addr as usize;
+        self.writes[addr] += 1;
+        self.initialized[addr] = true;
+        self.last_writer[addr] = self.pc;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::memory::{Memory, SimpleMemory};
+    use mini6502_macros::asm6502;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn run(bytes: &[u8], steps: usize) -> (Cpu<SimpleMemory>, Heatmap) {
+        let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(bytes));
+        let heatmap = Rc::new(RefCell::new(Heatmap::new()));
+        heatmap.borrow_mut().mark_initialized(0..bytes.len() as u32);
+        cpu.add_observer(Box::new(heatmap.clone()));
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+        cpu.clear_observers();
+        (cpu, heatmap.take())
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        let program = asm6502! {
+            lda #$A0;
+            sta patch;
+            patch: lda #$05;
+            sta $0200
+        };
+        // The LDA is patched into LDY #$05
+        let (cpu, heatmap) = run(&program.bytes, 4);
+        assert_eq!(cpu.y(), 0x05);
+        assert_eq!(cpu.mem.read_byte(0x0200), 0xA0);
+
+        let patch = program.symbol("patch");
+        assert_eq!(
+            heatmap.self_modified().collect::<Vec<_>>(),
+            [&SelfModification {
+                addr: patch,
+                written_by: 0x0002,
+                executed_by: patch,
+            }]
+        );
+        assert_eq!(heatmap.writes(patch), 1);
+        assert_eq!(heatmap.executes(patch), 1);
+        assert_eq!(heatmap.reads(patch), 0);
+        assert!(heatmap
+            .summary()
+            .contains("  $0005 written by $0002, executed by $0005"));
+    }
+
+    #[test]
+    fn test_summary_and_image() {
+        let program = asm6502! {
+            lda $0300;
+            sta $0301;
+            lda $0301
+        };
+        let (_, heatmap) = run(&program.bytes, 3);
+        assert_eq!(
+            heatmap.uninitialized_reads().collect::<Vec<_>>(),
+            [&UninitializedRead {
+                addr: 0x0300,
+                pc: 0x0000,
+                count: 1
+            }]
+        );
+        let summary: Vec<String> = heatmap.summary().lines().map(str::to_string).collect();
+        assert_eq!(summary[1], "$00              0           0           9");
+        assert_eq!(summary[2], "$03              2           1           0");
+
+        let mut image = vec![];
+        heatmap.write_ppm(&mut image).unwrap();
+        let header = b"P6\n256 256\n255\n";
+        assert_eq!(&image[..header.len()], header);
+        let pixel = |addr: usize| &image[header.len() + addr * 3..header.len() + addr * 3 + 3];
+        assert_eq!(pixel(0x0000), [0, 0, 255]);
+        assert_eq!(pixel(0x0301), [255, 255, 0]);
+        assert_eq!(pixel(0x0400), [0, 0, 0]);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::cpu::Cpu;
+use crate::error::Error6502;
+use crate::memory::Memory;
+
+/// What [`Cpu::run`] does after a hook returns.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Control {
+    #[default]
+    Continue,
+    /// Return from `run`
+    Stop,
+    /// Don't execute the instruction at PC, go on with the one after it. Same as `Continue`
+    /// where there is no instruction about to be executed.
+    Skip,
+    /// Reset the cpu and go on from the reset vector
+    Reset,
+}
+
+/// Callbacks around every instruction [`Cpu::run`] executes. All of them do nothing by default
+/// and `()` implements the trait with the defaults, so unused hooks cost nothing.
+///
+/// ```rust
+/// use mini6502::hooks::{Control, Hooks};
+/// use mini6502::{Cpu, SimpleMemory};
+///
+/// /// Stops at the first BRK, after it pushed PC and P
+/// struct StopAtBrk(u16);
+///
+/// impl Hooks<SimpleMemory> for StopAtBrk {
+///     fn on_interrupt(&mut self, cpu: &mut Cpu<SimpleMemory>, _vector: u16) -> Control {
+///         self.0 = cpu.sp();
+///         Control::Stop
+///     }
+/// }
+///
+/// // ldx #$03; dex; bne $0002; brk
+/// let mem = SimpleMemory::from_rom(&[0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x00]);
+/// let mut cpu = Cpu::with_mem(mem);
+/// let mut hooks = StopAtBrk(0);
+/// cpu.run(&mut hooks).unwrap();
+/// assert_eq!(hooks.0, 0x01FC);
+/// ```
+pub trait Hooks<M: Memory> {
+    /// Called with PC at the instruction about to be executed.
+    fn before_instruction(&mut self, _cpu: &mut Cpu<M>) -> Control {
+        Control::Continue
+    }
+
+    /// Called once the instruction is done, with the cycles it took.
+    fn after_instruction(&mut self, _cpu: &mut Cpu<M>, _cycles: u8) -> Control {
+        Control::Continue
+    }
+
+    /// Called when the cpu enters an interrupt handler, for BRK or an interrupt line of the
+    /// memory, with PC loaded from `vector`.
+    fn on_interrupt(&mut self, _cpu: &mut Cpu<M>, _vector: u16) -> Control {
+        Control::Continue
+    }
+
+    /// Called when the instruction at PC can't be executed. `Stop` returns `err` from `run`,
+    /// `Continue` tries the same instruction again, e.g. after the hook patched it, and `Skip`
+    /// steps over its opcode.
+    fn on_error(&mut self, _cpu: &mut Cpu<M>, _err: &Error6502) -> Control {
+        Control::Stop
+    }
+}
+
+impl<M: Memory> Hooks<M> for () {}
+
+/// Hooks stopping before the first instruction `done` returns `true` for, see [`until`].
+pub struct Until<F>(pub F);
+
+impl<M, F> Hooks<M> for Until<F>
+where
+    M: Memory,
+    F: FnMut(&Cpu<M>) -> bool,
+{
+    fn before_instruction(&mut self, cpu: &mut Cpu<M>) -> Control {
+        match (self.0)(cpu) {
+            true => Control::Stop,
+            false => Control::Continue,
+        }
+    }
+}
+
+/// Run until `done` returns `true` before an instruction.
+///
+/// ```rust
+/// use mini6502::hooks::until;
+/// use mini6502::{Cpu, SimpleMemory};
+///
+/// // ldx #$10; dex; bne $0002
+/// let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&[0xA2, 0x10, 0xCA, 0xD0, 0xFD]));
+/// cpu.run(&mut until(|cpu| cpu.x() == 0x08)).unwrap();
+/// assert_eq!(cpu.pc(), 0x0003);
+/// ```
+pub fn until<M, F>(done: F) -> Until<F>
+where
+    M: Memory,
+    F: FnMut(&Cpu<M>) -> bool,
+{
+    Until(done)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::SimpleMemory;
+    use mini6502_macros::asm6502;
+
+    /// Counts calls and answers with the given actions
+    #[derive(Default)]
+    struct Script {
+        before: Vec<(u16, Control)>,
+        after: usize,
+        interrupts: Vec<u16>,
+        errors: Vec<Error6502>,
+        on_error: Control,
+    }
+
+    impl Hooks<SimpleMemory> for Script {
+        fn before_instruction(&mut self, cpu: &mut Cpu<SimpleMemory>) -> Control {
+            let control = self
+                .before
+                .iter()
+                .find(|(pc, _)| *pc == cpu.pc())
+                .map_or(Control::Continue, |(_, control)| *control);
+            if control == Control::Reset {
+                self.before.clear();
+            }
+            control
+        }
+
+        fn after_instruction(&mut self, _cpu: &mut Cpu<SimpleMemory>, _cycles: u8) -> Control {
+            self.after += 1;
+            Control::Continue
+        }
+
+        fn on_interrupt(&mut self, _cpu: &mut Cpu<SimpleMemory>, vector: u16) -> Control {
+            self.interrupts.push(vector);
+            Control::Continue
+        }
+
+        fn on_error(&mut self, _cpu: &mut Cpu<SimpleMemory>, err: &Error6502) -> Control {
+            self.errors.push(err.clone());
+            self.on_error
+        }
+    }
+
+    #[test]
+    fn test_controls() {
+        let program = asm6502! {
+            ldx #$01;
+            skipped: inx;
+            stop: nop;
+            nop
+        };
+        let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&program.bytes));
+        let mut hooks = Script {
+            before: vec![
+                (program.symbol("skipped"), Control::Skip),
+                (program.symbol("stop"), Control::Stop),
+            ],
+            ..Script::default()
+        };
+        cpu.run(&mut hooks).unwrap();
+        assert_eq!((cpu.pc(), cpu.x()), (program.symbol("stop"), 0x01));
+        assert_eq!(hooks.after, 1);
+
+        // The reset vector points at $0000 here
+        hooks.before = vec![(program.symbol("stop"), Control::Reset)];
+        hooks.on_error = Control::Stop;
+        cpu.mem.load(program.symbol("stop") + 1, &[0xFF]);
+        cpu.set_sp(0xFF);
+        let err = cpu.run(&mut hooks).unwrap_err();
+        assert_eq!(err, Error6502::UnknownOpcode(0xFF));
+        assert_eq!((cpu.pc(), cpu.x()), (program.symbol("stop") + 1, 0x02));
+        assert_eq!(cpu.sp(), 0x01FC);
+        assert!(cpu.i_flag());
+    }
+
+    #[test]
+    fn test_errors_and_interrupts() {
+        // brk; .byte $FF, $FF; nop
+        let mut mem = SimpleMemory::from_rom(&[0x00, 0xFF, 0xFF, 0xEA]);
+        mem.load(0xFFFE, &[0x01, 0x00]);
+        let mut cpu = Cpu::with_mem(mem);
+        let mut hooks = Script {
+            before: vec![(0x0003, Control::Stop)],
+            on_error: Control::Skip,
+            ..Script::default()
+        };
+        cpu.run(&mut hooks).unwrap();
+        assert_eq!(hooks.interrupts, [0xFFFE]);
+        assert_eq!(
+            hooks.errors,
+            [
+                Error6502::UnknownOpcode(0xFF),
+                Error6502::UnknownOpcode(0xFF)
+            ]
+        );
+        assert_eq!(hooks.after, 1);
+        assert_eq!(cpu.pc(), 0x0003);
+    }
+}
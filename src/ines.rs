@@ -0,0 +1,342 @@
+use crate::error::InesError;
+use crate::memory::Memory;
+
+const INES_MAGIC: [u8; 4] = [b'N', b'E', b'S', 0x1A];
+const HEADER_LEN: usize = 16;
+const TRAINER_LEN: usize = 512;
+const PRG_BANK_LEN: usize = 0x4000;
+const CHR_BANK_LEN: usize = 0x2000;
+const INTERNAL_RAM_LEN: usize = 0x0800;
+const PRG_RAM_LEN: usize = 0x2000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+}
+
+/// Parsed contents of an iNES or NES 2.0 header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InesHeader {
+    /// `true` if the header uses the NES 2.0 extensions
+    pub nes2: bool,
+    pub mapper: u16,
+    pub submapper: u8,
+    /// PRG ROM size in bytes
+    pub prg_rom_size: usize,
+    /// CHR ROM size in bytes
+    pub chr_rom_size: usize,
+    /// PRG RAM size in bytes, if the header declares one
+    pub prg_ram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+}
+
+impl InesHeader {
+    /// Parse the 16 byte header at the start of `image`.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - Contents of a `.nes` file
+    pub fn parse(image: &[u8]) -> Result<InesHeader, InesError> {
+        if image.len() < INES_MAGIC.len() || image[..4] != INES_MAGIC {
+            return Err(InesError::BadMagic);
+        }
+        if image.len() < HEADER_LEN {
+            return Err(InesError::Truncated {
+                expected: HEADER_LEN,
+                found: image.len(),
+            });
+        }
+
+        let flags_6 = image[6];
+        let flags_7 = image[7];
+        let nes2 = flags_7 & 0b0000_1100 == 0b0000_1000;
+
+        let mirroring = if flags_6 & 0b0000_1000 != 0 {
+            Mirroring::FourScreen
+        } else if flags_6 & 0b0000_0001 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        let mapper_ll = (flags_6 >> 4) as u16;
+        let header = if nes2 {
+            let mapper = mapper_ll | (flags_7 & 0xF0) as u16 | ((image[8] & 0x0F) as u16) << 8;
+            let prg_ram_shift = image[10] & 0x0F;
+            InesHeader {
+                nes2,
+                mapper,
+                submapper: image[8] >> 4,
+                prg_rom_size: nes2_rom_size(image[4], image[9] & 0x0F, PRG_BANK_LEN)
+                    .ok_or(InesError::SizeOverflow)?,
+                chr_rom_size: nes2_rom_size(image[5], image[9] >> 4, CHR_BANK_LEN)
+                    .ok_or(InesError::SizeOverflow)?,
+                prg_ram_size: if prg_ram_shift == 0 {
+                    0
+                } else {
+                    64 << prg_ram_shift
+                },
+                mirroring,
+                battery: flags_6 & 0b0000_0010 != 0,
+                trainer: flags_6 & 0b0000_0100 != 0,
+            }
+        } else {
+            // Old dumping tools wrote garbage ("DiskDude!") into bytes 7-15, in which case the
+            // upper mapper nibble can't be trusted.
+            let dirty = image[12..HEADER_LEN].iter().any(|b| *b != 0);
+            let mapper = if dirty {
+                mapper_ll
+            } else {
+                mapper_ll | (flags_7 & 0xF0) as u16
+            };
+            InesHeader {
+                nes2,
+                mapper,
+                submapper: 0,
+                prg_rom_size: image[4] as usize * PRG_BANK_LEN,
+                chr_rom_size: image[5] as usize * CHR_BANK_LEN,
+                // A value of 0 means 8 KiB for compatibility
+                prg_ram_size: image[8].max(1) as usize * PRG_RAM_LEN,
+                mirroring,
+                battery: flags_6 & 0b0000_0010 != 0,
+                trainer: flags_6 & 0b0000_0100 != 0,
+            }
+        };
+
+        Ok(header)
+    }
+}
+
+/// Compute a NES 2.0 ROM size from its LSB and MSB nibble, including the exponent-multiplier
+/// notation used when the MSB nibble is $F. `None` if it doesn't fit in a `usize`.
+fn nes2_rom_size(lsb: u8, msb: u8, bank_len: usize) -> Option<usize> {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize.checked_shl(exponent)?.checked_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize).checked_mul(bank_len)
+    }
+}
+
+/// A `.nes` image split into its parts.
+#[derive(Clone, Debug)]
+pub struct InesRom {
+    pub header: InesHeader,
+    pub trainer: Option<Vec<u8>>,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+}
+
+impl InesRom {
+    /// Returns `true` if `image` starts with the iNES magic number.
+    pub fn is_ines(image: &[u8]) -> bool {
+        image.starts_with(&INES_MAGIC)
+    }
+
+    pub fn parse(image: &[u8]) -> Result<InesRom, InesError> {
+        let header = InesHeader::parse(image)?;
+
+        let trainer_len = if header.trainer { TRAINER_LEN } else { 0 };
+        let prg_start = HEADER_LEN + trainer_len;
+        let sizes = prg_start
+            .checked_add(header.prg_rom_size)
+            .and_then(|chr_start| Some((chr_start, chr_start.checked_add(header.chr_rom_size)?)));
+        let (chr_start, end) = sizes.ok_or(InesError::SizeOverflow)?;
+
+        if image.len() < end {
+            return Err(InesError::Truncated {
+                expected: end,
+                found: image.len(),
+            });
+        }
+
+        Ok(InesRom {
+            trainer: header
+                .trainer
+                .then(|| image[HEADER_LEN..prg_start].to_vec()),
+            prg_rom: image[prg_start..chr_start].to_vec(),
+            chr_rom: image[chr_start..end].to_vec(),
+            header,
+        })
+    }
+}
+
+/// CPU side of an NROM (mapper 0) cartridge plugged into a NES, enough to run CPU test ROMs
+/// such as nestest.
+///
+/// * `$0000-$1FFF`: 2 KiB of internal RAM, mirrored every `$0800`
+/// * `$2000-$5FFF`: PPU, APU and expansion registers, not emulated. Reads return `$00` and writes
+///   are ignored
+/// * `$6000-$7FFF`: 8 KiB of PRG RAM
+/// * `$8000-$FFFF`: PRG ROM. A 16 KiB image (NROM-128) is mirrored into `$C000-$FFFF`
+pub struct NromMemory {
+    pub ram: [u8; INTERNAL_RAM_LEN],
+    pub prg_ram: [u8; PRG_RAM_LEN],
+    prg_rom: Vec<u8>,
+}
+
+impl NromMemory {
+    pub fn from_rom(rom: &InesRom) -> Result<NromMemory, InesError> {
+        if rom.header.mapper != 0 {
+            return Err(InesError::UnsupportedMapper(rom.header.mapper));
+        }
+        let prg_len = rom.prg_rom.len();
+        if prg_len != PRG_BANK_LEN && prg_len != 2 * PRG_BANK_LEN {
+            return Err(InesError::BadPrgSize(prg_len));
+        }
+
+        let mut prg_ram = [0x00; PRG_RAM_LEN];
+        if let Some(trainer) = &rom.trainer {
+            // Trainers are mapped at $7000-$71FF
+            prg_ram[0x1000..0x1000 + TRAINER_LEN].copy_from_slice(trainer);
+        }
+
+        Ok(NromMemory {
+            ram: [0x00; INTERNAL_RAM_LEN],
+            prg_ram,
+            prg_rom: rom.prg_rom.clone(),
+        })
+    }
+
+    /// Parse a `.nes` image and map it.
+    ///
+    /// ```rust
+    /// use mini6502::ines::NromMemory;
+    /// use mini6502::memory::Memory;
+    ///
+    /// let mut image = vec![b'N', b'E', b'S', 0x1A, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    /// image.resize(16 + 0x4000, 0xEA);
+    ///
+    /// let mem = NromMemory::from_ines(&image).unwrap();
+    /// assert_eq!(mem.read_byte(0xC000), 0xEA);
+    /// ```
+    pub fn from_ines(image: &[u8]) -> Result<NromMemory, InesError> {
+        let rom = InesRom::parse(image)?;
+        NromMemory::from_rom(&rom)
+    }
+}
+
+impl Memory for NromMemory {
+    fn write_byte(&mut self, addr: u16, byte: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram[addr as usize % INTERNAL_RAM_LEN] = byte,
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000] = byte,
+            // Registers aren't emulated and ROM is read only
+            _ => {}
+        }
+    }
+
+    fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.ram[addr as usize % INTERNAL_RAM_LEN],
+            0x2000..=0x5FFF => 0x00,
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000],
+            0x8000..=0xFFFF => self.prg_rom[(addr as usize - 0x8000) % self.prg_rom.len()],
+        }
+    }
+
+    fn can_interrupt(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_image(prg_banks: u8, flags_6: u8, flags_7: u8) -> Vec<u8> {
+        let mut image = vec![0u8; HEADER_LEN];
+        image[..4].copy_from_slice(&INES_MAGIC);
+        image[4] = prg_banks;
+        image[5] = 1;
+        image[6] = flags_6;
+        image[7] = flags_7;
+        for bank in 0..prg_banks {
+            image.resize(image.len() + PRG_BANK_LEN, bank);
+        }
+        image.resize(image.len() + CHR_BANK_LEN, 0xCC);
+        image
+    }
+
+    #[test]
+    fn test_parse_ines_header() {
+        let image = make_image(2, 0b0001_0001, 0b0010_0000);
+        let header = InesHeader::parse(&image).unwrap();
+        assert!(!header.nes2);
+        assert_eq!(header.mapper, 0x21);
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_rom_size, 0x2000);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+
+        assert_eq!(InesHeader::parse(b"NOPE"), Err(InesError::BadMagic));
+    }
+
+    #[test]
+    fn test_parse_nes2_header() {
+        let mut image = make_image(1, 0b0000_0010, 0b0000_1000);
+        image[8] = 0x31;
+        image[10] = 0x07;
+        let header = InesHeader::parse(&image).unwrap();
+        assert!(header.nes2);
+        assert!(header.battery);
+        assert_eq!(header.mapper, 0x100);
+        assert_eq!(header.submapper, 3);
+        assert_eq!(header.prg_ram_size, 0x2000);
+
+        // Exponent-multiplier notation: 2^2 * 3
+        assert_eq!(nes2_rom_size(0b0000_1001, 0x0F, PRG_BANK_LEN), Some(12));
+
+        // 2^63 * 7 bytes of PRG ROM
+        image[4] = 0xFF;
+        image[9] = 0x0F;
+        assert_eq!(InesHeader::parse(&image), Err(InesError::SizeOverflow));
+        assert_eq!(
+            InesRom::parse(&image).unwrap_err().to_string(),
+            "iNES header declares ROM sizes too big to load"
+        );
+        // 2^63 bytes each of PRG and CHR ROM fit, but not together
+        image[4] = 0xFC;
+        image[5] = 0xFC;
+        image[9] = 0xFF;
+        assert!(InesHeader::parse(&image).is_ok());
+        assert_eq!(InesRom::parse(&image).err(), Some(InesError::SizeOverflow));
+    }
+
+    #[test]
+    fn test_nrom_mirroring() {
+        let image = make_image(1, 0, 0);
+        let mut mem = NromMemory::from_ines(&image).unwrap();
+
+        // NROM-128 appears twice
+        assert_eq!(mem.read_byte(0x8000), 0);
+        assert_eq!(mem.read_byte(0xC000), 0);
+
+        // Internal RAM is mirrored every 2 KiB
+        mem.write_byte(0x0012, 0x42);
+        assert_eq!(mem.read_byte(0x0812), 0x42);
+        assert_eq!(mem.read_byte(0x1812), 0x42);
+
+        // ROM is read only
+        mem.write_byte(0x8000, 0x42);
+        assert_eq!(mem.read_byte(0x8000), 0);
+
+        let image = make_image(2, 0, 0);
+        let mem = NromMemory::from_ines(&image).unwrap();
+        assert_eq!(mem.read_byte(0xBFFF), 0);
+        assert_eq!(mem.read_byte(0xC000), 1);
+    }
+
+    #[test]
+    fn test_nrom_rejects_other_mappers() {
+        let image = make_image(1, 0b0001_0000, 0);
+        assert_eq!(
+            NromMemory::from_ines(&image).err(),
+            Some(InesError::UnsupportedMapper(1))
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use crate::device::Device;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Registers, selected by the low 2 bits of the address
+/// Bit N is set while source N requests an interrupt, masked or not
+const STATUS: u16 = 0x0;
+/// Bit N set enables source N
+const MASK: u16 = 0x1;
+/// STATUS and MASK
+const PENDING: u16 = 0x2;
+/// The lowest numbered pending source, or $FF
+const SOURCE: u16 = 0x3;
+
+/// An interrupt controller ORing the IRQ outputs of up to 8 devices into its own, each enabled
+/// by its bit in a mask register. It decodes 2 address lines:
+///
+/// * `$0`: status, read only, bit N is set while source N requests an interrupt
+/// * `$1`: mask, bit N enables source N, all disabled after a reset
+/// * `$2`: pending, read only, the status of the enabled sources
+/// * `$3`: read only, the number of the lowest pending source, or $FF if there's none
+///
+/// Sources are acknowledged at the device. [`InterruptController::connect`] returns the handle
+/// to map a source with, so its IRQ goes through the controller rather than straight to the cpu.
+///
+/// ```rust
+/// use mini6502::device::DeviceMemory;
+/// use mini6502::interrupt::InterruptController;
+/// use mini6502::memory::Memory;
+/// use mini6502::timer::Timer;
+/// use mini6502::SimpleMemory;
+///
+/// let mut controller = InterruptController::new();
+/// let mut mem = DeviceMemory::new(SimpleMemory::from_rom(&[]));
+/// mem.map(0x8000..=0x8007, Box::new(controller.connect(Timer::new())));
+/// mem.map(0x8010..=0x8017, Box::new(controller.connect(Timer::new())));
+/// mem.map(0x8020..=0x8023, Box::new(controller));
+///
+/// // Source 1 times out after 10 cycles
+/// mem.write_byte(0x8010, 10);
+/// mem.write_byte(0x8015, 0x05);
+/// mem.tick(10);
+/// assert_eq!(mem.read_byte(0x8020), 0x02);
+/// assert!(!mem.irq());
+/// mem.write_byte(0x8021, 0x02);
+/// assert!(mem.irq());
+/// assert_eq!(mem.read_byte(0x8023), 1);
+/// ```
+pub struct InterruptController {
+    sources: Vec<Rc<RefCell<dyn Device>>>,
+    mask: u8,
+}
+
+impl Default for InterruptController {
+    fn default() -> Self {
+        InterruptController::new()
+    }
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        InterruptController {
+            sources: vec![],
+            mask: 0x00,
+        }
+    }
+
+    /// Connect the IRQ output of `device` as the next source, numbered from 0. Map the returned
+    /// handle in its place, it has no IRQ output of its own.
+    ///
+    /// Panics if 8 sources are connected already.
+    pub fn connect<D: Device + 'static>(&mut self, device: D) -> Source {
+        assert!(self.sources.len() < 8, "Only 8 interrupt sources fit");
+        let device = Rc::new(RefCell::new(device));
+        self.sources.push(device.clone());
+        Source(device)
+    }
+
+    /// Bit N set while source N requests an interrupt.
+    pub fn status(&self) -> u8 {
+        self.sources
+            .iter()
+            .enumerate()
+            .filter(|(_, source)| source.borrow().irq())
+            .fold(0x00, |status, (n, _)| status | 1 << n)
+    }
+
+    pub fn pending(&self) -> u8 {
+        self.status() & self.mask
+    }
+}
+
+impl Device for InterruptController {
+    fn read(&mut self, reg: u16) -> u8 {
+        self.peek(reg)
+    }
+
+    fn peek(&self, reg: u16) -> u8 {
+        match reg & 0x03 {
+            STATUS => self.status(),
+            MASK => self.mask,
+            PENDING => self.pending(),
+            SOURCE => match self.pending() {
+                0 => 0xFF,
+                pending => pending.trailing_zeros() as u8,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        if reg & 0x03 == MASK {
+            self.mask = value;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.pending() != 0
+    }
+}
+
+/// A device connected to an [`InterruptController`], its IRQ output only goes to the
+/// controller.
+pub struct Source(Rc<RefCell<dyn Device>>);
+
+impl Device for Source {
+    fn read(&mut self, reg: u16) -> u8 {
+        self.0.borrow_mut().read(reg)
+    }
+
+    fn peek(&self, reg: u16) -> u8 {
+        self.0.borrow().peek(reg)
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        self.0.borrow_mut().write(reg, value)
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.0.borrow_mut().tick(cycles)
+    }
+
+    fn nmi(&self) -> bool {
+        self.0.borrow().nmi()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::DeviceMemory;
+    use crate::hooks::until;
+    use crate::memory::SimpleMemory;
+    use crate::timer::Timer;
+    use crate::via::Via;
+    use crate::Cpu;
+    use mini6502_macros::asm6502;
+
+    #[test]
+    fn test_masks() {
+        let mut controller = InterruptController::new();
+        let via = Rc::new(RefCell::new(Via::new()));
+        let mut sources: Vec<Source> = (0..7).map(|_| controller.connect(Timer::new())).collect();
+        let mut via_source = controller.connect(via.clone());
+        assert_eq!(controller.read(SOURCE), 0xFF);
+
+        // The VIA interrupts on CA1, its own output is disconnected
+        via_source.write(0xE, 0x82);
+        via.borrow_mut().set_ca1(true);
+        via.borrow_mut().set_ca1(false);
+        assert!(via.borrow().irq() && !via_source.irq());
+        assert_eq!(controller.read(STATUS), 0x80);
+        assert!(!controller.irq());
+
+        // Timers 2 and 5 time out
+        for n in [2, 5] {
+            sources[n].write(0x0, 1);
+            sources[n].write(0x5, 0x05);
+            sources[n].tick(1);
+        }
+        controller.write(MASK, 0xA0);
+        assert_eq!(
+            (controller.read(PENDING), controller.read(SOURCE)),
+            (0xA0, 5)
+        );
+        controller.write(MASK, 0xFF);
+        assert_eq!(controller.read(SOURCE), 2);
+        // Only the mask can be written
+        controller.write(STATUS, 0x00);
+        assert_eq!(controller.read(STATUS), 0xA4);
+        assert!(controller.irq());
+    }
+
+    #[test]
+    fn test_dispatch() {
+        // Count the interrupts of the second timer, acknowledging the source the controller
+        // reports
+        let program = asm6502! {
+            .org $F000;
+            reset: ldy #$00;
+            lda #$02;
+            sta $8021;
+            lda #$32;
+            sta $8010;
+            sta $8000;
+            lda #$07;
+            sta $8015;
+            sta $8005;
+            cli;
+            wait: cpy #$03;
+            bne wait;
+            done: jmp done;
+            irq: lda $8023;
+            asl a;
+            asl a;
+            asl a;
+            asl a;
+            tax;
+            lda #$80;
+            sta $8006,x;
+            iny;
+            rti;
+            .res $FFFC - *;
+            .word reset;
+            .word irq
+        };
+        let mut rom = vec![0x00; 0xF000];
+        rom.extend(&program.bytes);
+        let mut mem = DeviceMemory::new(SimpleMemory::from_rom(&rom));
+        let mut controller = InterruptController::new();
+        let first = Rc::new(RefCell::new(Timer::new()));
+        mem.map(0x8000..=0x8007, Box::new(controller.connect(first.clone())));
+        mem.map(0x8010..=0x8017, Box::new(controller.connect(Timer::new())));
+        mem.map(0x8020..=0x8023, Box::new(controller));
+        let mut cpu = Cpu::with_mem(mem);
+        cpu.run(&mut until(|cpu| cpu.pc() == program.symbol("done")))
+            .unwrap();
+        // 3 periods of 50 cycles after the setup, the masked timer timed out too without
+        // interrupting
+        assert!((200..250).contains(&cpu.cycle_count()));
+        assert!(first.borrow().irq());
+    }
+}
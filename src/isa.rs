@@ -0,0 +1,361 @@
+use crate::disasm::{opcode_table, Mode, Options};
+use std::fmt::Display;
+use std::ops::BitOr;
+
+/// A set of status flags, with the bits they have in P.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Flags(pub u8);
+
+impl Flags {
+    pub const NONE: Flags = Flags(0);
+    pub const N: Flags = Flags(0b1000_0000);
+    pub const V: Flags = Flags(0b0100_0000);
+    pub const D: Flags = Flags(0b0000_1000);
+    pub const I: Flags = Flags(0b0000_0100);
+    pub const Z: Flags = Flags(0b0000_0010);
+    pub const C: Flags = Flags(0b0000_0001);
+    /// Every flag P holds, B and the unused bit only exist on the stack
+    pub const ALL: Flags = Flags(0b1100_1111);
+
+    pub const fn contains(self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Flags;
+
+    fn bitor(self, rhs: Flags) -> Flags {
+        Flags(self.0 | rhs.0)
+    }
+}
+
+/// The letters of the flags in the set, like `NZC`, or `-` if it's empty.
+impl Display for Flags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return f.write_str("-");
+        }
+        for (flag, letter) in [
+            (Flags::N, 'N'),
+            (Flags::V, 'V'),
+            (Flags::D, 'D'),
+            (Flags::I, 'I'),
+            (Flags::Z, 'Z'),
+            (Flags::C, 'C'),
+        ] {
+            if self.contains(flag) {
+                f.write_fmt(format_args!("{letter}"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Everything there is to know about an opcode short of executing it.
+///
+/// The timing is the real chip's. [`Cpu`](crate::Cpu) only counts `cycles`, it doesn't add the
+/// page crossing and taken branch penalties, so tools wanting exact timing have to add them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub mode: Mode,
+    /// Bytes, opcode included
+    pub len: u16,
+    /// Cycles before any penalty
+    pub cycles: u8,
+    /// One more cycle when indexing crosses a page
+    pub page_penalty: bool,
+    /// One more cycle when the branch is taken, and another when it lands in a different page
+    pub branch_penalty: bool,
+    pub flags_read: Flags,
+    pub flags_written: Flags,
+    /// Whether it reads the byte at the address its operand selects
+    pub reads_memory: bool,
+    /// Whether it writes the byte at the address its operand selects
+    pub writes_memory: bool,
+    pub documented: bool,
+}
+
+/// How an instruction goes about its operand, which decides its timing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Read,
+    Store,
+    Modify,
+    Branch,
+    /// Anything timed on its own
+    Other,
+}
+
+/// The kind of `mnemonic` and the flags it reads and writes.
+fn semantics(mnemonic: &str, cmos: bool) -> (Kind, Flags, Flags) {
+    use Flags as F;
+    let nz = F::N | F::Z;
+    let nzc = nz | F::C;
+    let nvzc = nzc | F::V;
+    // The bit instructions carry the bit number in their mnemonic
+    let mnemonic = match mnemonic.get(..3) {
+        Some(prefix @ ("RMB" | "SMB" | "BBR" | "BBS")) => prefix,
+        _ => mnemonic,
+    };
+    match mnemonic {
+        "ADC" | "SBC" | "ARR" => (Kind::Read, F::C | F::D, nvzc),
+        "AND" | "EOR" | "ORA" | "LDA" | "LDX" | "LDY" | "LAX" | "LAS" | "ANE" | "LXA" => {
+            (Kind::Read, F::NONE, nz)
+        }
+        "CMP" | "CPX" | "CPY" | "ANC" | "ALR" | "SBX" => (Kind::Read, F::NONE, nzc),
+        "BIT" => (Kind::Read, F::NONE, F::N | F::V | F::Z),
+        "NOP" => (Kind::Read, F::NONE, F::NONE),
+        "STA" | "STX" | "STY" | "STZ" | "SAX" | "SHA" | "SHX" | "SHY" | "TAS" => {
+            (Kind::Store, F::NONE, F::NONE)
+        }
+        "ASL" | "LSR" | "SLO" | "SRE" | "DCP" => (Kind::Modify, F::NONE, nzc),
+        "ROL" | "ROR" | "RLA" => (Kind::Modify, F::C, nzc),
+        "RRA" | "ISC" => (Kind::Modify, F::C | F::D, nvzc),
+        "INC" | "DEC" => (Kind::Modify, F::NONE, nz),
+        "TRB" | "TSB" => (Kind::Modify, F::NONE, F::Z),
+        "RMB" | "SMB" => (Kind::Modify, F::NONE, F::NONE),
+        "BCC" | "BCS" => (Kind::Branch, F::C, F::NONE),
+        "BEQ" | "BNE" => (Kind::Branch, F::Z, F::NONE),
+        "BMI" | "BPL" => (Kind::Branch, F::N, F::NONE),
+        "BVC" | "BVS" => (Kind::Branch, F::V, F::NONE),
+        "BRA" => (Kind::Branch, F::NONE, F::NONE),
+        // They test a bit of the zero page byte, not a flag
+        "BBR" | "BBS" => (Kind::Branch, F::NONE, F::NONE),
+        // P is pushed, and the 65C02 clears D too
+        "BRK" if cmos => (Kind::Other, F::ALL, F::I | F::D),
+        "BRK" => (Kind::Other, F::ALL, F::I),
+        "PHP" => (Kind::Other, F::ALL, F::NONE),
+        "PLP" | "RTI" => (Kind::Other, F::NONE, F::ALL),
+        "CLC" | "SEC" => (Kind::Other, F::NONE, F::C),
+        "CLD" | "SED" => (Kind::Other, F::NONE, F::D),
+        "CLI" | "SEI" => (Kind::Other, F::NONE, F::I),
+        "CLV" => (Kind::Other, F::NONE, F::V),
+        "TAX" | "TAY" | "TSX" | "TXA" | "TYA" | "INX" | "INY" | "DEX" | "DEY" | "PLA" | "PLX"
+        | "PLY" => (Kind::Other, F::NONE, nz),
+        _ => (Kind::Other, F::NONE, F::NONE),
+    }
+}
+
+/// Whether `mode` has the instruction access memory through its operand.
+const fn addresses_memory(mode: Mode) -> bool {
+    !matches!(mode, Mode::ACC | Mode::IMM | Mode::IMPL | Mode::REL)
+}
+
+/// Base cycles and whether crossing a page costs one more.
+fn timing(opcode: u8, mnemonic: &str, mode: Mode, kind: Kind, cmos: bool) -> (u8, bool) {
+    use Mode::*;
+    let indexed = matches!(mode, ABSX | ABSY | INDY);
+    match kind {
+        // The 65C02's reserved 1 byte NOPs take a single cycle, and $5C takes 8
+        Kind::Read if cmos && mnemonic == "NOP" && mode == IMPL && opcode != 0xEA => (1, false),
+        Kind::Read if cmos && opcode == 0x5C => (8, false),
+        Kind::Read => {
+            let cycles = match mode {
+                IMPL | IMM => 2,
+                ZPG => 3,
+                ZPGX | ZPGY | ABS | ABSX | ABSY => 4,
+                INDY | ZPGIND => 5,
+                _ => 6,
+            };
+            (cycles, indexed)
+        }
+        Kind::Store => {
+            let cycles = match mode {
+                ZPG => 3,
+                ZPGX | ZPGY | ABS => 4,
+                ABSX | ABSY | ZPGIND => 5,
+                _ => 6,
+            };
+            (cycles, false)
+        }
+        // The 65C02 fixed shifts and rotates to only pay for crossing a page
+        Kind::Modify if cmos && mode == ABSX && !matches!(mnemonic, "INC" | "DEC") => (6, true),
+        Kind::Modify => {
+            let cycles = match mode {
+                ACC => 2,
+                ZPG => 5,
+                ZPGX | ABS => 6,
+                ABSX | ABSY => 7,
+                _ => 8,
+            };
+            (cycles, false)
+        }
+        Kind::Branch if mode == ZPGREL => (5, false),
+        Kind::Branch => (2, false),
+        Kind::Other => {
+            let cycles = match (mnemonic, mode) {
+                ("BRK", _) => 7,
+                ("JMP", ABS) => 3,
+                ("JMP", IND) if !cmos => 5,
+                ("JMP", _) | ("JSR", _) | ("RTS", _) | ("RTI", _) => 6,
+                ("PHA" | "PHP" | "PHX" | "PHY", _) => 3,
+                ("PLA" | "PLP" | "PLX" | "PLY", _) => 4,
+                ("WAI" | "STP", _) => 3,
+                // Locks the cpu up
+                ("JAM", _) => 0,
+                _ => 2,
+            };
+            (cycles, false)
+        }
+    }
+}
+
+/// Every opcode of a cpu variant with its mnemonic, timing and effects, built from the same
+/// table the assembler and disassembler use.
+///
+/// ```rust
+/// use mini6502::disasm::Options;
+/// use mini6502::isa::{Flags, InstructionSet};
+/// use mini6502::disasm::Mode;
+///
+/// let isa = InstructionSet::new(Options::default());
+/// let lda = isa.get(0xBD).unwrap();
+/// assert_eq!((lda.mnemonic, lda.mode, lda.len), ("LDA", Mode::ABSX, 3));
+/// assert_eq!((lda.cycles, lda.page_penalty), (4, true));
+/// assert_eq!(lda.flags_written, Flags::N | Flags::Z);
+/// assert!(lda.reads_memory && !lda.writes_memory);
+///
+/// assert_eq!(isa.find("ROR", Mode::ZPG).unwrap().opcode, 0x66);
+/// assert!(isa.get(0x07).is_none());
+/// ```
+#[derive(Clone, Debug)]
+pub struct InstructionSet {
+    table: [Option<OpcodeInfo>; 0x100],
+}
+
+impl InstructionSet {
+    pub fn new(options: Options) -> Self {
+        let mut table = [None; 0x100];
+        for (byte, opcode) in opcode_table(options).iter().enumerate() {
+            let Some(opcode) = opcode else { continue };
+            let (mnemonic, mode) = (opcode.mnemonic, opcode.mode);
+            let (kind, mut flags_read, mut flags_written) = semantics(mnemonic, options.cmos);
+            let (cycles, page_penalty) = timing(byte as u8, mnemonic, mode, kind, options.cmos);
+            // BIT #imm has no memory operand to take N and V from
+            if mnemonic == "BIT" && mode == Mode::IMM {
+                flags_written = Flags::Z;
+            }
+            // Not a real instruction, the stuck cpu reads nothing
+            if mnemonic == "JAM" {
+                flags_read = Flags::NONE;
+            }
+            let memory = addresses_memory(mode) && !matches!(mnemonic, "JMP" | "JSR");
+            table[byte] = Some(OpcodeInfo {
+                opcode: byte as u8,
+                mnemonic,
+                mode,
+                len: mode.instr_len(),
+                cycles,
+                page_penalty,
+                branch_penalty: kind == Kind::Branch,
+                flags_read,
+                flags_written,
+                reads_memory: memory && matches!(kind, Kind::Read | Kind::Modify | Kind::Branch),
+                writes_memory: memory && matches!(kind, Kind::Store | Kind::Modify),
+                documented: opcode.documented,
+            });
+        }
+        InstructionSet { table }
+    }
+
+    pub fn get(&self, opcode: u8) -> Option<&OpcodeInfo> {
+        self.table[opcode as usize].as_ref()
+    }
+
+    /// Every opcode there is, in order.
+    pub fn iter(&self) -> impl Iterator<Item = &OpcodeInfo> {
+        self.table.iter().flatten()
+    }
+
+    /// The opcode for `mnemonic` in `mode`, preferring documented ones. Case insensitive.
+    pub fn find(&self, mnemonic: &str, mode: Mode) -> Option<&OpcodeInfo> {
+        let mut matching = self
+            .iter()
+            .filter(|info| info.mode == mode && info.mnemonic.eq_ignore_ascii_case(mnemonic));
+        let first = matching.next()?;
+        Some(if first.documented {
+            first
+        } else {
+            matching.find(|info| info.documented).unwrap_or(first)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::opc::{self, OpMode};
+
+    #[test]
+    fn test_matches_cpu() {
+        let isa = InstructionSet::new(Options::default());
+        // The cpu executes the documented NMOS opcodes and nothing else
+        let executed: Vec<u8> = opc::init_opc_array()
+            .iter()
+            .enumerate()
+            .filter_map(|(byte, op_mode)| op_mode.map(|_| byte as u8))
+            .collect();
+        let documented: Vec<u8> = isa.iter().map(|info| info.opcode).collect();
+        assert_eq!(executed, documented);
+        assert_eq!(documented.len(), 151);
+        for (byte, op_mode) in opc::init_opc_array().iter().enumerate() {
+            if let Some(OpMode(inst, mode, cycles)) = op_mode {
+                let info = isa.get(byte as u8).unwrap();
+                assert_eq!(
+                    (info.mnemonic, info.mode, info.cycles),
+                    (inst.mnemonic(), (*mode).into(), *cycles),
+                    "opcode ${byte:02X}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_variants() {
+        let nmos = InstructionSet::new(Options::default());
+        let info = |opcode| *nmos.get(opcode).unwrap();
+        assert_eq!(info(0x60).cycles, 6);
+        assert!(info(0xF0).branch_penalty);
+        assert_eq!(info(0xF0).flags_read.to_string(), "Z");
+        assert_eq!(info(0x28).flags_written.to_string(), "NVDIZC");
+        assert_eq!(info(0xEA).flags_written.to_string(), "-");
+        let inc = info(0xFE);
+        assert_eq!((inc.cycles, inc.page_penalty), (7, false));
+        assert!(inc.reads_memory && inc.writes_memory);
+        assert!(!info(0x4C).reads_memory);
+        assert!(!info(0x91).reads_memory && info(0x91).writes_memory);
+        assert_eq!(nmos.iter().filter(|info| info.documented).count(), 151);
+
+        let illegal = InstructionSet::new(Options {
+            illegal: true,
+            ..Options::default()
+        });
+        let lax = illegal.get(0xB3).unwrap();
+        assert_eq!(
+            (lax.mnemonic, lax.cycles, lax.page_penalty),
+            ("LAX", 5, true)
+        );
+        assert!(!lax.documented);
+        assert_eq!(illegal.find("SBC", Mode::IMM).unwrap().opcode, 0xE9);
+        assert_eq!(illegal.find("dcp", Mode::INDY).unwrap().cycles, 8);
+
+        let cmos = InstructionSet::new(Options {
+            cmos: true,
+            ..Options::default()
+        });
+        let bbr = cmos.get(0x2F).unwrap();
+        assert_eq!((bbr.mnemonic, bbr.len, bbr.cycles), ("BBR2", 3, 5));
+        assert!(bbr.reads_memory && bbr.branch_penalty);
+        assert_eq!(cmos.get(0x89).unwrap().flags_written, Flags::Z);
+        assert_eq!(cmos.get(0x6C).unwrap().cycles, 6);
+        assert_eq!(cmos.get(0x1E).unwrap().cycles, 6);
+        assert_eq!(cmos.get(0x00).unwrap().flags_written.to_string(), "DI");
+    }
+}
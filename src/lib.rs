@@ -1,11 +1,44 @@
 #![feature(bigint_helper_methods)]
+pub mod acia;
+pub mod apple1;
+pub mod asm;
 mod bcd;
+pub mod bus;
+pub mod callstack;
+pub mod coverage;
 pub mod cpu;
+pub mod dap;
+pub mod dbginfo;
+pub mod debugger;
+pub mod device;
+pub mod disasm;
 pub mod error;
+pub mod expr;
+pub mod gdb;
+pub mod heatmap;
+pub mod hooks;
+pub mod interrupt;
+pub mod isa;
 pub use cpu::Cpu;
+pub use format::CpuWithSymbols;
 pub use memory::SimpleMemory;
+pub use opc::{AddressMode, Inst};
 mod format;
+pub mod ines;
+mod json;
 pub mod memory;
 mod opc;
+pub mod pia;
+pub mod profile;
+pub mod riot;
+pub mod serial;
+pub mod stackcheck;
+pub mod symbols;
 mod test;
+pub mod timer;
+pub mod trace;
+pub mod tracediff;
+pub mod uninit;
 pub mod util;
+pub mod via;
+pub mod watchpoint;
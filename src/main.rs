@@ -1,42 +1,743 @@
-use clap::{Arg, Command};
+use clap::{Arg, ArgMatches, Command};
+use mini6502::acia::Acia;
+use mini6502::apple1;
+use mini6502::asm;
+use mini6502::coverage::Coverage;
 use mini6502::cpu::Cpu;
-use mini6502::memory::SimpleMemory;
+use mini6502::dap::DapServer;
+use mini6502::dbginfo::DebugInfo;
+use mini6502::debugger::Debugger;
+use mini6502::device::DeviceMemory;
+use mini6502::disasm::{self, Disassembler};
+use mini6502::gdb::GdbStub;
+use mini6502::heatmap::Heatmap;
+use mini6502::ines::{InesRom, NromMemory};
+use mini6502::memory::{Memory, SimpleMemory};
+use mini6502::profile::Profiler;
+use mini6502::serial::{self, Serial};
+use mini6502::stackcheck::{Severity, StackChecker};
+use mini6502::symbols::SymbolTable;
+use mini6502::trace::{TraceFormat, Tracer};
+use mini6502::tracediff::{self, DiffOptions};
+use mini6502::uninit::UninitChecker;
+use std::cell::RefCell;
 use std::error::Error;
 use std::fs;
-pub fn main() -> Result<(), Box<dyn Error>> {
-    let matches = Command::new("mini6502")
-        .author("Carlos Carral")
-        .arg(Arg::new("bin").value_name("FILE"))
-        .arg(
-            Arg::new("step")
-                .long("--step")
-                .short('s')
-                .required(false)
-                .help("Step trough every instruction."),
-        )
-        .get_matches();
+use std::io::{BufWriter, Write};
+use std::ops::{Range, RangeInclusive};
+use std::path::Path;
+use std::rc::Rc;
 
-    let file_name = matches.value_of("bin").unwrap();
+fn load_symbols(matches: &ArgMatches) -> Result<SymbolTable, Box<dyn Error>> {
+    match matches.value_of("symbols") {
+        Some(file_name) => Ok(SymbolTable::load(file_name)?),
+        None => Ok(SymbolTable::new()),
+    }
+}
 
-    match fs::read(file_name) {
-        Ok(contents) => {
-            let mem = SimpleMemory::from_rom(&contents);
-            let mut cpu = Cpu::with_mem(mem);
-            let stdin = std::io::stdin();
-            let mut buf = String::new();
-
-            let mut callable = |cpu: &Cpu<SimpleMemory>| {
-                print!("\r{cpu}\n");
-                stdin.read_line(&mut buf).unwrap();
-                false
+/// Where things are in the memory a program runs in.
+struct Layout {
+    /// The loaded program
+    code: RangeInclusive<u16>,
+    /// What can be read without being written first: ROM, the program and I/O registers
+    initialized: Vec<Range<u32>>,
+    /// RAM that isn't loaded with anything
+    ram: Vec<Range<u32>>,
+}
+
+fn run<M: Memory>(mem: M, layout: Layout, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut symbols = load_symbols(matches)?;
+    let debug_info = match matches.value_of("dbgfile") {
+        Some(file_name) => Some(DebugInfo::load(file_name)?),
+        None => None,
+    };
+    if let Some(info) = &debug_info {
+        symbols.extend(info.symbols());
+    }
+    let mut cpu = Cpu::with_mem(mem);
+    if let Some(pc) = matches.value_of("pc").map(parse_addr).transpose()? {
+        cpu.set_pc(pc);
+    }
+
+    if let Some(addr) = matches.value_of("gdb") {
+        GdbStub::new(cpu).listen(addr)?;
+        return Ok(());
+    }
+
+    if matches.is_present("step") {
+        return debug(cpu, symbols, debug_info, stack_checker(matches));
+    }
+
+    let code = &layout.code;
+    if matches.is_present("heatmap") || matches.is_present("memory-stats") {
+        let heatmap = Rc::new(RefCell::new(Heatmap::new()));
+        for range in &layout.initialized {
+            heatmap.borrow_mut().mark_initialized(range.clone());
+        }
+        cpu.add_observer(Box::new(heatmap.clone()));
+        let stopped = run_free(
+            &mut cpu,
+            code,
+            &symbols,
+            debug_info.as_ref(),
+            matches,
+            &mut |_| (),
+            &mut |_| (),
+        );
+        cpu.clear_observers();
+        let heatmap = heatmap.borrow();
+        if let Some(file_name) = matches.value_of("heatmap") {
+            let mut out = BufWriter::new(fs::File::create(file_name)?);
+            heatmap.write_ppm(&mut out)?;
+            out.flush()?;
+        }
+        if let Some(file_name) = matches.value_of("memory-stats") {
+            fs::write(file_name, heatmap.summary())?;
+        }
+        drop(heatmap);
+        return match stopped? {
+            Some(checker) => debug(cpu, symbols, debug_info, Some(checker)),
+            None => Ok(()),
+        };
+    }
+
+    if matches.is_present("check-uninit") || matches.is_present("randomize-ram") {
+        let checker = Rc::new(RefCell::new(UninitChecker::new()));
+        for range in &layout.initialized {
+            checker.borrow_mut().mark_initialized(range.clone());
+        }
+        if let Some(seed) = matches.value_of("randomize-ram") {
+            let seed: u64 = seed
+                .parse()
+                .map_err(|e| format!("Invalid seed \"{seed}\": {e}"))?;
+            for range in &layout.ram {
+                checker
+                    .borrow()
+                    .randomize(&mut cpu.mem, range.clone(), seed);
+            }
+        }
+        let report = matches.is_present("check-uninit");
+        // The cpu and both closures need the checker, one at a time
+        cpu.add_observer(Box::new(checker.clone()));
+        let stopped = run_free(
+            &mut cpu,
+            code,
+            &symbols,
+            debug_info.as_ref(),
+            matches,
+            &mut |cpu| checker.borrow_mut().before_instruction(cpu),
+            &mut |cpu| {
+                let mut checker = checker.borrow_mut();
+                let reads = checker.after_instruction(cpu);
+                if report {
+                    for read in reads {
+                        eprintln!("{}", read.describe(&symbols));
+                    }
+                }
+            },
+        )?;
+        cpu.clear_observers();
+        return match stopped {
+            Some(checker) => debug(cpu, symbols, debug_info, Some(checker)),
+            None => Ok(()),
+        };
+    }
+
+    let stopped = run_free(
+        &mut cpu,
+        code,
+        &symbols,
+        debug_info.as_ref(),
+        matches,
+        &mut |_| (),
+        &mut |_| (),
+    )?;
+    match stopped {
+        Some(checker) => debug(cpu, symbols, debug_info, Some(checker)),
+        None => Ok(()),
+    }
+}
+
+/// The stack checker `--check-stack` asks for, if any.
+fn stack_checker(matches: &ArgMatches) -> Option<StackChecker> {
+    let severity = Severity::parse(matches.value_of("check-stack")?)?;
+    let mut checker = StackChecker::new();
+    checker.severity = severity;
+    Some(checker)
+}
+
+/// Start the interactive debugger on `cpu`.
+fn debug<M: Memory>(
+    cpu: Cpu<M>,
+    symbols: SymbolTable,
+    debug_info: Option<DebugInfo>,
+    stack_checker: Option<StackChecker>,
+) -> Result<(), Box<dyn Error>> {
+    let mut debugger = Debugger::new(cpu);
+    debugger.symbols = symbols;
+    debugger.debug_info = debug_info;
+    debugger.stack_checker = stack_checker;
+    debugger.repl(&mut std::io::stdin().lock(), &mut std::io::stdout())?;
+    Ok(())
+}
+
+/// Run `cpu` without the debugger, writing whatever traces and reports `matches` asks for.
+/// `before` and `after` are called around every instruction the cpu executes. Returns the
+/// stack checker if it found a violation that should break into the debugger.
+#[allow(clippy::too_many_arguments)]
+fn run_free<M: Memory>(
+    cpu: &mut Cpu<M>,
+    code: &RangeInclusive<u16>,
+    symbols: &SymbolTable,
+    debug_info: Option<&DebugInfo>,
+    matches: &ArgMatches,
+    before: &mut dyn FnMut(&mut Cpu<M>),
+    after: &mut dyn FnMut(&mut Cpu<M>),
+) -> Result<Option<StackChecker>, Box<dyn Error>> {
+    let mut tracer = match matches.value_of("trace") {
+        Some(file_name) => {
+            let format = TraceFormat::parse(matches.value_of("trace-format").unwrap())?;
+            let out: Box<dyn Write> = if file_name == "-" {
+                Box::new(std::io::stdout())
+            } else {
+                Box::new(fs::File::create(file_name)?)
             };
-            cpu.run(&mut callable)?;
+            let mut tracer = Tracer::new(BufWriter::new(out), format);
+            tracer.symbols = symbols.clone();
+            tracer.debug_info = debug_info.cloned();
+            Some(tracer)
+        }
+        None => None,
+    };
+    let mut profiler = matches.value_of("profile").map(|_| {
+        let mut profiler = Profiler::new();
+        profiler.symbols = symbols.clone();
+        profiler
+    });
+    let mut coverage = matches.value_of("coverage").map(|_| Coverage::new());
+    if coverage.is_some()
+        && matches.value_of("coverage-format") == Some("lcov")
+        && debug_info.is_none()
+    {
+        return Err("lcov coverage needs --dbgfile to map addresses to source lines".into());
+    }
+    let mut stack_checker = stack_checker(matches);
+    let mut trace_error = None;
+    let mut stopped = false;
+    let result: Result<(), Box<dyn Error>> = loop {
+        if let Err(err) = cpu.next_inst() {
+            break Err(err.into());
+        }
+        if let Some(profiler) = profiler.as_mut() {
+            profiler.record(cpu);
+        }
+        if let Some(coverage) = coverage.as_mut() {
+            coverage.record(cpu);
+        }
+        if let Some(Err(err)) = tracer.as_mut().map(|tracer| tracer.trace(cpu)) {
+            trace_error = Some(err);
+            break Ok(());
+        }
+        if let Some(checker) = stack_checker.as_mut() {
+            checker.before_instruction(cpu);
+        }
+        before(cpu);
+        let step = cpu.step();
+        after(cpu);
+        if let Err(err) = step {
+            break Err(err.into());
+        }
+        if let Some(checker) = stack_checker.as_mut() {
+            if let Some(violation) = checker.after_instruction(cpu) {
+                match checker.severity {
+                    Severity::Warn => eprintln!("Warning: {violation}"),
+                    Severity::Error => break Err(violation.into()),
+                    Severity::Break => {
+                        eprintln!("{violation}");
+                        stopped = true;
+                        break Ok(());
+                    }
+                }
+            }
         }
+    };
+    if let Some(tracer) = tracer.as_mut() {
+        tracer.flush()?;
+    }
+    if let Some(err) = trace_error {
+        return Err(err.into());
+    }
+    if let Some(profiler) = profiler.as_mut() {
+        profiler.record(cpu);
+        write_profile(profiler, matches)?;
+    }
+    if let Some(coverage) = &coverage {
+        let file_name = matches.value_of("coverage").unwrap();
+        let mut out = BufWriter::new(fs::File::create(file_name)?);
+        match debug_info {
+            Some(info) if matches.value_of("coverage-format") == Some("lcov") => {
+                coverage.write_lcov(&mut out, &cpu.mem, info)?
+            }
+            _ => {
+                let listing = coverage.listing(&cpu.mem, *code.start(), *code.end(), Some(symbols));
+                out.write_all(listing.as_bytes())?
+            }
+        }
+        out.flush()?;
+    }
+    if let Err(err) = result {
+        eprintln!("{}", cpu.with_symbols(symbols));
+        if let Some(location) = debug_info.and_then(|info| info.location(cpu.pc())) {
+            eprintln!("    at {location}");
+        }
+        return Err(err);
+    }
+    Ok(stack_checker.filter(|_| stopped))
+}
+
+fn write_profile(profiler: &Profiler, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let file_name = matches.value_of("profile").unwrap();
+    let mut out: Box<dyn Write> = if file_name == "-" {
+        Box::new(std::io::stderr())
+    } else {
+        Box::new(BufWriter::new(fs::File::create(file_name)?))
+    };
+    match matches.value_of("profile-format").unwrap() {
+        "folded" => profiler.write_folded(&mut out)?,
+        _ => out.write_all(profiler.report().as_bytes())?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn parse_addr(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|e| format!("Invalid address \"{s}\": {e}"))
+}
+
+/// Open the --serial line for `device`.
+fn open_serial(device: &str, matches: &ArgMatches) -> Result<Box<dyn Serial>, Box<dyn Error>> {
+    let spec = matches.value_of("serial").unwrap();
+    if spec == "stdio" && matches.is_present("step") {
+        return Err(
+            format!("{device} can't share the terminal with the debugger, use --serial").into(),
+        );
+    }
+    Ok(serial::open(spec)?)
+}
+
+/// Map the devices `matches` asks for over `mem`, their registers count as initialized.
+fn map_devices<M: Memory>(
+    mem: M,
+    layout: &mut Layout,
+    matches: &ArgMatches,
+) -> Result<DeviceMemory<M>, Box<dyn Error>> {
+    let mut mem = DeviceMemory::new(mem);
+    if let Some(addr) = matches.value_of("acia").map(parse_addr).transpose()? {
+        let end = addr.saturating_add(3);
+        mem.map(
+            addr..=end,
+            Box::new(Acia::new(open_serial("The ACIA", matches)?)),
+        );
+        layout.initialized.push(addr as u32..end as u32 + 1);
+    }
+    Ok(mem)
+}
+
+fn read_file(file_name: &str) -> Vec<u8> {
+    match fs::read(file_name) {
+        Ok(contents) => contents,
         Err(os_err_msg) => {
             eprintln!("Error while opening file \"{file_name}\": {os_err_msg}");
             std::process::exit(-1);
         }
+    }
+}
+
+fn run_command(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let file_name = matches.value_of("bin").ok_or("No FILE to run was given")?;
+    let contents = read_file(file_name);
+
+    if matches.value_of("machine") == Some("apple1") {
+        let rom = apple1::rom_range(&contents)?;
+        let mem = apple1::memory(&contents, open_serial("The Apple I terminal", matches)?)?;
+        let pia = *apple1::PIA.start() as u32..*apple1::PIA.end() as u32 + 1;
+        let mut layout = Layout {
+            code: rom.clone(),
+            initialized: vec![*rom.start() as u32..*rom.end() as u32 + 1, pia],
+            ram: vec![apple1::RAM],
+        };
+        let mem = map_devices(mem, &mut layout, matches)?;
+        run(mem, layout, matches)
+    } else if InesRom::is_ines(&contents) {
+        let mem = NromMemory::from_ines(&contents)?;
+        let mut layout = Layout {
+            code: 0x8000..=0xFFFF,
+            initialized: vec![0x2000..0x6000, 0x8000..0x10000],
+            ram: vec![0x0000..0x0800, 0x6000..0x8000],
+        };
+        let mem = map_devices(mem, &mut layout, matches)?;
+        run(mem, layout, matches)
+    } else {
+        let mem = SimpleMemory::from_rom(&contents);
+        let end = contents.len().clamp(1, 0x10000) as u32;
+        let mut layout = Layout {
+            code: 0..=(end - 1) as u16,
+            initialized: std::iter::once(0..end).collect(),
+            ram: std::iter::once(end..0x10000).collect(),
+        };
+        let mem = map_devices(mem, &mut layout, matches)?;
+        run(mem, layout, matches)
+    }
+}
+
+fn disasm_command(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let file_name = matches.value_of("bin").unwrap();
+    let contents = read_file(file_name);
+    let disassembler = Disassembler::new(disasm::Options {
+        cmos: matches.is_present("65c02"),
+        illegal: matches.is_present("illegal"),
+    });
+    let start = matches.value_of("start").map(parse_addr).transpose()?;
+    let end = matches.value_of("end").map(parse_addr).transpose()?;
+
+    let instructions = if InesRom::is_ines(&contents) {
+        let mem = NromMemory::from_ines(&contents)?;
+        disassembler.disassemble_memory(&mem, start.unwrap_or(0x8000), end.unwrap_or(0xFFFF))
+    } else {
+        let origin = matches.value_of("org").map(parse_addr).transpose()?;
+        let origin = origin.unwrap_or(0x0000);
+        let start = start.unwrap_or(origin);
+        let end = end.unwrap_or(origin.wrapping_add(contents.len() as u16).wrapping_sub(1));
+        let from = start.wrapping_sub(origin) as usize;
+        let to = (end.wrapping_sub(origin) as usize + 1).min(contents.len());
+        disassembler.disassemble(&contents[from.min(to)..to], start)
+    };
+
+    let symbols = load_symbols(matches)?;
+    print!("{}", disasm::listing(&instructions, Some(&symbols)));
+    Ok(())
+}
+
+fn asm_command(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let file_name = matches.value_of("src").unwrap();
+    let source = String::from_utf8(read_file(file_name))?;
+    let options = asm::Options {
+        cmos: matches.is_present("65c02"),
+        illegal: matches.is_present("illegal"),
+    };
+
+    let assembly = match asm::assemble(&source, options) {
+        Ok(assembly) => assembly,
+        Err(err) => {
+            eprintln!("{file_name}: {err}");
+            std::process::exit(-1);
+        }
+    };
+
+    let output = match matches.value_of("output") {
+        Some(output) => output.to_string(),
+        None => Path::new(file_name)
+            .with_extension("bin")
+            .to_string_lossy()
+            .into_owned(),
+    };
+    fs::write(output, &assembly.binary)?;
+    if let Some(listing) = matches.value_of("listing") {
+        fs::write(listing, assembly.listing_text())?;
+    }
+    if let Some(symbols) = matches.value_of("symbols") {
+        fs::write(symbols, assembly.symbols_text())?;
+    }
+    Ok(())
+}
+
+fn dap_command() -> Result<(), Box<dyn Error>> {
+    let mut server = DapServer::new(std::io::stdout());
+    server.serve(std::io::stdin())?;
+    Ok(())
+}
+
+fn trace_diff_command(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let load = |file_name: &str| -> Result<_, Box<dyn Error>> {
+        let text = String::from_utf8_lossy(&read_file(file_name)).into_owned();
+        tracediff::parse_trace(&text).map_err(|err| format!("{file_name}: {err}").into())
+    };
+    let ours = load(matches.value_of("ours").unwrap())?;
+    let reference = load(matches.value_of("reference").unwrap())?;
+    let context = matches.value_of_t::<usize>("context")?;
+    let ignore_flags = match matches.value_of("ignore-flags") {
+        Some(s) => u8::from_str_radix(s.trim_start_matches('$').trim_start_matches("0x"), 16)
+            .map_err(|e| format!("Invalid flag mask \"{s}\": {e}"))?,
+        None => 0,
+    };
+    let options = DiffOptions {
+        ignore_cycles: matches.is_present("ignore-cycles"),
+        ignore_flags,
     };
 
+    let result = tracediff::diff(&ours, &reference, options);
+    print!("{}", result.report(&ours, &reference, context));
+    if result.diverged() {
+        std::process::exit(1);
+    }
     Ok(())
 }
+
+pub fn main() -> Result<(), Box<dyn Error>> {
+    let bin_arg = Arg::new("bin").value_name("FILE");
+    let symbols_arg = Arg::new("symbols")
+        .long("--symbols")
+        .takes_value(true)
+        .value_name("FILE")
+        .help("Import labels from a VICE, ld65, ACME or 64tass symbol file.");
+    let run_args = [
+        Arg::new("step")
+            .long("--step")
+            .short('s')
+            .alias("debug")
+            .required(false)
+            .help("Start in the interactive debugger instead of running freely."),
+        Arg::new("pc")
+            .long("--pc")
+            .takes_value(true)
+            .value_name("ADDR")
+            .help("Start execution at ADDR (hex) instead of the reset vector."),
+        Arg::new("gdb")
+            .long("--gdb")
+            .takes_value(true)
+            .value_name("ADDR")
+            .conflicts_with("step")
+            .help("Wait for a GDB connection on [HOST]:PORT or a Unix socket path."),
+        symbols_arg.clone(),
+        Arg::new("dbgfile")
+            .long("--dbgfile")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Load source lines and labels from the debug info written by ld65 --dbgfile."),
+        Arg::new("trace")
+            .long("--trace")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with_all(&["step", "gdb"])
+            .help("Write a line for every instruction executed to FILE, or stdout if FILE is -."),
+        Arg::new("trace-format")
+            .long("--trace-format")
+            .takes_value(true)
+            .value_name("FORMAT")
+            .default_value("nestest")
+            .help(
+                "Trace line format: nestest, json, or a template with {pc}, {bytes}, {inst}, \
+                 {a}, {x}, {y}, {p}, {sp}, {flags}, {cycles}, {label} and {source} fields.",
+            ),
+        Arg::new("profile")
+            .long("--profile")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with_all(&["step", "gdb"])
+            .help("Write the cycles spent in each routine to FILE, or stderr if FILE is -."),
+        Arg::new("profile-format")
+            .long("--profile-format")
+            .takes_value(true)
+            .value_name("FORMAT")
+            .possible_values(["report", "folded"])
+            .default_value("report")
+            .help("A table sorted by inclusive cycles, or folded stacks for flame graphs."),
+        Arg::new("coverage")
+            .long("--coverage")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with_all(&["step", "gdb"])
+            .help("Write the instructions and branch directions executed to FILE."),
+        Arg::new("coverage-format")
+            .long("--coverage-format")
+            .takes_value(true)
+            .value_name("FORMAT")
+            .possible_values(["listing", "lcov"])
+            .default_value("listing")
+            .help("An annotated disassembly, or an lcov tracefile of the sources in --dbgfile."),
+        Arg::new("heatmap")
+            .long("--heatmap")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with_all(&["step", "gdb"])
+            .help("Write a PPM image of the reads, writes and executes of every address to FILE."),
+        Arg::new("memory-stats")
+            .long("--memory-stats")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with_all(&["step", "gdb"])
+            .help(
+                "Write per-page access counts, self-modifying code and reads of never-written \
+                 memory to FILE.",
+            ),
+        Arg::new("check-uninit")
+            .long("--check-uninit")
+            .conflicts_with_all(&["step", "gdb", "heatmap", "memory-stats"])
+            .help("Report reads of memory that was never written, with the calls leading to them."),
+        Arg::new("randomize-ram")
+            .long("--randomize-ram")
+            .takes_value(true)
+            .value_name("SEED")
+            .conflicts_with_all(&["step", "gdb", "heatmap", "memory-stats"])
+            .help("Fill RAM with pseudo-random bytes derived from SEED before running."),
+        Arg::new("check-stack")
+            .long("--check-stack")
+            .takes_value(true)
+            .value_name("SEVERITY")
+            .possible_values(["warn", "error", "break"])
+            .conflicts_with("gdb")
+            .help(
+                "Check for stack overflow and underflow, returns that don't match their JSR or \
+                 interrupt and unbalanced pushes, then warn, stop with an error, or break into \
+                 the debugger.",
+            ),
+        Arg::new("machine")
+            .long("--machine")
+            .takes_value(true)
+            .value_name("MACHINE")
+            .possible_values(["apple1"])
+            .help(
+                "Run FILE as the ROM of MACHINE: apple1 maps 8 KiB of RAM, the PIA with the \
+                 keyboard and display on --serial at $D010 and FILE at $FF00, or lower if it \
+                 doesn't fit.",
+            ),
+        Arg::new("acia")
+            .long("--acia")
+            .takes_value(true)
+            .value_name("ADDR")
+            .conflicts_with("machine")
+            .help("Map a 6551 ACIA's 4 registers at ADDR (hex), connected to --serial."),
+        Arg::new("serial")
+            .long("--serial")
+            .takes_value(true)
+            .value_name("PORT")
+            .default_value("stdio")
+            .help(
+                "Serial line of the ACIA or the Apple I terminal: stdio for the terminal in raw mode (Ctrl-] quits), \
+                 files:IN,OUT, unix:PATH to wait for a connection on a Unix socket, or pty for a \
+                 new pseudo-terminal.",
+            ),
+    ];
+
+    let instruction_set_args = [
+        Arg::new("65c02")
+            .long("--65c02")
+            .help("Use the 65C02 instruction set."),
+        Arg::new("illegal")
+            .long("--illegal")
+            .help("Allow undocumented opcodes."),
+    ];
+
+    let matches = Command::new("mini6502")
+        .author("Carlos Carral")
+        .args_conflicts_with_subcommands(true)
+        .arg_required_else_help(true)
+        .arg(bin_arg.clone())
+        .args(run_args.clone())
+        .subcommand(
+            Command::new("run")
+                .about("Run a binary or an iNES image.")
+                .arg(bin_arg.clone().required(true))
+                .args(run_args),
+        )
+        .subcommand(
+            Command::new("disasm")
+                .about("Disassemble a binary or the PRG ROM of an iNES image.")
+                .arg(bin_arg.required(true))
+                .arg(
+                    Arg::new("org")
+                        .long("--org")
+                        .takes_value(true)
+                        .value_name("ADDR")
+                        .help("Address the binary is loaded at (hex), 0 by default."),
+                )
+                .arg(
+                    Arg::new("start")
+                        .long("--start")
+                        .takes_value(true)
+                        .value_name("ADDR")
+                        .help("First address to disassemble (hex)."),
+                )
+                .arg(
+                    Arg::new("end")
+                        .long("--end")
+                        .takes_value(true)
+                        .value_name("ADDR")
+                        .help("Last address to disassemble (hex)."),
+                )
+                .arg(symbols_arg)
+                .args(instruction_set_args.clone()),
+        )
+        .subcommand(
+            Command::new("asm")
+                .about("Assemble a source file into a binary.")
+                .arg(Arg::new("src").value_name("SOURCE").required(true))
+                .arg(
+                    Arg::new("output")
+                        .long("--output")
+                        .short('o')
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help(
+                            "Where to write the binary, SOURCE with a .bin extension by default.",
+                        ),
+                )
+                .arg(
+                    Arg::new("listing")
+                        .long("--listing")
+                        .short('l')
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Write a listing to FILE."),
+                )
+                .arg(
+                    Arg::new("symbols")
+                        .long("--symbols")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Write the symbol table to FILE."),
+                )
+                .args(instruction_set_args),
+        )
+        .subcommand(
+            Command::new("dap").about("Serve the Debug Adapter Protocol over stdin and stdout."),
+        )
+        .subcommand(
+            Command::new("trace-diff")
+                .about("Find the first instruction where two nestest or JSON traces differ.")
+                .arg(Arg::new("ours").value_name("OURS").required(true))
+                .arg(Arg::new("reference").value_name("REFERENCE").required(true))
+                .arg(
+                    Arg::new("context")
+                        .long("--context")
+                        .short('C')
+                        .takes_value(true)
+                        .value_name("N")
+                        .default_value("5")
+                        .help("Lines to show before the divergence."),
+                )
+                .arg(
+                    Arg::new("ignore-cycles")
+                        .long("--ignore-cycles")
+                        .help("Don't compare cycle counts. Needed against nestest.log, our count starts at 0 and has no branch or page crossing cycles."),
+                )
+                .arg(
+                    Arg::new("ignore-flags")
+                        .long("--ignore-flags")
+                        .takes_value(true)
+                        .value_name("MASK")
+                        .help("Don't compare the status flags set in MASK (hex), e.g. 30 for B and bit 5."),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        Some(("run", sub_matches)) => run_command(sub_matches),
+        Some(("disasm", sub_matches)) => disasm_command(sub_matches),
+        Some(("asm", sub_matches)) => asm_command(sub_matches),
+        Some(("dap", _)) => dap_command(),
+        Some(("trace-diff", sub_matches)) => trace_diff_command(sub_matches),
+        _ => run_command(&matches),
+    }
+}
@@ -1,6 +1,63 @@
 pub trait Memory {
     fn write_byte(&mut self, addr: u16, byte: u8);
     fn read_byte(&self, addr: u16) -> u8;
+
+    /// Read a byte without the side effects reading it can have, like clearing a device's
+    /// interrupt flags, for tools looking at memory.
+    fn peek(&self, addr: u16) -> u8 {
+        self.read_byte(addr)
+    }
+
+    /// Let `cycles` cpu cycles go by, for memory with devices that keep time.
+    fn tick(&mut self, _cycles: u8) {}
+
+    /// Level of the IRQ line, `true` when asserted.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Level of the NMI line, `true` when asserted. The cpu takes the interrupt when it goes
+    /// from `false` to `true`.
+    fn nmi(&self) -> bool {
+        false
+    }
+
+    /// Whether the IRQ or NMI line can ever be asserted, so a program spinning in place may
+    /// still be woken up by an interrupt. Memory without interrupt sources says so to let the
+    /// debugger stop on such loops.
+    fn can_interrupt(&self) -> bool {
+        true
+    }
+}
+
+impl<M: Memory + ?Sized> Memory for Box<M> {
+    fn write_byte(&mut self, addr: u16, byte: u8) {
+        (**self).write_byte(addr, byte)
+    }
+
+    fn read_byte(&self, addr: u16) -> u8 {
+        (**self).read_byte(addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        (**self).peek(addr)
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        (**self).tick(cycles)
+    }
+
+    fn irq(&self) -> bool {
+        (**self).irq()
+    }
+
+    fn nmi(&self) -> bool {
+        (**self).nmi()
+    }
+
+    fn can_interrupt(&self) -> bool {
+        (**self).can_interrupt()
+    }
 }
 
 pub struct SimpleMemory {
@@ -21,6 +78,13 @@ impl SimpleMemory {
         }
         mem
     }
+
+    /// Copy `bytes` into memory starting at `addr`, wrapping around at $FFFF.
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) {
+        for (offset, b) in bytes.iter().enumerate() {
+            self.write_byte(addr.wrapping_add(offset as u16), *b);
+        }
+    }
 }
 
 impl Memory for SimpleMemory {
@@ -31,4 +95,8 @@ impl Memory for SimpleMemory {
     fn read_byte(&self, addr: u16) -> u8 {
         self.inner[addr as usize]
     }
+
+    fn can_interrupt(&self) -> bool {
+        false
+    }
 }
@@ -80,7 +80,7 @@ pub fn init_opc_array() -> [Option<OpMode>; 0xFF] {
     add_to_opc_arr(0xF0, Inst::BEQ, AddressMode::REL, 2);
 
     add_to_opc_arr(0x24, Inst::BIT, AddressMode::ZPG, 3);
-    add_to_opc_arr(0x2C, Inst::BIT, AddressMode::ABS, 3);
+    add_to_opc_arr(0x2C, Inst::BIT, AddressMode::ABS, 4);
 
     add_to_opc_arr(0x30, Inst::BMI, AddressMode::REL, 2);
 
@@ -153,24 +153,24 @@ pub fn init_opc_array() -> [Option<OpMode>; 0xFF] {
 
     add_to_opc_arr(0xA9, Inst::LDA, AddressMode::IMM, 2);
     add_to_opc_arr(0xA5, Inst::LDA, AddressMode::ZPG, 3);
-    add_to_opc_arr(0xB5, Inst::LDA, AddressMode::ZPGX, 6);
-    add_to_opc_arr(0xAD, Inst::LDA, AddressMode::ABS, 6);
-    add_to_opc_arr(0xBD, Inst::LDA, AddressMode::ABSX, 6);
-    add_to_opc_arr(0xB9, Inst::LDA, AddressMode::ABSY, 6);
+    add_to_opc_arr(0xB5, Inst::LDA, AddressMode::ZPGX, 4);
+    add_to_opc_arr(0xAD, Inst::LDA, AddressMode::ABS, 4);
+    add_to_opc_arr(0xBD, Inst::LDA, AddressMode::ABSX, 4);
+    add_to_opc_arr(0xB9, Inst::LDA, AddressMode::ABSY, 4);
     add_to_opc_arr(0xA1, Inst::LDA, AddressMode::INDX, 6);
-    add_to_opc_arr(0xB1, Inst::LDA, AddressMode::INDY, 6);
+    add_to_opc_arr(0xB1, Inst::LDA, AddressMode::INDY, 5);
 
-    add_to_opc_arr(0xA2, Inst::LDX, AddressMode::IMM, 7);
-    add_to_opc_arr(0xA6, Inst::LDX, AddressMode::ZPG, 7);
-    add_to_opc_arr(0xB6, Inst::LDX, AddressMode::ZPGY, 7);
-    add_to_opc_arr(0xAE, Inst::LDX, AddressMode::ABS, 7);
-    add_to_opc_arr(0xBE, Inst::LDX, AddressMode::ABSY, 7);
+    add_to_opc_arr(0xA2, Inst::LDX, AddressMode::IMM, 2);
+    add_to_opc_arr(0xA6, Inst::LDX, AddressMode::ZPG, 3);
+    add_to_opc_arr(0xB6, Inst::LDX, AddressMode::ZPGY, 4);
+    add_to_opc_arr(0xAE, Inst::LDX, AddressMode::ABS, 4);
+    add_to_opc_arr(0xBE, Inst::LDX, AddressMode::ABSY, 4);
 
-    add_to_opc_arr(0xA0, Inst::LDY, AddressMode::IMM, 7);
-    add_to_opc_arr(0xA4, Inst::LDY, AddressMode::ZPG, 7);
-    add_to_opc_arr(0xB4, Inst::LDY, AddressMode::ZPGX, 7);
-    add_to_opc_arr(0xAC, Inst::LDY, AddressMode::ABS, 7);
-    add_to_opc_arr(0xBC, Inst::LDY, AddressMode::ABSX, 7);
+    add_to_opc_arr(0xA0, Inst::LDY, AddressMode::IMM, 2);
+    add_to_opc_arr(0xA4, Inst::LDY, AddressMode::ZPG, 3);
+    add_to_opc_arr(0xB4, Inst::LDY, AddressMode::ZPGX, 4);
+    add_to_opc_arr(0xAC, Inst::LDY, AddressMode::ABS, 4);
+    add_to_opc_arr(0xBC, Inst::LDY, AddressMode::ABSX, 4);
 
     add_to_opc_arr(0x4A, Inst::LSR, AddressMode::ACC, 2);
     add_to_opc_arr(0x46, Inst::LSR, AddressMode::ZPG, 5);
@@ -193,9 +193,9 @@ pub fn init_opc_array() -> [Option<OpMode>; 0xFF] {
 
     add_to_opc_arr(0x08, Inst::PHP, AddressMode::IMPL, 3);
 
-    add_to_opc_arr(0x68, Inst::PLA, AddressMode::IMPL, 3);
+    add_to_opc_arr(0x68, Inst::PLA, AddressMode::IMPL, 4);
 
-    add_to_opc_arr(0x28, Inst::PLP, AddressMode::IMPL, 3);
+    add_to_opc_arr(0x28, Inst::PLP, AddressMode::IMPL, 4);
 
     add_to_opc_arr(0x2A, Inst::ROL, AddressMode::ACC, 2);
     add_to_opc_arr(0x26, Inst::ROL, AddressMode::ZPG, 5);
@@ -319,7 +319,70 @@ pub enum Inst {
     TYA,
 }
 
-#[derive(Copy, Clone, Debug)]
+impl Inst {
+    pub const fn mnemonic(&self) -> &'static str {
+        match self {
+            Inst::ADC => "ADC",
+            Inst::AND => "AND",
+            Inst::ASL => "ASL",
+            Inst::BCC => "BCC",
+            Inst::BCS => "BCS",
+            Inst::BEQ => "BEQ",
+            Inst::BIT => "BIT",
+            Inst::BMI => "BMI",
+            Inst::BNE => "BNE",
+            Inst::BPL => "BPL",
+            Inst::BRK => "BRK",
+            Inst::BVC => "BVC",
+            Inst::BVS => "BVS",
+            Inst::CLC => "CLC",
+            Inst::CLD => "CLD",
+            Inst::CLI => "CLI",
+            Inst::CLV => "CLV",
+            Inst::CMP => "CMP",
+            Inst::CPX => "CPX",
+            Inst::CPY => "CPY",
+            Inst::DEC => "DEC",
+            Inst::DEX => "DEX",
+            Inst::DEY => "DEY",
+            Inst::EOR => "EOR",
+            Inst::INC => "INC",
+            Inst::INX => "INX",
+            Inst::INY => "INY",
+            Inst::JMP => "JMP",
+            Inst::JSR => "JSR",
+            Inst::LDA => "LDA",
+            Inst::LDX => "LDX",
+            Inst::LDY => "LDY",
+            Inst::LSR => "LSR",
+            Inst::NOP => "NOP",
+            Inst::ORA => "ORA",
+            Inst::PHA => "PHA",
+            Inst::PHP => "PHP",
+            Inst::PLA => "PLA",
+            Inst::PLP => "PLP",
+            Inst::ROL => "ROL",
+            Inst::ROR => "ROR",
+            Inst::RTI => "RTI",
+            Inst::RTS => "RTS",
+            Inst::SBC => "SBC",
+            Inst::SEC => "SEC",
+            Inst::SED => "SED",
+            Inst::SEI => "SEI",
+            Inst::STA => "STA",
+            Inst::STX => "STX",
+            Inst::STY => "STY",
+            Inst::TAX => "TAX",
+            Inst::TAY => "TAY",
+            Inst::TSX => "TSX",
+            Inst::TXA => "TXA",
+            Inst::TXS => "TXS",
+            Inst::TYA => "TYA",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
 pub enum AddressMode {
     ACC,
     ABS,
@@ -0,0 +1,364 @@
+use crate::device::Device;
+
+// Registers, selected by the low 2 bits of the address
+/// Output register or DDR of port A, depending on bit 2 of CRA
+const PORT_A: u16 = 0x0;
+const CRA: u16 = 0x1;
+/// Output register or DDR of port B, depending on bit 2 of CRB
+const PORT_B: u16 = 0x2;
+const CRB: u16 = 0x3;
+
+// Control register bits
+const IRQ1_FLAG: u8 = 0x80;
+const IRQ2_FLAG: u8 = 0x40;
+/// Set to access the output register instead of the DDR
+const OUTPUT_SELECT: u8 = 0x04;
+const C1_POSITIVE: u8 = 0x02;
+const C1_IRQ_ENABLE: u8 = 0x01;
+
+/// What a CA2 or CB2 line does, from bits 5-3 of its control register.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Control2 {
+    /// Sets IRQ2 on the active edge
+    Input {
+        positive: bool,
+        irq: bool,
+    },
+    /// Goes low when the port is accessed, high on the active edge of C1
+    Handshake,
+    /// Goes low for a cycle when the port is accessed
+    Pulse,
+    Manual(bool),
+}
+
+/// One side of the PIA: a port, its control register and its control lines.
+#[derive(Copy, Clone, Debug)]
+struct Side {
+    output: u8,
+    ddr: u8,
+    /// Levels driven by whatever is connected to the pins
+    input: u8,
+    control: u8,
+    c1: bool,
+    c2: bool,
+    /// C2 when it's a handshake or pulse output
+    c2_out: bool,
+    /// A pulse output that goes back high on the next cycle
+    c2_pulse: bool,
+}
+
+impl Side {
+    const fn new() -> Self {
+        Side {
+            output: 0x00,
+            ddr: 0x00,
+            input: 0xFF,
+            control: 0x00,
+            c1: true,
+            c2: true,
+            c2_out: true,
+            c2_pulse: false,
+        }
+    }
+
+    const fn pins(&self) -> u8 {
+        (self.output & self.ddr) | (self.input & !self.ddr)
+    }
+
+    const fn control2(&self) -> Control2 {
+        let bits = self.control >> 3;
+        match bits & 0b111 {
+            0b100 => Control2::Handshake,
+            0b101 => Control2::Pulse,
+            0b110 => Control2::Manual(false),
+            0b111 => Control2::Manual(true),
+            bits => Control2::Input {
+                positive: bits & 0b010 != 0,
+                irq: bits & 0b001 != 0,
+            },
+        }
+    }
+
+    /// Accessing the output register does this to C2.
+    fn handshake(&mut self) {
+        match self.control2() {
+            Control2::Handshake => self.c2_out = false,
+            Control2::Pulse => {
+                self.c2_out = false;
+                self.c2_pulse = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_c1(&mut self, level: bool) {
+        if self.c1 == level {
+            return;
+        }
+        self.c1 = level;
+        if level == (self.control & C1_POSITIVE != 0) {
+            self.control |= IRQ1_FLAG;
+            if self.control2() == Control2::Handshake {
+                self.c2_out = true;
+            }
+        }
+    }
+
+    fn set_c2(&mut self, level: bool) {
+        if self.c2 == level {
+            return;
+        }
+        self.c2 = level;
+        if let Control2::Input { positive, .. } = self.control2() {
+            if level == positive {
+                self.control |= IRQ2_FLAG;
+            }
+        }
+    }
+
+    fn c2(&self) -> bool {
+        match self.control2() {
+            Control2::Input { .. } => self.c2,
+            Control2::Handshake | Control2::Pulse => self.c2_out,
+            Control2::Manual(level) => level,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        let irq1 = self.control & (IRQ1_FLAG | C1_IRQ_ENABLE) == IRQ1_FLAG | C1_IRQ_ENABLE;
+        let irq2 = matches!(self.control2(), Control2::Input { irq: true, .. })
+            && self.control & IRQ2_FLAG != 0;
+        irq1 || irq2
+    }
+
+    fn write_control(&mut self, value: u8) {
+        // The flags are read only
+        self.control = (self.control & (IRQ1_FLAG | IRQ2_FLAG)) | (value & 0x3F);
+    }
+}
+
+/// A Motorola 6821 Peripheral Interface Adapter: two 8 bit ports, each with a control register
+/// and two control lines, CA1/CA2 and CB1/CB2, that can interrupt or handshake. It decodes 2
+/// address lines: port A, CRA, port B and CRB.
+///
+/// Both IRQA and IRQB drive the IRQ line. Where the board doesn't connect them, look at
+/// [`Pia::irq_a`] and [`Pia::irq_b`] instead of mapping the PIA directly.
+///
+/// ```rust
+/// use mini6502::device::Device;
+/// use mini6502::pia::Pia;
+///
+/// let mut pia = Pia::new();
+/// // Port B all outputs, then select its output register
+/// pia.write(0x3, 0x00);
+/// pia.write(0x2, 0xFF);
+/// pia.write(0x3, 0x04);
+/// pia.write(0x2, 0x5A);
+/// assert_eq!(pia.port_b(), 0x5A);
+///
+/// // Interrupt on the rising edge of CA1
+/// pia.write(0x1, 0x07);
+/// pia.set_port_a(0x42);
+/// pia.set_ca1(false);
+/// pia.set_ca1(true);
+/// assert!(pia.irq());
+/// assert_eq!(pia.read(0x1), 0x87);
+/// // Reading port A clears the flag
+/// assert_eq!(pia.read(0x0), 0x42);
+/// assert!(!pia.irq());
+/// ```
+#[derive(Clone, Debug)]
+pub struct Pia {
+    a: Side,
+    b: Side,
+}
+
+impl Default for Pia {
+    fn default() -> Self {
+        Pia::new()
+    }
+}
+
+impl Pia {
+    /// A PIA after a reset: every pin an input, the DDRs selected and no interrupts enabled.
+    pub fn new() -> Self {
+        Pia {
+            a: Side::new(),
+            b: Side::new(),
+        }
+    }
+
+    pub fn port_a(&self) -> u8 {
+        self.a.pins()
+    }
+
+    pub fn port_b(&self) -> u8 {
+        self.b.pins()
+    }
+
+    /// Drive the port A pins, only the ones set as inputs are read back.
+    pub fn set_port_a(&mut self, pins: u8) {
+        self.a.input = pins;
+    }
+
+    /// Drive the port B pins, only the ones set as inputs are read back.
+    pub fn set_port_b(&mut self, pins: u8) {
+        self.b.input = pins;
+    }
+
+    pub fn set_ca1(&mut self, level: bool) {
+        self.a.set_c1(level);
+    }
+
+    /// Drive CA2, which only does something while CRA makes it an input.
+    pub fn set_ca2(&mut self, level: bool) {
+        self.a.set_c2(level);
+    }
+
+    pub fn set_cb1(&mut self, level: bool) {
+        self.b.set_c1(level);
+    }
+
+    /// Drive CB2, which only does something while CRB makes it an input.
+    pub fn set_cb2(&mut self, level: bool) {
+        self.b.set_c2(level);
+    }
+
+    pub fn ca2(&self) -> bool {
+        self.a.c2()
+    }
+
+    pub fn cb2(&self) -> bool {
+        self.b.c2()
+    }
+
+    /// Whether the IRQA output is pulled.
+    pub fn irq_a(&self) -> bool {
+        self.a.irq()
+    }
+
+    /// Whether the IRQB output is pulled.
+    pub fn irq_b(&self) -> bool {
+        self.b.irq()
+    }
+}
+
+impl Device for Pia {
+    fn read(&mut self, reg: u16) -> u8 {
+        let value = self.peek(reg);
+        match reg & 0x03 {
+            PORT_A if self.a.control & OUTPUT_SELECT != 0 => {
+                self.a.control &= !(IRQ1_FLAG | IRQ2_FLAG);
+                self.a.handshake();
+            }
+            PORT_B if self.b.control & OUTPUT_SELECT != 0 => {
+                self.b.control &= !(IRQ1_FLAG | IRQ2_FLAG);
+            }
+            _ => {}
+        }
+        value
+    }
+
+    fn peek(&self, reg: u16) -> u8 {
+        match reg & 0x03 {
+            PORT_A if self.a.control & OUTPUT_SELECT != 0 => self.a.pins(),
+            PORT_A => self.a.ddr,
+            CRA => self.a.control,
+            // Output pins of port B read the output register, whatever their load
+            PORT_B if self.b.control & OUTPUT_SELECT != 0 => self.b.pins(),
+            PORT_B => self.b.ddr,
+            CRB => self.b.control,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg & 0x03 {
+            PORT_A if self.a.control & OUTPUT_SELECT != 0 => self.a.output = value,
+            PORT_A => self.a.ddr = value,
+            CRA => self.a.write_control(value),
+            // Port B handshakes on writes rather than reads
+            PORT_B if self.b.control & OUTPUT_SELECT != 0 => {
+                self.b.output = value;
+                self.b.handshake();
+            }
+            PORT_B => self.b.ddr = value,
+            CRB => self.b.write_control(value),
+            _ => unreachable!(),
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        if cycles == 0 {
+            return;
+        }
+        for side in [&mut self.a, &mut self.b] {
+            if side.c2_pulse {
+                side.c2_pulse = false;
+                side.c2_out = true;
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_a() || self.irq_b()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ports() {
+        let mut pia = Pia::new();
+        pia.write(PORT_A, 0x0F);
+        assert_eq!(pia.read(PORT_A), 0x0F);
+        pia.write(CRA, OUTPUT_SELECT);
+        pia.write(PORT_A, 0x55);
+        pia.set_port_a(0xA0);
+        assert_eq!((pia.port_a(), pia.read(PORT_A)), (0xA5, 0xA5));
+        // The DDR is still there when selected again
+        pia.write(CRA, 0x00);
+        assert_eq!(pia.read(PORT_A), 0x0F);
+
+        // Flags can't be written
+        pia.write(CRB, 0xFF);
+        assert_eq!(pia.read(CRB), 0x3F);
+        assert!(pia.cb2());
+        pia.write(CRB, 0x30 | OUTPUT_SELECT);
+        assert!(!pia.cb2());
+    }
+
+    #[test]
+    fn test_control_lines() {
+        let mut pia = Pia::new();
+        // CA2 interrupting on its rising edge, CA1 on its falling edge without interrupting
+        pia.write(CRA, 0x18 | OUTPUT_SELECT);
+        pia.set_ca1(false);
+        assert_eq!(pia.read(CRA), IRQ1_FLAG | 0x18 | OUTPUT_SELECT);
+        assert!(!pia.irq());
+        pia.set_ca2(false);
+        pia.set_ca2(true);
+        assert!(pia.irq_a() && !pia.irq_b());
+        pia.read(PORT_A);
+        assert_eq!(pia.read(CRA) & (IRQ1_FLAG | IRQ2_FLAG), 0x00);
+
+        // CB2 handshake: low after writing port B, high again on the active edge of CB1
+        pia.write(CRB, 0x20 | C1_POSITIVE | OUTPUT_SELECT);
+        pia.write(PORT_B, 0x41);
+        assert!(!pia.cb2());
+        pia.set_cb1(false);
+        assert!(!pia.cb2());
+        pia.set_cb1(true);
+        assert!(pia.cb2());
+        assert!(!pia.irq());
+
+        // CA2 pulse after reading port A
+        pia.write(CRA, 0x28 | OUTPUT_SELECT);
+        pia.read(PORT_A);
+        assert!(!pia.ca2());
+        pia.tick(1);
+        assert!(pia.ca2());
+    }
+}
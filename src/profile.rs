@@ -0,0 +1,348 @@
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use crate::symbols::SymbolTable;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+const JSR_OPCODE: u8 = 0x20;
+const NMI_VECTOR: u16 = 0xFFFA;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// A routine in the call tree, reached through a particular chain of calls.
+#[derive(Clone, Debug)]
+struct Node {
+    entry: u16,
+    parent: usize,
+    children: Vec<usize>,
+    /// Cycles spent in the routine itself while called through this chain
+    cycles: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Frame {
+    node: usize,
+    /// Stack pointer before the return address was pushed
+    sp: u16,
+}
+
+/// What the cpu looked like before the last instruction.
+#[derive(Copy, Clone, Debug)]
+struct Last {
+    sp: u16,
+    cycles: usize,
+    jsr: bool,
+}
+
+/// Cycles spent in a routine, over all the places it was called from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Routine {
+    pub entry: u16,
+    pub name: String,
+    /// Times it was called or, for interrupt handlers, entered
+    pub calls: u64,
+    /// Cycles spent in the routine and everything it called
+    pub inclusive: u64,
+    /// Cycles spent in the routine itself
+    pub exclusive: u64,
+}
+
+/// Attributes cycles to routines by following `JSR`s, interrupts and the stack pointer the same
+/// way [`CallStack`](crate::callstack::CallStack) does. Everything executed before the first
+/// call belongs to the routine profiling started in.
+///
+/// ```rust
+/// use mini6502::hooks::until;
+/// use mini6502::profile::Profiler;
+/// use mini6502::{Cpu, SimpleMemory};
+///
+/// // jsr $0005; nop; brk; nop; rts
+/// let mem = SimpleMemory::from_rom(&[0x20, 0x05, 0x00, 0xEA, 0x00, 0xEA, 0x60]);
+/// let mut cpu = Cpu::with_mem(mem);
+/// let mut profiler = Profiler::new();
+/// cpu.run(&mut until(|cpu| {
+///     profiler.record(cpu);
+///     cpu.pc() == 0x0004
+/// }))
+/// .unwrap();
+///
+/// let routines = profiler.routines();
+/// assert_eq!(routines[0].name, "$0000");
+/// assert_eq!(routines[0].inclusive, profiler.total_cycles());
+/// assert_eq!((routines[1].name.as_str(), routines[1].exclusive), ("$0005", 8));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    /// Names routines in reports, `$HHLL` is used for addresses without one
+    pub symbols: SymbolTable,
+    /// The first node is the routine profiling started in
+    nodes: Vec<Node>,
+    frames: Vec<Frame>,
+    calls: BTreeMap<u16, u64>,
+    last: Option<Last>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    /// Account for the instruction executed since the last call. Call it before every
+    /// instruction, e.g. from the [`Cpu::run`] callback, and once more when done.
+    pub fn record<M: Memory>(&mut self, cpu: &Cpu<M>) {
+        let now = cpu.cycle_count();
+        if let Some(last) = self.last {
+            let current = self.current();
+            self.nodes[current].cycles += now.saturating_sub(last.cycles) as u64;
+
+            let vector =
+                |addr: u16| u16::from_le_bytes([cpu.mem.peek(addr), cpu.mem.peek(addr + 1)]);
+            let interrupted = cpu.sp() + 3 <= last.sp
+                && (cpu.pc() == vector(IRQ_VECTOR) || cpu.pc() == vector(NMI_VECTOR));
+            if (last.jsr && cpu.sp() < last.sp) || interrupted {
+                self.enter(cpu.pc(), last.sp);
+            } else {
+                // Same as the call stack, a frame whose return address was popped has returned
+                while let Some(frame) = self.frames.last() {
+                    if frame.sp <= cpu.sp() {
+                        self.frames.pop();
+                    } else {
+                        break;
+                    }
+                }
+            }
+        } else if self.nodes.is_empty() {
+            self.nodes.push(Node {
+                entry: cpu.pc(),
+                parent: 0,
+                children: vec![],
+                cycles: 0,
+            });
+            self.calls.insert(cpu.pc(), 1);
+        }
+        self.last = Some(Last {
+            sp: cpu.sp(),
+            cycles: now,
+            jsr: cpu.mem.peek(cpu.pc()) == JSR_OPCODE,
+        });
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.nodes.iter().map(|node| node.cycles).sum()
+    }
+
+    /// Every routine that ran, by inclusive cycles, most expensive first.
+    pub fn routines(&self) -> Vec<Routine> {
+        // Children are always created after their parents
+        let mut inclusive: Vec<u64> = self.nodes.iter().map(|node| node.cycles).collect();
+        for index in (1..self.nodes.len()).rev() {
+            inclusive[self.nodes[index].parent] += inclusive[index];
+        }
+
+        let mut routines: BTreeMap<u16, Routine> = BTreeMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let routine = routines.entry(node.entry).or_insert_with(|| Routine {
+                entry: node.entry,
+                name: self.name(node.entry),
+                calls: self.calls.get(&node.entry).copied().unwrap_or(0),
+                inclusive: 0,
+                exclusive: 0,
+            });
+            routine.exclusive += node.cycles;
+            // A recursive call is already counted by the outermost one
+            if !self.ancestors(index).any(|other| other.entry == node.entry) {
+                routine.inclusive += inclusive[index];
+            }
+        }
+        let mut routines: Vec<Routine> = routines.into_values().collect();
+        routines.sort_by(|a, b| b.inclusive.cmp(&a.inclusive).then(a.entry.cmp(&b.entry)));
+        routines
+    }
+
+    /// Write one `outer;inner CYCLES` line per call chain, the folded stack format flame graph
+    /// tools read.
+    pub fn write_folded(&self, out: &mut impl Write) -> io::Result<()> {
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.cycles == 0 {
+                continue;
+            }
+            let mut path: Vec<String> = self
+                .ancestors(index)
+                .map(|other| self.name(other.entry))
+                .collect();
+            path.reverse();
+            path.push(self.name(node.entry));
+            writeln!(out, "{} {}", path.join(";"), node.cycles)?;
+        }
+        Ok(())
+    }
+
+    /// A table of the routines by inclusive cycles.
+    pub fn report(&self) -> String {
+        let total = self.total_cycles();
+        let percent = |cycles: u64| match total {
+            0 => 0.0,
+            _ => cycles as f64 * 100.0 / total as f64,
+        };
+        let mut out = format!("Total cycles: {total}\n");
+        let _ = writeln!(
+            out,
+            "{:>12} {:>6} {:>12} {:>6} {:>8}  Routine",
+            "Inclusive", "%", "Exclusive", "%", "Calls"
+        );
+        for routine in self.routines() {
+            let _ = writeln!(
+                out,
+                "{:>12} {:>6.1} {:>12} {:>6.1} {:>8}  {}",
+                routine.inclusive,
+                percent(routine.inclusive),
+                routine.exclusive,
+                percent(routine.exclusive),
+                routine.calls,
+                routine.name
+            );
+        }
+        out
+    }
+
+    fn current(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.node)
+    }
+
+    fn enter(&mut self, entry: u16, sp: u16) {
+        let parent = self.current();
+        let existing = self.nodes[parent]
+            .children
+            .iter()
+            .copied()
+            .find(|&child| self.nodes[child].entry == entry);
+        let node = match existing {
+            Some(node) => node,
+            None => {
+                self.nodes.push(Node {
+                    entry,
+                    parent,
+                    children: vec![],
+                    cycles: 0,
+                });
+                let node = self.nodes.len() - 1;
+                self.nodes[parent].children.push(node);
+                node
+            }
+        };
+        self.frames.push(Frame { node, sp });
+        *self.calls.entry(entry).or_insert(0) += 1;
+    }
+
+    /// Callers of the node at `index`, innermost first.
+    fn ancestors(&self, mut index: usize) -> impl Iterator<Item = &Node> {
+        std::iter::from_fn(move || {
+            if index == 0 {
+                return None;
+            }
+            index = self.nodes[index].parent;
+            Some(&self.nodes[index])
+        })
+    }
+
+    fn name(&self, addr: u16) -> String {
+        self.symbols
+            .name_of(addr)
+            .map_or_else(|| format!("${addr:04X}"), str::to_string)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hooks::until;
+    use crate::memory::SimpleMemory;
+    use mini6502_macros::asm6502;
+
+    fn profile(bytes: &[u8], symbols: SymbolTable, irq: Option<u16>, done: u16) -> Profiler {
+        let mut mem = SimpleMemory::from_rom(bytes);
+        if let Some(irq) = irq {
+            mem.load(IRQ_VECTOR, &irq.to_le_bytes());
+        }
+        let mut cpu = Cpu::with_mem(mem);
+        cpu.set_sp(0xFF);
+        let mut profiler = Profiler::new();
+        profiler.symbols = symbols;
+        cpu.run(&mut until(|cpu| {
+            profiler.record(cpu);
+            cpu.pc() == done
+        }))
+        .unwrap();
+        profiler
+    }
+
+    #[test]
+    fn test_cycles() {
+        let program = asm6502! {
+            main: jsr outer;
+            nop;
+            done: jmp done;
+            outer: jsr inner;
+            nop;
+            rts;
+            inner: lda #$01;
+            rts
+        };
+        let symbols = program.symbols.iter().copied().collect();
+        let profiler = profile(&program.bytes, symbols, None, program.symbol("done"));
+
+        assert_eq!(profiler.total_cycles(), 30);
+        let summary: Vec<_> = profiler
+            .routines()
+            .into_iter()
+            .map(|r| (r.name, r.calls, r.inclusive, r.exclusive))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("main".to_string(), 1, 30, 8),
+                ("outer".to_string(), 1, 22, 14),
+                ("inner".to_string(), 1, 8, 8),
+            ]
+        );
+        assert!(profiler
+            .report()
+            .contains("          30  100.0            8   26.7        1  main"));
+    }
+
+    #[test]
+    fn test_recursion_and_interrupts() {
+        let program = asm6502! {
+            main: ldy #$02;
+            jsr rec;
+            brk;
+            nop;
+            done: jmp done;
+            rec: dey;
+            beq out;
+            jsr rec;
+            out: rts;
+            irq: nop;
+            rti
+        };
+        let symbols = program.symbols.iter().copied().collect();
+        let irq = Some(program.symbol("irq"));
+        let profiler = profile(&program.bytes, symbols, irq, program.symbol("done"));
+
+        let routines = profiler.routines();
+        let rec = routines.iter().find(|r| r.name == "rec").unwrap();
+        assert_eq!(rec.calls, 2);
+        assert_eq!(rec.inclusive, rec.exclusive);
+        let irq = routines.iter().find(|r| r.name == "irq").unwrap();
+        assert_eq!((irq.calls, irq.exclusive), (1, 8));
+
+        let mut folded = vec![];
+        profiler.write_folded(&mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        let paths: Vec<_> = folded
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0)
+            .collect();
+        assert_eq!(paths, vec!["main", "main;rec", "main;rec;rec", "main;irq"]);
+    }
+}
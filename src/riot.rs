@@ -0,0 +1,331 @@
+use crate::device::Device;
+
+/// Set in a register's address to select the I/O and timer instead of the RAM
+const RS: u16 = 0x80;
+
+// I/O registers, selected by the low 3 bits of the address
+const ORA: u16 = 0x0;
+const DDRA: u16 = 0x1;
+const ORB: u16 = 0x2;
+const DDRB: u16 = 0x3;
+/// Set for the timer and interrupt registers, clear for the ports
+const TIMER_SELECT: u16 = 0x04;
+
+// Address bits of the timer and interrupt registers
+/// Set when writing to load the timer, clear to set up the PA7 edge detect
+const WRITE_TIMER: u16 = 0x10;
+/// Set when reading the interrupt flags, clear for the timer
+const READ_FLAGS: u16 = 0x01;
+/// Set when reading or writing the timer to enable its interrupt
+const TIMER_IRQ_ENABLE: u16 = 0x08;
+/// Set when writing the edge detect control to detect rising edges
+const PA7_POSITIVE: u16 = 0x01;
+/// Set when writing the edge detect control to enable its interrupt
+const PA7_IRQ_ENABLE: u16 = 0x02;
+
+// Interrupt flags
+const TIMER_FLAG: u8 = 0x80;
+const PA7_FLAG: u8 = 0x40;
+
+/// log2 of the 1, 8, 64 and 1024 cycle intervals, selected by the low 2 bits of the address
+const INTERVAL_SHIFTS: [u8; 4] = [0, 3, 6, 10];
+
+/// A MOS 6532 RAM-I/O-Timer: 128 bytes of RAM, two 8 bit ports with their data direction
+/// registers, and an interval timer that counts every 1, 8, 64 or 1024 cycles.
+///
+/// RS is wired to A7: the RAM is at registers $00-$7F, and the ports, timer and interrupt
+/// flags at $80-$9F, mirrored up to $FF.
+///
+/// A timer loaded with N reads N - 1 on the next cycle and interrupts N * interval + 1 cycles
+/// after the write, when it counts past 0. From then on it counts down every cycle until it's
+/// loaded again. PA7 can interrupt on either edge.
+///
+/// ```rust
+/// use mini6502::device::Device;
+/// use mini6502::riot::Riot;
+///
+/// let mut riot = Riot::new();
+/// riot.write(0x10, 0x42);
+/// assert_eq!(riot.read(0x10), 0x42);
+///
+/// // 3 * 8 cycles with the timer interrupt enabled
+/// riot.write(0x9D, 3);
+/// riot.tick(8);
+/// assert_eq!(riot.read(0x8C), 2);
+/// riot.tick(17);
+/// assert!(riot.irq());
+/// assert_eq!(riot.read(0x85), 0x80);
+/// // Reading the timer acknowledges the interrupt, it counts every cycle now
+/// assert_eq!(riot.read(0x8C), 0xFF);
+/// assert!(!riot.irq());
+/// riot.tick(1);
+/// assert_eq!(riot.read(0x84), 0xFE);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Riot {
+    pub ram: [u8; 128],
+    ora: u8,
+    ddra: u8,
+    /// Levels driven on the port A pins by whatever is connected to them
+    input_a: u8,
+    orb: u8,
+    ddrb: u8,
+    input_b: u8,
+    /// Cycles left until the timer counts past 0, or its value once it did
+    timer: u32,
+    shift: u8,
+    /// The timer counted past 0 and counts every cycle
+    expired: bool,
+    timer_irq_enabled: bool,
+    pa7_positive: bool,
+    pa7_irq_enabled: bool,
+    flags: u8,
+}
+
+impl Default for Riot {
+    fn default() -> Self {
+        Riot::new()
+    }
+}
+
+impl Riot {
+    /// A RIOT after a reset: every pin an input and no interrupts enabled. The RAM and the
+    /// timer aren't reset, they start at 0 and $FF counting every cycle.
+    pub fn new() -> Self {
+        Riot {
+            ram: [0x00; 128],
+            ora: 0x00,
+            ddra: 0x00,
+            input_a: 0xFF,
+            orb: 0x00,
+            ddrb: 0x00,
+            input_b: 0xFF,
+            timer: 0xFF,
+            shift: 0,
+            expired: true,
+            timer_irq_enabled: false,
+            pa7_positive: false,
+            pa7_irq_enabled: false,
+            flags: 0x00,
+        }
+    }
+
+    /// Levels of the port A pins, those set as inputs show what was last set with
+    /// [`Riot::set_port_a`].
+    pub fn port_a(&self) -> u8 {
+        (self.ora & self.ddra) | (self.input_a & !self.ddra)
+    }
+
+    pub fn port_b(&self) -> u8 {
+        (self.orb & self.ddrb) | (self.input_b & !self.ddrb)
+    }
+
+    /// Drive the port A pins, only the ones set as inputs are read back.
+    pub fn set_port_a(&mut self, pins: u8) {
+        let pa7 = self.port_a() & 0x80;
+        self.input_a = pins;
+        self.detect_edge(pa7);
+    }
+
+    /// Drive the port B pins, only the ones set as inputs are read back.
+    pub fn set_port_b(&mut self, pins: u8) {
+        self.input_b = pins;
+    }
+
+    /// Set the PA7 flag if PA7 went from `before` to the active level.
+    fn detect_edge(&mut self, before: u8) {
+        let after = self.port_a() & 0x80;
+        if before != after && (after != 0) == self.pa7_positive {
+            self.flags |= PA7_FLAG;
+        }
+    }
+
+    fn timer_value(&self) -> u8 {
+        match self.expired {
+            true => self.timer as u8,
+            false => (self.timer >> self.shift) as u8,
+        }
+    }
+
+    fn peek_io(&self, reg: u16) -> u8 {
+        if reg & TIMER_SELECT == 0 {
+            return match reg & 0x03 {
+                ORA => self.port_a(),
+                DDRA => self.ddra,
+                ORB => self.port_b(),
+                DDRB => self.ddrb,
+                _ => unreachable!(),
+            };
+        }
+        match reg & READ_FLAGS != 0 {
+            true => self.flags,
+            false => self.timer_value(),
+        }
+    }
+
+    /// Reading the interrupt flags clears the PA7 one, reading the timer clears the timer one
+    /// and sets whether it interrupts.
+    fn read_io(&mut self, reg: u16) -> u8 {
+        let value = self.peek_io(reg);
+        if reg & TIMER_SELECT == 0 {
+            return value;
+        }
+        if reg & READ_FLAGS != 0 {
+            self.flags &= !PA7_FLAG;
+        } else {
+            self.flags &= !TIMER_FLAG;
+            self.timer_irq_enabled = reg & TIMER_IRQ_ENABLE != 0;
+        }
+        value
+    }
+
+    fn write_io(&mut self, reg: u16, value: u8) {
+        if reg & TIMER_SELECT == 0 {
+            let pa7 = self.port_a() & 0x80;
+            match reg & 0x03 {
+                ORA => self.ora = value,
+                DDRA => self.ddra = value,
+                ORB => self.orb = value,
+                DDRB => self.ddrb = value,
+                _ => unreachable!(),
+            }
+            self.detect_edge(pa7);
+        } else if reg & WRITE_TIMER != 0 {
+            self.shift = INTERVAL_SHIFTS[(reg & 0x03) as usize];
+            self.timer = (value as u32) << self.shift;
+            self.expired = false;
+            self.flags &= !TIMER_FLAG;
+            self.timer_irq_enabled = reg & TIMER_IRQ_ENABLE != 0;
+        } else {
+            self.pa7_positive = reg & PA7_POSITIVE != 0;
+            self.pa7_irq_enabled = reg & PA7_IRQ_ENABLE != 0;
+        }
+    }
+}
+
+impl Device for Riot {
+    fn read(&mut self, reg: u16) -> u8 {
+        match reg & RS {
+            0 => self.ram[(reg & 0x7F) as usize],
+            _ => self.read_io(reg),
+        }
+    }
+
+    fn peek(&self, reg: u16) -> u8 {
+        match reg & RS {
+            0 => self.ram[(reg & 0x7F) as usize],
+            _ => self.peek_io(reg),
+        }
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg & RS {
+            0 => self.ram[(reg & 0x7F) as usize] = value,
+            _ => self.write_io(reg, value),
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        let cycles = cycles as u32;
+        if cycles <= self.timer {
+            self.timer -= cycles;
+            return;
+        }
+        // Cycles since it counted past 0
+        let late = cycles - self.timer - 1;
+        self.timer = 0xFF - late % 0x100;
+        self.expired = true;
+        self.flags |= TIMER_FLAG;
+    }
+
+    fn irq(&self) -> bool {
+        (self.timer_irq_enabled && self.flags & TIMER_FLAG != 0)
+            || (self.pa7_irq_enabled && self.flags & PA7_FLAG != 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::DeviceMemory;
+    use crate::hooks::until;
+    use crate::memory::SimpleMemory;
+    use crate::Cpu;
+    use mini6502_macros::asm6502;
+
+    #[test]
+    fn test_timer() {
+        let mut riot = Riot::new();
+        // 1024 cycle interval, interrupt disabled
+        riot.write(RS | TIMER_SELECT | WRITE_TIMER | 0x03, 2);
+        assert_eq!(riot.read(RS | TIMER_SELECT), 2);
+        riot.tick(1);
+        assert_eq!(riot.read(RS | TIMER_SELECT), 1);
+        for _ in 0..8 {
+            riot.tick(255);
+        }
+        assert_eq!(riot.read(RS | TIMER_SELECT), 0);
+        riot.tick(7);
+        assert_eq!(riot.read(RS | TIMER_SELECT | READ_FLAGS), 0x00);
+        riot.tick(1);
+        assert_eq!(riot.read(RS | TIMER_SELECT | READ_FLAGS), TIMER_FLAG);
+        assert!(!riot.irq());
+        // Counts every cycle and wraps around, still interrupting
+        riot.tick(255);
+        assert_eq!(riot.timer_value(), 0x00);
+        riot.tick(2);
+        assert_eq!(riot.read(RS | TIMER_SELECT | TIMER_IRQ_ENABLE), 0xFE);
+        riot.tick(255);
+        assert!(riot.irq());
+
+        // A timer interrupt ends the wait loop, 1 cycle interval
+        let program = asm6502! {
+            lda #$20;
+            sta $809C;
+            cli;
+            wait: jmp wait;
+            handler: lda $8084;
+            done: jmp done;
+            .res $FFFE - *;
+            .word handler
+        };
+        let mut mem = DeviceMemory::new(SimpleMemory::from_rom(&program.bytes));
+        mem.map(0x8000..=0x80FF, Box::new(Riot::new()));
+        let mut cpu = Cpu::with_mem(mem);
+        cpu.run(&mut until(|cpu| cpu.pc() == program.symbol("done")))
+            .unwrap();
+        assert!(cpu.cycle_count() > 0x20);
+        assert!(cpu.ac() >= 0xF0);
+    }
+
+    #[test]
+    fn test_ports_and_edge_detect() {
+        let mut riot = Riot::new();
+        riot.write(0x7F, 0x99);
+        assert_eq!((riot.read(0x7F), riot.ram[0x7F]), (0x99, 0x99));
+
+        riot.write(RS | DDRA, 0x0F);
+        riot.write(RS | ORA, 0x55);
+        riot.set_port_a(0x30);
+        assert_eq!((riot.port_a(), riot.read(RS | ORA)), (0x35, 0x35));
+        // The I/O registers are mirrored
+        assert_eq!(riot.read(0xE1), 0x0F);
+        riot.write(RS | DDRB, 0xF0);
+        riot.write(RS | ORB, 0xA5);
+        riot.set_port_b(0x00);
+        assert_eq!(riot.read(RS | ORB), 0xA0);
+
+        // Rising edges of PA7 interrupt, falling ones don't
+        riot.write(RS | TIMER_SELECT | PA7_POSITIVE | PA7_IRQ_ENABLE, 0x00);
+        riot.set_port_a(0x80);
+        assert!(riot.irq());
+        assert_eq!(riot.read(RS | TIMER_SELECT | READ_FLAGS), PA7_FLAG);
+        assert!(!riot.irq());
+        riot.set_port_a(0x00);
+        assert!(!riot.irq());
+        // PA7 as an output drives its own edges
+        riot.write(RS | ORA, 0x80);
+        riot.write(RS | DDRA, 0x80);
+        assert!(riot.irq());
+    }
+}
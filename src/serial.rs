@@ -0,0 +1,351 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+/// The byte typed at the terminal to quit, Ctrl-]
+#[cfg(unix)]
+const QUIT_BYTE: u8 = 0x1D;
+
+/// The host side of a serial line, like the one an [`Acia`](crate::acia::Acia) drives.
+pub trait Serial {
+    /// The next byte that came in, if any. Never blocks.
+    fn receive(&mut self) -> Option<u8>;
+
+    fn send(&mut self, byte: u8);
+}
+
+/// Lets the caller keep a handle to the serial line of a device.
+impl<T: Serial> Serial for Rc<RefCell<T>> {
+    fn receive(&mut self) -> Option<u8> {
+        self.borrow_mut().receive()
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.borrow_mut().send(byte)
+    }
+}
+
+/// A serial line in memory, to script input and look at the output.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Buffer {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+}
+
+impl Buffer {
+    pub fn new(input: &[u8]) -> Self {
+        Buffer {
+            input: input.iter().copied().collect(),
+            output: vec![],
+        }
+    }
+}
+
+impl Serial for Buffer {
+    fn receive(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.output.push(byte);
+    }
+}
+
+/// Read `input` on a thread of its own, sending every byte to `received` until it ends.
+fn spawn_reader(mut input: impl Read + Send + 'static, received: Sender<u8>) {
+    thread::spawn(move || {
+        let mut buf = [0; 256];
+        while let Ok(len @ 1..) = input.read(&mut buf) {
+            if buf[..len].iter().any(|&byte| received.send(byte).is_err()) {
+                break;
+            }
+        }
+    });
+}
+
+/// A serial line over a pair of streams, e.g. files, FIFOs or a socket. The input is read on
+/// a background thread so receiving never blocks. Output that can't be written is dropped, like
+/// on a line nobody listens to.
+pub struct Streams {
+    received: Receiver<u8>,
+    output: Box<dyn Write>,
+}
+
+impl Streams {
+    pub fn new(input: impl Read + Send + 'static, output: impl Write + 'static) -> Self {
+        let (sender, received) = mpsc::channel();
+        spawn_reader(input, sender);
+        Streams {
+            received,
+            output: Box::new(output),
+        }
+    }
+}
+
+impl Serial for Streams {
+    fn receive(&mut self) -> Option<u8> {
+        self.received.try_recv().ok()
+    }
+
+    fn send(&mut self, byte: u8) {
+        let _ = self
+            .output
+            .write_all(&[byte])
+            .and_then(|_| self.output.flush());
+    }
+}
+
+/// Puts a terminal in raw mode until dropped.
+#[cfg(unix)]
+struct RawMode {
+    fd: i32,
+    saved: libc::termios,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    /// Raw mode for `fd`, or `None` if it isn't a terminal. Output processing stays on so
+    /// line feeds still go back to the first column.
+    fn enable(fd: i32) -> io::Result<Option<RawMode>> {
+        // SAFETY: termios is plain data, filled in by tcgetattr before it's used
+        unsafe {
+            if libc::isatty(fd) == 0 {
+                return Ok(None);
+            }
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let saved = termios;
+            libc::cfmakeraw(&mut termios);
+            termios.c_oflag |= libc::OPOST | libc::ONLCR;
+            if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Some(RawMode { fd, saved }))
+        }
+    }
+
+    fn restore(&self) {
+        // SAFETY: `saved` came from tcgetattr on the same fd
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+/// The serial line connected to the terminal the emulator runs in. Keys go to the emulated
+/// machine as they're typed, so Ctrl-C doesn't interrupt the emulator; Ctrl-] quits instead.
+pub struct Terminal {
+    received: Receiver<u8>,
+    #[cfg(unix)]
+    _raw_mode: Option<RawMode>,
+}
+
+impl Terminal {
+    pub fn new() -> io::Result<Self> {
+        let (sender, received) = mpsc::channel();
+        #[cfg(unix)]
+        {
+            let raw_mode = RawMode::enable(libc::STDIN_FILENO)?;
+            let saved = raw_mode.as_ref().map(|raw| RawMode {
+                fd: raw.fd,
+                saved: raw.saved,
+            });
+            thread::spawn(move || {
+                for byte in io::stdin().lock().bytes() {
+                    match byte {
+                        Ok(QUIT_BYTE) if saved.is_some() => {
+                            drop(saved);
+                            std::process::exit(0);
+                        }
+                        Ok(byte) if sender.send(byte).is_ok() => {}
+                        _ => break,
+                    }
+                }
+                // Don't restore the terminal while the main thread still uses it
+                std::mem::forget(saved);
+            });
+            Ok(Terminal {
+                received,
+                _raw_mode: raw_mode,
+            })
+        }
+        #[cfg(not(unix))]
+        {
+            spawn_reader(io::stdin(), sender);
+            Ok(Terminal { received })
+        }
+    }
+}
+
+impl Serial for Terminal {
+    fn receive(&mut self) -> Option<u8> {
+        self.received.try_recv().ok()
+    }
+
+    fn send(&mut self, byte: u8) {
+        let mut stdout = io::stdout().lock();
+        let _ = stdout.write_all(&[byte]).and_then(|_| stdout.flush());
+    }
+}
+
+/// A pseudo-terminal for other programs, like `screen` or `minicom`, to connect to.
+#[cfg(unix)]
+pub struct Pty {
+    streams: Streams,
+    path: String,
+    /// Keeps the terminal side open, reading the other side fails while nothing has it open
+    _slave: File,
+}
+
+#[cfg(unix)]
+impl Pty {
+    pub fn open() -> io::Result<Self> {
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::os::unix::io::{AsRawFd, FromRawFd};
+
+        // SAFETY: the fd is checked before it's owned by a File, and ptsname's result is
+        // copied before anything else can call it
+        let (master, path) = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let path = std::ffi::CStr::from_ptr(name)
+                .to_string_lossy()
+                .into_owned();
+            (master, path)
+        };
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)?;
+        // Whoever connects gets bytes as they are, not lines
+        std::mem::forget(RawMode::enable(slave.as_raw_fd())?);
+        Ok(Pty {
+            streams: Streams::new(master.try_clone()?, master),
+            path,
+            _slave: slave,
+        })
+    }
+
+    /// The device to connect to, like `/dev/pts/3`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+#[cfg(unix)]
+impl Serial for Pty {
+    fn receive(&mut self) -> Option<u8> {
+        self.streams.receive()
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.streams.send(byte)
+    }
+}
+
+/// Open the serial line `spec` describes:
+///
+/// * `stdio`: the terminal, see [`Terminal`]
+/// * `files:IN,OUT`: read from file IN and write to file OUT, either can be a FIFO
+/// * `unix:PATH`: wait for a connection on a Unix socket created at PATH
+/// * `pty`: a new pseudo-terminal, whose path is printed to stderr
+pub fn open(spec: &str) -> io::Result<Box<dyn Serial>> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid serial port \"{spec}\", expected stdio, files:IN,OUT, unix:PATH or pty"
+            ),
+        )
+    };
+    let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+    match kind {
+        "stdio" => Ok(Box::new(Terminal::new()?)),
+        "files" => {
+            let (input, output) = arg.split_once(',').ok_or_else(invalid)?;
+            let input = File::open(input)?;
+            let output = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(output)?;
+            Ok(Box::new(Streams::new(input, output)))
+        }
+        #[cfg(unix)]
+        "unix" if !arg.is_empty() => {
+            let _ = std::fs::remove_file(arg);
+            let listener = UnixListener::bind(arg)?;
+            eprintln!("Waiting for a serial connection on {arg}");
+            let (stream, _) = listener.accept()?;
+            Ok(Box::new(Streams::new(stream.try_clone()?, stream)))
+        }
+        #[cfg(unix)]
+        "pty" => {
+            let pty = Pty::open()?;
+            eprintln!("Serial port on {}", pty.path());
+            Ok(Box::new(pty))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_files() {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("mini6502-serial-in-{}", std::process::id()));
+        let output = dir.join(format!("mini6502-serial-out-{}", std::process::id()));
+        std::fs::write(&input, b"AB").unwrap();
+        let spec = format!("files:{},{}", input.display(), output.display());
+        let mut serial = open(&spec).unwrap();
+
+        let mut received = vec![];
+        let start = Instant::now();
+        while received.len() < 2 && start.elapsed() < Duration::from_secs(5) {
+            received.extend(serial.receive());
+        }
+        assert_eq!(received, b"AB");
+        assert_eq!(serial.receive(), None);
+        serial.send(b'C');
+        drop(serial);
+        assert_eq!(std::fs::read(&output).unwrap(), b"C");
+        let _ = std::fs::remove_file(input);
+        let _ = std::fs::remove_file(output);
+
+        for spec in ["serial", "files:in", "unix:"] {
+            let err = open(spec).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{spec}");
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use crate::cpu::Cpu;
+use crate::error::{StackViolation, StackViolationKind};
+use crate::memory::Memory;
+
+const JSR_OPCODE: u8 = 0x20;
+const BRK_OPCODE: u8 = 0x00;
+const RTS_OPCODE: u8 = 0x60;
+const RTI_OPCODE: u8 = 0x40;
+const NMI_VECTOR: u16 = 0xFFFA;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// What to do about a violation.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Severity {
+    /// Report it and carry on
+    #[default]
+    Warn,
+    /// Stop execution with an error
+    Error,
+    /// Stop in the debugger
+    Break,
+}
+
+impl Severity {
+    pub fn parse(s: &str) -> Option<Severity> {
+        match s {
+            "warn" => Some(Severity::Warn),
+            "error" => Some(Severity::Error),
+            "break" => Some(Severity::Break),
+            _ => None,
+        }
+    }
+}
+
+/// Bytes pushed and pulled by the stack instructions that don't move control.
+const fn stack_bytes(opcode: u8) -> (u8, u8) {
+    match opcode {
+        // PHA, PHP
+        0x48 | 0x08 => (1, 0),
+        // PLA, PLP
+        0x68 | 0x28 => (0, 1),
+        JSR_OPCODE => (2, 0),
+        BRK_OPCODE => (3, 0),
+        RTS_OPCODE => (0, 2),
+        RTI_OPCODE => (0, 3),
+        _ => (0, 0),
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FrameKind {
+    Call,
+    Interrupt,
+}
+
+/// A call or interrupt that hasn't returned yet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Frame {
+    kind: FrameKind,
+    /// Low byte of the stack pointer before the return address was pushed
+    sp: u8,
+    /// Where execution should resume after returning
+    resume: u16,
+}
+
+/// The cpu before the instruction being checked.
+#[derive(Copy, Clone, Debug)]
+struct Before {
+    pc: u16,
+    sp: u8,
+    opcode: u8,
+    /// Whether the cpu takes an interrupt instead of executing `opcode`
+    interrupt: bool,
+}
+
+/// Keeps a shadow stack of calls and interrupts to check that code uses the stack the way it
+/// should: no wrapping SP around, every RTS and RTI returning where its JSR or interrupt came
+/// from, and subroutines pulling everything they push.
+///
+/// ```rust
+/// use mini6502::error::StackViolationKind;
+/// use mini6502::stackcheck::StackChecker;
+/// use mini6502::{Cpu, SimpleMemory};
+///
+/// // jsr $0004; brk; pha; rts
+/// let mem = SimpleMemory::from_rom(&[0x20, 0x04, 0x00, 0x00, 0x48, 0x60]);
+/// let mut cpu = Cpu::with_mem(mem);
+/// let mut checker = StackChecker::new();
+/// let mut violations = vec![];
+/// for _ in 0..3 {
+///     checker.before_instruction(&cpu);
+///     cpu.step().unwrap();
+///     violations.extend(checker.after_instruction(&cpu));
+/// }
+///
+/// assert_eq!(violations.len(), 1);
+/// assert_eq!(violations[0].pc, 0x0005);
+/// assert_eq!(violations[0].kind, StackViolationKind::Unbalanced { bytes: 1 });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StackChecker {
+    pub severity: Severity,
+    frames: Vec<Frame>,
+    before: Option<Before>,
+}
+
+impl StackChecker {
+    pub fn new() -> Self {
+        StackChecker::default()
+    }
+
+    /// Call before the cpu executes each instruction.
+    pub fn before_instruction<M: Memory>(&mut self, cpu: &Cpu<M>) {
+        self.before = Some(Before {
+            pc: cpu.pc(),
+            sp: cpu.sp() as u8,
+            opcode: cpu.mem.peek(cpu.pc()),
+            interrupt: cpu.pending_interrupt().is_some(),
+        });
+    }
+
+    /// Call after the cpu executes each instruction. Returns what it did wrong, if anything.
+    pub fn after_instruction<M: Memory>(&mut self, cpu: &Cpu<M>) -> Option<StackViolation> {
+        let before = self.before.take()?;
+        let violation = |kind| {
+            Some(StackViolation {
+                pc: before.pc,
+                kind,
+            })
+        };
+        let sp = cpu.sp() as u8;
+
+        let (pushed, pulled) = match before.interrupt {
+            true => stack_bytes(BRK_OPCODE),
+            false => stack_bytes(before.opcode),
+        };
+        let mut found = if before.sp < pushed {
+            violation(StackViolationKind::Overflow)
+        } else if 0xFF - before.sp < pulled {
+            violation(StackViolationKind::Underflow)
+        } else {
+            None
+        };
+
+        let vector = |addr: u16| u16::from_le_bytes([cpu.mem.peek(addr), cpu.mem.peek(addr + 1)]);
+        let interrupted = before.interrupt
+            || before.opcode == BRK_OPCODE
+            || (sp == before.sp.wrapping_sub(3)
+                && (cpu.pc() == vector(IRQ_VECTOR) || cpu.pc() == vector(NMI_VECTOR)));
+        match before.opcode {
+            _ if before.interrupt => self.push_interrupt(cpu, before.sp),
+            JSR_OPCODE => self.frames.push(Frame {
+                kind: FrameKind::Call,
+                sp: before.sp,
+                resume: before.pc.wrapping_add(3),
+            }),
+            RTS_OPCODE | RTI_OPCODE => {
+                let returned = self.check_return(before, cpu.pc());
+                found = found.or_else(|| returned.and_then(violation));
+            }
+            _ if interrupted => self.push_interrupt(cpu, before.sp),
+            _ => {}
+        }
+        if before.opcode != JSR_OPCODE && !interrupted {
+            // Frames whose return address was pulled, or discarded with TXS, are gone
+            while self.frames.last().is_some_and(|frame| frame.sp <= sp) {
+                self.frames.pop();
+            }
+        }
+        found
+    }
+
+    /// Enter an interrupt taken with the stack pointer at `sp`.
+    fn push_interrupt<M: Memory>(&mut self, cpu: &Cpu<M>, sp: u8) {
+        // The return address is what the interrupt pushed
+        let stack = |offset: u8| cpu.mem.peek(0x100 + sp.wrapping_sub(offset) as u16);
+        self.frames.push(Frame {
+            kind: FrameKind::Interrupt,
+            sp,
+            resume: u16::from_le_bytes([stack(1), stack(0)]),
+        });
+    }
+
+    /// Check an RTS or RTI against the innermost frame.
+    fn check_return(&self, before: Before, resumed: u16) -> Option<StackViolationKind> {
+        let rti = before.opcode == RTI_OPCODE;
+        let expected_kind = if rti {
+            FrameKind::Interrupt
+        } else {
+            FrameKind::Call
+        };
+        let frame = match self.frames.last() {
+            Some(frame) if frame.kind == expected_kind => frame,
+            _ if rti => return Some(StackViolationKind::RtiWithoutInterrupt),
+            _ => {
+                return Some(StackViolationKind::WrongReturn {
+                    expected: None,
+                    actual: resumed,
+                })
+            }
+        };
+        let pushed = if rti { 3 } else { 2 };
+        let bytes = frame.sp.wrapping_sub(pushed) as i16 - before.sp as i16;
+        if bytes != 0 {
+            Some(StackViolationKind::Unbalanced { bytes })
+        } else if resumed != frame.resume {
+            Some(StackViolationKind::WrongReturn {
+                expected: Some(frame.resume),
+                actual: resumed,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::SimpleMemory;
+    use mini6502_macros::asm6502;
+
+    fn check(bytes: &[u8], sp: u8, steps: usize) -> Vec<StackViolation> {
+        let mut mem = SimpleMemory::from_rom(bytes);
+        // BRK goes to the RTI at the end of the program
+        mem.load(IRQ_VECTOR, &(bytes.len() as u16 - 1).to_le_bytes());
+        let mut cpu = Cpu::with_mem(mem);
+        cpu.set_pc(0x0000);
+        cpu.set_sp(sp);
+        let mut checker = StackChecker::new();
+        let mut violations = vec![];
+        for _ in 0..steps {
+            checker.before_instruction(&cpu);
+            cpu.step().unwrap();
+            violations.extend(checker.after_instruction(&cpu));
+        }
+        violations
+    }
+
+    #[test]
+    fn test_returns() {
+        let program = asm6502! {
+            jsr balanced;
+            brk;
+            nop;
+            jsr pulls;
+            rti;
+            balanced: pha;
+            pla;
+            rts;
+            pulls: pla;
+            pla;
+            lda #$00;
+            pha;
+            pha;
+            pha;
+            pha;
+            lda #$07;
+            pha;
+            rts;
+            rti
+        };
+        assert_eq!(check(&program.bytes, 0xFF, 6), []);
+
+        let violations = check(&program.bytes, 0xFF, 18);
+        let kinds: Vec<_> = violations.iter().map(|v| v.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                StackViolationKind::WrongReturn {
+                    expected: None,
+                    actual: 0x0008
+                },
+                StackViolationKind::RtiWithoutInterrupt,
+            ]
+        );
+        assert_eq!(violations[0].pc, program.symbol("pulls") + 11);
+        assert_eq!(
+            violations[1].to_string(),
+            "Stack check at $0008: RTI without a matching interrupt"
+        );
+    }
+
+    #[test]
+    fn test_overflow_and_underflow() {
+        // pha; pha; pla; pla; pla
+        let violations = check(&[0x48, 0x48, 0x68, 0x68, 0x68], 0x00, 5);
+        assert_eq!(
+            violations,
+            [
+                StackViolation {
+                    pc: 0x0000,
+                    kind: StackViolationKind::Overflow,
+                },
+                StackViolation {
+                    pc: 0x0003,
+                    kind: StackViolationKind::Underflow,
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use crate::error::SymbolError;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// How far past a label an address can be and still be shown as `label+offset`
+const MAX_OFFSET: u16 = 0x100;
+
+/// Names for addresses, imported from the label files written by assemblers and linkers.
+///
+/// The format is detected from the contents:
+///
+/// * VICE monitor labels and ld65 `-Ln` output: `al C:0810 .start`
+/// * ld65 `-m` map files: the "Exports list by name" section
+/// * ACME `--symbollist`, 64tass `--labels` and plain files: `name = $0810`
+///
+/// ```rust
+/// use mini6502::symbols::SymbolTable;
+///
+/// let symbols = SymbolTable::parse("al C:0600 .start\nptr = $20 ; zero page").unwrap();
+/// assert_eq!(symbols.lookup("start"), Some(0x0600));
+/// assert_eq!(symbols.name_of(0x0020), Some("ptr"));
+/// assert_eq!(symbols.describe(0x0603), "start+3");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    by_name: BTreeMap<String, u16>,
+    by_addr: BTreeMap<u16, Vec<String>>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    /// Add `name`, replacing any previous definition.
+    pub fn insert(&mut self, name: &str, addr: u16) {
+        if let Some(old) = self.by_name.insert(name.to_string(), addr) {
+            if let Some(names) = self.by_addr.get_mut(&old) {
+                names.retain(|n| n != name);
+            }
+        }
+        let names = self.by_addr.entry(addr).or_default();
+        names.push(name.to_string());
+        // Prefer names that look global, shortest first
+        names.sort_by_key(|n| (is_local(n), n.len()));
+    }
+
+    /// Add every symbol in `other`.
+    pub fn extend(&mut self, other: &SymbolTable) {
+        for (name, addr) in other.iter() {
+            self.insert(name, addr);
+        }
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    /// The preferred name for `addr`, if there is one.
+    pub fn name_of(&self, addr: u16) -> Option<&str> {
+        self.by_addr
+            .get(&addr)
+            .and_then(|names| names.first())
+            .map(String::as_str)
+    }
+
+    /// `name` or `name+offset` for the closest symbol at or below `addr`, `$HHLL` if there is
+    /// none nearby.
+    pub fn describe(&self, addr: u16) -> String {
+        let nearest = self
+            .by_addr
+            .range(addr.saturating_sub(MAX_OFFSET)..=addr)
+            .rev()
+            .find(|(_, names)| !names.is_empty());
+        match nearest {
+            Some((base, names)) if *base == addr => names[0].clone(),
+            Some((base, names)) => format!("{}+{}", names[0], addr - base),
+            None => format!("${addr:04X}"),
+        }
+    }
+
+    /// Addresses from `name` up to the next symbol, which for a code label is usually the
+    /// routine it names.
+    pub fn extent(&self, name: &str) -> Option<std::ops::Range<u32>> {
+        let start = self.lookup(name)?;
+        let end = self
+            .by_addr
+            .range(start.saturating_add(1)..)
+            .find(|(_, names)| !names.is_empty())
+            .map_or(0x10000, |(addr, _)| *addr as u32);
+        Some(start as u32..end)
+    }
+
+    /// Resolve `text` as a symbol name or a hex address (`$HHLL`, `0xHHLL` or `HHLL`).
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        if let Some(addr) = self.lookup(text) {
+            return Some(addr);
+        }
+        let digits = text.trim_start_matches('$').trim_start_matches("0x");
+        u16::from_str_radix(digits, 16).ok()
+    }
+
+    /// Name and address of every symbol, ordered by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.by_name
+            .iter()
+            .map(|(name, addr)| (name.as_str(), *addr))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<SymbolTable, SymbolError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| SymbolError::Io(format!("{}: {e}", path.display())))?;
+        SymbolTable::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<SymbolTable, SymbolError> {
+        let mut table = SymbolTable::new();
+        if text.contains("Exports list by name:") {
+            table.parse_ld65_map(text)?;
+            return Ok(table);
+        }
+
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+            let error = || SymbolError::Syntax {
+                line: idx + 1,
+                text: line.to_string(),
+            };
+            let (name, addr) = if line.starts_with("al ") {
+                parse_vice(line).ok_or_else(error)?
+            } else {
+                parse_assignment(line).ok_or_else(error)?
+            };
+            table.insert(name, addr);
+        }
+        Ok(table)
+    }
+
+    /// Read the exports of an ld65 map file, listed two per line as `name  00HHLL TYPE`.
+    fn parse_ld65_map(&mut self, text: &str) -> Result<(), SymbolError> {
+        let lines = text
+            .lines()
+            .enumerate()
+            .skip_while(|(_, line)| !line.starts_with("Exports list by name:"))
+            .skip(2);
+        for (idx, line) in lines {
+            if line.trim().is_empty() {
+                break;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            for export in words.chunks(3) {
+                let addr = match export {
+                    [_, value, _] => u32::from_str_radix(value, 16).ok(),
+                    _ => None,
+                };
+                match addr {
+                    Some(addr) => self.insert(export[0], addr as u16),
+                    None => {
+                        return Err(SymbolError::Syntax {
+                            line: idx + 1,
+                            text: line.to_string(),
+                        })
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> FromIterator<(&'a str, u16)> for SymbolTable {
+    fn from_iter<I: IntoIterator<Item = (&'a str, u16)>>(iter: I) -> Self {
+        let mut table = SymbolTable::new();
+        for (name, addr) in iter {
+            table.insert(name, addr);
+        }
+        table
+    }
+}
+
+/// Local labels (`global@local`, `.local`, `@local`) and linker generated ones (`__NAME__`)
+fn is_local(name: &str) -> bool {
+    name.contains('@') || name.starts_with('.') || name.starts_with("__")
+}
+
+/// `al C:0810 .start`, the `C:` memory space and the leading dot are optional.
+fn parse_vice(line: &str) -> Option<(&str, u16)> {
+    let mut words = line.split_whitespace().skip(1);
+    let addr = words.next()?;
+    let name = words.next()?;
+    let addr = addr.strip_prefix("C:").unwrap_or(addr);
+    let addr = u32::from_str_radix(addr, 16).ok()?;
+    let name = name.strip_prefix('.').unwrap_or(name);
+    (!name.is_empty() && words.next().is_none()).then_some((name, addr as u16))
+}
+
+/// `name = value`, where value is `$hex`, `0xhex`, `%binary` or decimal, maybe followed by a
+/// `;` comment.
+fn parse_assignment(line: &str) -> Option<(&str, u16)> {
+    let (name, value) = line.split_once('=')?;
+    let name = name.trim();
+    let value = value.split(';').next()?.trim();
+    let value = if let Some(hex) = value.strip_prefix('$').or_else(|| value.strip_prefix("0x")) {
+        u32::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = value.strip_prefix('%') {
+        u32::from_str_radix(bin, 2).ok()?
+    } else {
+        value.parse::<u32>().ok()?
+    };
+    let valid_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '@'));
+    valid_name.then_some((name, value as u16))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        let vice = SymbolTable::parse("al C:0810 .start\nal 00C000 .__STARTUP__\n").unwrap();
+        assert_eq!(vice.lookup("start"), Some(0x0810));
+        assert_eq!(vice.lookup("__STARTUP__"), Some(0xC000));
+
+        let acme =
+            SymbolTable::parse("; ******** Source: main.a\n\tloop\t= $0812\n\tcount\t= 3 ; ?\n")
+                .unwrap();
+        assert_eq!(acme.lookup("loop"), Some(0x0812));
+        assert_eq!(acme.lookup("count"), Some(3));
+
+        let map = "\
+Modules list:
+-------------
+main.o:
+    CODE              Offs=000000  Size=000010  Align=00001  Fill=0000
+
+Exports list by name:
+---------------------
+__STACKSIZE__             000800 REA    _main                     000812 RLA
+reset                     00C000 RLA
+
+Exports list by value:
+----------------------
+_main                     000812 RLA
+";
+        let map = SymbolTable::parse(map).unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.lookup("_main"), Some(0x0812));
+        assert_eq!(map.lookup("reset"), Some(0xC000));
+
+        assert_eq!(
+            SymbolTable::parse("start: $0810"),
+            Err(SymbolError::Syntax {
+                line: 1,
+                text: "start: $0810".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_names() {
+        let symbols: SymbolTable = [("loop@inner", 0x0602), ("loop", 0x0602), ("start", 0x0600)]
+            .into_iter()
+            .collect();
+        assert_eq!(symbols.name_of(0x0602), Some("loop"));
+        assert_eq!(symbols.describe(0x0601), "start+1");
+        assert_eq!(symbols.describe(0x0500), "$0500");
+        assert_eq!(symbols.resolve("start"), Some(0x0600));
+        assert_eq!(symbols.resolve("$12"), Some(0x0012));
+        assert_eq!(symbols.extent("start"), Some(0x0600..0x0602));
+
+        let mut symbols = symbols;
+        symbols.insert("loop", 0x0700);
+        assert_eq!(symbols.name_of(0x0602), Some("loop@inner"));
+        assert_eq!(symbols.name_of(0x0700), Some("loop"));
+    }
+}
@@ -0,0 +1,46 @@
+use crate::{Cpu, SimpleMemory};
+use mini6502_macros::asm6502;
+
+/// The cycles each of the first `count` instructions of `program` takes.
+fn cycles(program: &[u8], count: usize) -> Vec<u8> {
+    let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(program));
+    (0..count).map(|_| cpu.step().unwrap()).collect()
+}
+
+#[test]
+fn test_load_cycles() {
+    let program = asm6502! {
+        lda $10,x;
+        lda $1234;
+        lda $1234,x;
+        lda $1234,y;
+        lda ($10),y;
+        ldx #$01;
+        ldx $10;
+        ldx $10,y;
+        ldx $1234;
+        ldx $1234,y;
+        ldy #$01;
+        ldy $10;
+        ldy $10,x;
+        ldy $1234;
+        ldy $1234,x
+    };
+    assert_eq!(
+        cycles(&program.bytes, 15),
+        [4, 4, 4, 4, 5, 2, 3, 4, 4, 4, 2, 3, 4, 4, 4]
+    );
+}
+
+#[test]
+fn test_bit_and_stack_cycles() {
+    let program = asm6502! {
+        bit $10;
+        bit $1234;
+        pha;
+        pla;
+        php;
+        plp
+    };
+    assert_eq!(cycles(&program.bytes, 6), [3, 4, 3, 4, 3, 4]);
+}
@@ -1,5 +1,18 @@
+use crate::hooks::until;
 use crate::opc::{AddressMode, Inst};
 use crate::{util, Cpu, SimpleMemory};
+use mini6502_macros::asm6502;
+
+/// Assemble the program and load it at its origin, with the pc pointing at it.
+macro_rules! load {
+    ($cpu:ident, $($program:tt)*) => {
+        let program = asm6502! { $($program)* };
+        for (addr, byte) in (program.origin..).zip(program.bytes) {
+            $cpu.write_to_mem(addr, byte);
+        }
+        $cpu.set_pc(program.origin);
+    };
+}
 
 #[test]
 pub fn test_adc() {
@@ -7,62 +20,55 @@ pub fn test_adc() {
 
     cpu.write_c_flag(false);
     cpu.set_ac(0x01);
-    cpu.write_to_mem(0x0001, 0x01);
-    cpu.step_inst(Inst::ADC, AddressMode::IMM).unwrap();
+    load!(cpu, adc #$01);
+    cpu.step().unwrap();
     assert!(!cpu.c_flag());
     assert_eq!(cpu.ac(), 0x02);
 
-    cpu.set_pc(0x00);
     cpu.write_c_flag(false);
     cpu.set_ac(0x01);
-    cpu.write_to_mem(0x0001, 0xFF);
-    cpu.step_inst(Inst::ADC, AddressMode::IMM).unwrap();
+    load!(cpu, adc #$FF);
+    cpu.step().unwrap();
     assert_eq!(cpu.ac(), 0x00);
     assert!(cpu.c_flag());
 
-    cpu.set_pc(0x00);
     cpu.write_c_flag(false);
     cpu.set_ac(0x80);
-    cpu.write_to_mem(0x0001, 0xFF);
-    cpu.step_inst(Inst::ADC, AddressMode::IMM).unwrap();
+    load!(cpu, adc #$FF);
+    cpu.step().unwrap();
     assert!(cpu.v_flag());
     assert!(cpu.c_flag());
 
-    cpu.set_pc(0x00);
     cpu.write_c_flag(true);
     cpu.set_ac(0x3F);
-    cpu.write_to_mem(0x0001, 0x40);
-    cpu.step_inst(Inst::ADC, AddressMode::IMM).unwrap();
+    load!(cpu, adc #$40);
+    cpu.step().unwrap();
     assert!(cpu.v_flag());
     assert!(!cpu.c_flag());
 
-    cpu.set_pc(0x00);
     cpu.write_c_flag(false);
     cpu.set_ac(0b01000000);
-    cpu.write_to_mem(0x0001, 0b01000001);
-    cpu.step_inst(Inst::ADC, AddressMode::IMM).unwrap();
+    load!(cpu, adc #0b01000001);
+    cpu.step().unwrap();
     assert!(cpu.v_flag());
 
-    cpu.set_pc(0x00);
     cpu.write_c_flag(true);
     cpu.set_ac(0b01000000);
-    cpu.write_to_mem(0x0001, 0b01000001);
-    cpu.step_inst(Inst::ADC, AddressMode::IMM).unwrap();
+    load!(cpu, adc #0b01000001);
+    cpu.step().unwrap();
     assert!(cpu.v_flag());
 
-    cpu.set_pc(0x00);
     cpu.write_c_flag(false);
     cpu.set_ac(0b11111111);
-    cpu.write_to_mem(0x0001, 0b11111111);
-    cpu.step_inst(Inst::ADC, AddressMode::IMM).unwrap();
+    load!(cpu, adc #0b11111111);
+    cpu.step().unwrap();
     assert!(!cpu.v_flag());
     assert!(cpu.c_flag());
 
-    cpu.set_pc(0x00);
     cpu.write_c_flag(false);
     cpu.set_ac(0b11000000);
-    cpu.write_to_mem(0x0001, 0b10111111);
-    cpu.step_inst(Inst::ADC, AddressMode::IMM).unwrap();
+    load!(cpu, adc #0b10111111);
+    cpu.step().unwrap();
     assert!(cpu.v_flag());
     assert!(cpu.c_flag());
     assert_eq!(cpu.ac(), 0b01111111);
@@ -72,10 +78,9 @@ pub fn test_adc() {
 pub fn test_and() {
     let mut cpu = util::new_cpu_empty_mem();
     cpu.set_ac(0x4A);
-    cpu.write_to_mem(0x0001, 0x56);
-    cpu.write_to_mem(0x0002, 0x44);
     cpu.write_to_mem(0x4456, 0x48);
-    cpu.step_inst(Inst::AND, AddressMode::ABS).unwrap();
+    load!(cpu, and $4456);
+    cpu.step().unwrap();
     assert_eq!(cpu.ac(), 0x48);
 }
 
@@ -91,10 +96,9 @@ pub fn test_asl() {
     assert!(cpu.c_flag());
 
     // Test with memory
-    cpu.set_pc(0x0600);
     cpu.write_to_mem(0x0040, 0b0010_0000);
-    cpu.write_to_mem(0x0601, 0x40);
-    cpu.step_inst(Inst::ASL, AddressMode::ZPG).unwrap();
+    load!(cpu, .org $0600; asl $40);
+    cpu.step().unwrap();
     assert_eq!(cpu.read_byte_from_mem(0x0040), 0b0100_0000);
 }
 
@@ -104,98 +108,90 @@ pub fn test_branch_instructions() {
 
     // BCC
     cpu.write_c_flag(true);
-    cpu.set_pc(0x0200);
-    cpu.step_inst(Inst::BCC, AddressMode::REL).unwrap();
+    load!(cpu, .org $0200; bcc next; next: bcc $01E6);
+    cpu.step().unwrap();
     // No jump
     assert_eq!(cpu.pc(), 0x0202);
     cpu.write_c_flag(false);
     // -30
-    cpu.write_to_mem(0x0203, 0xE2);
-    cpu.step_inst(Inst::BCC, AddressMode::REL).unwrap();
+    cpu.step().unwrap();
     assert_eq!(cpu.pc(), 0x01E6);
 
     // BCS
     cpu.write_c_flag(false);
-    cpu.set_pc(0x0300);
-    cpu.step_inst(Inst::BCS, AddressMode::REL).unwrap();
+    load!(cpu, .org $0300; bcs next; next: bcs $02E6);
+    cpu.step().unwrap();
     // No jump
     assert_eq!(cpu.pc(), 0x0302);
     cpu.write_c_flag(true);
     // -30
-    cpu.write_to_mem(0x0303, 0xE2);
-    cpu.step_inst(Inst::BCS, AddressMode::REL).unwrap();
+    cpu.step().unwrap();
     assert_eq!(cpu.pc(), 0x02E6);
 
     // BEQ
     cpu.write_z_flag(false);
-    cpu.set_pc(0x0400);
-    cpu.step_inst(Inst::BEQ, AddressMode::REL).unwrap();
+    load!(cpu, .org $0400; beq next; next: beq $03E6);
+    cpu.step().unwrap();
     // No jump
     assert_eq!(cpu.pc(), 0x0402);
     cpu.write_z_flag(true);
     // -30
-    cpu.write_to_mem(0x0403, 0xE2);
-    cpu.step_inst(Inst::BEQ, AddressMode::REL).unwrap();
+    cpu.step().unwrap();
     assert_eq!(cpu.pc(), 0x03E6);
 
     // BMI
     cpu.update_n_flag_with(0x01);
-    cpu.set_pc(0x0500);
-    cpu.step_inst(Inst::BMI, AddressMode::REL).unwrap();
+    load!(cpu, .org $0500; bmi next; next: bmi $04E6);
+    cpu.step().unwrap();
     // No jump
     assert_eq!(cpu.pc(), 0x0502);
     cpu.update_n_flag_with(0xFF);
     // -30
-    cpu.write_to_mem(0x0503, 0xE2);
-    cpu.step_inst(Inst::BMI, AddressMode::REL).unwrap();
+    cpu.step().unwrap();
     assert_eq!(cpu.pc(), 0x04E6);
 
     // BNE
     cpu.write_z_flag(true);
-    cpu.set_pc(0x0600);
-    cpu.step_inst(Inst::BNE, AddressMode::REL).unwrap();
+    load!(cpu, .org $0600; bne next; next: bne $05E6);
+    cpu.step().unwrap();
     // No jump
     assert_eq!(cpu.pc(), 0x0602);
     cpu.write_z_flag(false);
     // -30
-    cpu.write_to_mem(0x0603, 0xE2);
-    cpu.step_inst(Inst::BNE, AddressMode::REL).unwrap();
+    cpu.step().unwrap();
     assert_eq!(cpu.pc(), 0x05E6);
 
     // BPL
     cpu.update_n_flag_with(0b11111111);
-    cpu.set_pc(0x0700);
-    cpu.step_inst(Inst::BPL, AddressMode::REL).unwrap();
+    load!(cpu, .org $0700; bpl next; next: bpl $06E6);
+    cpu.step().unwrap();
     // No jump
     assert_eq!(cpu.pc(), 0x0702);
     cpu.update_n_flag_with(0b00000001);
     // -30
-    cpu.write_to_mem(0x0703, 0xE2);
-    cpu.step_inst(Inst::BPL, AddressMode::REL).unwrap();
+    cpu.step().unwrap();
     assert_eq!(cpu.pc(), 0x06E6);
 
     // BVC
     cpu.write_v_flag(true);
-    cpu.set_pc(0x0800);
-    cpu.step_inst(Inst::BVC, AddressMode::REL).unwrap();
+    load!(cpu, .org $0800; bvc next; next: bvc $07E6);
+    cpu.step().unwrap();
     // No jump
     assert_eq!(cpu.pc(), 0x0802);
     cpu.write_v_flag(false);
     // -30
-    cpu.write_to_mem(0x0803, 0xE2);
-    cpu.step_inst(Inst::BVC, AddressMode::REL).unwrap();
+    cpu.step().unwrap();
     assert_eq!(cpu.pc(), 0x07E6);
 
     // BVS
     cpu.write_v_flag(false);
-    cpu.set_pc(0x0900);
-    cpu.step_inst(Inst::BVS, AddressMode::REL).unwrap();
+    load!(cpu, .org $0900; bvs next; next: bvs $08E6);
+    cpu.step().unwrap();
     // No jump
     assert_eq!(cpu.pc(), 0x0902);
     cpu.write_v_flag(true);
     // -30
-    cpu.write_to_mem(0x0903, 0xE2);
-    cpu.step_inst(Inst::BVS, AddressMode::REL).unwrap();
+    cpu.step().unwrap();
     assert_eq!(cpu.pc(), 0x08E6);
 }
 
@@ -203,21 +199,19 @@ pub fn test_branch_instructions() {
 pub fn test_bit() {
     let mut cpu = util::new_cpu_empty_mem();
     cpu.write_to_mem(0x0500, 0b1100_0000);
+    cpu.write_to_mem(0x0040, 0b0100_0000);
+    load!(cpu, bit $0500; bit $40);
     cpu.set_ac(0b0011_0000);
-    cpu.write_to_mem(0x0001, 0x00);
-    cpu.write_to_mem(0x0002, 0x05);
     println!("{cpu}");
-    cpu.step_inst(Inst::BIT, AddressMode::ABS).unwrap();
+    cpu.step().unwrap();
     // A & M == 0 -> Z == 1
     assert!(cpu.z_flag());
     assert!(cpu.v_flag());
     assert!(cpu.n_flag());
 
     cpu.set_ac(0b0100_0000);
-    cpu.write_to_mem(0x0040, 0b0100_0000);
-    cpu.write_to_mem(0x0004, 0x40);
     println!("{cpu}");
-    cpu.step_inst(Inst::BIT, AddressMode::ZPG).unwrap();
+    cpu.step().unwrap();
     // A & Z != 0, so Z == 0
     assert!(!cpu.z_flag());
     assert!(cpu.v_flag());
@@ -250,22 +244,19 @@ pub fn test_brk() {
 pub fn test_cmp() {
     let mut cpu = util::new_cpu_empty_mem();
     cpu.set_y(0x10);
-    cpu.write_to_mem(0x0001, 0x42);
     cpu.write_to_mem(0x0052, 0x69);
+    cpu.write_to_mem(0xFFFF, 0xE0);
+    load!(cpu, cmp ($42),y; cmp $FFFF);
     cpu.set_ac(0x20);
     // 0x20 - 0x69
-    cpu.step_inst(Inst::CMP, AddressMode::INDY).unwrap();
+    cpu.step().unwrap();
     assert!(!cpu.z_flag());
     assert!(cpu.n_flag());
     assert!(!cpu.c_flag());
 
     cpu.set_ac(0xE0);
-    cpu.write_to_mem(0xFFFF, 0xE0);
-    cpu.write_to_mem(0x03, 0xFF);
-    cpu.write_to_mem(0x04, 0xFF);
-
     // 0xE0 - 0xE0
-    cpu.step_inst(Inst::CMP, AddressMode::ABS).unwrap();
+    cpu.step().unwrap();
     assert!(cpu.z_flag());
     assert!(!cpu.n_flag());
     assert!(cpu.c_flag());
@@ -275,44 +266,39 @@ pub fn test_cmp() {
 pub fn test_cpx() {
     let mut cpu = util::new_cpu_empty_mem();
     cpu.write_to_mem(0x0052, 0x69);
-    cpu.write_to_mem(0x0001, 0x52);
+    load!(cpu, cpx $52; cpx #$E0; cpx #$10);
     cpu.set_x(0x20);
     // 0x20 - 0x69
-    cpu.step_inst(Inst::CPX, AddressMode::ZPG).unwrap();
+    cpu.step().unwrap();
     assert!(!cpu.z_flag());
     assert!(cpu.n_flag());
     assert!(!cpu.c_flag());
 
     cpu.set_x(0xE1);
-    cpu.write_to_mem(0x03, 0xE0);
-
     // 0xE1 - 0xE0
-    cpu.step_inst(Inst::CPX, AddressMode::IMM).unwrap();
+    cpu.step().unwrap();
     assert!(!cpu.z_flag());
     assert!(!cpu.n_flag());
     assert!(cpu.c_flag());
 
-    cpu.write_to_mem(0x05, 0x10);
     cpu.set_x(0x10);
-    cpu.step_inst(Inst::CPX, AddressMode::IMM).unwrap();
+    cpu.step().unwrap();
     assert!(cpu.z_flag());
 }
 #[test]
 pub fn test_cpy() {
     let mut cpu = util::new_cpu_empty_mem();
-    cpu.write_to_mem(0x0001, 0x69);
+    load!(cpu, cpy #$69; cpy #$E0);
     cpu.set_y(0x20);
     // 0x20 - 0x69
-    cpu.step_inst(Inst::CPY, AddressMode::IMM).unwrap();
+    cpu.step().unwrap();
     assert!(!cpu.z_flag());
     assert!(cpu.n_flag());
     assert!(!cpu.c_flag());
 
     cpu.set_y(0xE1);
-    cpu.write_to_mem(0x03, 0xE0);
-
     // 0xE1 - 0xE0
-    cpu.step_inst(Inst::CPY, AddressMode::IMM).unwrap();
+    cpu.step().unwrap();
     assert!(!cpu.z_flag());
     assert!(!cpu.n_flag());
     assert!(cpu.c_flag());
@@ -322,12 +308,11 @@ pub fn test_cpy() {
 pub fn test_dec() {
     let mut cpu = util::new_cpu_empty_mem();
     cpu.write_to_mem(0x0020, 0x01);
-    cpu.write_to_mem(0x0001, 0x20);
-    cpu.step_inst(Inst::DEC, AddressMode::ZPG).unwrap();
+    load!(cpu, dec $20; dec $20);
+    cpu.step().unwrap();
     assert!(cpu.z_flag());
     assert!(!cpu.n_flag());
-    cpu.write_to_mem(0x0003, 0x20);
-    cpu.step_inst(Inst::DEC, AddressMode::ZPG).unwrap();
+    cpu.step().unwrap();
     assert!(!cpu.z_flag());
     assert!(cpu.n_flag());
 }
@@ -357,16 +342,15 @@ pub fn test_dey() {
 #[test]
 pub fn test_eor() {
     let mut cpu = util::new_cpu_empty_mem();
+    load!(cpu, eor #0b10000000; eor #0b10000000);
     cpu.set_ac(0b10000000);
-    cpu.write_to_mem(0x0001, 0b10000000);
-    cpu.step_inst(Inst::EOR, AddressMode::IMM).unwrap();
+    cpu.step().unwrap();
     assert!(cpu.z_flag());
     assert!(!cpu.n_flag());
     assert_eq!(cpu.ac(), 0);
 
     cpu.set_ac(0b00000001);
-    cpu.write_to_mem(0x0003, 0b10000000);
-    cpu.step_inst(Inst::EOR, AddressMode::IMM).unwrap();
+    cpu.step().unwrap();
     assert!(!cpu.z_flag());
     assert!(cpu.n_flag());
     assert_eq!(cpu.ac(), 0b10000001);
@@ -376,13 +360,11 @@ pub fn test_eor() {
 pub fn test_inc() {
     let mut cpu = util::new_cpu_empty_mem();
     cpu.write_to_mem(0x0100, 0xFE);
-    cpu.write_to_mem(0x01, 0x00);
-    cpu.write_to_mem(0x02, 0x01);
-    cpu.step_inst(Inst::INC, AddressMode::ABS).unwrap();
+    load!(cpu, inc $0100; inc $0100);
+    cpu.step().unwrap();
     let b = cpu.read_byte_from_mem(0x0100);
     assert_eq!(b, 0xFF);
-    cpu.set_pc(0x00);
-    cpu.step_inst(Inst::INC, AddressMode::ABS).unwrap();
+    cpu.step().unwrap();
     let b = cpu.read_byte_from_mem(0x0100);
     assert_eq!(b, 0x00);
     assert!(cpu.z_flag());
@@ -416,41 +398,38 @@ pub fn test_iny() {
 #[test]
 pub fn test_jmp() {
     let mut cpu = util::new_cpu_empty_mem();
-    cpu.write_to_mem(0x01, 0x30);
-    cpu.write_to_mem(0x02, 0x50);
-    cpu.step_inst(Inst::JMP, AddressMode::ABS).unwrap();
+    load!(cpu, jmp $5030);
+    cpu.step().unwrap();
     assert_eq!(cpu.pc(), 0x5030);
 }
 
 #[test]
 pub fn test_jsr() {
     let mut cpu = util::new_cpu_empty_mem();
-    cpu.set_pc(0x0069);
-    cpu.write_to_mem(0x006A, 0x50);
-    cpu.write_to_mem(0x006B, 0x45);
+    load!(cpu, .org $4550; rts);
+    load!(cpu, .org $0069; jsr $4550);
     // Pushes 0x006B to stack
-    cpu.step_inst(Inst::JSR, AddressMode::ABS).unwrap();
+    cpu.step().unwrap();
     assert_eq!(cpu.pc(), 0x4550);
     // Pulls 0x006B and sets pc to 0x006C
-    cpu.step_inst(Inst::RTS, AddressMode::IMPL).unwrap();
+    cpu.step().unwrap();
     assert_eq!(cpu.pc(), 0x006C);
 }
 
 #[test]
 pub fn test_lda() {
     let mut cpu = util::new_cpu_empty_mem();
-    cpu.write_to_mem(0x01, 0x35);
-    cpu.step_inst(Inst::LDA, AddressMode::IMM).unwrap();
+    load!(cpu, lda #$35);
+    cpu.step().unwrap();
     assert_eq!(cpu.ac(), 0x35);
 }
 
 #[test]
 pub fn test_ldx() {
     let mut cpu = util::new_cpu_empty_mem();
-    cpu.write_to_mem(0x0001, 0x35);
-    cpu.write_to_mem(0x0002, 0x37);
     cpu.write_to_mem(0x3735, 0b1000_0000);
-    cpu.step_inst(Inst::LDX, AddressMode::ABS).unwrap();
+    load!(cpu, ldx $3735);
+    cpu.step().unwrap();
     assert_eq!(cpu.x(), 0b1000_0000);
     assert!(cpu.n_flag());
     assert!(!cpu.z_flag());
@@ -459,11 +438,10 @@ pub fn test_ldx() {
 #[test]
 pub fn test_ldy() {
     let mut cpu = util::new_cpu_empty_mem();
-    cpu.write_to_mem(0x0001, 0x35);
-    cpu.write_to_mem(0x0002, 0x37);
     cpu.write_to_mem(0x3735, 0b0000_0000);
     cpu.set_y(40);
-    cpu.step_inst(Inst::LDY, AddressMode::ABS).unwrap();
+    load!(cpu, ldy $3735);
+    cpu.step().unwrap();
     assert_eq!(cpu.y(), 0b0000_0000);
     assert!(!cpu.n_flag());
     assert!(cpu.z_flag());
@@ -480,8 +458,8 @@ pub fn test_lsr() {
 
     let mut cpu = util::new_cpu_empty_mem();
     cpu.write_to_mem(0x0034, 0b00000001);
-    cpu.write_to_mem(0x0001, 0x34);
-    cpu.step_inst(Inst::LSR, AddressMode::ZPG).unwrap();
+    load!(cpu, lsr $34);
+    cpu.step().unwrap();
     assert_eq!(cpu.read_byte_from_mem(0x0034), 0b00000000);
     assert!(cpu.z_flag());
     assert!(cpu.z_flag());
@@ -491,8 +469,8 @@ pub fn test_lsr() {
 pub fn test_ora() {
     let mut cpu = util::new_cpu_empty_mem();
     cpu.set_ac(0b00100001);
-    cpu.write_to_mem(0x0001, 0b10000000);
-    cpu.step_inst(Inst::ORA, AddressMode::IMM).unwrap();
+    load!(cpu, ora #0b10000000);
+    cpu.step().unwrap();
     assert_eq!(cpu.ac(), 0b00100001 | 0b10000000);
     assert!(!cpu.z_flag());
     assert!(cpu.n_flag());
@@ -548,12 +526,12 @@ pub fn test_ror() {
 #[test]
 pub fn test_sbc() {
     let mut cpu = util::new_cpu_empty_mem();
+    load!(cpu, sbc #$01; sbc #$FF; sbc #$40);
 
     // -128 - 1 = -129, returns V = 1
     cpu.write_c_flag(true);
     cpu.set_ac(0x80);
-    cpu.write_to_mem(0x0001, 0x01);
-    cpu.step_inst(Inst::SBC, AddressMode::IMM).unwrap();
+    cpu.step().unwrap();
     assert!(cpu.v_flag());
     assert!(cpu.c_flag());
     assert_eq!(cpu.ac(), 0x7F);
@@ -562,8 +540,7 @@ pub fn test_sbc() {
     cpu.write_c_flag(true);
     cpu.write_v_flag(false);
     cpu.set_ac(0x7F);
-    cpu.write_to_mem(0x0003, 0xFF);
-    cpu.step_inst(Inst::SBC, AddressMode::IMM).unwrap();
+    cpu.step().unwrap();
     assert!(cpu.v_flag());
     assert!(!cpu.c_flag());
     assert_eq!(cpu.ac(), 0x80);
@@ -571,8 +548,7 @@ pub fn test_sbc() {
     cpu.set_ac(0xC0);
     cpu.write_c_flag(false);
     cpu.write_v_flag(false);
-    cpu.write_to_mem(0x0005, 0x40);
-    cpu.step_inst(Inst::SBC, AddressMode::IMM).unwrap();
+    cpu.step().unwrap();
     assert!(cpu.v_flag());
     assert!(cpu.c_flag());
     assert_eq!(cpu.ac(), 0x7F);
@@ -605,29 +581,27 @@ fn test_sei() {
 #[test]
 fn test_sta() {
     let mut cpu = util::new_cpu_empty_mem();
-    cpu.write_to_mem(0x0001, 0xF8);
     cpu.set_ac(0xF1);
-    cpu.step_inst(Inst::STA, AddressMode::ZPG).unwrap();
+    load!(cpu, sta $F8);
+    cpu.step().unwrap();
     assert_eq!(cpu.read_byte_from_mem(0x00F8), 0xF1);
 }
 
 #[test]
 fn test_stx() {
     let mut cpu = util::new_cpu_empty_mem();
-    cpu.write_to_mem(0x0001, 0xF8);
-    cpu.write_to_mem(0x0002, 0x03);
     cpu.set_x(0xF1);
-    cpu.step_inst(Inst::STX, AddressMode::ABS).unwrap();
+    load!(cpu, stx $03F8);
+    cpu.step().unwrap();
     assert_eq!(cpu.read_byte_from_mem(0x03F8), 0xF1);
 }
 
 #[test]
 fn test_sty() {
     let mut cpu = util::new_cpu_empty_mem();
-    cpu.write_to_mem(0x0001, 0xE8);
-    cpu.write_to_mem(0x0002, 0x04);
     cpu.set_y(0xF1);
-    cpu.step_inst(Inst::STY, AddressMode::ABS).unwrap();
+    load!(cpu, sty $04E8);
+    cpu.step().unwrap();
     assert_eq!(cpu.read_byte_from_mem(0x04E8), 0xF1);
 }
 
@@ -693,7 +667,7 @@ fn functional_test() {
 
     // let mut jmp_count = 0;
     cpu.set_pc(0x0400);
-    cpu.run(&mut |cpu: &Cpu<SimpleMemory>| {
+    cpu.run(&mut until(|cpu: &Cpu<SimpleMemory>| {
         println!("{cpu}");
         // println!(
         // "{:#?}",
@@ -706,7 +680,7 @@ fn functional_test() {
         // cpu.pc() == 0x37C9
         // cpu.sp() != 0x01FF
         false
-    })
+    }))
     .unwrap();
 
     println!("pc: {:#06x}", cpu.pc());
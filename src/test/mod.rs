@@ -1,6 +1,8 @@
 #[cfg(test)]
 mod address;
 #[cfg(test)]
+mod cycles;
+#[cfg(test)]
 mod flags;
 #[cfg(test)]
 mod instructions;
@@ -1,5 +1,10 @@
+use crate::hooks::until;
+use crate::memory::Memory;
 use crate::opc;
 use crate::{Cpu, SimpleMemory};
+use mini6502_macros::asm6502;
+use std::cell::Cell;
+
 #[test]
 fn test_no_repeated_instructions() {
     let _opc_arr = opc::init_opc_array();
@@ -7,20 +12,105 @@ fn test_no_repeated_instructions() {
 
 #[test]
 fn test_run() {
-    let buf = [0xa2, 0x03, 0xCA, 0xD0, 0xFD];
+    let program = asm6502! {
+        ldx #$03;
+        loop: dex;
+        bne loop
+    };
+    let buf = program.bytes;
     let mem = SimpleMemory::from_rom(&buf);
 
     let mut cpu = Cpu::with_mem(mem);
 
     assert_eq!(cpu.pc(), 0);
 
-    cpu.run(&mut |cpu: &Cpu<SimpleMemory>| {
+    cpu.run(&mut until(|cpu: &Cpu<SimpleMemory>| {
         println!("{cpu}");
         println!("{:#40x?}", &cpu.mem.inner[0x04e5..0x04e5 + 3]);
         cpu.pc() as usize >= buf.len()
         // false
-    })
+    }))
     .unwrap();
 
     assert_eq!(cpu.x(), 0x00);
 }
+
+#[test]
+fn test_asm6502_macro() {
+    let program = asm6502! {
+        ptr = $20;
+        .org $0600;
+        start: lda #<table;
+        sta ptr;
+        lda #>table;
+        sta ptr + 1;
+        @loop: dex;
+        bne @loop;
+        lda (ptr),y;
+        jmp (0x1E);
+        table: .byte "hi", 0b0000_0001
+    };
+
+    assert_eq!(program.origin, 0x0600);
+    assert_eq!(
+        program.bytes,
+        [
+            0xA9, 0x10, 0x85, 0x20, 0xA9, 0x06, 0x85, 0x21, 0xCA, 0xD0, 0xFD, 0xB1, 0x20, 0x6C,
+            0x1E, 0x00, 0x68, 0x69, 0x01
+        ]
+    );
+    assert_eq!(program.symbol("ptr"), 0x20);
+    assert_eq!(program.symbol("start@loop"), 0x0608);
+
+    let mut mem = SimpleMemory::from_rom(&[]);
+    mem.load(program.origin, &program.bytes);
+    assert_eq!(mem.inner[0x0610], b'h');
+}
+
+/// Memory counting the reads that reach it.
+struct CountingMemory {
+    mem: SimpleMemory,
+    reads: Cell<usize>,
+}
+
+impl Memory for CountingMemory {
+    fn write_byte(&mut self, addr: u16, byte: u8) {
+        self.mem.write_byte(addr, byte)
+    }
+
+    fn read_byte(&self, addr: u16) -> u8 {
+        self.reads.set(self.reads.get() + 1);
+        self.mem.read_byte(addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.mem.peek(addr)
+    }
+}
+
+#[test]
+fn test_run_reads_like_step() {
+    let program = asm6502! {
+        lda #$01;
+        ldx $10;
+        done: jmp done
+    };
+    let counting = || {
+        Cpu::with_mem(CountingMemory {
+            mem: SimpleMemory::from_rom(&program.bytes),
+            reads: Cell::new(0),
+        })
+    };
+
+    let mut stepped = counting();
+    stepped.step().unwrap();
+    stepped.step().unwrap();
+
+    // Looking ahead at the next opcode must not show up as a read on the bus
+    let mut ran = counting();
+    ran.run(&mut until(|cpu: &Cpu<CountingMemory>| {
+        cpu.pc() == program.symbol("done")
+    }))
+    .unwrap();
+    assert_eq!(ran.mem.reads.get(), stepped.mem.reads.get());
+}
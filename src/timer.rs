@@ -0,0 +1,252 @@
+use crate::device::Device;
+
+// Registers, selected by the low 3 bits of the address
+const RELOAD_L: u16 = 0x0;
+const RELOAD_H: u16 = 0x1;
+/// Reading it latches the high byte, so COUNT_H reads the same count
+const COUNT_L: u16 = 0x2;
+const COUNT_H: u16 = 0x3;
+/// The timer counts every PRESCALER + 1 cycles
+const PRESCALER: u16 = 0x4;
+const CONTROL: u16 = 0x5;
+const STATUS: u16 = 0x6;
+
+// Control bits
+const ENABLE: u8 = 0x01;
+/// Set to reload and keep counting after a timeout, clear to stop
+const PERIODIC: u8 = 0x02;
+const IRQ_ENABLE: u8 = 0x04;
+/// Set to interrupt on NMI instead of IRQ
+const NMI_SELECT: u8 = 0x08;
+
+/// Status bit set on a timeout, writing it back acknowledges it
+const TIMEOUT: u8 = 0x80;
+
+/// A programmable interval timer, for simple boards that don't have a chip like the
+/// [`Via`](crate::via::Via). It decodes 3 address lines:
+///
+/// * `$0`-`$1`: the reload value, low byte first
+/// * `$2`-`$3`: the count, read only, reading the low byte latches the high one
+/// * `$4`: the prescaler, the timer counts every prescaler + 1 cycles
+/// * `$5`: control, bit 0 enables the timer, bit 1 makes it periodic rather than one shot,
+///   bit 2 enables its interrupt and bit 3 sends it to NMI instead of IRQ
+/// * `$6`: status, bit 7 is set on a timeout until a 1 is written to it
+///
+/// Enabling the timer loads the count with the reload value, 0 meaning 65536. It times out
+/// when the count gets to 0, then reloads and keeps going if periodic, or stops. Its interrupt
+/// is held until acknowledged, so on NMI, where only edges count, every timeout has to be
+/// acknowledged for the next one to interrupt.
+///
+/// ```rust
+/// use mini6502::device::Device;
+/// use mini6502::timer::Timer;
+///
+/// let mut timer = Timer::new();
+/// // Every 100 * 10 cycles, on IRQ
+/// timer.write(0x0, 100);
+/// timer.write(0x1, 0);
+/// timer.write(0x4, 9);
+/// timer.write(0x5, 0x07);
+/// timer.tick(200);
+/// assert_eq!((timer.read(0x2), timer.read(0x3)), (80, 0));
+///
+/// for _ in 0..4 {
+///     timer.tick(200);
+/// }
+/// assert!(timer.irq());
+/// assert_eq!(timer.read(0x6), 0x80);
+/// timer.write(0x6, 0x80);
+/// assert!(!timer.irq());
+/// assert_eq!(timer.read(0x2), 100);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Timer {
+    reload: u16,
+    /// Counts left until the timeout, from 1 to 65536
+    count: u32,
+    count_h_latch: u8,
+    prescaler: u8,
+    /// Cycles left until the next count
+    divider: u16,
+    control: u8,
+    status: u8,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Timer::new()
+    }
+}
+
+impl Timer {
+    /// A stopped timer with its interrupt disabled.
+    pub fn new() -> Self {
+        Timer {
+            reload: 0x0000,
+            count: 0x10000,
+            count_h_latch: 0x00,
+            prescaler: 0,
+            divider: 1,
+            control: 0x00,
+            status: 0x00,
+        }
+    }
+
+    fn start(&mut self) {
+        self.count = match self.reload {
+            0 => 0x10000,
+            reload => reload as u32,
+        };
+        self.divider = self.prescaler as u16 + 1;
+    }
+
+    fn count_down(&mut self) {
+        self.count -= 1;
+        if self.count > 0 {
+            return;
+        }
+        self.status |= TIMEOUT;
+        match self.control & PERIODIC != 0 {
+            true => self.start(),
+            false => {
+                self.control &= !ENABLE;
+                // The count stays at 0
+                self.count = 0x10000;
+            }
+        }
+    }
+
+    fn interrupting(&self) -> bool {
+        self.status & TIMEOUT != 0 && self.control & IRQ_ENABLE != 0
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, reg: u16) -> u8 {
+        if reg & 0x07 == COUNT_L {
+            self.count_h_latch = (self.count >> 8) as u8;
+        }
+        self.peek(reg)
+    }
+
+    fn peek(&self, reg: u16) -> u8 {
+        match reg & 0x07 {
+            RELOAD_L => self.reload as u8,
+            RELOAD_H => (self.reload >> 8) as u8,
+            COUNT_L => self.count as u8,
+            COUNT_H => self.count_h_latch,
+            PRESCALER => self.prescaler,
+            CONTROL => self.control,
+            STATUS => self.status,
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg & 0x07 {
+            RELOAD_L => self.reload = (self.reload & 0xFF00) | value as u16,
+            RELOAD_H => self.reload = (self.reload & 0x00FF) | ((value as u16) << 8),
+            PRESCALER => self.prescaler = value,
+            CONTROL => {
+                if value & ENABLE != 0 && self.control & ENABLE == 0 {
+                    self.start();
+                }
+                self.control = value & (ENABLE | PERIODIC | IRQ_ENABLE | NMI_SELECT);
+            }
+            STATUS => self.status &= !(value & TIMEOUT),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        let mut cycles = cycles as u16;
+        while cycles > 0 && self.control & ENABLE != 0 {
+            let elapsed = cycles.min(self.divider);
+            cycles -= elapsed;
+            self.divider -= elapsed;
+            if self.divider == 0 {
+                self.divider = self.prescaler as u16 + 1;
+                self.count_down();
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.interrupting() && self.control & NMI_SELECT == 0
+    }
+
+    fn nmi(&self) -> bool {
+        self.interrupting() && self.control & NMI_SELECT != 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::DeviceMemory;
+    use crate::hooks::until;
+    use crate::memory::SimpleMemory;
+    use crate::Cpu;
+    use mini6502_macros::asm6502;
+
+    #[test]
+    fn test_one_shot() {
+        let mut timer = Timer::new();
+        timer.write(RELOAD_L, 3);
+        timer.write(CONTROL, ENABLE | IRQ_ENABLE);
+        timer.tick(2);
+        assert_eq!(timer.read(COUNT_L), 1);
+        assert!(!timer.irq());
+        timer.tick(200);
+        assert!(timer.irq() && !timer.nmi());
+        assert_eq!(timer.read(CONTROL), IRQ_ENABLE);
+        // Writing 0 doesn't acknowledge it
+        timer.write(STATUS, 0x00);
+        assert!(timer.irq());
+        timer.write(STATUS, TIMEOUT);
+        assert!(!timer.irq());
+
+        // 0 counts 65536 times, several timeouts in one tick only interrupt once
+        timer.write(RELOAD_L, 0);
+        timer.write(CONTROL, ENABLE | PERIODIC);
+        timer.tick(1);
+        assert_eq!((timer.read(COUNT_L), timer.read(COUNT_H)), (0xFF, 0xFF));
+        timer.write(CONTROL, 0x00);
+        timer.write(RELOAD_L, 2);
+        timer.write(CONTROL, ENABLE | PERIODIC);
+        timer.tick(5);
+        assert_eq!((timer.read(STATUS), timer.read(COUNT_L)), (TIMEOUT, 1));
+    }
+
+    #[test]
+    fn test_periodic_nmi() {
+        // Count 3 NMIs, every 100 cycles
+        let program = asm6502! {
+            .org $F000;
+            reset: ldx #$00;
+            lda #$64;
+            sta $8000;
+            lda #$0F;
+            sta $8005;
+            wait: cpx #$03;
+            bne wait;
+            done: jmp done;
+            nmi: inx;
+            lda #$80;
+            sta $8006;
+            rti;
+            .res $FFFA - *;
+            .word nmi;
+            .word reset;
+            .word $0000
+        };
+        let mut rom = vec![0x00; 0xF000];
+        rom.extend(&program.bytes);
+        let mut mem = DeviceMemory::new(SimpleMemory::from_rom(&rom));
+        mem.map(0x8000..=0x8007, Box::new(Timer::new()));
+        let mut cpu = Cpu::with_mem(mem);
+        cpu.run(&mut until(|cpu| cpu.pc() == program.symbol("done")))
+            .unwrap();
+        assert!((300..400).contains(&cpu.cycle_count()));
+    }
+}
@@ -0,0 +1,393 @@
+use crate::cpu::Cpu;
+use crate::dbginfo::DebugInfo;
+use crate::disasm::{self, Disassembler, Instruction, Mode};
+use crate::error::TraceFormatError;
+use crate::format::flags_text;
+use crate::json::Json;
+use crate::memory::Memory;
+use crate::symbols::SymbolTable;
+use std::io::{self, Write};
+
+/// Where the registers start in a nestest line
+const NESTEST_REGS_COLUMN: usize = 48;
+
+/// A value that can be placed in a custom trace line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// `HHLL`
+    Pc,
+    /// `A9 10`
+    Bytes,
+    /// `LDA #$10`, with names from the symbol table
+    Inst,
+    A,
+    X,
+    Y,
+    P,
+    /// Low byte of the stack pointer
+    Sp,
+    /// `Nv-bdIzc`, set flags in uppercase
+    Flags,
+    /// Decimal cycle count
+    Cycles,
+    /// Closest symbol to PC
+    Label,
+    /// `file:line` from the debug info
+    Source,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        match name {
+            "pc" => Some(Field::Pc),
+            "bytes" => Some(Field::Bytes),
+            "inst" => Some(Field::Inst),
+            "a" => Some(Field::A),
+            "x" => Some(Field::X),
+            "y" => Some(Field::Y),
+            "p" => Some(Field::P),
+            "sp" => Some(Field::Sp),
+            "flags" => Some(Field::Flags),
+            "cycles" => Some(Field::Cycles),
+            "label" => Some(Field::Label),
+            "source" => Some(Field::Source),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Piece {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// The format of `nestest.log`, without the PPU columns. CYC is the cpu's own count, which
+    /// starts at 0 rather than the 7 of the reset sequence and leaves out the cycles of taken
+    /// branches and page crossings, so compare against `nestest.log` ignoring cycles.
+    Nestest,
+    /// Text with `{field}` placeholders, see `Field`
+    Custom(Vec<Piece>),
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl TraceFormat {
+    /// `nestest`, `json` or a template such as `{pc} {inst} A={a}`. `{{` and `}}` stand for
+    /// literal braces.
+    pub fn parse(s: &str) -> Result<TraceFormat, TraceFormatError> {
+        match s {
+            "nestest" => return Ok(TraceFormat::Nestest),
+            "json" | "jsonl" => return Ok(TraceFormat::JsonLines),
+            _ => {}
+        }
+        let mut pieces = vec![];
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        return Err(TraceFormatError::Unclosed(name));
+                    }
+                    let field = Field::parse(&name)
+                        .ok_or_else(|| TraceFormatError::UnknownField(name.clone()))?;
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    pieces.push(Piece::Field(field));
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(TraceFormat::Custom(pieces))
+    }
+}
+
+/// Writes a line for every instruction executed, before it is executed.
+///
+/// ```rust
+/// use mini6502::hooks::until;
+/// use mini6502::trace::{TraceFormat, Tracer};
+/// use mini6502::{Cpu, SimpleMemory};
+///
+/// let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&[0xA2, 0x10, 0xCA]));
+/// let mut tracer = Tracer::new(vec![], TraceFormat::parse("{pc} {inst} X={x}").unwrap());
+/// cpu.run(&mut until(|cpu| cpu.pc() >= 0x0003 || tracer.trace(cpu).is_err()))
+///     .unwrap();
+///
+/// let trace = String::from_utf8(tracer.into_inner()).unwrap();
+/// assert_eq!(trace, "0000 LDX #$10 X=00\n0002 DEX X=10\n");
+/// ```
+pub struct Tracer<W> {
+    out: W,
+    format: TraceFormat,
+    disassembler: Disassembler,
+    /// Names for the `label` field and for operands
+    pub symbols: SymbolTable,
+    /// Source lines for the `source` field
+    pub debug_info: Option<DebugInfo>,
+}
+
+impl<W> Tracer<W>
+where
+    W: Write,
+{
+    pub fn new(out: W, format: TraceFormat) -> Self {
+        Tracer {
+            out,
+            format,
+            disassembler: Disassembler::new(disasm::Options::default()),
+            symbols: SymbolTable::new(),
+            debug_info: None,
+        }
+    }
+
+    /// Write the line for the instruction at PC.
+    pub fn trace<M: Memory>(&mut self, cpu: &Cpu<M>) -> io::Result<()> {
+        let line = self.line(cpu);
+        writeln!(self.out, "{line}")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// The line for the instruction at PC, without a newline.
+    pub fn line<M: Memory>(&self, cpu: &Cpu<M>) -> String {
+        let read = |addr: u16| Some(cpu.mem.peek(addr));
+        let inst = self.disassembler.decode(cpu.pc(), read).unwrap();
+        match &self.format {
+            TraceFormat::Nestest => nestest_line(&inst, cpu),
+            TraceFormat::Custom(pieces) => pieces
+                .iter()
+                .map(|piece| match piece {
+                    Piece::Text(text) => text.clone(),
+                    Piece::Field(field) => self.field(*field, &inst, cpu),
+                })
+                .collect(),
+            TraceFormat::JsonLines => {
+                let bytes: Vec<Json> = inst.bytes.iter().map(|b| Json::from(*b)).collect();
+                let mut fields = vec![
+                    ("pc", Json::from(cpu.pc())),
+                    ("bytes", Json::from(bytes)),
+                    ("inst", Json::from(self.field(Field::Inst, &inst, cpu))),
+                    ("a", Json::from(cpu.ac())),
+                    ("x", Json::from(cpu.x())),
+                    ("y", Json::from(cpu.y())),
+                    ("p", Json::from(cpu.p())),
+                    ("sp", Json::from(cpu.sp() as u8)),
+                    ("cycles", Json::from(cpu.cycle_count())),
+                ];
+                if !self.symbols.is_empty() {
+                    fields.push(("label", Json::from(self.symbols.describe(cpu.pc()))));
+                }
+                let source = self.field(Field::Source, &inst, cpu);
+                if !source.is_empty() {
+                    fields.push(("source", Json::from(source)));
+                }
+                Json::object(fields).to_string()
+            }
+        }
+    }
+
+    fn field<M: Memory>(&self, field: Field, inst: &Instruction, cpu: &Cpu<M>) -> String {
+        match field {
+            Field::Pc => format!("{:04X}", cpu.pc()),
+            Field::Bytes => hex_bytes(&inst.bytes),
+            Field::Inst => inst.text(Some(&self.symbols)),
+            Field::A => format!("{:02X}", cpu.ac()),
+            Field::X => format!("{:02X}", cpu.x()),
+            Field::Y => format!("{:02X}", cpu.y()),
+            Field::P => format!("{:02X}", cpu.p()),
+            Field::Sp => format!("{:02X}", cpu.sp() as u8),
+            Field::Flags => flags_text(cpu.p()),
+            Field::Cycles => cpu.cycle_count().to_string(),
+            Field::Label if self.symbols.is_empty() => String::new(),
+            Field::Label => self.symbols.describe(cpu.pc()),
+            Field::Source => self
+                .debug_info
+                .as_ref()
+                .and_then(|info| info.location(cpu.pc()))
+                .map(|location| location.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{b:02X}")).collect();
+    bytes.join(" ")
+}
+
+/// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7`
+fn nestest_line<M: Memory>(inst: &Instruction, cpu: &Cpu<M>) -> String {
+    let undocumented = inst.opcode.is_some_and(|opcode| !opcode.documented);
+    let mut line = format!(
+        "{:04X}  {:<8} {}{}{}",
+        cpu.pc(),
+        hex_bytes(&inst.bytes),
+        if undocumented { '*' } else { ' ' },
+        inst.text(None),
+        nestest_operand_values(inst, cpu)
+    );
+    while line.len() < NESTEST_REGS_COLUMN {
+        line.push(' ');
+    }
+    line.push_str(&format!(
+        "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        cpu.ac(),
+        cpu.x(),
+        cpu.y(),
+        cpu.p(),
+        cpu.sp() as u8,
+        cpu.cycle_count()
+    ));
+    line
+}
+
+/// The effective address and the value there, as nestest.log shows them after the operand.
+fn nestest_operand_values<M: Memory>(inst: &Instruction, cpu: &Cpu<M>) -> String {
+    let opcode = match inst.opcode {
+        Some(opcode) => opcode,
+        None => return String::new(),
+    };
+    let read = |addr: u16| cpu.mem.peek(addr);
+    // Pointers in zero page wrap around within it
+    let read_zp_word =
+        |addr: u8| u16::from_le_bytes([read(addr as u16), read(addr.wrapping_add(1) as u16)]);
+    let operand = inst.operand().unwrap_or(0);
+    match opcode.mode {
+        Mode::ZPG => format!(" = {:02X}", read(operand)),
+        Mode::ZPGX | Mode::ZPGY => {
+            let index = if opcode.mode == Mode::ZPGX {
+                cpu.x()
+            } else {
+                cpu.y()
+            };
+            let addr = (operand as u8).wrapping_add(index) as u16;
+            format!(" @ {addr:02X} = {:02X}", read(addr))
+        }
+        Mode::ABS if matches!(opcode.mnemonic, "JMP" | "JSR") => String::new(),
+        Mode::ABS => format!(" = {:02X}", read(operand)),
+        Mode::ABSX | Mode::ABSY => {
+            let index = if opcode.mode == Mode::ABSX {
+                cpu.x()
+            } else {
+                cpu.y()
+            };
+            let addr = operand.wrapping_add(index as u16);
+            format!(" @ {addr:04X} = {:02X}", read(addr))
+        }
+        Mode::IND => {
+            // The NMOS 6502 doesn't carry into the high byte of the pointer
+            let high_addr = (operand & 0xFF00) | (operand.wrapping_add(1) & 0x00FF);
+            let target = u16::from_le_bytes([read(operand), read(high_addr)]);
+            format!(" = {target:04X}")
+        }
+        Mode::INDX => {
+            let pointer = (operand as u8).wrapping_add(cpu.x());
+            let addr = read_zp_word(pointer);
+            format!(" @ {pointer:02X} = {addr:04X} = {:02X}", read(addr))
+        }
+        Mode::INDY => {
+            let base = read_zp_word(operand as u8);
+            let addr = base.wrapping_add(cpu.y() as u16);
+            format!(" = {base:04X} @ {addr:04X} = {:02X}", read(addr))
+        }
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SimpleMemory;
+
+    fn cpu() -> Cpu<SimpleMemory> {
+        // jmp $c5f5; lda ($80),y; sta $0300,x
+        let mut mem = SimpleMemory::from_rom(&[]);
+        mem.load(0xC000, &[0x4C, 0xF5, 0xC5, 0xB1, 0x80, 0x9D, 0x00, 0x03]);
+        mem.load(0x0080, &[0x00, 0x02]);
+        mem.load(0x0204, &[0x5A]);
+        let mut cpu = Cpu::with_mem(mem);
+        cpu.set_pc(0xC000);
+        cpu.set_sp(0xFD);
+        cpu.set_p(0x24);
+        cpu
+    }
+
+    #[test]
+    fn test_nestest_format() {
+        let mut cpu = cpu();
+        let tracer = Tracer::new(vec![], TraceFormat::Nestest);
+        assert_eq!(
+            tracer.line(&cpu),
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:0"
+        );
+        cpu.set_pc(0xC003);
+        cpu.set_y(0x04);
+        assert_eq!(
+            tracer.line(&cpu),
+            "C003  B1 80     LDA ($80),Y = 0200 @ 0204 = 5A  A:00 X:00 Y:04 P:24 SP:FD CYC:0"
+        );
+        cpu.set_pc(0xC005);
+        cpu.set_x(0x10);
+        assert_eq!(
+            tracer.line(&cpu),
+            "C005  9D 00 03  STA $0300,X @ 0310 = 00         A:00 X:10 Y:04 P:24 SP:FD CYC:0"
+        );
+    }
+
+    #[test]
+    fn test_custom_and_json() {
+        let cpu = cpu();
+        let mut tracer = Tracer::new(
+            vec![],
+            TraceFormat::parse("{{{pc}}} {bytes} {flags} {label}").unwrap(),
+        );
+        tracer.symbols.insert("reset", 0xC000);
+        assert_eq!(tracer.line(&cpu), "{C000} 4C F5 C5 nv-bdIzc reset");
+
+        let tracer = Tracer::new(vec![], TraceFormat::JsonLines);
+        let json = Json::parse(&tracer.line(&cpu)).unwrap();
+        assert_eq!(json.get("pc").and_then(Json::as_i64), Some(0xC000));
+        assert_eq!(json.get("inst").and_then(Json::as_str), Some("JMP $C5F5"));
+        assert_eq!(json.get("sp").and_then(Json::as_i64), Some(0xFD));
+
+        assert_eq!(
+            TraceFormat::parse("{pc} {acc}"),
+            Err(TraceFormatError::UnknownField("acc".into()))
+        );
+        assert_eq!(
+            TraceFormat::parse("{pc"),
+            Err(TraceFormatError::Unclosed("pc".into()))
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use crate::error::TraceParseError;
+use crate::json::Json;
+use std::fmt::Write;
+
+/// How far into each trace to look for the first instruction of the other one
+const MAX_ALIGN_SEARCH: usize = 0x10000;
+
+/// One instruction of a trace log, as written by `Tracer` in the nestest or JSON Lines
+/// formats, or by other emulators in the nestest format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// 1-based line in the log
+    pub line: usize,
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub cycles: Option<u64>,
+    /// The line as it appears in the log
+    pub text: String,
+}
+
+impl TraceEntry {
+    /// Parse a nestest line, `C000  4C F5 C5  JMP $C5F5  A:00 X:00 Y:00 P:24 SP:FD CYC:7`,
+    /// with or without the PPU columns, or a JSON Lines object.
+    pub fn parse(line: usize, text: &str) -> Result<TraceEntry, TraceParseError> {
+        let parsed = if text.trim_start().starts_with('{') {
+            parse_json(text)
+        } else {
+            parse_nestest(text)
+        };
+        let (pc, [a, x, y, p, sp], cycles) = parsed.ok_or_else(|| TraceParseError {
+            line,
+            text: text.to_string(),
+        })?;
+        Ok(TraceEntry {
+            line,
+            pc,
+            a,
+            x,
+            y,
+            p,
+            sp,
+            cycles,
+            text: text.to_string(),
+        })
+    }
+}
+
+type Registers = (u16, [u8; 5], Option<u64>);
+
+fn parse_nestest(text: &str) -> Option<Registers> {
+    let pc = u16::from_str_radix(text.get(..4)?, 16).ok()?;
+    let regs_start = text.find(" A:")?;
+    let mut regs = [None; 5];
+    let mut cycles = None;
+    for word in text[regs_start..].split_whitespace() {
+        let (name, value) = match word.split_once(':') {
+            Some(field) => field,
+            None => continue,
+        };
+        let index = match name {
+            "A" => 0,
+            "X" => 1,
+            "Y" => 2,
+            "P" => 3,
+            "SP" => 4,
+            "CYC" => {
+                cycles = Some(value.parse().ok()?);
+                continue;
+            }
+            // PPU columns
+            _ => continue,
+        };
+        regs[index] = Some(u8::from_str_radix(value, 16).ok()?);
+    }
+    let [a, x, y, p, sp] = regs;
+    Some((pc, [a?, x?, y?, p?, sp?], cycles))
+}
+
+fn parse_json(text: &str) -> Option<Registers> {
+    let json = Json::parse(text).ok()?;
+    let field = |name: &str| json.get(name).and_then(Json::as_i64);
+    let byte = |name: &str| field(name).and_then(|value| u8::try_from(value).ok());
+    let pc = u16::try_from(field("pc")?).ok()?;
+    let cycles = field("cycles").map(|cycles| cycles as u64);
+    Some((
+        pc,
+        [byte("a")?, byte("x")?, byte("y")?, byte("p")?, byte("sp")?],
+        cycles,
+    ))
+}
+
+/// Parse every non-empty line of a trace log.
+pub fn parse_trace(text: &str) -> Result<Vec<TraceEntry>, TraceParseError> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| TraceEntry::parse(idx + 1, line))
+        .collect()
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// Don't compare cycle counts
+    pub ignore_cycles: bool,
+    /// Bits of P that aren't compared, e.g. $30 for B and the unused bit, which emulators show
+    /// differently
+    pub ignore_flags: u8,
+}
+
+/// A register, flag or cycle count that differs between two entries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub field: &'static str,
+    pub left: String,
+    pub right: String,
+}
+
+/// Result of comparing two traces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceDiff {
+    /// Indices of the first entry compared in each trace, after aligning them
+    pub left_start: usize,
+    pub right_start: usize,
+    /// Instructions that matched before the divergence, or in total if there is none. Only
+    /// as many instructions as the shorter trace has are compared
+    pub matched: usize,
+    /// What differs in the first divergent instruction
+    pub differences: Vec<Difference>,
+}
+
+impl TraceDiff {
+    pub fn diverged(&self) -> bool {
+        !self.differences.is_empty()
+    }
+
+    /// Describe the divergence with `context` matching instructions before it.
+    pub fn report(&self, left: &[TraceEntry], right: &[TraceEntry], context: usize) -> String {
+        let mut out = String::new();
+        if self.left_start > 0 || self.right_start > 0 {
+            let _ = writeln!(
+                out,
+                "Aligned line {} with line {}",
+                left[self.left_start].line, right[self.right_start].line
+            );
+        }
+        if !self.diverged() {
+            let _ = writeln!(out, "Traces match for {} instructions", self.matched);
+            return out;
+        }
+        let index = self.matched;
+        let _ = writeln!(out, "Traces diverge after {index} matching instructions:");
+        for i in index.saturating_sub(context)..index {
+            let entry = &left[self.left_start + i];
+            let _ = writeln!(out, "  {:>6}  {}", entry.line, entry.text);
+        }
+        for (sign, trace, start) in [('-', left, self.left_start), ('+', right, self.right_start)] {
+            let entry = &trace[start + index];
+            let _ = writeln!(out, "{sign} {:>6}  {}", entry.line, entry.text);
+        }
+        for difference in &self.differences {
+            let _ = writeln!(
+                out,
+                "{}: {} != {}",
+                difference.field, difference.left, difference.right
+            );
+        }
+        out
+    }
+}
+
+/// Compare two traces instruction by instruction and find the first one that differs.
+///
+/// When the traces don't start at the same instruction, the later start is looked for in the
+/// other trace, so a reference log that starts after reset can be compared with one that
+/// starts at power on. Cycle counts are compared relative to the aligned starts.
+///
+/// ```rust
+/// use mini6502::tracediff::{diff, parse_trace, DiffOptions};
+///
+/// let ours = parse_trace(
+///     "0000  A2 03     LDX #$03    A:00 X:00 Y:00 P:20 SP:FF CYC:0
+/// 0002  CA        DEX         A:00 X:03 Y:00 P:20 SP:FF CYC:2
+/// 0003  D0 FD     BNE $0002   A:00 X:02 Y:00 P:20 SP:FF CYC:4",
+/// )
+/// .unwrap();
+/// let reference = parse_trace(
+///     "0002  CA        DEX         A:00 X:03 Y:00 P:20 SP:FF CYC:7
+/// 0003  D0 FD     BNE $0002   A:00 X:02 Y:00 P:A0 SP:FF CYC:9",
+/// )
+/// .unwrap();
+///
+/// let result = diff(&ours, &reference, DiffOptions::default());
+/// assert_eq!((result.left_start, result.matched), (1, 1));
+/// assert!(result.report(&ours, &reference, 3).contains("P: 20 != A0 (N)"));
+/// ```
+pub fn diff(left: &[TraceEntry], right: &[TraceEntry], options: DiffOptions) -> TraceDiff {
+    let (left_start, right_start) = align(left, right, options);
+    let cycles_base = |trace: &[TraceEntry], start: usize| {
+        trace.get(start).and_then(|entry| entry.cycles).unwrap_or(0)
+    };
+    let (left_base, right_base) = (
+        cycles_base(left, left_start),
+        cycles_base(right, right_start),
+    );
+
+    let pairs = left[left_start.min(left.len())..]
+        .iter()
+        .zip(&right[right_start.min(right.len())..]);
+    let mut matched = 0;
+    for (l, r) in pairs {
+        let mut differences = compare(l, r, options);
+        if !options.ignore_cycles {
+            let l_cycles = l.cycles.map(|c| c.wrapping_sub(left_base));
+            let r_cycles = r.cycles.map(|c| c.wrapping_sub(right_base));
+            if let (Some(l_cycles), Some(r_cycles)) = (l_cycles, r_cycles) {
+                if l_cycles != r_cycles {
+                    differences.push(Difference {
+                        field: "CYC",
+                        left: format!("+{l_cycles}"),
+                        right: format!("+{r_cycles}"),
+                    });
+                }
+            }
+        }
+        if !differences.is_empty() {
+            return TraceDiff {
+                left_start,
+                right_start,
+                matched,
+                differences,
+            };
+        }
+        matched += 1;
+    }
+    TraceDiff {
+        left_start,
+        right_start,
+        matched,
+        differences: vec![],
+    }
+}
+
+/// Registers and flags that differ, ignoring cycles.
+fn compare(l: &TraceEntry, r: &TraceEntry, options: DiffOptions) -> Vec<Difference> {
+    let mut differences = vec![];
+    if l.pc != r.pc {
+        differences.push(Difference {
+            field: "PC",
+            left: format!("{:04X}", l.pc),
+            right: format!("{:04X}", r.pc),
+        });
+    }
+    for (field, l_value, r_value) in [
+        ("A", l.a, r.a),
+        ("X", l.x, r.x),
+        ("Y", l.y, r.y),
+        ("SP", l.sp, r.sp),
+    ] {
+        if l_value != r_value {
+            differences.push(Difference {
+                field,
+                left: format!("{l_value:02X}"),
+                right: format!("{r_value:02X}"),
+            });
+        }
+    }
+    let flags = (l.p ^ r.p) & !options.ignore_flags;
+    if flags != 0 {
+        let names: String = "NV-BDIZC"
+            .chars()
+            .enumerate()
+            .filter(|(i, _)| flags & (0x80 >> i) != 0)
+            .map(|(_, c)| c)
+            .collect();
+        differences.push(Difference {
+            field: "P",
+            left: format!("{:02X}", l.p),
+            right: format!("{:02X} ({names})", r.p),
+        });
+    }
+    differences
+}
+
+/// Indices where both traces are at the same instruction with the same registers: the start
+/// of one of them, and where it first appears in the other.
+fn align(left: &[TraceEntry], right: &[TraceEntry], options: DiffOptions) -> (usize, usize) {
+    let same = |l: &TraceEntry, r: &TraceEntry| compare(l, r, options).is_empty();
+    let (first_left, first_right) = match (left.first(), right.first()) {
+        (Some(l), Some(r)) if !same(l, r) => (l, r),
+        _ => return (0, 0),
+    };
+    let find = |trace: &[TraceEntry], entry: &TraceEntry| {
+        trace
+            .iter()
+            .take(MAX_ALIGN_SEARCH)
+            .position(|other| same(other, entry))
+    };
+    if let Some(index) = find(left, first_right) {
+        (index, 0)
+    } else if let Some(index) = find(right, first_left) {
+        (0, index)
+    } else {
+        (0, 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const NESTEST: &str = "\
+C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10
+C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12
+C5F9  86 10     STX $10 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 45 CYC:15
+";
+
+    #[test]
+    fn test_parse() {
+        let entries = parse_trace(NESTEST).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[1].pc, 0xC5F5);
+        assert_eq!(entries[2].p, 0x26);
+        assert_eq!(entries[3].cycles, Some(15));
+
+        let json = TraceEntry::parse(
+            1,
+            r#"{"pc":49152,"bytes":[76,245,197],"inst":"JMP $C5F5","a":0,"x":0,"y":0,"p":36,"sp":253,"cycles":7}"#,
+        )
+        .unwrap();
+        assert_eq!((json.pc, json.p, json.sp), (0xC000, 0x24, 0xFD));
+
+        assert_eq!(
+            parse_trace("C000  EA  NOP  A:00 X:00"),
+            Err(TraceParseError {
+                line: 1,
+                text: "C000  EA  NOP  A:00 X:00".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let reference = parse_trace(NESTEST).unwrap();
+        let ours = NESTEST
+            .replace("PPU:  0, 45 CYC:15", "CYC:14")
+            .replace("P:24 SP:FD PPU:  0, 30", "P:34 SP:FD");
+        let ours = parse_trace(&ours).unwrap();
+
+        let result = diff(&ours, &reference, DiffOptions::default());
+        assert_eq!(result.matched, 1);
+        assert_eq!(
+            result.differences,
+            vec![Difference {
+                field: "P",
+                left: "34".into(),
+                right: "24 (B)".into()
+            }]
+        );
+
+        let options = DiffOptions {
+            ignore_flags: 0x30,
+            ..DiffOptions::default()
+        };
+        let result = diff(&ours, &reference, options);
+        assert_eq!(result.matched, 3);
+        let report = result.report(&ours, &reference, 2);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "Traces diverge after 3 matching instructions:");
+        assert!(lines[1].starts_with("       2  C5F5"));
+        assert!(lines[3].starts_with("-      4  C5F9"));
+        assert!(lines[4].starts_with("+      4  C5F9"));
+        assert_eq!(lines[5], "CYC: +7 != +8");
+
+        let options = DiffOptions {
+            ignore_cycles: true,
+            ignore_flags: 0x30,
+        };
+        assert!(!diff(&ours, &reference, options).diverged());
+    }
+}
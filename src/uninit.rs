@@ -0,0 +1,267 @@
+use crate::bus::{BusAccess, BusObserver};
+use crate::callstack::{CallStack, Frame};
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use crate::symbols::SymbolTable;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::ops::Range;
+
+const ADDR_SPACE: usize = 0x10000;
+
+/// An instruction reading a byte nothing had written or loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UninitRead {
+    pub addr: u16,
+    /// The instruction that read it
+    pub pc: u16,
+    /// Calls that led to the first read, outermost first
+    pub backtrace: Vec<Frame>,
+    /// Times the instruction read it
+    pub count: u64,
+}
+
+impl UninitRead {
+    /// `Read of uninitialized $HHLL at ...` followed by a line per caller, innermost first.
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let mut out = format!(
+            "Read of uninitialized {} at {}",
+            addr_text(self.addr, symbols),
+            addr_text(self.pc, symbols)
+        );
+        if self.count > 1 {
+            let _ = write!(out, ", {} times", self.count);
+        }
+        for frame in self.backtrace.iter().rev() {
+            let _ = write!(
+                out,
+                "\n    called from {}",
+                addr_text(frame.call_site, symbols)
+            );
+        }
+        out
+    }
+}
+
+/// `$HHLL <name+offset>`, or just `$HHLL` when there is no symbol nearby.
+fn addr_text(addr: u16, symbols: &SymbolTable) -> String {
+    let name = symbols.describe(addr);
+    if name.starts_with('$') {
+        name
+    } else {
+        format!("${addr:04X} <{name}>")
+    }
+}
+
+/// Catches code depending on what RAM happens to hold at power on: as a [`BusObserver`] of the
+/// cpu it knows which bytes were ever written, and collects the instructions reading the others
+/// along with the calls that led to them. Each instruction and address is reported once, with
+/// the number of times it happened.
+///
+/// ```rust
+/// use mini6502::uninit::UninitChecker;
+/// use mini6502::{Cpu, SimpleMemory};
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// // lda $0300; sta $0301; lda $0301
+/// let rom = [0xAD, 0x00, 0x03, 0x8D, 0x01, 0x03, 0xAD, 0x01, 0x03];
+/// let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&rom));
+/// let checker = Rc::new(RefCell::new(UninitChecker::new()));
+/// checker.borrow_mut().mark_initialized(0..rom.len() as u32);
+/// cpu.add_observer(Box::new(checker.clone()));
+/// for _ in 0..3 {
+///     checker.borrow_mut().before_instruction(&cpu);
+///     cpu.step().unwrap();
+///     checker.borrow_mut().after_instruction(&cpu);
+/// }
+///
+/// let checker = checker.borrow();
+/// let reads: Vec<(u16, u16)> = checker.reads().iter().map(|r| (r.addr, r.pc)).collect();
+/// assert_eq!(reads, [(0x0300, 0x0000)]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct UninitChecker {
+    initialized: Vec<bool>,
+    /// Whether an instruction is being executed, reads are only checked while it is
+    executing: bool,
+    /// Uninitialized bytes read by the instruction being executed
+    pending: Vec<u16>,
+    calls: CallStack,
+    reads: Vec<UninitRead>,
+    /// Index into `reads` by instruction and address
+    seen: HashMap<(u16, u16), usize>,
+    pc: u16,
+    sp: u16,
+}
+
+impl Default for UninitChecker {
+    fn default() -> Self {
+        UninitChecker::new()
+    }
+}
+
+impl UninitChecker {
+    pub fn new() -> Self {
+        UninitChecker {
+            initialized: vec![false; ADDR_SPACE],
+            executing: false,
+            pending: vec![],
+            calls: CallStack::default(),
+            reads: vec![],
+            seen: HashMap::new(),
+            pc: 0x0000,
+            sp: 0x0000,
+        }
+    }
+
+    /// Count `addrs` as initialized, e.g. for ROM, a loaded program or I/O registers.
+    pub fn mark_initialized(&mut self, addrs: Range<u32>) {
+        for addr in addrs {
+            self.initialized[addr as usize & 0xFFFF] = true;
+        }
+    }
+
+    pub fn is_initialized(&self, addr: u16) -> bool {
+        self.initialized[addr as usize]
+    }
+
+    /// Fill the uninitialized bytes of `mem` in `addrs` with pseudo-random values derived
+    /// from `seed`, like RAM at power on. They stay uninitialized.
+    pub fn randomize<M: Memory>(&self, mem: &mut M, addrs: Range<u32>, seed: u64) {
+        // SplitMix64
+        let mut state = seed;
+        for addr in addrs {
+            let addr = addr as u16;
+            if self.initialized[addr as usize] {
+                continue;
+            }
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            mem.write_byte(addr, (z ^ (z >> 31)) as u8);
+        }
+    }
+
+    /// Call before the cpu executes each instruction, to start checking its reads, of its own
+    /// bytes too.
+    pub fn before_instruction<M: Memory>(&mut self, cpu: &Cpu<M>) {
+        self.pc = cpu.pc();
+        self.sp = cpu.sp();
+        self.executing = true;
+        self.pending.clear();
+    }
+
+    /// Call after the cpu executes each instruction. Returns the reads not reported before.
+    pub fn after_instruction<M: Memory>(&mut self, cpu: &Cpu<M>) -> &[UninitRead] {
+        self.executing = false;
+        let new = self.reads.len();
+        for addr in std::mem::take(&mut self.pending) {
+            match self.seen.get(&(self.pc, addr)) {
+                Some(&index) => self.reads[index].count += 1,
+                None => {
+                    self.seen.insert((self.pc, addr), self.reads.len());
+                    self.reads.push(UninitRead {
+                        addr,
+                        pc: self.pc,
+                        backtrace: self.calls.frames().to_vec(),
+                        count: 1,
+                    });
+                }
+            }
+        }
+        self.calls.update(cpu, self.pc, self.sp);
+        &self.reads[new..]
+    }
+
+    /// Every uninitialized read so far, in the order they first happened.
+    pub fn reads(&self) -> &[UninitRead] {
+        &self.reads
+    }
+}
+
+impl BusObserver for UninitChecker {
+    fn read(&mut self, access: &BusAccess) {
+        if self.executing && !self.initialized[access.addr as usize] {
+            self.pending.push(access.addr);
+        }
+    }
+
+    fn write(&mut self, access: &BusAccess) {
+        self.initialized[access.addr as usize] = true;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::SimpleMemory;
+    use mini6502_macros::asm6502;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_backtrace() {
+        let program = asm6502! {
+            main: jsr init;
+            jsr init;
+            done: jmp done;
+            init: lda #$00;
+            sta $0301;
+            lda $0300;
+            ora $0301;
+            rts
+        };
+        let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&program.bytes));
+        cpu.set_sp(0xFF);
+        let checker = Rc::new(RefCell::new(UninitChecker::new()));
+        checker
+            .borrow_mut()
+            .mark_initialized(0..program.bytes.len() as u32);
+        cpu.add_observer(Box::new(checker.clone()));
+        while cpu.pc() != program.symbol("done") {
+            checker.borrow_mut().before_instruction(&cpu);
+            cpu.step().unwrap();
+            checker.borrow_mut().after_instruction(&cpu);
+        }
+        let checker = checker.borrow();
+
+        let read = &checker.reads()[0];
+        assert_eq!(checker.reads().len(), 1);
+        assert_eq!((read.addr, read.count), (0x0300, 2));
+        assert_eq!(read.backtrace[0].entry, program.symbol("init"));
+        let symbols: SymbolTable = program.symbols.iter().copied().collect();
+        assert_eq!(
+            read.describe(&symbols),
+            "Read of uninitialized $0300 at $000E <init+5>, 2 times\n    called from $0000 <main>"
+        );
+    }
+
+    #[test]
+    fn test_randomize() {
+        let fill = |seed| {
+            let mut mem = SimpleMemory::from_rom(&[0xEA; 4]);
+            let mut checker = UninitChecker::new();
+            checker.mark_initialized(0..4);
+            checker.randomize(&mut mem, 0..0x100, seed);
+            (0..0x100)
+                .map(|addr| mem.read_byte(addr))
+                .collect::<Vec<u8>>()
+        };
+        let bytes = fill(1);
+        assert_eq!(&bytes[..4], [0xEA; 4]);
+        assert_eq!(bytes, fill(1));
+        assert_ne!(bytes, fill(2));
+        assert!(bytes[4..].iter().any(|&b| b != 0));
+
+        // Only writes by the cpu initialize memory
+        let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&[0x85, 0x10]));
+        let checker = Rc::new(RefCell::new(UninitChecker::new()));
+        cpu.add_observer(Box::new(checker.clone()));
+        checker.borrow().randomize(&mut cpu.mem, 0x10..0x20, 1);
+        assert!(!checker.borrow().is_initialized(0x0010));
+        cpu.step().unwrap();
+        assert!(checker.borrow().is_initialized(0x0010));
+    }
+}
@@ -0,0 +1,657 @@
+use crate::device::Device;
+
+// Registers, selected by the low 4 bits of the address
+const ORB: u16 = 0x0;
+const ORA: u16 = 0x1;
+const DDRB: u16 = 0x2;
+const DDRA: u16 = 0x3;
+const T1C_L: u16 = 0x4;
+const T1C_H: u16 = 0x5;
+const T1L_L: u16 = 0x6;
+const T1L_H: u16 = 0x7;
+const T2C_L: u16 = 0x8;
+const T2C_H: u16 = 0x9;
+const SR: u16 = 0xA;
+const ACR: u16 = 0xB;
+const PCR: u16 = 0xC;
+const IFR: u16 = 0xD;
+const IER: u16 = 0xE;
+/// ORA without handshake
+const ORA_NH: u16 = 0xF;
+
+// Interrupt flags, the same bits enable them in IER
+const IRQ_FLAG: u8 = 0x80;
+const T1_FLAG: u8 = 0x40;
+const T2_FLAG: u8 = 0x20;
+const CB1_FLAG: u8 = 0x10;
+const CB2_FLAG: u8 = 0x08;
+const SR_FLAG: u8 = 0x04;
+const CA1_FLAG: u8 = 0x02;
+const CA2_FLAG: u8 = 0x01;
+
+// ACR bits
+const PA_LATCH: u8 = 0x01;
+const PB_LATCH: u8 = 0x02;
+const T2_PULSE_COUNT: u8 = 0x20;
+const T1_FREE_RUN: u8 = 0x40;
+const PB7_OUTPUT: u8 = 0x80;
+
+/// What a CA2 or CB2 line does, from its 3 bits of PCR.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Control2 {
+    /// Sets its flag on the active edge. Unless independent, reading or writing the port
+    /// clears the flag.
+    Input {
+        positive: bool,
+        independent: bool,
+    },
+    /// Goes low when the port is accessed, high on the active edge of CA1 or CB1
+    Handshake,
+    /// Goes low for a cycle when the port is accessed
+    Pulse,
+    Manual(bool),
+}
+
+impl Control2 {
+    const fn from_bits(bits: u8) -> Self {
+        match bits & 0b111 {
+            0b100 => Control2::Handshake,
+            0b101 => Control2::Pulse,
+            0b110 => Control2::Manual(false),
+            0b111 => Control2::Manual(true),
+            bits => Control2::Input {
+                positive: bits & 0b010 != 0,
+                independent: bits & 0b001 != 0,
+            },
+        }
+    }
+}
+
+/// One of the two 8 bit ports with its control lines.
+#[derive(Copy, Clone, Debug)]
+struct Port {
+    output: u8,
+    ddr: u8,
+    /// Levels driven by whatever is connected to the pins
+    input: u8,
+    /// Input latched on the active edge of C1
+    latch: u8,
+    c1: bool,
+    c2: bool,
+    /// C2 when it's a handshake or pulse output
+    c2_out: bool,
+    /// A pulse output that goes back high on the next cycle
+    c2_pulse: bool,
+}
+
+impl Port {
+    const fn new() -> Self {
+        Port {
+            output: 0x00,
+            ddr: 0x00,
+            // The pins have pull-ups
+            input: 0xFF,
+            latch: 0xFF,
+            c1: true,
+            c2: true,
+            c2_out: true,
+            c2_pulse: false,
+        }
+    }
+
+    const fn pins(&self) -> u8 {
+        (self.output & self.ddr) | (self.input & !self.ddr)
+    }
+
+    /// Reading or writing the output register does this to C2.
+    fn handshake(&mut self, control: Control2) {
+        match control {
+            Control2::Handshake => self.c2_out = false,
+            Control2::Pulse => {
+                self.c2_out = false;
+                self.c2_pulse = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn c2(&self, control: Control2) -> bool {
+        match control {
+            Control2::Input { .. } => self.c2,
+            Control2::Handshake | Control2::Pulse => self.c2_out,
+            Control2::Manual(level) => level,
+        }
+    }
+}
+
+/// A MOS 6522 Versatile Interface Adapter: two 8 bit ports with handshaking, two 16 bit
+/// timers, a shift register and the interrupt logic to drive the IRQ line. It decodes 4 address
+/// lines so it shows up every 16 bytes of the range it's mapped at with
+/// [`DeviceMemory::map`](crate::device::DeviceMemory::map).
+///
+/// Timers count cpu cycles: a timer loaded with N times out N + 1 cycles later, and in free
+/// running mode T1 reloads on the cycle after that, for a period of N + 2 cycles.
+///
+/// ```rust
+/// use mini6502::device::Device;
+/// use mini6502::via::Via;
+///
+/// let mut via = Via::new();
+/// // Free running T1 every 100 cycles, toggling PB7, with its interrupt enabled
+/// via.write(0xB, 0xC0);
+/// via.write(0xE, 0xC0);
+/// via.write(0x4, 98);
+/// via.write(0x5, 0);
+/// assert!(!via.irq());
+///
+/// via.tick(99);
+/// assert!(via.irq());
+/// assert_eq!(via.read(0xD), 0xC0);
+/// assert_eq!(via.port_b() & 0x80, 0x80);
+///
+/// // Reading T1C-L acknowledges the interrupt
+/// via.read(0x4);
+/// assert!(!via.irq());
+/// via.tick(100);
+/// assert!(via.irq());
+/// assert_eq!(via.port_b() & 0x80, 0x00);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Via {
+    a: Port,
+    b: Port,
+    t1_counter: u16,
+    t1_latch: u16,
+    /// Whether T1 interrupts when it times out, one shot mode only does once per load
+    t1_armed: bool,
+    /// T1 reloads from its latch instead of counting on the next cycle
+    t1_reload: bool,
+    /// The PB7 output of T1
+    pb7: bool,
+    t2_counter: u16,
+    t2_latch_low: u8,
+    t2_armed: bool,
+    sr: u8,
+    /// Bits left to shift
+    shift_count: u8,
+    /// Cycles since the shift clock last changed
+    shift_timer: u16,
+    /// The shift clock on CB1, idling high
+    shift_clock: bool,
+    /// The bit shifted out on CB2
+    shift_out: bool,
+    acr: u8,
+    pcr: u8,
+    ifr: u8,
+    ier: u8,
+}
+
+impl Default for Via {
+    fn default() -> Self {
+        Via::new()
+    }
+}
+
+impl Via {
+    /// A VIA after a reset: every pin an input, timers and shift register stopped and no
+    /// interrupts enabled.
+    pub fn new() -> Self {
+        Via {
+            a: Port::new(),
+            b: Port::new(),
+            t1_counter: 0xFFFF,
+            t1_latch: 0xFFFF,
+            t1_armed: false,
+            t1_reload: false,
+            pb7: true,
+            t2_counter: 0xFFFF,
+            t2_latch_low: 0xFF,
+            t2_armed: false,
+            sr: 0x00,
+            shift_count: 0,
+            shift_timer: 0,
+            shift_clock: true,
+            shift_out: true,
+            acr: 0x00,
+            pcr: 0x00,
+            ifr: 0x00,
+            ier: 0x00,
+        }
+    }
+
+    /// Levels of the port A pins, those set as inputs show what was last set with
+    /// [`Via::set_port_a`].
+    pub fn port_a(&self) -> u8 {
+        self.a.pins()
+    }
+
+    /// Levels of the port B pins, PB7 is T1's output when ACR enables it.
+    pub fn port_b(&self) -> u8 {
+        let pins = self.b.pins();
+        match self.acr & PB7_OUTPUT != 0 {
+            true => (pins & 0x7F) | ((self.pb7 as u8) << 7),
+            false => pins,
+        }
+    }
+
+    /// Drive the port A pins, only the ones set as inputs are read back.
+    pub fn set_port_a(&mut self, pins: u8) {
+        self.a.input = pins;
+    }
+
+    /// Drive the port B pins. In pulse counting mode every falling edge of PB6 counts T2 down.
+    pub fn set_port_b(&mut self, pins: u8) {
+        let falling = self.b.input & !pins & 0x40 != 0;
+        self.b.input = pins;
+        if falling && self.acr & T2_PULSE_COUNT != 0 {
+            self.t2_counter = self.t2_counter.wrapping_sub(1);
+            if self.t2_counter == 0 && self.t2_armed {
+                self.t2_armed = false;
+                self.ifr |= T2_FLAG;
+            }
+        }
+    }
+
+    pub fn set_ca1(&mut self, level: bool) {
+        if self.a.c1 == level {
+            return;
+        }
+        self.a.c1 = level;
+        if level == (self.pcr & 0x01 != 0) {
+            self.ifr |= CA1_FLAG;
+            self.a.latch = self.a.pins();
+            if self.ca2_control() == Control2::Handshake {
+                self.a.c2_out = true;
+            }
+        }
+    }
+
+    /// Drive CA2, which only does something while PCR makes it an input.
+    pub fn set_ca2(&mut self, level: bool) {
+        if self.a.c2 == level {
+            return;
+        }
+        self.a.c2 = level;
+        if let Control2::Input { positive, .. } = self.ca2_control() {
+            if level == positive {
+                self.ifr |= CA2_FLAG;
+            }
+        }
+    }
+
+    /// Drive CB1, which is also the shift clock when the shift register uses an external one.
+    pub fn set_cb1(&mut self, level: bool) {
+        if self.b.c1 == level {
+            return;
+        }
+        self.b.c1 = level;
+        if level && matches!(self.shift_mode(), 0b011 | 0b111) && self.shift_count > 0 {
+            self.shift();
+        }
+        if level == (self.pcr & 0x10 != 0) {
+            self.ifr |= CB1_FLAG;
+            self.b.latch = self.b.pins();
+            if self.cb2_control() == Control2::Handshake {
+                self.b.c2_out = true;
+            }
+        }
+    }
+
+    /// Drive CB2, the shift register's input when it shifts in.
+    pub fn set_cb2(&mut self, level: bool) {
+        if self.b.c2 == level {
+            return;
+        }
+        self.b.c2 = level;
+        if let Control2::Input { positive, .. } = self.cb2_control() {
+            if level == positive && self.shift_mode() == 0 {
+                self.ifr |= CB2_FLAG;
+            }
+        }
+    }
+
+    pub fn ca2(&self) -> bool {
+        self.a.c2(self.ca2_control())
+    }
+
+    /// CB1 is the shift clock output while the shift register is clocked internally.
+    pub fn cb1(&self) -> bool {
+        match self.shift_mode() {
+            0b001 | 0b010 | 0b100 | 0b101 | 0b110 => self.shift_clock,
+            _ => self.b.c1,
+        }
+    }
+
+    /// CB2 is the shift register's output while it shifts out.
+    pub fn cb2(&self) -> bool {
+        match self.shift_mode() & 0b100 != 0 {
+            true => self.shift_out,
+            false => self.b.c2(self.cb2_control()),
+        }
+    }
+
+    const fn ca2_control(&self) -> Control2 {
+        Control2::from_bits(self.pcr >> 1)
+    }
+
+    const fn cb2_control(&self) -> Control2 {
+        Control2::from_bits(self.pcr >> 5)
+    }
+
+    const fn shift_mode(&self) -> u8 {
+        (self.acr >> 2) & 0b111
+    }
+
+    /// Accessing port A or B through its output register clears the flags of its control lines.
+    fn clear_port_flags(&mut self, c1_flag: u8, c2_flag: u8, c2: Control2) {
+        self.ifr &= !c1_flag;
+        if !matches!(
+            c2,
+            Control2::Input {
+                independent: true,
+                ..
+            }
+        ) {
+            self.ifr &= !c2_flag;
+        }
+    }
+
+    /// Reading or writing SR starts shifting 8 bits.
+    fn start_shift(&mut self) {
+        self.ifr &= !SR_FLAG;
+        if self.shift_mode() != 0 {
+            self.shift_count = 8;
+            self.shift_timer = 0;
+        }
+    }
+
+    fn shift(&mut self) {
+        let mode = self.shift_mode();
+        if mode & 0b100 == 0 {
+            self.sr = (self.sr << 1) | self.b.c2 as u8;
+        } else {
+            self.shift_out = self.sr & 0x80 != 0;
+            self.sr = self.sr.rotate_left(1);
+        }
+        // Shifting out at the T2 rate goes on forever, without interrupting
+        if mode != 0b100 {
+            self.shift_count -= 1;
+            if self.shift_count == 0 {
+                self.ifr |= SR_FLAG;
+            }
+        }
+    }
+
+    fn tick_timers(&mut self) {
+        if self.t1_reload {
+            self.t1_reload = false;
+            self.t1_counter = self.t1_latch;
+        } else {
+            let (counter, timed_out) = self.t1_counter.overflowing_sub(1);
+            self.t1_counter = counter;
+            if timed_out && self.acr & T1_FREE_RUN != 0 {
+                self.ifr |= T1_FLAG;
+                self.pb7 = !self.pb7;
+                self.t1_reload = true;
+            } else if timed_out && self.t1_armed {
+                self.t1_armed = false;
+                self.ifr |= T1_FLAG;
+                self.pb7 = true;
+            }
+        }
+
+        if self.acr & T2_PULSE_COUNT == 0 {
+            let (counter, timed_out) = self.t2_counter.overflowing_sub(1);
+            self.t2_counter = counter;
+            if timed_out && self.t2_armed {
+                self.t2_armed = false;
+                self.ifr |= T2_FLAG;
+            }
+        }
+    }
+
+    fn tick_shift(&mut self) {
+        let mode = self.shift_mode();
+        // Cycles between changes of the shift clock
+        let half_period = match mode {
+            0b001 | 0b100 | 0b101 => self.t2_latch_low as u16 + 2,
+            0b010 | 0b110 => 1,
+            _ => return,
+        };
+        if self.shift_count == 0 && mode != 0b100 {
+            return;
+        }
+        self.shift_timer += 1;
+        if self.shift_timer >= half_period {
+            self.shift_timer = 0;
+            self.shift_clock = !self.shift_clock;
+            if self.shift_clock {
+                self.shift();
+            }
+        }
+    }
+}
+
+impl Device for Via {
+    fn read(&mut self, reg: u16) -> u8 {
+        let value = self.peek(reg);
+        match reg & 0x0F {
+            ORB => self.clear_port_flags(CB1_FLAG, CB2_FLAG, self.cb2_control()),
+            ORA => {
+                self.clear_port_flags(CA1_FLAG, CA2_FLAG, self.ca2_control());
+                self.a.handshake(self.ca2_control());
+            }
+            T1C_L => self.ifr &= !T1_FLAG,
+            T2C_L => self.ifr &= !T2_FLAG,
+            SR => self.start_shift(),
+            _ => {}
+        }
+        value
+    }
+
+    fn peek(&self, reg: u16) -> u8 {
+        match reg & 0x0F {
+            ORB => {
+                let input = match self.acr & PB_LATCH != 0 {
+                    true => self.b.latch,
+                    false => self.b.input,
+                };
+                let value = (self.b.output & self.b.ddr) | (input & !self.b.ddr);
+                match self.acr & PB7_OUTPUT != 0 {
+                    true => (value & 0x7F) | ((self.pb7 as u8) << 7),
+                    false => value,
+                }
+            }
+            ORA | ORA_NH => match self.acr & PA_LATCH != 0 {
+                true => self.a.latch,
+                false => self.a.pins(),
+            },
+            DDRB => self.b.ddr,
+            DDRA => self.a.ddr,
+            T1C_L => self.t1_counter as u8,
+            T1C_H => (self.t1_counter >> 8) as u8,
+            T1L_L => self.t1_latch as u8,
+            T1L_H => (self.t1_latch >> 8) as u8,
+            T2C_L => self.t2_counter as u8,
+            T2C_H => (self.t2_counter >> 8) as u8,
+            SR => self.sr,
+            ACR => self.acr,
+            PCR => self.pcr,
+            IFR => match self.irq() {
+                true => self.ifr | IRQ_FLAG,
+                false => self.ifr,
+            },
+            IER => self.ier | IRQ_FLAG,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg & 0x0F {
+            ORB => {
+                self.b.output = value;
+                self.clear_port_flags(CB1_FLAG, CB2_FLAG, self.cb2_control());
+                self.b.handshake(self.cb2_control());
+            }
+            ORA => {
+                self.a.output = value;
+                self.clear_port_flags(CA1_FLAG, CA2_FLAG, self.ca2_control());
+                self.a.handshake(self.ca2_control());
+            }
+            ORA_NH => self.a.output = value,
+            DDRB => self.b.ddr = value,
+            DDRA => self.a.ddr = value,
+            T1C_L | T1L_L => self.t1_latch = (self.t1_latch & 0xFF00) | value as u16,
+            T1C_H => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | ((value as u16) << 8);
+                self.t1_counter = self.t1_latch;
+                self.t1_armed = true;
+                self.t1_reload = false;
+                self.ifr &= !T1_FLAG;
+                self.pb7 = false;
+            }
+            T1L_H => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | ((value as u16) << 8);
+                self.ifr &= !T1_FLAG;
+            }
+            T2C_L => self.t2_latch_low = value,
+            T2C_H => {
+                self.t2_counter = u16::from_le_bytes([self.t2_latch_low, value]);
+                self.t2_armed = true;
+                self.ifr &= !T2_FLAG;
+            }
+            SR => {
+                self.sr = value;
+                self.start_shift();
+            }
+            ACR => self.acr = value,
+            PCR => self.pcr = value,
+            IFR => self.ifr &= !value,
+            IER => match value & IRQ_FLAG != 0 {
+                true => self.ier |= value & !IRQ_FLAG,
+                false => self.ier &= !value,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            for port in [&mut self.a, &mut self.b] {
+                if port.c2_pulse {
+                    port.c2_pulse = false;
+                    port.c2_out = true;
+                }
+            }
+            self.tick_timers();
+            self.tick_shift();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.ifr & self.ier & !IRQ_FLAG != 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timers() {
+        let mut via = Via::new();
+        via.write(IER, IRQ_FLAG | T1_FLAG | T2_FLAG);
+        // One shot T1 with PB7, interrupting once
+        via.write(ACR, PB7_OUTPUT);
+        via.write(T1C_L, 0x10);
+        via.write(T1C_H, 0x00);
+        assert_eq!(via.port_b() & 0x80, 0x00);
+        via.tick(0x10);
+        assert_eq!((via.irq(), via.read(T1C_L)), (false, 0x00));
+        via.tick(1);
+        assert!(via.irq());
+        assert_eq!(via.port_b() & 0x80, 0x80);
+        via.write(IFR, T1_FLAG);
+        via.tick(0xFF);
+        assert!(!via.irq());
+        assert_eq!(via.read(T1C_H), 0xFF);
+
+        // T2 counts cycles, or pulses on PB6
+        via.write(T2C_L, 0x03);
+        via.write(T2C_H, 0x00);
+        via.tick(3);
+        assert!(!via.irq());
+        via.tick(1);
+        assert_eq!(via.read(IFR), IRQ_FLAG | T2_FLAG);
+        assert_eq!(via.read(T2C_L), 0xFF);
+        assert!(!via.irq());
+
+        via.write(ACR, T2_PULSE_COUNT);
+        via.write(T2C_L, 0x02);
+        via.write(T2C_H, 0x00);
+        via.tick(100);
+        via.set_port_b(0xBF);
+        via.set_port_b(0xFF);
+        assert!(!via.irq());
+        via.set_port_b(0xBF);
+        assert!(via.irq());
+        assert_eq!(via.read(IER), IRQ_FLAG | T1_FLAG | T2_FLAG);
+    }
+
+    #[test]
+    fn test_ports_and_handshake() {
+        let mut via = Via::new();
+        via.write(DDRA, 0xF0);
+        via.write(ORA, 0xA5);
+        via.set_port_a(0x3C);
+        assert_eq!((via.port_a(), via.read(ORA_NH)), (0xAC, 0xAC));
+
+        // Latch port A on the rising edge of CA1, with CA2 as a read handshake
+        via.write(ACR, PA_LATCH);
+        via.write(PCR, 0x09);
+        via.write(IER, IRQ_FLAG | CA1_FLAG);
+        via.set_ca1(false);
+        via.set_ca1(true);
+        assert!(via.irq() && via.ca2());
+        via.set_port_a(0x00);
+        assert_eq!(via.read(ORA), 0xAC);
+        assert!(!via.irq() && !via.ca2());
+        via.set_ca1(false);
+        via.set_ca1(true);
+        assert!(via.ca2());
+
+        // Independent CB2 input keeps its flag when port B is read
+        via.write(PCR, 0x60);
+        via.set_cb2(false);
+        via.set_cb2(true);
+        via.read(ORB);
+        assert_eq!(via.read(IFR) & CB2_FLAG, CB2_FLAG);
+        // Pulse output on port B writes
+        via.write(PCR, 0xA0);
+        via.write(ORB, 0x00);
+        assert!(!via.cb2());
+        via.tick(1);
+        assert!(via.cb2());
+
+        // Shift out under φ2, one bit every 2 cycles
+        via.write(ACR, 0x18);
+        via.write(IER, IRQ_FLAG | SR_FLAG);
+        via.write(SR, 0b1010_0000);
+        let mut bits = vec![];
+        for _ in 0..8 {
+            via.tick(2);
+            bits.push(via.cb2() as u8);
+        }
+        assert_eq!(bits, [1, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(via.read(IFR) & SR_FLAG, SR_FLAG);
+        // Shift in on an external clock
+        via.write(ACR, 0x0C);
+        via.read(SR);
+        for bit in [true, true, false, false, true, false, true, true] {
+            via.set_cb2(bit);
+            via.set_cb1(false);
+            via.set_cb1(true);
+        }
+        assert_eq!(via.read(SR), 0b1100_1011);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::bus::{BusAccess, BusKind, BusObserver};
+use crate::symbols::SymbolTable;
+use std::fmt::Display;
+use std::ops::Range;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    /// An instruction is about to be executed at the address
+    Execute,
+}
+
+/// A bus access that hit a watchpoint.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub addr: u16,
+    pub kind: AccessKind,
+    /// The byte read, written or about to be executed
+    pub value: u8,
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            AccessKind::Read => f.write_fmt(format_args!(
+                "read ${:02X} from ${:04X}",
+                self.value, self.addr
+            )),
+            AccessKind::Write => f.write_fmt(format_args!(
+                "write ${:02X} to ${:04X}",
+                self.value, self.addr
+            )),
+            AccessKind::Execute => f.write_fmt(format_args!("execute ${:04X}", self.addr)),
+        }
+    }
+}
+
+/// Stops execution when any of the accesses it watches for touches its addresses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addrs: Range<u32>,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Watchpoint {
+    /// Parse `[MODE] START[..END]`, where `MODE` is any of `r`, `w` and `x` (`w` by default),
+    /// `END` is exclusive, and `START` and `END` are hex or names from `symbols`.
+    pub fn parse(s: &str, symbols: &SymbolTable) -> Result<Watchpoint, String> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let (mode, range) = match words.as_slice() {
+            [range] => ("w", *range),
+            [mode, range] => (*mode, *range),
+            _ => return Err("Usage: [r|w|x] START[..END]".to_string()),
+        };
+        if mode.is_empty() || !mode.chars().all(|c| matches!(c, 'r' | 'w' | 'x')) {
+            return Err(format!(
+                "Invalid watchpoint mode \"{mode}\", use r, w and x"
+            ));
+        }
+        let resolve = |s: &str| {
+            symbols
+                .resolve(s)
+                .ok_or_else(|| format!("\"{s}\" is neither an address nor a symbol"))
+        };
+        let addrs = match range.split_once("..") {
+            Some((start, end)) => resolve(start)? as u32..resolve(end)? as u32,
+            None => {
+                let addr = resolve(range)? as u32;
+                addr..addr + 1
+            }
+        };
+        if addrs.is_empty() {
+            return Err(format!("Empty watchpoint range \"{range}\""));
+        }
+        Ok(Watchpoint {
+            addrs,
+            read: mode.contains('r'),
+            write: mode.contains('w'),
+            execute: mode.contains('x'),
+        })
+    }
+
+    pub fn matches(&self, addr: u16, kind: AccessKind) -> bool {
+        let wanted = match kind {
+            AccessKind::Read => self.read,
+            AccessKind::Write => self.write,
+            AccessKind::Execute => self.execute,
+        };
+        wanted && self.addrs.contains(&(addr as u32))
+    }
+}
+
+impl Display for Watchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mode: String = [(self.read, 'r'), (self.write, 'w'), (self.execute, 'x')]
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, c)| c)
+            .collect();
+        if self.addrs.len() == 1 {
+            f.write_fmt(format_args!("{mode} ${:04X}", self.addrs.start))
+        } else {
+            f.write_fmt(format_args!(
+                "{mode} ${:04X}..${:04X}",
+                self.addrs.start, self.addrs.end
+            ))
+        }
+    }
+}
+
+/// Remembers the first access to hit one of its watchpoints, as a
+/// [`BusObserver`](crate::bus::BusObserver) of the cpu. The bytes of the instruction being
+/// executed are read without hitting watchpoints.
+///
+/// ```rust
+/// use mini6502::symbols::SymbolTable;
+/// use mini6502::watchpoint::{AccessKind, Watcher, Watchpoint};
+/// use mini6502::{Cpu, SimpleMemory};
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// // lda $01FF; sta $0204
+/// let rom = [0xAD, 0xFF, 0x01, 0x8D, 0x04, 0x02];
+/// let mut cpu = Cpu::with_mem(SimpleMemory::from_rom(&rom));
+/// let watcher = Rc::new(RefCell::new(Watcher::new()));
+/// watcher
+///     .borrow_mut()
+///     .watchpoints
+///     .push(Watchpoint::parse("rw 200..210", &SymbolTable::new()).unwrap());
+/// cpu.add_observer(Box::new(watcher.clone()));
+/// cpu.step().unwrap();
+/// assert_eq!(watcher.borrow_mut().take_hit(), None);
+/// cpu.step().unwrap();
+/// let hit = watcher.borrow_mut().take_hit().unwrap();
+/// assert_eq!((hit.addr, hit.kind, hit.value), (0x0204, AccessKind::Write, 0x00));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Watcher {
+    pub watchpoints: Vec<Watchpoint>,
+    hit: Option<Access>,
+}
+
+impl Watcher {
+    pub fn new() -> Self {
+        Watcher::default()
+    }
+
+    /// The first access to hit a watchpoint since the last call. For a write it's the last
+    /// value written, read-modify-write instructions write the old one back first.
+    pub fn take_hit(&mut self) -> Option<Access> {
+        self.hit.take()
+    }
+
+    /// Whether an instruction at `pc` is watched for execution.
+    pub fn watches_execute(&self, pc: u16) -> bool {
+        self.watchpoints
+            .iter()
+            .any(|wp| wp.matches(pc, AccessKind::Execute))
+    }
+
+    fn record(&mut self, addr: u16, kind: AccessKind, value: u8) {
+        let new_hit = match self.hit {
+            Some(hit) => hit.kind == kind && hit.addr == addr && kind == AccessKind::Write,
+            None => true,
+        };
+        if new_hit && self.watchpoints.iter().any(|wp| wp.matches(addr, kind)) {
+            self.hit = Some(Access { addr, kind, value });
+        }
+    }
+}
+
+impl BusObserver for Watcher {
+    fn fetch(&mut self, _access: &BusAccess) {}
+
+    fn read(&mut self, access: &BusAccess) {
+        if access.kind != BusKind::Operand {
+            self.record(access.addr, AccessKind::Read, access.value);
+        }
+    }
+
+    fn write(&mut self, access: &BusAccess) {
+        self.record(access.addr, AccessKind::Write, access.value);
+    }
+}